@@ -1,65 +1,157 @@
 use std::fmt;
 use serde::{de, ser::SerializeStruct, Deserialize, Deserializer, Serialize, Serializer};
 use serde::de::{MapAccess, Visitor};
-use crate::node::{ArgValue, ParticleCluster, ParticleData, ProcessNode, StartNode};
+use crate::logger;
+use crate::node::{Arg as NodeArg, ArgValue, ExpressionNode, ParticleCluster, ParticleData, ProcessNode, StartNode};
+use crate::token::Range;
 use crate::{node::{ActionNode, ActionType, CallNode, ConditionalNode, ConditionalType, EventNode, Expression, FileNode, FunctionNode, RepeatNode}, token::{get_type_str, Selector}};
 
-pub fn compile(node: FileNode, debug: bool) -> Vec<CompiledLine> {
+#[derive(Debug)]
+pub enum CompileError {
+    MissingEventType { event: String },
+    Serialize { msg: String }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CompileError::MissingEventType { event } => write!(f, "Event '{event}' has no player or entity type, it has to be validated before compiling"),
+            CompileError::Serialize { msg } => write!(f, "Failed to compile: {msg}")
+        }
+    }
+}
+
+impl From<serde_json::Error> for CompileError {
+    fn from(err: serde_json::Error) -> Self {
+        CompileError::Serialize { msg: err.to_string() }
+    }
+}
+
+pub fn compile(mut node: FileNode, debug: bool, source_map: bool, variable_prefix: &str) -> Result<Vec<CompiledLine>, CompileError> {
+    if !variable_prefix.is_empty() {
+        for expressions in node.functions.iter_mut().map(|function| &mut function.expressions)
+            .chain(node.processes.iter_mut().map(|process| &mut process.expressions))
+            .chain(node.events.iter_mut().map(|event| &mut event.expressions)) {
+            prefix_variables(expressions, variable_prefix);
+        }
+    }
     let mut res: Vec<CompiledLine> = vec![];
     for function in node.functions.clone() {
-        match function_node(function.clone()) {
-            Ok(result) => {
-                res.push(CompiledLine {
-                    name: format!("Function {} {}", function.dfrs_name, function.df_name),
-                    code: result.clone()
-                });
-                if debug {
-                    println!("{:?}", result);
-                }
-            }
-            Err(err) => {
-                panic!("Failed to compile: {}", err)
-            }
+        let result = function_node(function.clone())?;
+        res.push(CompiledLine {
+            name: format!("Function {} {}", function.dfrs_name, function.df_name),
+            code: result.clone(),
+            source_map: if source_map { line_source_map(Range::new(function.name_start_pos, function.name_end_pos), &function.expressions) } else { vec![] }
+        });
+        if debug {
+            logger::info(format!("{:?}", result));
         }
     }
     for process in node.processes.clone() {
-        match process_node(process.clone()) {
-            Ok(result) => {
-                res.push(CompiledLine {
-                    name: format!("Process {}", process.name),
-                    code: result.clone()
-                });
-                if debug {
-                    println!("{:?}", result);
-                }
+        let result = process_node(process.clone())?;
+        res.push(CompiledLine {
+            name: format!("Process {}", process.name),
+            code: result.clone(),
+            source_map: if source_map { line_source_map(Range::new(process.name_start_pos, process.name_end_pos), &process.expressions) } else { vec![] }
+        });
+        if debug {
+            logger::info(format!("{:?}", result));
+        }
+    }
+    for event in node.events.clone() {
+        let result = event_node(event.clone())?;
+        let kind = if event.event_type == Some(ActionType::Entity) { "Entity Event" } else { "Event" };
+        res.push(CompiledLine {
+            name: format!("{kind} {}", event.event),
+            code: result.clone(),
+            source_map: if source_map { line_source_map(Range::new(event.start_pos, event.name_end_pos), &event.expressions) } else { vec![] }
+        });
+        if debug {
+            logger::info(format!("{:?}", result));
+        }
+    }
+    Ok(res)
+}
+
+// Game and saved variables are shared by every plot, so the prefix keeps the ones of this project apart
+fn prefix_variables(expressions: &mut [ExpressionNode], prefix: &str) {
+    for expression in expressions {
+        match &mut expression.node {
+            Expression::Action { node } => prefix_args(&mut node.args, prefix),
+            Expression::Conditional { node } => {
+                prefix_args(&mut node.args, prefix);
+                prefix_variables(&mut node.expressions, prefix);
+                prefix_variables(&mut node.else_expressions, prefix);
             }
-            Err(err) => {
-                panic!("Failed to compile: {}", err)
+            Expression::Call { node } => prefix_args(&mut node.args, prefix),
+            Expression::Start { node } => prefix_args(&mut node.args, prefix),
+            Expression::Repeat { node } => {
+                prefix_args(&mut node.args, prefix);
+                prefix_variables(&mut node.expressions, prefix);
             }
+            Expression::Variable { .. } => {}
         }
     }
-    for event in node.events.clone() {
-        match event_node(event.clone()) {
-            Ok(result) => {
-                res.push(CompiledLine {
-                    name: format!("Event {}", event.event),
-                    code: result.clone()
-                });
-                if debug {
-                    println!("{:?}", result);
+}
+
+fn prefix_args(args: &mut [NodeArg], prefix: &str) {
+    for arg in args {
+        match &mut arg.value {
+            ArgValue::Variable { name, scope } if scope == "unsaved" || scope == "saved" => name.insert_str(0, prefix),
+            ArgValue::Condition { args, .. } => prefix_args(args, prefix),
+            _ => {}
+        }
+    }
+}
+
+// One range for every block of the line, in the order expression_node emits them
+fn line_source_map(header: Range, expressions: &[ExpressionNode]) -> Vec<Range> {
+    let mut ranges = vec![header];
+    for expression in expressions {
+        expression_ranges(expression, &mut ranges);
+    }
+    ranges
+}
+
+fn expression_ranges(expression: &ExpressionNode, ranges: &mut Vec<Range>) {
+    let range = Range::new(expression.start_pos.clone(), expression.end_pos.clone());
+    match &expression.node {
+        Expression::Action { .. } | Expression::Call { .. } | Expression::Start { .. } => ranges.push(range),
+        Expression::Conditional { node } => {
+            ranges.extend([range.clone(), range.clone()]);
+            for expression in &node.expressions {
+                expression_ranges(expression, ranges);
+            }
+            ranges.push(range.clone());
+            if !node.else_expressions.is_empty() {
+                ranges.extend([range.clone(), range.clone()]);
+                for expression in &node.else_expressions {
+                    expression_ranges(expression, ranges);
                 }
+                ranges.push(range);
             }
-            Err(err) => {
-                panic!("Failed to compile: {}", err)
+        }
+        Expression::Repeat { node } => {
+            ranges.extend([range.clone(), range.clone()]);
+            for expression in &node.expressions {
+                expression_ranges(expression, ranges);
             }
+            ranges.push(range);
         }
+        Expression::Variable { .. } => {}
     }
-    res
 }
 
-fn event_node(event_node: EventNode) -> Result<String, serde_json::Error> {
+fn event_node(event_node: EventNode) -> Result<String, CompileError> {
     let mut codeline = Codeline { blocks: vec![] };
 
+    // Validation decides whether an event is a player or an entity event
+    let block = match event_node.event_type {
+        Some(ActionType::Player) => "event",
+        Some(_) => "entity_event",
+        None => return Err(CompileError::MissingEventType { event: event_node.event })
+    };
+
     let attribute = if event_node.cancelled {
         Some("LS-CANCEL".into())
     } else {
@@ -69,7 +161,7 @@ fn event_node(event_node: EventNode) -> Result<String, serde_json::Error> {
     let event_block = Block {
         id: "block".to_owned(), 
         sub_action: None,
-        block: if event_node.event_type.unwrap() == ActionType::Player { Some("event".to_owned()) } else { Some("entity_event".to_owned()) }, 
+        block: Some(block.to_owned()),
         action: Some(event_node.event),
         args: Some(Args { items: vec![] }),
         target: None,
@@ -98,7 +190,7 @@ fn function_node(function_node: FunctionNode) -> Result<String, serde_json::Erro
 
     let mut items = vec![
         Arg { item: ArgItem { data: ArgValueData::Id { id: "function".into() }, id: "hint".into() }, slot: 25 },
-        Arg { item: ArgItem { data: ArgValueData::Tag { action: "dynamic".into(), block: "func".into(), option: "False".into(),tag: "Is Hidden".into() }, id: "bl_tag".into() }, slot: 26 }
+        Arg { item: ArgItem { data: ArgValueData::Tag { action: "dynamic".into(), block: "func".into(), option: hidden_option(function_node.hidden), tag: "Is Hidden".into() }, id: "bl_tag".into() }, slot: 26 }
     ];
 
     for (slot, param) in function_node.params.into_iter().enumerate() {
@@ -118,7 +210,7 @@ fn function_node(function_node: FunctionNode) -> Result<String, serde_json::Erro
                     ArgValueData::Id { id } => FunctionDefaultItemData::Id { id },
                     ArgValueData::Location { is_block, loc } => FunctionDefaultItemData::Location { is_block, loc },
                     ArgValueData::Vector { x, y, z } => FunctionDefaultItemData::Vector { x, y, z },
-                    ArgValueData::Sound { sound, volume, pitch } => FunctionDefaultItemData::Sound { sound, volume, pitch },
+                    ArgValueData::Sound { sound, variant, volume, pitch } => FunctionDefaultItemData::Sound { sound, variant, volume, pitch },
                     ArgValueData::Potion { potion, amplifier, duration } => FunctionDefaultItemData::Potion { potion, amplifier, duration },
                     _ => unreachable!()
                 },
@@ -168,11 +260,15 @@ fn function_node(function_node: FunctionNode) -> Result<String, serde_json::Erro
     Ok(res)
 }
 
+fn hidden_option(hidden: bool) -> String {
+    if hidden { "True".into() } else { "False".into() }
+}
+
 fn process_node(process_node: ProcessNode) -> Result<String, serde_json::Error> {
     let mut codeline = Codeline { blocks: vec![] };
 
     let items = vec![
-        Arg { item: ArgItem { data: ArgValueData::Tag { action: "dynamic".into(), block: "process".into(), option: "False".into(),tag: "Is Hidden".into() }, id: "bl_tag".into() }, slot: 26 }
+        Arg { item: ArgItem { data: ArgValueData::Tag { action: "dynamic".into(), block: "process".into(), option: hidden_option(process_node.hidden), tag: "Is Hidden".into() }, id: "bl_tag".into() }, slot: 26 }
     ];
 
     let process_block = Block {
@@ -570,8 +666,8 @@ fn arg_val_from_arg(arg: crate::node::Arg, node_name: String, block: String) ->
         ArgValue::Vector { x, y, z } => {
             Some( Arg { item: ArgItem { data: ArgValueData::Vector { x, y, z }, id: String::from("vec") }, slot: arg.index } )
         }
-        ArgValue::Sound { sound, volume, pitch } => {
-            Some( Arg { item: ArgItem { data: ArgValueData::Sound { sound, volume, pitch }, id: String::from("snd") }, slot: arg.index } )
+        ArgValue::Sound { sound, variant, volume, pitch } => {
+            Some( Arg { item: ArgItem { data: ArgValueData::Sound { sound, variant, volume, pitch }, id: String::from("snd") }, slot: arg.index } )
         }
         ArgValue::Potion { potion, amplifier, duration } => {
             Some( Arg { item: ArgItem { data: ArgValueData::Potion { potion, amplifier, duration }, id: String::from("pot") }, slot: arg.index } )
@@ -666,7 +762,7 @@ pub enum ArgValueData {
     Variable { name: String, scope: String },
     Location { is_block: bool, loc: Location },
     Vector { x: f32, y: f32, z: f32 },
-    Sound { sound: String, volume: f32, pitch: f32 },
+    Sound { sound: String, variant: Option<String>, volume: f32, pitch: f32 },
     Potion { potion: String, amplifier: f32, duration: f32 },
     Tag { action: String, block: String, option: String, tag: String },
     FunctionParam {
@@ -695,7 +791,7 @@ pub enum FunctionDefaultItemData {
     Id { id: String },
     Location { is_block: bool, loc: Location },
     Vector { x: f32, y: f32, z: f32 },
-    Sound { sound: String, volume: f32, pitch: f32 },
+    Sound { sound: String, variant: Option<String>, volume: f32, pitch: f32 },
     Potion { potion: String, amplifier: f32, duration: f32 },
     Particle { particle: String, cluster: ParticleCluster, data: ParticleData },
 }
@@ -746,9 +842,16 @@ impl Serialize for ArgValueData {
                 state.serialize_field("z", z)?;
                 state.end()
             }
-            ArgValueData::Sound { sound, volume, pitch } => {
-                let mut state = serializer.serialize_struct("MyEnum", 3)?;
-                state.serialize_field("sound", sound)?;
+            ArgValueData::Sound { sound, variant, volume, pitch } => {
+                let mut state = serializer.serialize_struct("MyEnum", 4)?;
+                if sound.contains(':') {
+                    state.serialize_field("key", sound)?;
+                } else {
+                    state.serialize_field("sound", sound)?;
+                }
+                if let Some(variant) = variant {
+                    state.serialize_field("variant", variant)?;
+                }
                 state.serialize_field("vol", volume)?;
                 state.serialize_field("pitch", pitch)?;
                 state.end()
@@ -810,6 +913,8 @@ impl<'de> Deserialize<'de> for ArgValueData {
             Y,
             Z,
             Sound,
+            Key,
+            Variant,
             Vol,
             Pitch,
             Pot,
@@ -853,6 +958,7 @@ impl<'de> Deserialize<'de> for ArgValueData {
                 let mut y = None;
                 let mut z = None;
                 let mut sound = None;
+                let mut variant = None;
                 let mut volume = None;
                 let mut pitch = None;
                 let mut potion = None;
@@ -937,12 +1043,18 @@ impl<'de> Deserialize<'de> for ArgValueData {
                             }
                             z = Some(map.next_value()?);
                         }
-                        Field::Sound => {
+                        Field::Sound | Field::Key => {
                             if sound.is_some() {
                                 return Err(de::Error::duplicate_field("sound"));
                             }
                             sound = Some(map.next_value()?);
                         }
+                        Field::Variant => {
+                            if variant.is_some() {
+                                return Err(de::Error::duplicate_field("variant"));
+                            }
+                            variant = Some(map.next_value()?);
+                        }
                         Field::Vol => {
                             if volume.is_some() {
                                 return Err(de::Error::duplicate_field("vol"));
@@ -1059,7 +1171,7 @@ impl<'de> Deserialize<'de> for ArgValueData {
                 } else if let (Some(x), Some(y), Some(z)) = (x, y, z) {
                     Ok(ArgValueData::Vector { x, y, z })
                 } else if let (Some(sound), Some(volume), Some(pitch)) = (sound, volume, pitch) {
-                    Ok(ArgValueData::Sound { sound, volume, pitch })
+                    Ok(ArgValueData::Sound { sound, variant, volume, pitch })
                 } else if let (Some(potion), Some(amplifier), Some(duration)) =
                     (potion, amplifier, duration)
                 {
@@ -1110,6 +1222,8 @@ impl<'de> Deserialize<'de> for FunctionDefaultItemData {
             Y,
             Z,
             Sound,
+            Key,
+            Variant,
             Vol,
             Pitch,
             Pot,
@@ -1141,6 +1255,7 @@ impl<'de> Deserialize<'de> for FunctionDefaultItemData {
                 let mut y = None;
                 let mut z = None;
                 let mut sound = None;
+                let mut variant = None;
                 let mut volume = None;
                 let mut pitch = None;
                 let mut potion = None;
@@ -1194,12 +1309,18 @@ impl<'de> Deserialize<'de> for FunctionDefaultItemData {
                             }
                             z = Some(map.next_value()?);
                         }
-                        Field::Sound => {
+                        Field::Sound | Field::Key => {
                             if sound.is_some() {
                                 return Err(de::Error::duplicate_field("sound"));
                             }
                             sound = Some(map.next_value()?);
                         }
+                        Field::Variant => {
+                            if variant.is_some() {
+                                return Err(de::Error::duplicate_field("variant"));
+                            }
+                            variant = Some(map.next_value()?);
+                        }
                         Field::Vol => {
                             if volume.is_some() {
                                 return Err(de::Error::duplicate_field("vol"));
@@ -1260,7 +1381,7 @@ impl<'de> Deserialize<'de> for FunctionDefaultItemData {
                 } else if let (Some(x), Some(y), Some(z)) = (x, y, z) {
                     Ok(FunctionDefaultItemData::Vector { x, y, z })
                 } else if let (Some(sound), Some(volume), Some(pitch)) = (sound, volume, pitch) {
-                    Ok(FunctionDefaultItemData::Sound { sound, volume, pitch })
+                    Ok(FunctionDefaultItemData::Sound { sound, variant, volume, pitch })
                 } else if let (Some(potion), Some(amplifier), Some(duration)) =
                     (potion, amplifier, duration)
                 {
@@ -1315,9 +1436,16 @@ impl Serialize for FunctionDefaultItemData {
                 state.serialize_field("z", z)?;
                 state.end()
             }
-            FunctionDefaultItemData::Sound { sound, volume, pitch } => {
-                let mut state = serializer.serialize_struct("MyEnum", 3)?;
-                state.serialize_field("sound", sound)?;
+            FunctionDefaultItemData::Sound { sound, variant, volume, pitch } => {
+                let mut state = serializer.serialize_struct("MyEnum", 4)?;
+                if sound.contains(':') {
+                    state.serialize_field("key", sound)?;
+                } else {
+                    state.serialize_field("sound", sound)?;
+                }
+                if let Some(variant) = variant {
+                    state.serialize_field("variant", variant)?;
+                }
                 state.serialize_field("vol", volume)?;
                 state.serialize_field("pitch", pitch)?;
                 state.end()
@@ -1372,7 +1500,302 @@ impl Serialize for Location {
     }
 }
 
+#[derive(Clone, Deserialize, Serialize)]
 pub struct CompiledLine {
     pub name: String,
-    pub code: String
-}
\ No newline at end of file
+    pub code: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub source_map: Vec<Range>
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::compile;
+    use crate::testing::{actions, blocks, layout, validate};
+    use crate::token::{Position, Range};
+
+    fn item(block: &Value, index: usize) -> &Value {
+        &block["args"]["items"][index]["item"]
+    }
+
+    #[test]
+    fn nested_action_uses_temp_variable() {
+        let lines = blocks("@join {\n    p.sendMessage(v.add(1, 2));\n}");
+        assert_eq!(actions(&lines[0]), vec!["event Join", "set_var +", "player_action SendMessage"]);
+        let temp = json!({ "id": "var", "data": { "name": "dfrs_tmp_1", "scope": "line" } });
+        assert_eq!(item(&lines[0][1], 0), &temp);
+        assert_eq!(item(&lines[0][1], 1)["data"]["name"], "1");
+        assert_eq!(item(&lines[0][2], 0), &temp);
+    }
+
+    #[test]
+    fn infinite_potion() {
+        let lines = blocks("@join {\n    p.givePotion(Potion(\"speed\", 1, infinite));\n}");
+        assert_eq!(item(&lines[0][1], 0), &json!({ "id": "pot", "data": { "pot": "speed", "amp": 1.0, "dur": 1000000.0 } }));
+    }
+
+    #[test]
+    fn and_chain_nests_conditionals() {
+        let blocks = &blocks("@join {\n    ifp isSneaking() and !isFlying() and ife isNear(Location(0,0,0), 10) {\n        p.sendMessage(\"A\");\n    }\n}")[0];
+        assert_eq!(layout(blocks), [
+            "event Join",
+            "if_player IsSneaking", "{",
+            "if_player IsFlying NOT", "{",
+            "if_entity IsNear", "{",
+            "player_action SendMessage",
+            "}", "}", "}"
+        ]);
+    }
+
+    #[test]
+    fn and_chain_repeats_else() {
+        let blocks = &blocks("@join {\n    ifv equal(1, 1) and equal(2, 2) {\n        p.sendMessage(\"A\");\n    } else {\n        p.sendMessage(\"B\");\n    }\n}")[0];
+        assert_eq!(layout(blocks), [
+            "event Join",
+            "if_var =", "{",
+            "if_var =", "{",
+            "player_action SendMessage",
+            "}", "else", "{",
+            "player_action SendMessage",
+            "}",
+            "}", "else", "{",
+            "player_action SendMessage",
+            "}"
+        ]);
+    }
+
+    // The option of the Is Hidden tag on the first block of the first codeline
+    fn hidden(source: &str) -> Value {
+        let blocks = blocks(source);
+        let tag = blocks[0][0]["args"]["items"].as_array().unwrap().iter().find(|item| item["slot"] == 26).unwrap();
+        assert_eq!(tag["item"]["data"]["tag"], "Is Hidden");
+        tag["item"]["data"]["option"].clone()
+    }
+
+    #[test]
+    fn hidden_function() {
+        assert_eq!(hidden("fn foo() #hidden {\n}"), "True");
+        assert_eq!(hidden("fn foo() {\n}"), "False");
+        assert_eq!(hidden("fn foo(a: number) #hidden {\n}"), "True");
+    }
+
+    #[test]
+    fn hidden_process() {
+        assert_eq!(hidden("proc loop #hidden {\n}"), "True");
+        assert_eq!(hidden("proc loop {\n}"), "False");
+    }
+
+    #[test]
+    fn entity_event_line() {
+        let lines = crate::testing::compile_lines("@entity:teleport {\n}\n@teleport {\n}");
+        let names: Vec<&str> = lines.iter().map(|line| line.name.as_str()).collect();
+        assert_eq!(names, ["Entity Event Teleport", "Event Teleport"]);
+        let blocks = blocks("@entity:teleport {\n}");
+        assert_eq!(layout(&blocks[0]), ["entity_event Teleport"]);
+    }
+
+    // The variable and number of a block setting or checking a repeat-else flag
+    fn flag(block: &Value) -> (String, String) {
+        assert_eq!(item(block, 0)["id"], "var");
+        assert_eq!(item(block, 0)["data"]["scope"], "line");
+        (item(block, 0)["data"]["name"].as_str().unwrap().into(), item(block, 1)["data"]["name"].as_str().unwrap().into())
+    }
+
+    #[test]
+    fn repeat_else_expansion() {
+        let blocks = &blocks("@join {\n    line i;\n    repeat multiple(i, 3) {\n        p.sendMessage(\"A\");\n    } else {\n        p.sendMessage(\"B\");\n    }\n    p.sendMessage(\"C\");\n}")[0];
+        assert_eq!(layout(blocks), [
+            "event Join",
+            "set_var =",
+            "repeat Multiple", "{",
+            "set_var =",
+            "player_action SendMessage",
+            "}",
+            "if_var =", "{",
+            "player_action SendMessage",
+            "}",
+            "player_action SendMessage"
+        ]);
+        assert_eq!(flag(&blocks[1]), ("dfrs_tmp_1".into(), "0".into()));
+        assert_eq!(flag(&blocks[4]), ("dfrs_tmp_1".into(), "1".into()));
+        assert_eq!(flag(&blocks[7]), ("dfrs_tmp_1".into(), "0".into()));
+        assert_eq!(item(&blocks[9], 0)["data"]["name"], "B");
+        assert_eq!(item(&blocks[11], 0)["data"]["name"], "C");
+    }
+
+    #[test]
+    fn repeat_else_flags_are_unique() {
+        let blocks = &blocks("@join {\n    repeat forever() {\n    } else {\n        p.sendMessage(\"A\");\n    }\n    repeat forever() {\n    } else {\n        p.sendMessage(\"B\");\n    }\n}")[0];
+        let flags: Vec<String> = blocks.iter().filter(|block| block["block"] == "set_var" || block["block"] == "if_var").map(|block| flag(block).0).collect();
+        assert_eq!(flags, ["dfrs_tmp_1", "dfrs_tmp_1", "dfrs_tmp_1", "dfrs_tmp_2", "dfrs_tmp_2", "dfrs_tmp_2"]);
+    }
+
+    #[test]
+    fn repeat_else_inside_conditional() {
+        let blocks = &blocks("@join {\n    ifp isSneaking() {\n        repeat forever() {\n            c.wait(1);\n        } else {\n            p.sendMessage(\"B\");\n        }\n    }\n    p.sendMessage(\"C\");\n}")[0];
+        assert_eq!(layout(blocks), [
+            "event Join",
+            "if_player IsSneaking", "{",
+            "set_var =",
+            "repeat Forever", "{",
+            "set_var =",
+            "control Wait",
+            "}",
+            "if_var =", "{",
+            "player_action SendMessage",
+            "}",
+            "}",
+            "player_action SendMessage"
+        ]);
+    }
+
+    #[test]
+    fn repeat_else_after_nested_action() {
+        let blocks = &blocks("@join {\n    line i;\n    repeat multiple(i, v.add(1, 2)) {\n    } else {\n        p.sendMessage(\"B\");\n    }\n}")[0];
+        assert_eq!(actions(blocks), ["event Join", "set_var +", "set_var =", "repeat Multiple", "set_var =", "if_var =", "player_action SendMessage"]);
+        assert_eq!(flag(&blocks[2]), ("dfrs_tmp_2".into(), "0".into()));
+    }
+
+    #[test]
+    fn select_reset_and_filters() {
+        let blocks = &blocks("game points;\n@join {\n    s.reset();\n    s.filterCondition(ifp isSneaking(), ifv equal(points, 10));\n}")[0];
+        assert_eq!(actions(blocks), vec!["event Join", "select_obj Reset", "select_obj FilterCondition", "select_obj FilterCondition"]);
+        assert_eq!(blocks[2]["subAction"], "IsSneaking");
+        assert!(blocks[2]["args"]["items"].as_array().unwrap().is_empty());
+        assert_eq!(blocks[3]["subAction"], "=");
+        assert_eq!(item(&blocks[3], 0)["data"]["name"], "points");
+        assert_eq!(item(&blocks[3], 1)["data"]["name"], "10");
+    }
+
+    // Test threads have a 2MB stack, like the language server's, so code nested up to the default limit has to fit in one
+    #[test]
+    fn deep_nesting_compiles() {
+        let conditionals = format!("@join {{\n{}p.sendMessage(\"A\");\n{}}}", "ifp isSneaking() {\n".repeat(48), "}\n".repeat(48));
+        assert_eq!(blocks(&conditionals)[0].len(), 1 + 48 * 3 + 1);
+        let args = format!("@join {{\n    p.sendMessage({}1{});\n}}", "v.add(".repeat(48), ", 1)".repeat(48));
+        assert_eq!(blocks(&args)[0].len(), 1 + 48 + 1);
+    }
+
+    fn source_maps(source: &str) -> Vec<(Vec<Value>, Vec<Range>)> {
+        let node = validate(source).expect("Failed to validate");
+        compile(node, false, true, "").expect("Failed to compile").into_iter()
+            .map(|line| (serde_json::from_str::<Value>(&line.code).unwrap()["blocks"].as_array().cloned().unwrap(), line.source_map))
+            .collect()
+    }
+
+    fn range(start: (u32, u32), end: (u32, u32)) -> Range {
+        Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1))
+    }
+
+    #[test]
+    fn source_map_ranges() {
+        let source = "fn greet() {\n    p.sendMessage(\"Hi\");\n}\n@join {\n    ifp isSneaking() {\n        call(\"greet\");\n    } else {\n        p.heal(1);\n    }\n}";
+        let lines = source_maps(source);
+        let (blocks, map) = &lines[0];
+        assert_eq!(actions(blocks), vec!["func greet", "player_action SendMessage"]);
+        assert_eq!(map, &vec![range((1, 4), (1, 9)), range((2, 5), (2, 18))]);
+
+        let (blocks, map) = &lines[1];
+        assert_eq!(layout(blocks), ["event Join", "if_player IsSneaking", "{", "call_func greet", "}", "else", "{", "player_action Heal", "}"]);
+        assert_eq!(map.len(), blocks.len());
+        assert_eq!(map[0], range((4, 2), (4, 6)));
+        assert_eq!(map[3], range((6, 9), (6, 23)));
+        assert_eq!(map[7], range((8, 9), (8, 15)));
+        assert!([1, 2, 4, 5, 6, 8].iter().all(|&index| map[index].start.line == 5));
+    }
+
+    // Every sugar that adds blocks has to add a range for each of them too
+    #[test]
+    fn source_map_covers_every_block() {
+        let source = "game points;\nproc loop {\n    start(\"loop\");\n}\n@join {\n    p.sendMessage(v.add(1, 2));\n    ifp isSneaking() and !isFlying() {\n        p.heal(1);\n    } else {\n        s.filterCondition(ifp isSneaking(), ifv equal(points, 10));\n    }\n    line i;\n    repeat multiple(i, 3) {\n        p.heal(i);\n    } else {\n        p.heal(2);\n    }\n}\n@entity:blockFall {\n    e.heal(1);\n}";
+        for (blocks, map) in source_maps(source) {
+            assert_eq!(map.len(), blocks.len(), "{}", layout(&blocks).join(", "));
+        }
+        let node = validate("@join {\n    p.sendMessage(\"Hi\");\n}").unwrap();
+        assert!(compile(node, false, false, "").unwrap()[0].source_map.is_empty());
+    }
+
+    // The action dump has no per-action default, so an action without a selector targets whoever the event is about
+    #[test]
+    fn implicit_selector_is_default() {
+        let blocks = &blocks("@join {\n    p.sendMessage(\"A\");\n    p:all.sendMessage(\"B\");\n    e.heal(1);\n    g.cancelEvent();\n}")[0];
+        let targets: Vec<Value> = blocks[1..].iter().map(|block| block["target"].clone()).collect();
+        assert_eq!(targets, vec![json!("Default"), json!("AllPlayers"), json!("Default"), Value::Null]);
+    }
+
+    #[test]
+    fn grouped_events_share_body() {
+        let lines = blocks("@join, @respawn {\n    p.sendMessage(\"A\");\n    p.heal(1);\n}");
+        assert_eq!(lines.len(), 2);
+        assert_eq!((lines[0][0]["action"].as_str(), lines[1][0]["action"].as_str()), (Some("Join"), Some("Respawn")));
+        assert_eq!(lines[0][1..], lines[1][1..]);
+        assert_eq!(actions(&lines[0][1..]), ["player_action SendMessage", "player_action Heal"]);
+    }
+
+    #[test]
+    fn temp_variables_skip_source_names() {
+        let blocks = &blocks("@join {\n    line dfrs_tmp_1;\n    line dfrs_tmp_3;\n    p.sendMessage(v.add(dfrs_tmp_1, 2));\n    repeat forever() {\n    } else {\n        p.sendMessage(dfrs_tmp_3);\n    }\n}")[0];
+        assert_eq!(actions(blocks), ["event Join", "set_var +", "player_action SendMessage", "set_var =", "repeat Forever", "set_var =", "if_var =", "player_action SendMessage"]);
+        assert_eq!(item(&blocks[1], 0)["data"]["name"], "dfrs_tmp_2");
+        assert_eq!(item(&blocks[1], 1)["data"]["name"], "dfrs_tmp_1");
+        assert_eq!(flag(&blocks[3]).0, "dfrs_tmp_4");
+        assert_eq!(item(blocks.iter().rfind(|block| block["action"] == "SendMessage").unwrap(), 0)["data"]["name"], "dfrs_tmp_3");
+    }
+
+    #[test]
+    fn return_in_event_ends_thread() {
+        let blocks = &blocks("@join {\n    ifp isSneaking() {\n        return;\n    }\n    p.sendMessage(\"A\");\n}")[0];
+        assert_eq!(layout(blocks), ["event Join", "if_player IsSneaking", "{", "control End", "}", "player_action SendMessage"]);
+    }
+
+    #[test]
+    fn return_in_function_returns() {
+        let lines = blocks("fn foo() {\n    return;\n}\nproc bar {\n    return;\n}\n@join {\n    return;\n}");
+        let ends: Vec<String> = lines.iter().map(|blocks| actions(blocks).pop().unwrap()).collect();
+        assert_eq!(ends, ["control Return", "control End", "control End"]);
+    }
+
+    #[test]
+    fn unless_compiles_to_not() {
+        let blocks = &blocks("@join {\n    unless ifp isSneaking() {\n        p.sendMessage(\"A\");\n    }\n}")[0];
+        assert_eq!(layout(blocks), ["event Join", "if_player IsSneaking NOT", "{", "player_action SendMessage", "}"]);
+        assert_eq!(blocks[1]["attribute"], "NOT");
+    }
+
+    #[test]
+    fn unvalidated_event_is_an_error() {
+        let node = crate::testing::parse("@join {\n    p.sendMessage(\"A\");\n}").unwrap();
+        match compile(node, false, false, "") {
+            Err(err @ super::CompileError::MissingEventType { .. }) => assert_eq!(err.to_string(), "Event 'join' has no player or entity type, it has to be validated before compiling"),
+            Err(err) => panic!("Expected a missing event type, got {err}"),
+            Ok(_) => panic!("Expected a missing event type")
+        }
+    }
+
+    #[test]
+    fn event_blocks_follow_event_type() {
+        let lines = blocks("@join {\n}\n@entity:blockFall {\n}\n@player:teleport {\n}\n@entity:teleport {\n}");
+        let events: Vec<String> = lines.iter().map(|blocks| actions(blocks).remove(0)).collect();
+        assert_eq!(events, ["event Join", "entity_event BlockFall", "event Teleport", "entity_event Teleport"]);
+    }
+
+    #[test]
+    fn wait_shorthand_compiles_to_wait() {
+        assert_eq!(blocks("@join {\n    wait(2 seconds);\n}"), blocks("@join {\n    c.wait(2, timeUnit=\"Seconds\");\n}"));
+        assert_eq!(blocks("@join {\n    wait(1 minute);\n}"), blocks("@join {\n    c.wait(1, timeUnit=\"Minutes\");\n}"));
+        assert_eq!(blocks("@join {\n    wait(5);\n}"), blocks("@join {\n    c.wait(5);\n}"));
+        assert_eq!(actions(&blocks("@join {\n    wait(5 ticks);\n}")[0]), ["event Join", "control Wait"]);
+    }
+
+    #[test]
+    fn guard_compiles_to_inverted_conditional() {
+        let guarded = blocks("@join {\n    guard ifp isFlying() else {\n        p.sendMessage(\"A\");\n    }\n    p.launchUp(1);\n}");
+        assert_eq!(guarded, blocks("@join {\n    ifp !isFlying() {\n        p.sendMessage(\"A\");\n        return;\n    }\n    p.launchUp(1);\n}"));
+        assert_eq!(actions(&guarded[0]), ["event Join", "if_player IsFlying", "player_action SendMessage", "control End", "player_action LaunchUp"]);
+        assert_eq!(guarded[0][1]["attribute"], "NOT");
+        let function = blocks("fn check() {\n    guard ifp isFlying() else {\n    }\n}");
+        assert_eq!(actions(&function[0]), ["func check", "if_player IsFlying", "control Return"]);
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::compile::{compile, CompileError, CompiledLine};
+use crate::config::Config;
+use crate::definitions::Definitions;
+use crate::import::{resolve_imports, ImportError};
+use crate::lexer::{Lexer, LexerError};
+use crate::node::{Expression, FileNode, VariableNode};
+use crate::parser::{ParseError, Parser};
+use crate::validate::{ValidateError, ValidateWarning, Validator};
+
+pub enum CompilerError {
+    Lexer(LexerError),
+    Parse(ParseError),
+    Import(ImportError),
+    Validate(ValidateError),
+    Compile(CompileError)
+}
+
+// Runs the whole pipeline from source to codelines. The definitions are loaded once and shared, so one compiler
+// can compile many sources without loading the action dump again
+pub struct Compiler {
+    config: Config,
+    definitions: Arc<Definitions>,
+    globals: Vec<VariableNode>,
+    plot_variables: Option<HashSet<String>>
+}
+
+impl Compiler {
+    pub fn new(config: Config, definitions: Arc<Definitions>) -> Compiler {
+        Compiler { config, definitions, globals: vec![], plot_variables: None }
+    }
+
+    // Game and saved variables declared in other files of the project
+    pub fn with_globals(mut self, globals: Vec<VariableNode>) -> Compiler {
+        self.globals = globals;
+        self
+    }
+
+    pub fn with_plot_variables(mut self, plot_variables: Option<HashSet<String>>) -> Compiler {
+        self.plot_variables = plot_variables;
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn into_config(self) -> Config {
+        self.config
+    }
+
+    pub fn compile(&self, source: &str) -> Result<Vec<CompiledLine>, CompilerError> {
+        self.compile_file(source, Path::new(""))
+    }
+
+    // Imports are looked up next to the file
+    pub fn compile_file(&self, source: &str, file: &Path) -> Result<Vec<CompiledLine>, CompilerError> {
+        let (node, _) = self.check(source, file)?;
+        self.compile_node(node).map_err(CompilerError::Compile)
+    }
+
+    // Everything before compiling, returning the validated file with its imported functions and the warnings
+    pub fn check(&self, source: &str, file: &Path) -> Result<(FileNode, Vec<ValidateWarning>), CompilerError> {
+        let tokens = Lexer::new(source.to_owned()).with_tab_width(self.config.tab_width).run().map_err(CompilerError::Lexer)?;
+        if self.config.debug.tokens {
+            for token in &tokens {
+                println!("{}", token);
+            }
+            println!("\n");
+        }
+
+        let mut parser = Parser::new(tokens);
+        parser.add_variables(self.globals.clone());
+        parser.set_max_depth(self.config.validation.max_depth);
+        let node = parser.run().map_err(CompilerError::Parse)?;
+        if self.config.debug.nodes {
+            print_nodes(&node);
+        }
+
+        let imports = resolve_imports(&node, file, &self.config, &self.definitions).map_err(CompilerError::Import)?;
+
+        let mut validator = Validator::new(&self.config, self.definitions.clone());
+        validator.set_imports(&imports);
+        if let Some(variables) = &self.plot_variables {
+            validator.set_plot_variables(variables.clone());
+        }
+        let mut validated = validator.validate(node).map_err(CompilerError::Validate)?;
+        validated.functions.extend(imports.functions);
+        Ok((validated, validator.take_warnings()))
+    }
+
+    pub fn compile_node(&self, node: FileNode) -> Result<Vec<CompiledLine>, CompileError> {
+        compile(node, self.config.debug.compile, self.config.debug.source_map, &self.config.variable_prefix)
+    }
+}
+
+fn print_nodes(res: &FileNode) {
+    for event in &res.events {
+        println!("{}", event.event);
+        for expression in &event.expressions {
+            match &expression.node {
+                Expression::Action { node } => {
+                    println!("{:?} {:?} {:?} {:?}", node.action_type, node.selector, node.name, node.args)
+                } 
+                Expression::Conditional { node } => {
+                    println!("{:?} {:?} {:?} {:?}", node.conditional_type, node.selector, node.name, node.args)
+                },
+                Expression::Call { node } => {
+                    println!("{:?} {:?}", node.name, node.args)
+                }
+                Expression::Start { node } => {
+                    println!("{:?} {:?}", node.name, node.args)
+                }
+                Expression::Repeat { node } => {
+                    println!("{:?} {:?}", node.name, node.args)
+                },
+                Expression::Variable { node } => {
+                    println!("{:?} {:?} {:?}", node.var_type, node.dfrs_name, node.df_name)
+                },
+                
+            }
+        }
+    }
+    println!("\n");
+    for function in &res.functions {
+        println!("{} / {}", function.dfrs_name, function.df_name);
+        for param in &function.params {
+            println!("{:?}", param);
+        }
+        for expression in &function.expressions {
+            match &expression.node {
+                Expression::Action { node } => {
+                    println!("{:?} {:?} {:?} {:?}", node.action_type, node.selector, node.name, node.args)
+                }
+                Expression::Conditional { node } => {
+                    println!("{:?} {:?} {:?} {:?}", node.conditional_type, node.selector, node.name, node.args)
+                }
+                Expression::Call { node } => {
+                    println!("{:?} {:?}", node.name, node.args)
+                }
+                Expression::Start { node } => {
+                    println!("{:?} {:?}", node.name, node.args)
+                }
+                Expression::Repeat { node } => {
+                    println!("{:?} {:?}", node.name, node.args)
+                },
+                Expression::Variable { node } => {
+                    println!("{:?} {:?} {:?}", node.var_type, node.dfrs_name, node.df_name)
+                },
+                
+            }
+        }
+    }
+    println!("\n");
+}
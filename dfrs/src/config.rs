@@ -3,22 +3,67 @@ use std::path::PathBuf;
 use serde::{Deserialize, Serialize};
 
 
-#[derive(Deserialize, Serialize, Debug, Default)]
+#[derive(Deserialize, Serialize, Debug)]
 pub struct Config {
     #[serde(default)]
     pub sending: Sending,
     #[serde(default)]
-    pub debug: Debug
+    pub scanning: Scanning,
+    #[serde(default)]
+    pub validation: Validation,
+    #[serde(default)]
+    pub logging: Logging,
+    #[serde(default)]
+    pub decompiling: Decompiling,
+    #[serde(default)]
+    pub debug: Debug,
+    #[serde(default)]
+    pub variable_prefix: String,
+    #[serde(default = "default_tab_width")]
+    pub tab_width: u32
+}
+
+fn default_tab_width() -> u32 {
+    4
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            sending: Sending::default(),
+            scanning: Scanning::default(),
+            validation: Validation::default(),
+            logging: Logging::default(),
+            decompiling: Decompiling::default(),
+            debug: Debug::default(),
+            variable_prefix: String::new(),
+            tab_width: default_tab_width()
+        }
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug)]
 pub struct Sending {
-    pub api: SendApi
+    pub api: SendApi,
+    #[serde(default = "bool::default")]
+    pub only_changed: bool,
+    #[serde(default = "default_minify")]
+    pub minify: bool,
+    #[serde(default = "default_compression_level")]
+    pub compression_level: u32
+}
+
+fn default_minify() -> bool {
+    true
+}
+
+fn default_compression_level() -> u32 {
+    6
 }
 
 impl Default for Sending {
     fn default() -> Self {
-        Sending { api: SendApi::CodeClient }
+        Sending { api: SendApi::CodeClient, only_changed: false, minify: default_minify(), compression_level: default_compression_level() }
     }
 }
 
@@ -29,6 +74,113 @@ pub enum SendApi {
     Recode
 }
 
+#[derive(Deserialize, Serialize, Debug)]
+pub struct Scanning {
+    #[serde(default = "default_scan_url")]
+    pub url: String,
+    #[serde(default = "default_scope_message")]
+    pub scope_message: String,
+    #[serde(default = "default_scan_message")]
+    pub scan_message: String
+}
+
+fn default_scan_url() -> String {
+    "ws://localhost:31375".into()
+}
+
+fn default_scope_message() -> String {
+    "scopes read_plot".into()
+}
+
+fn default_scan_message() -> String {
+    "scan".into()
+}
+
+impl Default for Scanning {
+    fn default() -> Self {
+        Scanning { url: default_scan_url(), scope_message: default_scope_message(), scan_message: default_scan_message() }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Validation {
+    #[serde(default = "bool::default")]
+    pub coerce_types: bool,
+    #[serde(default = "bool::default")]
+    pub deny_recursion: bool,
+    #[serde(default)]
+    pub extra_sounds: Vec<String>,
+    #[serde(default)]
+    pub extra_potions: Vec<String>,
+    #[serde(default)]
+    pub extra_particles: Vec<String>,
+    #[serde(default = "default_max_stack_size")]
+    pub max_stack_size: u32,
+    #[serde(default = "default_max_depth")]
+    pub max_depth: u32,
+    #[serde(default = "bool::default")]
+    pub plot_variables: bool
+}
+
+fn default_max_stack_size() -> u32 {
+    64
+}
+
+// Kept low enough that a debug build parsing on a 2MB thread stack, like the language server's, errors before overflowing
+pub fn default_max_depth() -> u32 {
+    50
+}
+
+impl Default for Validation {
+    fn default() -> Self {
+        Validation {
+            coerce_types: false,
+            deny_recursion: false,
+            extra_sounds: vec![],
+            extra_potions: vec![],
+            extra_particles: vec![],
+            max_stack_size: default_max_stack_size(),
+            max_depth: default_max_depth(),
+            plot_variables: false
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct Logging {
+    #[serde(default)]
+    pub level: LogLevel
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all="lowercase")]
+pub enum LogLevel {
+    Error,
+    Warn,
+    #[default]
+    Info,
+    Debug
+}
+
+#[derive(Deserialize, Serialize, Debug, Default)]
+pub struct Decompiling {
+    #[serde(default = "bool::default")]
+    pub explicit_selectors: bool,
+    #[serde(default)]
+    pub unknown_blocks: UnknownBlocks,
+    #[serde(default = "bool::default")]
+    pub preserve_variable_names: bool
+}
+
+#[derive(Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all="lowercase")]
+pub enum UnknownBlocks {
+    #[default]
+    Skip,
+    Error,
+    Comment
+}
+
 #[derive(Deserialize, Serialize, Debug, Default)]
 pub struct Debug {
     #[serde(default = "bool::default")]
@@ -38,7 +190,9 @@ pub struct Debug {
     #[serde(default = "bool::default")]
     pub compile: bool,
     #[serde(default = "bool::default")]
-    pub connection: bool
+    pub connection: bool,
+    #[serde(default = "bool::default")]
+    pub source_map: bool
 }
 
 impl Config {
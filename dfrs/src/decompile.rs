@@ -1,19 +1,22 @@
-use std::collections::HashMap;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::io::{Cursor, Read};
+use std::sync::Arc;
 use base64::Engine;
 use base64::prelude::BASE64_STANDARD;
 use flate2::read::GzDecoder;
 use crate::compile::{ArgValueData, Block, Codeline, FunctionDefaultItemData};
-use crate::definitions::action_dump::{Action, ActionDump, RawActionDump};
-use crate::definitions::{ArgType, DefinedArg};
-use crate::node::{ActionType, ConditionalType};
+use crate::config::{Config, UnknownBlocks};
+use crate::definitions::action_dump::Action;
+use crate::definitions::{ArgType, DefinedArg, Definitions};
+use crate::node::{ActionType, ConditionalType, INFINITE_POTION_DURATION};
 use crate::token::{Selector, SELECTORS};
 use crate::utility::{to_camel_case, to_dfrs_name};
 
-fn decompress(compressed_code: &str) -> String {
+fn decompress(compressed_code: &str) -> Result<String, DecompileError> {
     let compressed_data = match BASE64_STANDARD.decode(compressed_code) {
         Ok(data) => data,
-        Err(err) => panic!("Failed to decode base64: {}", err),
+        Err(err) => return Err(DecompileError::InvalidCode { msg: format!("Failed to decode base64: {}", err) }),
     };
 
     let mut decoder = GzDecoder::new(Cursor::new(compressed_data));
@@ -21,33 +24,131 @@ fn decompress(compressed_code: &str) -> String {
 
     match decoder.read_to_string(&mut decompressed_data) {
         Ok(_) => {},
-        Err(err) => panic!("Failed to decompress data: {}", err),
+        Err(err) => return Err(DecompileError::InvalidCode { msg: format!("Failed to decompress data: {}", err) }),
     }
 
-    decompressed_data
+    Ok(decompressed_data)
+}
+
+pub enum DecompileWarning {
+    UnknownVariableScope { scope: String, block: usize },
+    UnhandledBlockId { id: String, block: usize },
+    UnhandledBlock { name: String, block: usize },
+    UnknownAction { action_type: ActionType, name: String, block: usize },
+    UnhandledFunctionArg { id: String, block: usize },
+    UnhandledArg { id: String, block: usize },
+    MismatchedBracket { open: String, close: String, block: usize },
+    UnmatchedBracket { block: usize }
+}
+
+impl std::fmt::Display for DecompileWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DecompileWarning::UnknownVariableScope { scope, block } => write!(f, "Block {block}: Unknown variable scope '{scope}'"),
+            DecompileWarning::UnhandledBlockId { id, block } => write!(f, "Block {block}: Unhandled block id '{id}'"),
+            DecompileWarning::UnhandledBlock { name, block } => write!(f, "Block {block}: Unhandled block '{name}'"),
+            DecompileWarning::UnknownAction { action_type, name, block } => write!(f, "Block {block}: Unknown {action_type:?} action '{name}'"),
+            DecompileWarning::UnhandledFunctionArg { id, block } => write!(f, "Block {block}: Unhandled function parameter default '{id}'"),
+            DecompileWarning::UnhandledArg { id, block } => write!(f, "Block {block}: Unhandled argument '{id}'"),
+            DecompileWarning::MismatchedBracket { open, close, block } => write!(f, "Block {block}: Closing {close} bracket does not match opening {open} bracket"),
+            DecompileWarning::UnmatchedBracket { block } => write!(f, "Block {block}: Closing bracket without matching opening bracket")
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum DecompileError {
+    InvalidCode { msg: String },
+    UnhandledBlock { name: String, block: usize }
+}
+
+impl std::fmt::Display for DecompileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecompileError::InvalidCode { msg } => write!(f, "Invalid code: {msg}"),
+            DecompileError::UnhandledBlock { name, block } => write!(f, "Block {block}: Unhandled block '{name}'")
+        }
+    }
+}
+
+// Names of the game and saved variables used by a plot scan, which has one codeline per line
+pub fn scan_variables(scan: &str) -> Vec<String> {
+    let mut names = vec![];
+    for code in scan.split('\n').filter(|code| !code.trim().is_empty()) {
+        let line: Codeline = match decompress(code.trim()).map(|json| serde_json::from_str(&json)) {
+            Ok(Ok(line)) => line,
+            _ => continue
+        };
+        for args in line.blocks.into_iter().filter_map(|block| block.args) {
+            for arg in args.items {
+                if let ArgValueData::Variable { name, scope } = arg.item.data {
+                    if scope == "unsaved" || scope == "saved" {
+                        names.push(name);
+                    }
+                }
+            }
+        }
+    }
+    names.sort();
+    names.dedup();
+    names
 }
 
 pub struct Decompiler {
     indentation: i32,
-    action_dump: ActionDump,
+    definitions: Arc<Definitions>,
     vars: HashMap<String, String>,
-    result: String
+    result: String,
+    pretty: bool,
+    explicit_selectors: bool,
+    unknown_blocks: UnknownBlocks,
+    preserve_variable_names: bool,
+    variable_prefix: String,
+    block_index: Option<usize>,
+    current_block: usize,
+    brackets: Vec<String>,
+    else_ifs: HashSet<usize>,
+    inline: bool,
+    warnings: RefCell<Vec<DecompileWarning>>
 }
 
 impl Decompiler {
-    pub fn new() -> Decompiler {
-        let ad = RawActionDump::load();
+    pub fn new(pretty: bool, config: &Config, definitions: Arc<Definitions>) -> Decompiler {
         Decompiler {
             indentation: 0,
-            action_dump: ActionDump::new(&ad),
+            definitions,
             vars: HashMap::new(),
             result: String::new(),
+            pretty,
+            explicit_selectors: config.decompiling.explicit_selectors,
+            unknown_blocks: config.decompiling.unknown_blocks,
+            preserve_variable_names: config.decompiling.preserve_variable_names,
+            variable_prefix: config.variable_prefix.clone(),
+            block_index: None,
+            current_block: 0,
+            brackets: vec![],
+            else_ifs: HashSet::new(),
+            inline: false,
+            warnings: RefCell::new(vec![])
         }
     }
 
+    pub fn take_warnings(&self) -> Vec<DecompileWarning> {
+        self.warnings.take()
+    }
+
     fn add(&mut self, line: &str) {
-        let indentation = " ".repeat((self.indentation*2) as usize);
-        self.result.push_str(&format!("{indentation}{line}\n"));
+        let indentation = if self.inline {
+            self.inline = false;
+            String::new()
+        } else {
+            " ".repeat((self.indentation*2) as usize)
+        };
+        let annotation = match self.block_index.take() {
+            Some(index) if self.pretty => format!(" // block {index}"),
+            _ => String::new()
+        };
+        self.result.push_str(&format!("{indentation}{line}{annotation}\n"));
     }
 
     fn indent(&mut self) {
@@ -62,31 +163,42 @@ impl Decompiler {
         self.vars.insert(old_name.to_string(), new_name.to_string());
     }
 
-    pub fn decompile(&mut self, code: &str) -> String {
-        let json = decompress(code);
-        let line: Codeline = serde_json::from_str(&json).unwrap();
+    pub fn decompile(&mut self, code: &str) -> Result<String, DecompileError> {
+        let json = decompress(code)?;
+        let line: Codeline = match serde_json::from_str(&json) {
+            Ok(line) => line,
+            Err(err) => return Err(DecompileError::InvalidCode { msg: format!("Failed to read codeline: {}", err) })
+        };
         let mut global_vars = vec![];
         let mut vars = vec![];
 
-        for block in &line.blocks {
+        for (index, block) in line.blocks.iter().enumerate() {
             if let Some(args) = &block.args {
                 for arg in &args.items {
                     match &arg.item.data {
                         ArgValueData::Variable { name, scope} => {
-                            let new_name = name.replace("-", "_").replace("%", "").replace(" ", "_").replace("(", "_").replace(")", "");
-                            let var = if &new_name != name {
+                            let unprefixed = match scope.as_str() {
+                                "unsaved" | "saved" if !self.variable_prefix.is_empty() => name.strip_prefix(&self.variable_prefix).unwrap_or(name),
+                                _ => name
+                            };
+                            let new_name = unprefixed.replace("-", "_").replace("%", "").replace(" ", "_").replace("(", "_").replace(")", "");
+                            let var = if new_name == unprefixed {
                                 self.set_var(name, &new_name);
-                                format!("{} = `{name}`", new_name)
+                                new_name
+                            } else if self.preserve_variable_names {
+                                let quoted = format!("`{unprefixed}`");
+                                self.set_var(name, &quoted);
+                                quoted
                             } else {
-                                self.set_var(name, name);
-                                name.to_string()
+                                self.set_var(name, &new_name);
+                                format!("{} = `{unprefixed}`", new_name)
                             };
                             match scope.as_str() {
                                 "unsaved" => global_vars.push(format!("game {var};")),
                                 "saved" => global_vars.push(format!("save {var};")),
                                 "local" => vars.push(format!("local {var};")),
                                 "line" => vars.push(format!("line {var};")),
-                                err => println!("ERR: Unknown var type {err}")
+                                other => self.warnings.borrow_mut().push(DecompileWarning::UnknownVariableScope { scope: other.into(), block: index })
                             }
                         }
                         _ => {}
@@ -95,6 +207,18 @@ impl Decompiler {
             }
         }
 
+        if self.pretty {
+            if let Some(first) = line.blocks.first() {
+                let name = match first.block.as_deref() {
+                    Some("event") | Some("entity_event") => format!("Event {}", first.action.clone().unwrap_or_default()),
+                    Some("func") => format!("Function {}", first.data.clone().unwrap_or_default()),
+                    Some("process") => format!("Process {}", first.data.clone().unwrap_or_default()),
+                    _ => "Codeline".into()
+                };
+                self.add(&format!("// {name}, {} blocks", line.blocks.len()));
+            }
+        }
+
         global_vars.sort();
         global_vars.dedup();
         for var in global_vars {
@@ -104,112 +228,172 @@ impl Decompiler {
         vars.sort();
         vars.dedup();
 
-        for block in line.blocks {
+        let (else_ifs, skipped) = else_if_blocks(&line.blocks);
+        self.else_ifs = else_ifs;
+
+        for (index, block) in line.blocks.into_iter().enumerate() {
+            if skipped.contains(&index) {
+                continue;
+            }
+            self.current_block = index;
             match block.id.as_str() {
                 "block" => {
-                    self.decompile_block(block, vars.clone());
+                    self.block_index = Some(index);
+                    self.decompile_block(block, vars.clone())?;
+                    self.block_index = None;
                 },
                 "bracket" => {
-                    self.decompile_bracket(block);
+                    self.decompile_bracket(block)?;
                 }
                 other => {
-                    println!("WARN: Unhandled block id {other}")
+                    self.warnings.borrow_mut().push(DecompileWarning::UnhandledBlockId { id: other.into(), block: index })
                 }
             }
         }
         self.unindent();
         self.add("}");
-        self.result.clone()
+        Ok(self.result.clone())
     }
 
-    fn decompile_bracket(&mut self, block: Block) {
-        match block.direct.unwrap().as_str() {
+    fn decompile_bracket(&mut self, block: Block) -> Result<(), DecompileError> {
+        let bracket_type = block.bracket_type.unwrap_or("norm".into());
+        match self.required(block.direct, "direction")?.as_str() {
             "open" => {
+                self.brackets.push(bracket_type);
                 self.indent();
             }
             "close" => {
+                match self.brackets.pop() {
+                    Some(open_type) if open_type != bracket_type => {
+                        self.warnings.borrow_mut().push(DecompileWarning::MismatchedBracket { open: open_type, close: bracket_type, block: self.current_block });
+                    }
+                    None => {
+                        self.warnings.borrow_mut().push(DecompileWarning::UnmatchedBracket { block: self.current_block });
+                        return Ok(());
+                    }
+                    _ => {}
+                }
                 self.unindent();
                 self.add("}");
             }
             _ => {}
         }
+        Ok(())
     }
 
-    fn decompile_block(&mut self, block: Block, vars: Vec<String>) {
+    fn decompile_block(&mut self, block: Block, vars: Vec<String>) -> Result<(), DecompileError> {
         if let Some(block_name) = block.block.clone() {
             match block_name.as_str() {
-                "event" => {
-                    self.decompile_event(block, vars);
+                "event" | "entity_event" => {
+                    self.decompile_event(block, vars)?;
                 }
                 "func" => {
-                    self.decompile_function(block, vars);
+                    self.decompile_function(block, vars)?;
                 }
                 "process" => {
-                    self.decompile_process(block, vars);
+                    self.decompile_process(block, vars)?;
                 }
                 "player_action" => {
-                    self.decompile_action(block, ActionType::Player);
+                    self.decompile_action(block, ActionType::Player)?;
                 }
                 "entity_action" => {
-                    self.decompile_action(block, ActionType::Entity);
+                    self.decompile_action(block, ActionType::Entity)?;
                 }
                 "game_action" => {
-                    self.decompile_action(block, ActionType::Game);
+                    self.decompile_action(block, ActionType::Game)?;
                 }
                 "set_var" => {
-                    self.decompile_action(block, ActionType::Variable);
+                    self.decompile_action(block, ActionType::Variable)?;
                 }
                 "control" => {
-                    self.decompile_action(block, ActionType::Control);
+                    self.decompile_action(block, ActionType::Control)?;
                 }
                 "select_obj" => {
-                    self.decompile_action(block, ActionType::Select);
+                    self.decompile_action(block, ActionType::Select)?;
                 }
                 "if_player" => {
-                    self.decompile_conditional(block, ConditionalType::Player);
+                    self.decompile_conditional(block, ConditionalType::Player)?;
                 }
                 "if_entity" => {
-                    self.decompile_conditional(block, ConditionalType::Entity);
+                    self.decompile_conditional(block, ConditionalType::Entity)?;
                 }
                 "if_game" => {
-                    self.decompile_conditional(block, ConditionalType::Game);
+                    self.decompile_conditional(block, ConditionalType::Game)?;
                 }
                 "if_var" => {
-                    self.decompile_conditional(block, ConditionalType::Variable);
+                    self.decompile_conditional(block, ConditionalType::Variable)?;
                 }
                 "repeat" => {
-                    self.decompile_repeat(block);
+                    self.decompile_repeat(block)?;
+                }
+                "else" if self.else_ifs.contains(&self.current_block) => {
+                    if self.result.ends_with("}\n") {
+                        self.result.truncate(self.result.len() - 1);
+                        self.result.push(' ');
+                    } else {
+                        self.result.push_str(&" ".repeat((self.indentation*2) as usize));
+                    }
+                    // The conditional of the else body continues this line
+                    self.result.push_str("else ");
+                    self.inline = true;
                 }
                 "else" => {
-                    self.add("else {");
+                    if self.result.ends_with("}\n") {
+                        self.result.truncate(self.result.len() - 1);
+                        self.result.push_str(" else {");
+                        if let Some(index) = self.block_index.take().filter(|_| self.pretty) {
+                            self.result.push_str(&format!(" // block {index}"));
+                        }
+                        self.result.push('\n');
+                    } else {
+                        self.add("else {");
+                    }
                 }
                 "call_func" => {
-                    self.decompile_call(block);
+                    self.decompile_call(block)?;
                 }
                 "start_process" => {
-                    self.decompile_start(block);
+                    self.decompile_start(block)?;
                 }
                 other => {
-                    println!("WARN: Unhandled block block {other}")
+                    match self.unknown_blocks {
+                        UnknownBlocks::Skip => {}
+                        UnknownBlocks::Error => return Err(DecompileError::UnhandledBlock { name: other.into(), block: self.current_block }),
+                        // Keep the raw block for the reader, compiling drops comments so the block is still lost
+                        UnknownBlocks::Comment => {
+                            let json = serde_json::to_string(&block).unwrap_or_default();
+                            self.add(&format!("// {json}"));
+                        }
+                    }
+                    self.warnings.borrow_mut().push(DecompileWarning::UnhandledBlock { name: other.into(), block: self.current_block })
                 }
             }
         }
+        Ok(())
     }
 
-    fn decompile_event(&mut self, block: Block, vars: Vec<String>) {
+    fn decompile_event(&mut self, block: Block, vars: Vec<String>) -> Result<(), DecompileError> {
         let extra = if block.attribute.is_some() && block.attribute.unwrap() == "LS-CANCEL" {
             "!"
         } else {
             ""
         };
-        self.add(&format!("@{}{extra} {{", to_dfrs_name(&block.action.unwrap())));
+        let name = to_dfrs_name(&self.required(block.action.clone(), "action")?);
+        let prefix = if block.block.as_deref() == Some("entity_event") && self.definitions.player_events.get(name.clone()).is_some() {
+            "entity:"
+        } else {
+            ""
+        };
+        self.add(&format!("@{prefix}{name}{extra} {{"));
         self.indent();
         for var in vars {
             self.add(&var);
         }
+        Ok(())
     }
 
-    fn decompile_function(&mut self, block: Block, vars: Vec<String>) {
+    fn decompile_function(&mut self, block: Block, vars: Vec<String>) -> Result<(), DecompileError> {
+        let hidden = hidden_setting(&block);
         let mut result = String::from("");
         if let Some(args) = block.args {
             let mut is_first_iter = true;
@@ -226,7 +410,7 @@ impl Decompiler {
                                         "num" => format!("{name}"),
                                         "txt" => format!("'{name}'"),
                                         other => {
-                                            println!("ERR: Unhandled simple function arg {other}");
+                                            self.warnings.borrow_mut().push(DecompileWarning::UnhandledFunctionArg { id: other.into(), block: self.current_block });
                                             "".into()
                                         }
                                     }
@@ -246,11 +430,11 @@ impl Decompiler {
                                 FunctionDefaultItemData::Vector { x, y, z } => {
                                     format!("Vector({x}, {y}, {z})")
                                 }
-                                FunctionDefaultItemData::Sound { sound, pitch, volume } => {
-                                    format!("Sound(\"{sound}\", {pitch}, {volume})")
+                                FunctionDefaultItemData::Sound { sound, variant, volume, pitch } => {
+                                    decompile_sound(sound, variant, volume, pitch)
                                 }
                                 FunctionDefaultItemData::Potion { potion, amplifier, duration } => {
-                                    format!("Potion(\"{potion}\", {amplifier}, {duration})")
+                                    format!("Potion(\"{potion}\", {amplifier}, {})", potion_duration(duration))
                                 }
                                 FunctionDefaultItemData::Particle { particle, cluster, data } => {
                                     // TODO
@@ -278,52 +462,56 @@ impl Decompiler {
                             "var" => "variable",
                             "list" => "list",
                             "dict" => "dict",
-                            _ => panic!("unknown param type")
+                            other => return Err(self.invalid(format!("unknown parameter type '{other}'")))
                         };
                         result.push_str(&format!("{name}: {value_type}{is_optional}{is_plural}{default}"))
                     }
                     ArgValueData::Id { .. } => {}
                     ArgValueData::Tag { .. } => {}
                     ArgValueData::Item { .. } => {}
-                    other => panic!("Found {other:?}")
+                    other => return Err(self.invalid(format!("unexpected function parameter {other:?}")))
                 }
             }
         }
-        let name = block.data.clone().unwrap();
+        let name = self.required(block.data.clone(), "function name")?;
         let new_name = name.replace("-", "_").replace("%", "").replace(" ", "_").replace("(", "_").replace(")", "");
         if new_name != name {
-            self.add(&format!("fn {} = `{}`({}) {{", new_name, name, result));
+            self.add(&format!("fn {} = `{}`({}){hidden} {{", new_name, name, result));
         } else {
-            self.add(&format!("fn {}({}) {{", new_name, result));
+            self.add(&format!("fn {}({}){hidden} {{", new_name, result));
         }
         self.indent();
         for var in vars {
             self.add(&var);
         }
+        Ok(())
     }
 
-    fn decompile_process(&mut self, block: Block, vars: Vec<String>) {
-        self.add(&format!("proc {} {{", &block.data.unwrap()));
+    fn decompile_process(&mut self, block: Block, vars: Vec<String>) -> Result<(), DecompileError> {
+        let hidden = hidden_setting(&block);
+        let name = self.required(block.data.clone(), "process name")?;
+        self.add(&format!("proc {name}{hidden} {{"));
         self.indent();
         for var in vars {
             self.add(&var);
         }
+        Ok(())
     }
 
-    fn decompile_action(&mut self, block: Block, action_type: ActionType) {
-        let name = to_dfrs_name(&block.action.clone().unwrap());
+    fn decompile_action(&mut self, block: Block, action_type: ActionType) -> Result<(), DecompileError> {
+        let name = to_dfrs_name(&self.required(block.action.clone(), "action")?);
         let action = match match action_type {
-            ActionType::Player => self.action_dump.player_actions.get(name.clone()),
-            ActionType::Entity => self.action_dump.entity_actions.get(name.clone()),
-            ActionType::Game => self.action_dump.game_actions.get(name.clone()),
-            ActionType::Variable => self.action_dump.variable_actions.get(name.clone()),
-            ActionType::Control => self.action_dump.control_actions.get(name.clone()),
-            ActionType::Select => self.action_dump.select_actions.get(name.clone()),
+            ActionType::Player => self.definitions.action_dump.player_actions.get(name.clone()),
+            ActionType::Entity => self.definitions.action_dump.entity_actions.get(name.clone()),
+            ActionType::Game => self.definitions.action_dump.game_actions.get(name.clone()),
+            ActionType::Variable => self.definitions.action_dump.variable_actions.get(name.clone()),
+            ActionType::Control => self.definitions.action_dump.control_actions.get(name.clone()),
+            ActionType::Select => self.definitions.action_dump.select_actions.get(name.clone()),
         } {
             Some(res) => res,
             None => {
-                println!("ERROR DECOMPILING ACTION: {action_type:?} {name:?}");
-                return;
+                self.warnings.borrow_mut().push(DecompileWarning::UnknownAction { action_type, name, block: self.current_block });
+                return Ok(());
             }
         };
         let prefix = match action_type {
@@ -335,20 +523,23 @@ impl Decompiler {
             ActionType::Select => "s",
         };
         let selector = match block.target.clone() {
-            Some(res) => &format!(":{}", SELECTORS.entries().find(|e| e.1 == &res).unwrap().0),
+            Some(res) => &format!(":{}", self.selector_name(&res)?),
+            None if self.explicit_selectors && matches!(action_type, ActionType::Player | ActionType::Entity) => ":default",
             None => ""
         };
-        self.add(&format!("{prefix}{selector}.{}({});", name, self.decompile_params(block, action)))
+        let params = self.decompile_params(block, action)?;
+        self.add(&format!("{prefix}{selector}.{}({});", name, params));
+        Ok(())
     }
 
-    fn decompile_conditional(&mut self, block: Block, conditional_type: ConditionalType) {
-        let name = to_dfrs_name(&block.action.clone().unwrap());
+    fn decompile_conditional(&mut self, block: Block, conditional_type: ConditionalType) -> Result<(), DecompileError> {
+        let name = to_dfrs_name(&self.required(block.action.clone(), "action")?);
         let action = match conditional_type {
-            ConditionalType::Player => self.action_dump.player_conditionals.get(name.clone()),
-            ConditionalType::Entity => self.action_dump.entity_conditionals.get(name.clone()),
-            ConditionalType::Game => self.action_dump.game_conditionals.get(name.clone()),
-            ConditionalType::Variable =>self.action_dump.variable_conditionals.get(name.clone())
-        }.unwrap().clone();
+            ConditionalType::Player => self.definitions.action_dump.player_conditionals.get(name.clone()),
+            ConditionalType::Entity => self.definitions.action_dump.entity_conditionals.get(name.clone()),
+            ConditionalType::Game => self.definitions.action_dump.game_conditionals.get(name.clone()),
+            ConditionalType::Variable =>self.definitions.action_dump.variable_conditionals.get(name.clone())
+        }.ok_or_else(|| self.invalid(format!("unknown conditional '{name}'")))?.clone();
         let prefix = match conditional_type {
             ConditionalType::Player => "ifp",
             ConditionalType::Entity => "ife",
@@ -356,7 +547,8 @@ impl Decompiler {
             ConditionalType::Variable => "ifv"
         };
         let selector = match block.target.clone() {
-            Some(res) => &format!("{}:", SELECTORS.entries().find(|e| e.1 == &res).unwrap().0),
+            Some(res) => &format!("{}:", self.selector_name(&res)?),
+            None if self.explicit_selectors && matches!(conditional_type, ConditionalType::Player | ConditionalType::Entity) => "default:",
             None => ""
         };
         let inverted = if block.attribute.is_some() && block.attribute.clone().unwrap() == "NOT".to_string() {
@@ -364,16 +556,21 @@ impl Decompiler {
         } else {
             ""
         };
-        self.add(&format!("{prefix} {inverted}{selector}{}({}) {{", name, self.decompile_params(block, &action)))
+        let params = self.decompile_params(block, &action)?;
+        self.add(&format!("{prefix} {inverted}{selector}{}({}) {{", name, params));
+        Ok(())
     }
 
-    fn decompile_repeat(&mut self, block: Block) {
-        let name = to_dfrs_name(&block.action.clone().unwrap());
-        let action = self.action_dump.repeats.get(name.clone()).unwrap().clone();
-        self.add(&format!("repeat {}({}) {{", name, self.decompile_params(block, &action)))
+    fn decompile_repeat(&mut self, block: Block) -> Result<(), DecompileError> {
+        let name = to_dfrs_name(&self.required(block.action.clone(), "action")?);
+        let action = self.definitions.action_dump.repeats.get(name.clone())
+            .ok_or_else(|| self.invalid(format!("unknown repeat '{name}'")))?.clone();
+        let params = self.decompile_params(block, &action)?;
+        self.add(&format!("repeat {}({}) {{", name, params));
+        Ok(())
     }
 
-    fn decompile_call(&mut self, block: Block) {
+    fn decompile_call(&mut self, block: Block) -> Result<(), DecompileError> {
         let mut args = vec![];
         for _ in &block.args {
             args.push(DefinedArg {
@@ -381,6 +578,7 @@ impl Decompiler {
                 name: "".into(),
                 allow_multiple: false,
                 optional: false,
+                range: None
             })
         }
         let action = &Action {
@@ -388,26 +586,46 @@ impl Decompiler {
             dfrs_name: "internal".into(),
             args,
             tags: vec![],
-            has_conditional_arg: false
+            sub_action_blocks: vec![],
+            description: "".into(),
+            deprecated: None
         };
+        let name = to_dfrs_name(&self.required(block.data.clone(), "function name")?);
         if block.args.is_some() && block.args.clone().unwrap().items.len() > 0 {
-            self.add(&format!("call(\"{}\", {});", to_dfrs_name(&block.data.clone().unwrap()), self.decompile_params(block.clone(), action)));
+            let params = self.decompile_params(block, action)?;
+            self.add(&format!("call(\"{}\", {});", name, params));
         } else {
-            self.add(&format!("call(\"{}\");", to_dfrs_name(&block.data.clone().unwrap())));
+            self.add(&format!("call(\"{}\");", name));
         }
+        Ok(())
     }
 
-    fn decompile_start(&mut self, block: Block) {
-        let params = self.decompile_params(block.clone(), &self.action_dump.start_process_action.clone());
+    fn decompile_start(&mut self, block: Block) -> Result<(), DecompileError> {
+        let name = to_dfrs_name(&self.required(block.data.clone(), "process name")?);
+        let params = self.decompile_params(block, &self.definitions.action_dump.start_process_action.clone())?;
         if &params == "" {
-            self.add(&format!("start(\"{}\");", to_dfrs_name(&block.data.clone().unwrap())));
+            self.add(&format!("start(\"{}\");", name));
         } else {
-            self.add(&format!("start(\"{}\", {});", to_dfrs_name(&block.data.clone().unwrap()), params));
+            self.add(&format!("start(\"{}\", {});", name, params));
         }
+        Ok(())
+    }
 
+    // Codelines can come from anywhere, so blocks missing a field or naming something unknown are errors instead of panics
+    fn invalid(&self, msg: String) -> DecompileError {
+        DecompileError::InvalidCode { msg: format!("Block {}: {msg}", self.current_block) }
     }
 
-    fn decompile_params(&self, block: Block, action: &Action) -> String {
+    fn required(&self, value: Option<String>, field: &str) -> Result<String, DecompileError> {
+        value.ok_or_else(|| self.invalid(format!("missing {field}")))
+    }
+
+    fn selector_name(&self, selector: &Selector) -> Result<&'static str, DecompileError> {
+        SELECTORS.entries().find(|entry| entry.1 == selector).map(|entry| *entry.0)
+            .ok_or_else(|| self.invalid(format!("unknown target {selector:?}")))
+    }
+
+    fn decompile_params(&self, block: Block, action: &Action) -> Result<String, DecompileError> {
         let mut result = String::from("");
         if let Some(args) = block.args {
             let mut is_first_iter = true;
@@ -440,7 +658,7 @@ impl Decompiler {
                                 }
                             },
                             "txt" => result.push_str(&format!("'{name}'")),
-                            other => println!("WARN: Unhandled simple arg {other}")
+                            other => self.warnings.borrow_mut().push(DecompileWarning::UnhandledArg { id: other.into(), block: self.current_block })
                         }
                     }
                     ArgValueData::Id { .. } => {}
@@ -451,11 +669,14 @@ impl Decompiler {
                         let selector = if target == Selector::Default {
                             ""
                         } else {
-                            &format!("{}:", SELECTORS.entries().find(|e| e.1 == &target).unwrap().0)
+                            &format!("{}:", self.selector_name(&target)?)
                         };
                         result.push_str(&format!("${selector}{}", to_dfrs_name(&game_value)))
                     }
-                    ArgValueData::Variable { name, .. } => result.push_str(&format!("{}", self.vars.get(&name).unwrap())),
+                    ArgValueData::Variable { name, .. } => match self.vars.get(&name) {
+                        Some(var) => result.push_str(var),
+                        None => return Err(self.invalid(format!("unknown variable '{name}'")))
+                    },
                     ArgValueData::Location { loc, .. } => {
                         let mut res_loc = format!("Location({}, {}, {}", loc.x, loc.y, loc.z);
                         if let Some(pitch) = loc.pitch {
@@ -470,11 +691,11 @@ impl Decompiler {
                     ArgValueData::Vector { x, y, z } => {
                         result.push_str(&format!("Vector({x}, {y}, {z})"));
                     }
-                    ArgValueData::Sound { sound, pitch, volume } => {
-                        result.push_str(&format!("Sound(\"{sound}\", {pitch}, {volume})"));
+                    ArgValueData::Sound { sound, variant, volume, pitch } => {
+                        result.push_str(&decompile_sound(sound, variant, volume, pitch));
                     }
                     ArgValueData::Potion { potion, amplifier, duration } => {
-                        result.push_str(&format!("Potion(\"{potion}\", {amplifier}, {duration})"));
+                        result.push_str(&format!("Potion(\"{potion}\", {amplifier}, {})", potion_duration(duration)));
                     }
                     ArgValueData::Tag { tag, option, .. } => {
                         for action_tag in &action.tags {
@@ -527,6 +748,358 @@ impl Decompiler {
                 }
             }
         }
-        result
+        Ok(result)
+    }
+}
+
+fn decompile_sound(sound: String, variant: Option<String>, volume: f32, pitch: f32) -> String {
+    match variant {
+        Some(variant) => format!("Sound(\"{sound}\", {volume}, {pitch}, \"{variant}\")"),
+        None => format!("Sound(\"{sound}\", {volume}, {pitch})")
+    }
+}
+
+fn potion_duration(duration: f32) -> String {
+    if duration == INFINITE_POTION_DURATION {
+        "infinite".into()
+    } else {
+        duration.to_string()
+    }
+}
+
+// Else blocks whose body is a single conditional, which are written as `else ifp ... { }`, and the brackets of
+// those else bodies which are left out
+fn else_if_blocks(blocks: &[Block]) -> (HashSet<usize>, HashSet<usize>) {
+    let mut else_ifs = HashSet::new();
+    let mut skipped = HashSet::new();
+    for (index, block) in blocks.iter().enumerate() {
+        if block.block.as_deref() != Some("else") || !is_bracket(blocks.get(index + 1), "open") {
+            continue;
+        }
+        let close = match matching_bracket(blocks, index + 1) {
+            Some(close) => close,
+            None => continue
+        };
+        let conditional = index + 2;
+        let is_conditional = blocks.get(conditional).is_some_and(|block| matches!(block.block.as_deref(), Some("if_player" | "if_entity" | "if_game" | "if_var")));
+        if !is_conditional || !is_bracket(blocks.get(conditional + 1), "open") {
+            continue;
+        }
+        let mut end = match matching_bracket(blocks, conditional + 1) {
+            Some(end) => end,
+            None => continue
+        };
+        if blocks.get(end + 1).is_some_and(|block| block.block.as_deref() == Some("else")) && is_bracket(blocks.get(end + 2), "open") {
+            end = match matching_bracket(blocks, end + 2) {
+                Some(else_end) => else_end,
+                None => continue
+            };
+        }
+        if end + 1 == close {
+            else_ifs.insert(index);
+            skipped.insert(index + 1);
+            skipped.insert(close);
+        }
+    }
+    (else_ifs, skipped)
+}
+
+fn is_bracket(block: Option<&Block>, direction: &str) -> bool {
+    block.is_some_and(|block| block.id == "bracket" && block.direct.as_deref() == Some(direction))
+}
+
+fn matching_bracket(blocks: &[Block], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    for (index, block) in blocks.iter().enumerate().skip(open) {
+        if is_bracket(Some(block), "open") {
+            depth += 1;
+        } else if is_bracket(Some(block), "close") {
+            depth -= 1;
+            if depth == 0 {
+                return Some(index);
+            }
+        }
+    }
+    None
+}
+
+fn hidden_setting(block: &Block) -> &'static str {
+    let hidden = block.args.as_ref().is_some_and(|args| args.items.iter().any(|arg| matches!(
+        &arg.item.data,
+        ArgValueData::Tag { tag, option, .. } if tag == "Is Hidden" && option == "True"
+    )));
+    if hidden { " #hidden" } else { "" }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DecompileError, DecompileWarning, Decompiler};
+    use crate::config::{Config, UnknownBlocks};
+    use crate::testing::{actions, blocks, compress, decompile_with, definitions, layout, parse, round_trip};
+
+    const SOURCE: &str = "@join {\n    p.sendMessage(\"A\");\n    ifp isSneaking() {\n        p.sendMessage(\"B\");\n    } else {\n        p.sendMessage(\"C\");\n    }\n}";
+
+    #[test]
+    fn pretty_annotations() {
+        assert_eq!(round_trip(SOURCE, true), "\
+// Event Join, 10 blocks
+@join { // block 0
+  p:default.sendMessage(\"A\"); // block 1
+  ifp default:isSneaking() { // block 2
+    p:default.sendMessage(\"B\"); // block 4
+  } else { // block 6
+    p:default.sendMessage(\"C\"); // block 8
+  }
+}
+");
+    }
+
+    #[test]
+    fn no_annotations_by_default() {
+        let result = round_trip(SOURCE, false);
+        assert!(!result.contains("//"));
+        assert!(result.starts_with("@join {\n"));
+    }
+
+    #[test]
+    fn pretty_function_header() {
+        let result = round_trip("fn greet(name: string) {\n    p.sendMessage(name);\n}", true);
+        assert!(result.starts_with("// Function greet, 2 blocks\n"), "{result}");
+    }
+
+    #[test]
+    fn repeat_and_conditional_round_trip() {
+        let source = "@join {\n    line i;\n    repeat forever() {\n        ifp isSneaking() {\n            repeat multiple(i, 3) {\n                p.sendMessage(\"A\");\n            }\n            c.wait(1);\n        }\n        c.wait(1);\n    }\n    p.sendMessage(\"B\");\n}";
+        let (original, decompiled) = (&blocks(source)[0], &blocks(&round_trip(source, false))[0]);
+        assert_eq!(layout(decompiled), layout(original));
+        let bracket_types = |blocks: &[serde_json::Value]| blocks.iter().filter_map(|block| block["type"].as_str().map(String::from)).collect::<Vec<String>>();
+        assert_eq!(bracket_types(decompiled), ["repeat", "norm", "repeat", "repeat", "norm", "repeat"]);
+    }
+
+    const EVENT: &str = r#"{"id":"block","block":"event","action":"Join"}"#;
+    const CONDITIONAL: &str = r#"{"id":"block","block":"if_player","action":"IsSneaking","target":"Selection","args":{"items":[]}}"#;
+
+    fn bracket(direct: &str, bracket_type: &str) -> String {
+        format!(r#"{{"id":"bracket","direct":"{direct}","type":"{bracket_type}"}}"#)
+    }
+
+    fn decompile_blocks(blocks: &[&str]) -> (String, Vec<DecompileWarning>) {
+        decompile_with(&format!(r#"{{"blocks":[{}]}}"#, blocks.join(",")), false, &Config::default()).unwrap()
+    }
+
+    #[test]
+    fn mismatched_bracket() {
+        let (result, warnings) = decompile_blocks(&[EVENT, CONDITIONAL, &bracket("open", "norm"), &bracket("close", "repeat")]);
+        assert!(matches!(warnings.as_slice(), [DecompileWarning::MismatchedBracket { open, close, block: 3 }] if open == "norm" && close == "repeat"));
+        assert_eq!(result.matches('}').count(), 2);
+    }
+
+    #[test]
+    fn unmatched_bracket() {
+        let (result, warnings) = decompile_blocks(&[EVENT, &bracket("close", "norm")]);
+        assert!(matches!(warnings.as_slice(), [DecompileWarning::UnmatchedBracket { block: 1 }]));
+        assert_eq!(result, "@join {\n}\n");
+    }
+
+    fn explicit(explicit_selectors: bool) -> String {
+        let mut config = Config::default();
+        config.decompiling.explicit_selectors = explicit_selectors;
+        // Templates made in game leave out the target when it is the default one
+        let blocks = [
+            EVENT,
+            r#"{"id":"block","block":"player_action","action":"SendMessage","args":{"items":[]}}"#,
+            r#"{"id":"block","block":"player_action","action":"SendMessage","target":"AllPlayers","args":{"items":[]}}"#,
+            r#"{"id":"block","block":"if_entity","action":"IsGrounded","attribute":"NOT","args":{"items":[]}}"#,
+            &bracket("open", "norm"),
+            r#"{"id":"block","block":"entity_action","action":"Heal","args":{"items":[]}}"#,
+            &bracket("close", "norm"),
+            r#"{"id":"block","block":"game_action","action":"CancelEvent","args":{"items":[]}}"#
+        ];
+        decompile_with(&format!(r#"{{"blocks":[{}]}}"#, blocks.join(",")), false, &config).unwrap().0
+    }
+
+    #[test]
+    fn explicit_selectors() {
+        let result = explicit(true);
+        assert_eq!(result, "@join {\n  p:default.sendMessage();\n  p:all.sendMessage();\n  ife !default:isGrounded() {\n    e:default.heal();\n  }\n  g.cancelEvent();\n}\n");
+        assert!(parse(&result).is_ok());
+        assert_eq!(explicit(false), "@join {\n  p.sendMessage();\n  p:all.sendMessage();\n  ife !isGrounded() {\n    e.heal();\n  }\n  g.cancelEvent();\n}\n");
+    }
+
+    #[test]
+    fn unhandled_blocks_are_warned() {
+        let unknown_block = r#"{"id":"block","block":"fancy_block","action":"Something","args":{"items":[]}}"#;
+        let unknown_id = r#"{"id":"sticker"}"#;
+        let unknown_action = r#"{"id":"block","block":"player_action","action":"Dance","args":{"items":[]}}"#;
+        let (result, warnings) = decompile_blocks(&[EVENT, unknown_block, unknown_id, unknown_action]);
+        assert_eq!(result, "@join {\n}\n");
+        let warnings: Vec<String> = warnings.iter().map(|warning| warning.to_string()).collect();
+        assert_eq!(warnings, vec![
+            "Block 1: Unhandled block 'fancy_block'",
+            "Block 2: Unhandled block id 'sticker'",
+            "Block 3: Unknown Player action 'dance'"
+        ]);
+    }
+
+    #[test]
+    fn unknown_variable_scope_is_warned() {
+        let block = r#"{"id":"block","block":"player_action","action":"SendMessage","args":{"items":[{"item":{"id":"var","data":{"name":"x","scope":"plot"}},"slot":0}]}}"#;
+        let (_, warnings) = decompile_blocks(&[EVENT, block]);
+        assert!(matches!(warnings.as_slice(), [DecompileWarning::UnknownVariableScope { scope, block: 1 }] if scope == "plot"));
+    }
+
+    fn decompile_unknown(unknown_blocks: UnknownBlocks) -> Result<(String, Vec<DecompileWarning>), DecompileError> {
+        let mut config = Config::default();
+        config.decompiling.unknown_blocks = unknown_blocks;
+        let message = r#"{"id":"block","block":"player_action","action":"SendMessage","args":{"items":[]}}"#;
+        let unknown = r#"{"id":"block","block":"fancy_block","action":"Something","args":{"items":[]}}"#;
+        decompile_with(&format!(r#"{{"blocks":[{EVENT},{unknown},{message}]}}"#), false, &config)
+    }
+
+    #[test]
+    fn unknown_blocks_are_skipped() {
+        let (result, warnings) = decompile_unknown(UnknownBlocks::Skip).unwrap();
+        assert_eq!(result, "@join {\n  p.sendMessage();\n}\n");
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn unknown_blocks_can_fail() {
+        match decompile_unknown(UnknownBlocks::Error) {
+            Err(err @ DecompileError::UnhandledBlock { .. }) => assert_eq!(err.to_string(), "Block 1: Unhandled block 'fancy_block'"),
+            Err(err) => panic!("Expected an unhandled block, got {err}"),
+            Ok((result, _)) => panic!("Expected an unhandled block, got {result}")
+        }
+    }
+
+    #[test]
+    fn unknown_blocks_are_kept_as_comments() {
+        let (result, _) = decompile_unknown(UnknownBlocks::Comment).unwrap();
+        let lines: Vec<&str> = result.lines().collect();
+        assert_eq!(lines[2], "  p.sendMessage();");
+        let json: serde_json::Value = serde_json::from_str(lines[1].strip_prefix("  // ").unwrap()).unwrap();
+        assert_eq!((json["block"].as_str(), json["action"].as_str()), (Some("fancy_block"), Some("Something")));
+
+        // The comment is ignored when compiling the result again
+        assert_eq!(actions(&blocks(&result)[0]), ["event Join", "player_action SendMessage"]);
+    }
+
+    const GLOBALS: &str = "game coins;\nsave level;\n@join {\n    line x;\n    v.add(x, coins, level);\n    ifv equal(coins, 1) {\n        repeat multiple(x, level) {\n        }\n    }\n    s.filterCondition(ifv equal(level, 2));\n}";
+
+    fn variables(blocks: &[serde_json::Value]) -> Vec<String> {
+        let mut names = vec![];
+        for block in blocks {
+            for item in block["args"]["items"].as_array().into_iter().flatten() {
+                if item["item"]["id"] == "var" {
+                    names.push(format!("{} {}", item["item"]["data"]["scope"].as_str().unwrap(), item["item"]["data"]["name"].as_str().unwrap()));
+                }
+            }
+        }
+        names
+    }
+
+    #[test]
+    fn variable_prefix_round_trip() {
+        let config = Config { variable_prefix: "shop_".into(), ..Default::default() };
+        let node = crate::testing::validate(GLOBALS).unwrap();
+        let code = crate::compile::compile(node, false, false, &config.variable_prefix).unwrap().remove(0).code;
+        let blocks: serde_json::Value = serde_json::from_str(&code).unwrap();
+        assert_eq!(variables(blocks["blocks"].as_array().unwrap()), [
+            "line x", "unsaved shop_coins", "saved shop_level",
+            "unsaved shop_coins",
+            "line x", "saved shop_level",
+            "saved shop_level"
+        ]);
+
+        let (prefixed, _) = decompile_with(&code, false, &config).unwrap();
+        assert_eq!(prefixed, round_trip(GLOBALS, false));
+        assert!(!prefixed.contains("shop_"));
+
+        // Without the prefix configured the names are kept as they are
+        assert!(decompile_with(&code, false, &Config::default()).unwrap().0.contains("shop_coins"));
+    }
+
+    #[test]
+    fn scanned_plot_variables() {
+        let variable = |name: &str, scope: &str| format!(r#"{{"item":{{"id":"var","data":{{"name":"{name}","scope":"{scope}"}}}},"slot":0}}"#);
+        let action = |items: Vec<String>| format!(r#"{{"id":"block","block":"player_action","action":"SendMessage","args":{{"items":[{}]}}}}"#, items.join(","));
+        let first = format!(r#"{{"blocks":[{EVENT},{}]}}"#, action(vec![variable("coins", "unsaved"), variable("x", "line"), variable("gems", "saved")]));
+        let second = format!(r#"{{"blocks":[{EVENT},{},{}]}}"#, action(vec![variable("coins", "unsaved")]), action(vec![variable("amount", "saved"), variable("y", "local")]));
+        let scan = format!("{}\n\nnot a codeline\n {} \n", compress(&first), compress(&second));
+        assert_eq!(super::scan_variables(&scan), ["amount", "coins", "gems"]);
+    }
+
+    #[test]
+    fn else_if_chains_are_collapsed() {
+        let source = "@join {\n    ifp isSneaking() {\n        p.sendMessage(\"A\");\n    } else ifv equal(1, 2) {\n        p.sendMessage(\"B\");\n    } else ifp isFlying() {\n        p.sendMessage(\"C\");\n    } else {\n        p.sendMessage(\"D\");\n    }\n}";
+        let result = round_trip(source, false);
+        assert_eq!(result, "\
+@join {
+  ifp default:isSneaking() {
+    p:default.sendMessage(\"A\");
+  } else ifv equal(1, 2) {
+    p:default.sendMessage(\"B\");
+  } else ifp default:isFlying() {
+    p:default.sendMessage(\"C\");
+  } else {
+    p:default.sendMessage(\"D\");
+  }
+}
+");
+        assert_eq!(layout(&blocks(&result)[0]), layout(&blocks(source)[0]));
+    }
+
+    #[test]
+    fn else_with_more_than_a_conditional_is_kept() {
+        let source = "@join {\n    ifp isSneaking() {\n    } else {\n        ifv equal(1, 2) {\n            p.sendMessage(\"A\");\n        }\n        p.sendMessage(\"B\");\n    }\n}";
+        let result = round_trip(source, false);
+        assert!(result.contains("  } else {\n    ifv equal(1, 2) {\n"), "{result}");
+        assert!(!result.contains("else if"), "{result}");
+    }
+
+    #[test]
+    fn malformed_codelines_are_errors() {
+        let config = Config::default();
+        for code in ["not base64!".to_string(), "aGVsbG8=".to_string(), compress("{\"blocks\": ")] {
+            match Decompiler::new(false, &config, definitions()).decompile(&code) {
+                Err(DecompileError::InvalidCode { .. }) => {}
+                _ => panic!("Expected '{code}' to be invalid")
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_block_contents_are_errors() {
+        let codelines = [
+            (r#"{"id":"block","block":"repeat","action":"NotARealRepeat"},{"id":"bracket","direct":"open","type":"repeat"},{"id":"bracket","direct":"close","type":"repeat"}"#, "Block 1: unknown repeat 'notARealRepeat'"),
+            (r#"{"id":"block","block":"if_player","action":"NotARealCondition"},{"id":"bracket","direct":"open"},{"id":"bracket","direct":"close"}"#, "Block 1: unknown conditional 'notARealCondition'"),
+            (r#"{"id":"bracket","type":"norm"}"#, "Block 1: missing direction"),
+            (r#"{"id":"block","block":"player_action"}"#, "Block 1: missing action"),
+            (r#"{"id":"block","block":"call_func"}"#, "Block 1: missing function name")
+        ];
+        for (blocks, expected) in codelines {
+            let code = format!(r#"{{"blocks":[{{"id":"block","block":"event","action":"Join"}},{blocks}]}}"#);
+            match decompile_with(&code, false, &Config::default()) {
+                Err(err @ DecompileError::InvalidCode { .. }) => assert_eq!(err.to_string(), format!("Invalid code: {expected}")),
+                _ => panic!("Expected {blocks} to be invalid")
+            }
+        }
+    }
+
+    #[test]
+    fn original_variable_names_can_be_preserved() {
+        let source = "game `%default coins`;\n@join {\n    line `my-var`;\n    v.equal(`my-var`, `%default coins`);\n    p.sendMessage(`my-var`);\n}";
+        let code = &crate::testing::compile_lines(source)[0].code;
+
+        let (renamed, _) = decompile_with(code, false, &Config::default()).unwrap();
+        assert!(renamed.contains("game default_coins = `%default coins`;"), "{renamed}");
+        assert!(renamed.contains("line my_var = `my-var`;") && renamed.contains("p:default.sendMessage(my_var);"), "{renamed}");
+
+        let config = Config { decompiling: crate::config::Decompiling { preserve_variable_names: true, ..Default::default() }, ..Default::default() };
+        let (preserved, _) = decompile_with(code, false, &config).unwrap();
+        assert!(preserved.contains("game `%default coins`;"), "{preserved}");
+        assert!(preserved.contains("line `my-var`;") && preserved.contains("p:default.sendMessage(`my-var`);"), "{preserved}");
+        assert!(!preserved.contains("my_var"), "{preserved}");
+        assert_eq!(blocks(&preserved)[0], blocks(source)[0]);
     }
 }
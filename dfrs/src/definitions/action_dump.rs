@@ -1,5 +1,7 @@
+use std::sync::OnceLock;
+
 use serde::Deserialize;
-use crate::utility::{to_camel_case, to_dfrs_name};
+use crate::utility::{sha256, to_camel_case, to_dfrs_name};
 
 use super::{ArgType, DefinedArg, DefinedTag};
 
@@ -38,6 +40,16 @@ impl DFRSValue for ADParticle {
     }
 }
 
+impl ADParticle {
+    pub fn name(&self) -> &String {
+        &self.icon.name
+    }
+
+    pub fn fields(&self) -> &Vec<String> {
+        &self.fields
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct ADSound {
     sound: String,
@@ -50,6 +62,19 @@ impl DFRSValue for ADSound {
     }
 }
 
+impl ADSound {
+    pub fn name(&self) -> &String {
+        &self.icon.name
+    }
+
+    pub fn variants(&self) -> u32 {
+        self.icon.description.iter()
+            .find_map(|line| line.strip_suffix(" variants"))
+            .and_then(|count| count.parse().ok())
+            .unwrap_or(0)
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 pub struct ADPotion {
     potion: String,
@@ -62,6 +87,12 @@ impl DFRSValue for ADPotion {
     }
 }
 
+impl ADPotion {
+    pub fn name(&self) -> &String {
+        &self.icon.name
+    }
+}
+
 #[derive(Deserialize)]
 pub struct ADCodeBlock {
     pub name: String,
@@ -120,6 +151,16 @@ pub struct ADIcon {
     pub return_type: Option<String>
 }
 
+impl ADIcon {
+    // The note is split into lines like the description and usually names the replacement
+    pub fn deprecated(&self) -> Option<String> {
+        if self.deprecated_note.is_empty() {
+            return None
+        }
+        Some(self.deprecated_note.join(" "))
+    }
+}
+
 #[derive(Deserialize, Debug, Clone)]
 #[serde(rename_all="camelCase")]
 pub struct ADArgument {
@@ -152,10 +193,16 @@ pub struct ADGameValue {
     pub icon: ADIcon
 }
 
+const ACTION_DUMP: &str = include_str!("action_dump.json");
+
 impl RawActionDump {
-    pub fn load() -> RawActionDump {
-        let file = include_str!("action_dump.json");
-        serde_json::from_str(file).expect("Failed to parse action dump")
+    pub fn load() -> &'static RawActionDump {
+        static DUMP: OnceLock<RawActionDump> = OnceLock::new();
+        DUMP.get_or_init(|| serde_json::from_str(ACTION_DUMP).expect("Failed to parse action dump"))
+    }
+
+    pub fn hash() -> String {
+        sha256(ACTION_DUMP)
     }
 }
 
@@ -187,14 +234,16 @@ fn default_vec_return() -> Vec<ADReturnValue> {
 pub struct Action {
     pub dfrs_name: String,
     pub df_name: String,
-    pub has_conditional_arg: bool,
+    pub sub_action_blocks: Vec<String>,
+    pub description: String,
     pub args: Vec<DefinedArg>,
-    pub tags: Vec<DefinedTag>
+    pub tags: Vec<DefinedTag>,
+    pub deprecated: Option<String>
 }
 
 impl Action {
-    pub fn new(dfrs_name: String, df_name: &str, args: Vec<DefinedArg>, tags: Vec<DefinedTag>, has_conditional_arg: bool) -> Action {
-        Action {dfrs_name, df_name: df_name.to_owned(), args, tags, has_conditional_arg}
+    pub fn new(dfrs_name: String, df_name: &str, args: Vec<DefinedArg>, tags: Vec<DefinedTag>, sub_action_blocks: Vec<String>, description: String, deprecated: Option<String>) -> Action {
+        Action {dfrs_name, df_name: df_name.to_owned(), args, tags, sub_action_blocks, description, deprecated}
     }
 }
 
@@ -264,7 +313,7 @@ pub fn get_action(action: &ADAction) -> Action {
             },
             "" => {
                 if is_or {
-                    return Action::new(action.name.clone() + "-NotYetSupported", &action.name, vec![], vec![], action.sub_action_blocks.is_some() && !action.sub_action_blocks.clone().unwrap().is_empty());
+                    return Action::new(action.name.clone() + "-NotYetSupported", &action.name, vec![], vec![], action.sub_action_blocks.clone().unwrap_or_default(), action.icon.description.join(" "), action.icon.deprecated());
                 }
                 for arg in current_args {
                     args.push(arg);
@@ -280,14 +329,14 @@ pub fn get_action(action: &ADAction) -> Action {
 
         if is_or {
             if index_after_or > args_before_or - 1 {
-                let new_arg = DefinedArg::new(arg.description.first().expect("No description"), vec![arg_type], true, arg.plural);
+                let new_arg = DefinedArg::from_description(&arg.description, vec![arg_type], true, arg.plural);
                 current_args.push(new_arg);
             } else {
                 current_args.get_mut(index_after_or).unwrap().arg_types.push(arg_type);
             }
             index_after_or += 1;
         } else {
-            let new_arg = DefinedArg::new(arg.description.first().expect("No description"), vec![arg_type], arg.optional, arg.plural);
+            let new_arg = DefinedArg::from_description(&arg.description, vec![arg_type], arg.optional, arg.plural);
             current_args.push(new_arg);
         }
     }
@@ -313,7 +362,7 @@ pub fn get_action(action: &ADAction) -> Action {
     }
 
     let name = to_dfrs_name(&action.name);
-    Action::new(name, &action.name, args, tags, action.sub_action_blocks.is_some() && !action.sub_action_blocks.clone().unwrap().is_empty())
+    Action::new(name, &action.name, args, tags, action.sub_action_blocks.clone().unwrap_or_default(), action.icon.description.join(" "), action.icon.deprecated())
 }
 
 trait DFRSValue {
@@ -370,7 +419,9 @@ impl ActionDump {
             df_name: action.df_name.clone(),
             dfrs_name: action.dfrs_name.clone(),
             tags: action.tags.clone(),
-            has_conditional_arg: action.has_conditional_arg.clone()
+            sub_action_blocks: action.sub_action_blocks.clone(),
+            description: action.description.clone(),
+            deprecated: action.deprecated.clone()
         };
 
         ActionDump {
@@ -394,4 +445,32 @@ impl ActionDump {
             particles: ValueList::new(action_dump.particles.clone())
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hash_is_the_dump_digest() {
+        assert_eq!(RawActionDump::hash(), sha256(ACTION_DUMP));
+        assert_eq!(RawActionDump::hash().len(), 64);
+    }
+
+    #[test]
+    fn dump_is_parsed_once() {
+        let first = RawActionDump::load() as *const RawActionDump as usize;
+        let others: Vec<usize> = (0..4).map(|_| std::thread::spawn(|| RawActionDump::load() as *const RawActionDump as usize)).map(|handle| handle.join().unwrap()).collect();
+        assert!(others.iter().all(|other| *other == first));
+    }
+
+    #[test]
+    fn deprecated_notes() {
+        let find = |name: &str| RawActionDump::load().actions.iter().find(|action| action.name == name).unwrap();
+        let note = "Use 'Set Variable: Set to Random Value' instead. This will likely be removed in 5.4.";
+        assert_eq!(find("RngTeleport").icon.deprecated().as_deref(), Some(note));
+        assert_eq!(get_action(find("RngTeleport")).deprecated.as_deref(), Some(note));
+        assert_eq!(find("SendMessage").icon.deprecated(), None);
+        assert_eq!(get_action(find("SendMessage")).deprecated, None);
+    }
+}
@@ -4,7 +4,8 @@ use crate::utility::to_dfrs_name;
 #[derive(Debug)]
 pub struct Event {
     pub dfrs_name: String,
-    pub df_name: String
+    pub df_name: String,
+    pub deprecated: Option<String>
 }
 
 #[derive(Debug)]
@@ -20,7 +21,8 @@ impl PlayerEvents {
                 let name = to_dfrs_name(&entry.name.clone());
                 events.push(Event {
                     df_name: entry.name.clone(),
-                    dfrs_name: name
+                    dfrs_name: name,
+                    deprecated: entry.icon.deprecated()
                 })
             }
         }
@@ -49,7 +51,8 @@ impl EntityEvents {
                 let name: String = to_dfrs_name(&entry.name.clone());
                 events.push(Event {
                     df_name: entry.name.clone(),
-                    dfrs_name: name
+                    dfrs_name: name,
+                    deprecated: entry.icon.deprecated()
                 })
             }
         }
@@ -11,7 +11,8 @@ pub struct GameValues {
 pub struct GameValue {
     pub df_name: String,
     pub dfrs_name: String,
-    pub value_type: ArgType
+    pub value_type: ArgType,
+    pub selector_allowed: bool
 }
 
 impl GameValues {
@@ -33,7 +34,8 @@ impl GameValues {
             let new_value = GameValue {
                 df_name: game_value.icon.name.clone(),
                 dfrs_name: to_dfrs_name(&game_value.icon.name.clone()),
-                value_type
+                value_type,
+                selector_allowed: game_value.category != "Plot Values" && game_value.category != "Event Values"
             };
             game_values.push(new_value);
         }
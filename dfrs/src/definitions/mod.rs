@@ -1,21 +1,68 @@
+use action_dump::{ActionDump, RawActionDump};
+use events::{EntityEvents, PlayerEvents};
+use game_values::GameValues;
+
 pub mod action_dump;
 pub mod game_values;
 pub mod events;
 
+/// Everything read from the action dump, loaded once and shared between compiles
+#[derive(Debug)]
+pub struct Definitions {
+    pub player_events: PlayerEvents,
+    pub entity_events: EntityEvents,
+    pub action_dump: ActionDump,
+    pub game_values: GameValues
+}
+
+impl Definitions {
+    pub fn load() -> Definitions {
+        let action_dump = RawActionDump::load();
+        Definitions {
+            player_events: PlayerEvents::new(action_dump),
+            entity_events: EntityEvents::new(action_dump),
+            action_dump: ActionDump::new(action_dump),
+            game_values: GameValues::new(action_dump)
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct DefinedArg {
     pub arg_types: Vec<ArgType>,
     pub name: String,
     pub allow_multiple: bool,
-    pub optional: bool
+    pub optional: bool,
+    pub range: Option<(f32, f32)>
 }
 
 impl DefinedArg {
     pub fn new(name: &str, arg_types: Vec<ArgType>, optional: bool, allow_multiple: bool) -> DefinedArg {
-        DefinedArg {name: name.to_owned(), arg_types, allow_multiple, optional}
+        DefinedArg {name: name.to_owned(), arg_types, allow_multiple, optional, range: parse_range(name)}
+    }
+
+    /// Named after the first line of the description, the range can be on any line
+    pub fn from_description(description: &[String], arg_types: Vec<ArgType>, optional: bool, allow_multiple: bool) -> DefinedArg {
+        let mut arg = DefinedArg::new(description.first().expect("No description"), arg_types, optional, allow_multiple);
+        arg.range = description.iter().find_map(|line| parse_range(line));
+        arg
     }
 }
 
+/// Reads ranges like "Ticks (0-140)" or "Pitch (-90 to 90)" from an argument description
+fn parse_range(description: &str) -> Option<(f32, f32)> {
+    let inner = description.rsplit_once('(')?.1.strip_suffix(')')?;
+    let inner = inner.rsplit(',').next()?.trim();
+    let (min, max) = match inner.split_once(" to ") {
+        Some(res) => res,
+        None => {
+            let split = inner.get(1..)?.find('-')? + 1;
+            (&inner[..split], &inner[split + 1..])
+        }
+    };
+    Some((min.trim().parse().ok()?, max.trim().parse().ok()?))
+}
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum ArgType {
     EMPTY,
@@ -48,4 +95,32 @@ impl DefinedTag {
     pub fn new(dfrs_name: &str, df_name: &str, slot: i8, options: Vec<String>, default: String) -> DefinedTag {
         DefinedTag {dfrs_name: dfrs_name.to_owned(), df_name: df_name.to_owned(), slot, options, default}
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges() {
+        assert_eq!(parse_range("Ticks (0-140)"), Some((0.0, 140.0)));
+        assert_eq!(parse_range("Pitch (-90 to 90)"), Some((-90.0, 90.0)));
+        assert_eq!(parse_range("Offset (-5--1)"), Some((-5.0, -1.0)));
+        assert_eq!(parse_range("Amount (optional, 1-20)"), Some((1.0, 20.0)));
+    }
+
+    #[test]
+    fn not_ranges() {
+        assert_eq!(parse_range("Location"), None);
+        assert_eq!(parse_range("Subtract Numbers (-)"), None);
+        assert_eq!(parse_range("(+) Forwards / (-) Backwards"), None);
+        assert_eq!(parse_range("Name (text)"), None);
+    }
+
+    #[test]
+    fn range_on_later_line() {
+        let arg = DefinedArg::from_description(&["% of normal".into(), "walk speed (0 to 500)".into()], vec![ArgType::NUMBER], false, false);
+        assert_eq!(arg.name, "% of normal");
+        assert_eq!(arg.range, Some((0.0, 500.0)));
+    }
+}
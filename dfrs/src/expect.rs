@@ -0,0 +1,113 @@
+use crate::compile::{Block, CompiledLine, Codeline};
+
+// `// #expect event.Join player_action.SendMessage` checks the blocks of the codeline starting with the first one
+const DIRECTIVE: &str = "#expect";
+
+pub struct Expectation {
+    pub line: usize,
+    pub blocks: Vec<String>
+}
+
+impl Expectation {
+    pub fn codeline(&self) -> &str {
+        self.blocks.first().map(String::as_str).unwrap_or_default()
+    }
+}
+
+pub enum ExpectationError {
+    MissingCodeline,
+    WrongBlocks { found: Vec<String> }
+}
+
+pub fn expectations(source: &str) -> Vec<Expectation> {
+    let mut result = vec![];
+    for (index, line) in source.lines().enumerate() {
+        let directive = match line.trim().strip_prefix("//").map(|comment| comment.trim_start()) {
+            Some(comment) => match comment.strip_prefix(DIRECTIVE) {
+                Some(directive) => directive,
+                None => continue
+            },
+            None => continue
+        };
+        result.push(Expectation {
+            line: index + 1,
+            blocks: directive.split_whitespace().map(String::from).collect()
+        });
+    }
+    result
+}
+
+pub fn check(expectation: &Expectation, compiled: &[CompiledLine]) -> Result<(), ExpectationError> {
+    let found = match compiled.iter().map(|line| block_names(&line.code)).find(|blocks| blocks.first().map(String::as_str) == Some(expectation.codeline())) {
+        Some(found) => found,
+        None => return Err(ExpectationError::MissingCodeline)
+    };
+    if found != expectation.blocks {
+        return Err(ExpectationError::WrongBlocks { found })
+    }
+    Ok(())
+}
+
+// Blocks are written as `block.action`, or `block.data` for blocks like functions that have no action, and brackets as `{` and `}`
+pub fn block_names(code: &str) -> Vec<String> {
+    let codeline: Codeline = match serde_json::from_str(code) {
+        Ok(codeline) => codeline,
+        Err(_) => return vec![]
+    };
+    codeline.blocks.iter().map(block_name).collect()
+}
+
+fn block_name(block: &Block) -> String {
+    if block.id == "bracket" {
+        return if block.direct.as_deref() == Some("open") { "{".into() } else { "}".into() }
+    }
+    let name = block.block.clone().unwrap_or_default();
+    match block.action.as_ref().or(block.data.as_ref()) {
+        Some(action) => format!("{name}.{action}"),
+        None => name
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::compile_lines;
+
+    const SOURCE: &str = "fn greet() {\n}\n@join {\n    ifp isSneaking() {\n        p.sendMessage(\"Hi\");\n    }\n    call(\"greet\");\n}";
+
+    fn expectation(blocks: &str) -> Expectation {
+        Expectation { line: 1, blocks: blocks.split_whitespace().map(String::from).collect() }
+    }
+
+    #[test]
+    fn finds_expect_comments() {
+        let found = expectations("// #expect event.Join {\n@join {\n    //#expect   func.greet\n    // expect event.Quit\n    # expect event.Quit\n}");
+        assert_eq!(found.iter().map(|expectation| (expectation.line, expectation.blocks.clone())).collect::<Vec<_>>(), [
+            (1, vec!["event.Join".to_string(), "{".to_string()]),
+            (3, vec!["func.greet".to_string()])
+        ]);
+        assert_eq!(found[0].codeline(), "event.Join");
+        assert_eq!(expectation("").codeline(), "");
+    }
+
+    #[test]
+    fn names_blocks() {
+        let compiled = compile_lines(SOURCE);
+        let names: Vec<Vec<String>> = compiled.iter().map(|line| block_names(&line.code)).collect();
+        assert!(names.contains(&vec!["func.greet".to_string()]), "{names:?}");
+        assert!(names.contains(&["event.Join", "if_player.IsSneaking", "{", "player_action.SendMessage", "}", "call_func.greet"].map(String::from).to_vec()), "{names:?}");
+        assert!(block_names("not json").is_empty());
+    }
+
+    #[test]
+    fn checks_blocks() {
+        let compiled = compile_lines(SOURCE);
+        assert!(check(&expectation("event.Join if_player.IsSneaking { player_action.SendMessage } call_func.greet"), &compiled).is_ok());
+        assert!(check(&expectation("func.greet"), &compiled).is_ok());
+        assert!(matches!(check(&expectation("event.Quit"), &compiled), Err(ExpectationError::MissingCodeline)));
+        match check(&expectation("event.Join player_action.SendMessage"), &compiled) {
+            Err(ExpectationError::WrongBlocks { found }) => assert_eq!(found.len(), 6),
+            _ => panic!("Expected the blocks to differ")
+        }
+    }
+}
@@ -0,0 +1,528 @@
+// Every code handed out by ValidateError, ParseError and ImportError
+pub const ERROR_CODES: &[&str] = &[
+    "E001", "E002", "E003", "E004", "E005", "E006", "E007", "E008", "E009", "E010", "E011",
+    "E012", "E013", "E014", "E015", "E016", "E017", "E018", "E019", "E020", "E021", "E022",
+    "E101", "E102", "E103", "E104", "E105", "E106", "E107", "E108", "E109", "E110", "E111",
+    "E112", "E113", "E114", "E115", "E116", "E117", "E118", "E119", "E120",
+    "E201", "E202", "E203"
+];
+
+pub fn explain(code: &str) -> Option<&'static str> {
+    let explanation = match code.to_uppercase().as_str() {
+        "E001" => r#"Unknown action
+
+The action does not exist in the action block it is called on.
+Action names are the DiamondFire names in camel case, for example
+'Send Message' becomes 'sendMessage'.
+
+    p.sendMesage("Hi");
+
+Check the spelling and the block prefix of the action:
+
+    p.sendMessage("Hi");"#,
+        "E002" => r#"Unknown event
+
+The event does not exist. Events are used as @name with the camel case
+name of the event. Player events are chosen before entity events of the
+same name, the 'player:' and 'entity:' prefixes choose one explicitly.
+A prefixed event has to exist for that kind.
+
+    @joinGame {
+        p.sendMessage("Hi");
+    }
+
+    @player:blockFall {}
+
+Use the camel case name of the event and a matching prefix:
+
+    @join {
+        p.sendMessage("Hi");
+    }
+
+    @entity:blockFall {}"#,
+        "E003" => r#"Unknown game value
+
+The game value does not exist. Game value names are the DiamondFire
+names in camel case.
+
+    p.sendMessage($health);
+
+Use the full name of the game value:
+
+    p.sendMessage($currentHealth);"#,
+        "E004" => r#"Missing argument
+
+The action requires an argument that was not provided.
+
+    p.giveItems();
+
+Provide every required argument:
+
+    p.giveItems(Item("{Count:1b,id:\"minecraft:stone\"}"));"#,
+        "E005" => r#"Wrong argument type
+
+An argument has a type the action does not accept at that position.
+
+    p.sendMessage(Location(0, 0, 0));
+
+Pass a value of the expected type, or convert it first:
+
+    p.sendMessage("0, 0, 0");
+
+Numbers and strings can be converted automatically by enabling
+'coerce_types' in the [validation] section of dfrs.toml."#,
+        "E006" => r#"Too many arguments
+
+More arguments were provided than the action accepts.
+
+    p.sendMessage("Hi", "there");
+
+Remove the extra arguments, or combine them:
+
+    p.sendMessage("Hi there");"#,
+        "E007" => r#"Invalid tag option
+
+The value given for a tag is not one of its options.
+
+    p.sendMessage("Hi", alignmentMode="Middle");
+
+Use one of the options listed in the error:
+
+    p.sendMessage("Hi", alignmentMode="Centered");"#,
+        "E008" => r#"Unknown tag
+
+The action has no tag with this name.
+
+    p.sendMessage("Hi", alignment="Centered");
+
+Use one of the tags listed in the error:
+
+    p.sendMessage("Hi", alignmentMode="Centered");"#,
+        "E009" => r#"Recursive function
+
+A function calls itself, either directly or through other functions.
+This is only an error when 'deny_recursion' is enabled in the
+[validation] section of dfrs.toml.
+
+    fn loop() {
+        call("loop");
+    }
+
+Use a repeat instead of recursion:
+
+    fn loop() {
+        repeat forever() {
+            c.wait(1);
+        }
+    }"#,
+        "E010" => r#"Invalid sound variant
+
+A variant was given for a sound that has no variants.
+
+    p.playSound(Sound("Pling", 1, 1, "variant"));
+
+Remove the variant:
+
+    p.playSound(Sound("Pling", 1, 1));"#,
+        "E011" => r#"Missing target variable
+
+Set variable actions store their result in the variable passed as
+the first argument.
+
+    v.add(1, 2);
+
+Pass the variable to store the result in first:
+
+    line result;
+    v.add(result, 1, 2);"#,
+        "E012" => r#"Unexpected game value selector
+
+Plot and event game values have no target, so they can't be used with
+a selector.
+
+    p.sendMessage($selection:playerCount);
+
+Remove the selector:
+
+    p.sendMessage($playerCount);"#,
+        "E013" => r#"Duplicate definition
+
+Two functions, processes or events have the same name. DiamondFire
+only allows one of each, and calls to the name would be ambiguous.
+
+    fn greet() {}
+    fn greet() {}
+
+Rename or remove one of them:
+
+    fn greet() {}
+    fn greetAll() {}"#,
+        "E014" => r#"Unknown sound, potion or particle
+
+The name is not one of the sounds, potions or particles DiamondFire
+knows about. Names are the ones shown in the DiamondFire menus.
+
+    p.playSound(Sound("Plink", 1, 1));
+
+Check the spelling of the name:
+
+    p.playSound(Sound("Pling", 1, 1));
+
+Custom content from resource packs can be allowed by adding it to
+'extra_sounds', 'extra_potions' or 'extra_particles' in the
+[validation] section of dfrs.toml:
+
+    [validation]
+    extra_sounds = ["Custom Boom"]"#,
+        "E015" => r#"Number out of range
+
+Some number arguments only accept values in a certain range, which is
+shown in the argument's description on DiamondFire.
+
+    e.setRotation(120, 0);
+
+Use a number inside the range:
+
+    e.setRotation(90, 0);"#,
+        "E016" => r#"Unsupported condition type
+
+Actions that take a condition, like select filters, only accept certain
+kinds of conditions. Selecting players by an entity condition is not
+possible:
+
+    s.playersCond(ife isNearLocation(Location(0, 64, 0)));
+
+Use one of the condition types listed in the error:
+
+    s.playersCond(ifp isNear(Location(0, 64, 0)));"#,
+        "E017" => r#"Read-only game value
+
+Game values can be read, but not changed. Arguments that store a result,
+like the first argument of set variable actions, need a variable:
+
+    v.equal($default:currentHealth, 20);
+
+Store the value in a variable instead:
+
+    v.equal(health, $default:currentHealth);"#,
+        "E018" => r#"Unexpected condition
+
+Only a few actions, like `s.filterCondition` or `repeat while`, take a
+condition as their argument. Other actions can not use one:
+
+    p.sendMessage(ifp isSneaking());
+
+Wrap the action in an if block instead:
+
+    ifp isSneaking() {
+        p.sendMessage("Sneaking");
+    }"#,
+        "E019" => r#"Unexpected selector
+
+Selectors choose which player or entity a block targets. Game,
+variable, control and select blocks do not target anyone, so they can
+not have one:
+
+    ifv default:equal(counter, 1) {
+    }
+
+Remove the selector:
+
+    ifv equal(counter, 1) {
+    }"#,
+        "E020" => r#"Invalid default value
+
+The default value of a function parameter has to match the parameter's
+type:
+
+    fn heal(amount?: number = "full") {
+    }
+
+Use a value of the right type, or change the type of the parameter:
+
+    fn heal(amount?: number = 20) {
+    }"#,
+        "E021" => r#"Required argument is null
+
+`null` leaves out an optional argument so later arguments can still be
+given. Required arguments always need a value:
+
+    p.sendMessage(null);
+
+Pass a value for the argument:
+
+    p.sendMessage("Hi");"#,
+        "E022" => r#"Unknown function
+
+A file that imports functions with use can only call functions it defines
+or imports. Functions of an imported file that are not in its import list
+can't be called either.
+
+    use "lib.dfrs" { greet };
+
+    call("farewell");
+
+Add the function to the import list, or import the whole file:
+
+    use "lib.dfrs" { greet, farewell };"#,
+        "E101" => r#"Invalid token
+
+The parser found a token it did not expect at this position. This is
+usually caused by a missing semicolon, bracket or comma.
+
+    p.sendMessage("Hi")
+    p.sendMessage("there");
+
+Add the missing token:
+
+    p.sendMessage("Hi");
+    p.sendMessage("there");"#,
+        "E102" => r#"Unknown variable
+
+Variables need to be declared before they are used.
+
+    p.sendMessage(count);
+
+Declare the variable first:
+
+    line count;
+    p.sendMessage(count);"#,
+        "E103" => r#"Invalid function call
+
+Function calls and process starts need the name as their first argument.
+
+    call(functionName);
+
+Pass the name as a string:
+
+    call("functionName");"#,
+        "E104" => r#"Invalid number
+
+Complex numbers take a single string or text.
+
+    Number(5);
+
+Pass the expression as a string:
+
+    Number("%math(%var(test)+1)");"#,
+        "E105" => r#"Invalid location
+
+Locations take three to five numbers: x, y, z and optionally pitch and yaw.
+
+    Location(1, 2);
+
+Provide all coordinates:
+
+    Location(1, 2, 3);"#,
+        "E106" => r#"Invalid vector
+
+Vectors take exactly three numbers.
+
+    Vector(1, 2);
+
+Provide all components:
+
+    Vector(1, 2, 3);"#,
+        "E107" => r#"Invalid sound
+
+Sounds take a name, a volume, a pitch and optionally a variant.
+
+    Sound("Pling");
+
+Provide the volume and pitch:
+
+    Sound("Pling", 1, 1);"#,
+        "E108" => r#"Invalid potion
+
+Potions take a type, an amplifier and a duration. The duration can be
+'infinite', the amplifier can't.
+
+    Potion("speed", infinite, 10);
+
+Use a number for the amplifier:
+
+    Potion("speed", 1, infinite);"#,
+        "E109" => r#"Invalid particle
+
+Particles take a type, an amount, a horizontal and a vertical spread,
+followed by optional fields.
+
+    Particle("Cloud");
+
+Provide the amount and spread:
+
+    Particle("Cloud", 1, 0, 0);"#,
+        "E110" => r#"Invalid item
+
+Items take their NBT as a single string.
+
+    Item(stone);
+
+Pass the NBT as a string:
+
+    Item("{Count:1b,id:\"minecraft:stone\"}");"#,
+        "E111" => r#"Invalid type
+
+Function parameters need a valid type.
+
+    fn greet(name: word) {}
+
+Use one of the available types, like any, string, text, number,
+location, vector, sound, potion, particle, item, variable, list or dict:
+
+    fn greet(name: string) {}"#,
+        "E112" => r#"Missing body
+
+Conditionals, repeats and else need a body in curly brackets.
+
+    ifp isSneaking();
+
+Add a body:
+
+    ifp isSneaking() {
+        p.sendMessage("Sneaking");
+    }"#,
+        "E113" => r#"Multiple selectors
+
+DiamondFire blocks only have a single target, so an action can only
+use one selector.
+
+    p:selection:default.teleport(Location(0, 0, 0));
+
+Use one selector and reference other targets using game values:
+
+    p:selection.teleport($default:location);"#,
+        "E114" => r#"Division by zero
+
+Coordinates of locations and vectors can contain constant arithmetic,
+which is calculated when compiling. Dividing by zero has no result.
+
+    Location(10 / 0, 0, 0);
+
+Divide by a different number:
+
+    Location(10 / 2, 0, 0);"#,
+        "E115" => r#"Unknown setting
+
+Functions and processes can be configured with settings after their
+parameters or name. The setting does not exist.
+
+    fn helper() #invisible {}
+
+Use one of the available settings:
+
+    fn helper() #hidden {}"#,
+        "E116" => r#"Unknown event prefix
+
+Events can be prefixed with 'player' or 'entity' to choose between
+player and entity events that have the same name.
+
+    @mob:teleport {}
+
+Use one of the available prefixes:
+
+    @entity:teleport {}"#,
+        "E117" => r#"Missing semicolon
+
+Every action, call and variable declaration ends with a semicolon.
+
+    p.sendMessage("Hi")
+    p.sendMessage("there");
+
+Add the semicolon at the end of the statement:
+
+    p.sendMessage("Hi");
+    p.sendMessage("there");"#,
+        "E118" => r#"Expansion too deep
+
+Blocks and nested arguments can only be nested a limited number of levels
+deep, so that malformed or generated code can't crash the compiler.
+
+    p.sendMessage(v.add(v.add(v.add(...))));
+
+Store intermediate results in variables instead of nesting them, or raise
+the limit with `max_depth` in the [validation] section of dfrs.toml."#,
+        "E119" => r#"Unknown time unit
+
+The wait shorthand takes a duration followed by a time unit. The unit is
+not one of the units DiamondFire can wait for.
+
+    wait(2 hours);
+
+Use ticks, seconds or minutes, or leave the unit out to wait ticks:
+
+    wait(120 minutes);"#,
+        "E120" => r#"Action in a repeat condition
+
+Actions used as arguments run once before the code they are used in. The
+condition of a repeat is checked again on every iteration, but the action
+would not run again, so the condition would keep seeing its first result.
+
+    repeat while(ifv lessThan(v.add(count, 1), 10)) { }
+
+Update the value inside the loop and check the variable instead:
+
+    repeat while(ifv lessThan(count, 10)) {
+        v.add(count, count, 1);
+    }"#,
+        "E201" => r#"Imported file not found
+
+The file given in a use statement does not exist. Paths are relative to
+the file containing the use statement.
+
+    use "libs/helpers.dfrs";
+
+Check the path of the file:
+
+    use "lib/helpers.dfrs";"#,
+        "E202" => r#"Invalid imported file
+
+The file given in a use statement contains errors. Compile it directly
+to see them:
+
+    dfrs compile lib.dfrs"#,
+        "E203" => r#"Unknown imported function
+
+A function listed in a use statement does not exist in the imported file.
+
+    use "lib.dfrs" { helper };
+
+Use the name of a function defined in that file:
+
+    use "lib.dfrs" { helperA };"#,
+        _ => return None
+    };
+    Some(explanation)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{explain, ERROR_CODES};
+
+    #[test]
+    fn every_error_code_is_explained() {
+        for code in ERROR_CODES {
+            assert!(explain(code).is_some(), "{code} has no explanation");
+        }
+    }
+
+    #[test]
+    fn only_error_codes_are_explained() {
+        for number in 0..1000 {
+            let code = format!("E{number:03}");
+            assert_eq!(explain(&code).is_some(), ERROR_CODES.contains(&code.as_str()), "{code}");
+        }
+    }
+
+    #[test]
+    fn codes_are_case_insensitive() {
+        assert_eq!(explain("e001"), explain("E001"));
+        assert!(explain("E001").unwrap().starts_with("Unknown action"));
+    }
+
+    #[test]
+    fn unknown_code() {
+        assert!(explain("E999").is_none());
+        assert!(explain("").is_none());
+    }
+}
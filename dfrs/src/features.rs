@@ -0,0 +1,85 @@
+use serde_json::{json, Value};
+
+use crate::definitions::action_dump::{Action, ValueList};
+use crate::definitions::Definitions;
+use crate::token::{KEYWORDS, SELECTORS, TYPES};
+
+// Everything is read from the lexer tables and the action dump, so the manifest can't get out of date
+pub fn features(definitions: &Definitions) -> Value {
+    let action_dump = &definitions.action_dump;
+    let categories = [
+        ("player", "p", &action_dump.player_actions),
+        ("entity", "e", &action_dump.entity_actions),
+        ("game", "g", &action_dump.game_actions),
+        ("variable", "v", &action_dump.variable_actions),
+        ("control", "c", &action_dump.control_actions),
+        ("select", "s", &action_dump.select_actions),
+        ("player conditional", "ifp", &action_dump.player_conditionals),
+        ("entity conditional", "ife", &action_dump.entity_conditionals),
+        ("game conditional", "ifg", &action_dump.game_conditionals),
+        ("variable conditional", "ifv", &action_dump.variable_conditionals),
+        ("repeat", "repeat", &action_dump.repeats)
+    ];
+
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "keywords": sorted(KEYWORDS.keys().copied()),
+        "selectors": sorted(SELECTORS.keys().copied()),
+        "types": sorted(TYPES.keys().copied()),
+        "categories": categories.iter().map(|(name, prefix, actions)| json!({
+            "name": name,
+            "prefix": prefix,
+            "actions": action_names(actions)
+        })).collect::<Vec<Value>>(),
+        "events": {
+            "player": sorted(definitions.player_events.all().iter().map(|event| event.dfrs_name.as_str())),
+            "entity": sorted(definitions.entity_events.all().iter().map(|event| event.dfrs_name.as_str()))
+        }
+    })
+}
+
+fn action_names(actions: &ValueList<Action>) -> Vec<&str> {
+    sorted(actions.all().iter().map(|action| action.dfrs_name.as_str()))
+}
+
+fn sorted<'a>(names: impl Iterator<Item = &'a str>) -> Vec<&'a str> {
+    let mut names: Vec<&str> = names.collect();
+    names.sort();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::definitions;
+
+    fn names(value: &Value) -> Vec<&str> {
+        value.as_array().unwrap().iter().map(|name| name.as_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn lists_keywords_and_selectors() {
+        let manifest = features(&definitions());
+        let keywords = names(&manifest["keywords"]);
+        assert_eq!(keywords.len(), KEYWORDS.len());
+        for keyword in ["fn", "proc", "unless", "return", "ifp", "repeat", "game", "line"] {
+            assert!(keywords.contains(&keyword), "Missing keyword {keyword}");
+        }
+        assert!(keywords.windows(2).all(|pair| pair[0] < pair[1]));
+        assert!(names(&manifest["selectors"]).contains(&"selection"));
+        assert!(names(&manifest["types"]).contains(&"location"));
+    }
+
+    #[test]
+    fn lists_actions_and_events() {
+        let manifest = features(&definitions());
+        let categories = manifest["categories"].as_array().unwrap();
+        let player = categories.iter().find(|category| category["prefix"] == "p").unwrap();
+        assert_eq!(player["name"], "player");
+        assert!(names(&player["actions"]).contains(&"sendMessage"));
+        let repeat = categories.iter().find(|category| category["prefix"] == "repeat").unwrap();
+        assert!(names(&repeat["actions"]).contains(&"forever"));
+        assert!(names(&manifest["events"]["player"]).contains(&"join"));
+        assert!(names(&manifest["events"]["entity"]).contains(&"blockFall"));
+    }
+}
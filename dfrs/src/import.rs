@@ -0,0 +1,235 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::config::Config;
+use crate::definitions::Definitions;
+use crate::lexer::Lexer;
+use crate::node::{FileNode, FunctionNode, VariableNode};
+use crate::parser::Parser;
+use crate::token::Position;
+use crate::validate::Validator;
+
+#[derive(Debug)]
+pub enum ImportError {
+    FileNotFound { path: String, start_pos: Position, end_pos: Position },
+    InvalidFile { path: String, start_pos: Position, end_pos: Position },
+    UnknownFunction { name: String, path: String, start_pos: Position, end_pos: Position }
+}
+
+// The functions a file imports, resolved before it is validated so calls to them can be checked
+#[derive(Default)]
+pub struct Imports {
+    pub functions: Vec<FunctionNode>,
+    // Functions of imported files that are not in the import list, with the path of their file
+    pub left_out: HashMap<String, String>,
+    // Imports leading back to a file that is already being imported are skipped, so not every function is known
+    pub partial: bool
+}
+
+impl ImportError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ImportError::FileNotFound { .. } => "E201",
+            ImportError::InvalidFile { .. } => "E202",
+            ImportError::UnknownFunction { .. } => "E203"
+        }
+    }
+}
+
+pub fn project_globals(dir: &Path) -> Vec<VariableNode> {
+    let mut globals = vec![];
+    let entries = match std::fs::read_dir(dir) {
+        Ok(res) => res,
+        Err(_) => return globals
+    };
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if !path.is_file() || path.extension().is_none_or(|extension| extension != "dfrs") {
+            continue;
+        }
+        let Ok(data) = std::fs::read_to_string(&path) else { continue };
+        if let Ok(tokens) = Lexer::new(data).run() {
+            globals.extend(Parser::new(tokens).global_variables());
+        }
+    }
+    globals
+}
+
+pub fn resolve_imports(node: &FileNode, file: &Path, config: &Config, definitions: &Arc<Definitions>) -> Result<Imports, ImportError> {
+    let mut stack = vec![file.canonicalize().unwrap_or(file.to_path_buf())];
+    imports(node, file, config, definitions, &mut stack)
+}
+
+fn imports(node: &FileNode, file: &Path, config: &Config, definitions: &Arc<Definitions>, stack: &mut Vec<PathBuf>) -> Result<Imports, ImportError> {
+    let mut result = Imports::default();
+
+    for use_node in &node.uses {
+        let path = file.parent().unwrap_or(Path::new("")).join(&use_node.path);
+        let data = match std::fs::read_to_string(&path) {
+            Ok(res) => res,
+            Err(_) => return Err(ImportError::FileNotFound { path: use_node.path.clone(), start_pos: use_node.start_pos.clone(), end_pos: use_node.end_pos.clone() })
+        };
+
+        let canonical = path.canonicalize().unwrap_or(path.clone());
+        if stack.contains(&canonical) {
+            result.partial = true;
+            continue;
+        }
+
+        let invalid = || ImportError::InvalidFile { path: use_node.path.clone(), start_pos: use_node.start_pos.clone(), end_pos: use_node.end_pos.clone() };
+        let tokens = Lexer::new(data).run().map_err(|_| invalid())?;
+        let mut parser = Parser::new(tokens);
+        parser.set_max_depth(config.validation.max_depth);
+        let library = parser.run().map_err(|_| invalid())?;
+
+        stack.push(canonical);
+        let nested = imports(&library, &path, config, definitions, stack).map_err(|_| invalid())?;
+        stack.pop();
+        let mut validator = Validator::new(config, definitions.clone());
+        validator.set_imports(&nested);
+        let mut library = validator.validate(library).map_err(|_| invalid())?;
+        library.functions.extend(nested.functions);
+        result.partial |= nested.partial;
+
+        match &use_node.names {
+            Some(names) => {
+                for import in names {
+                    match library.functions.iter().find(|function| function.dfrs_name == import.name) {
+                        Some(function) => result.functions.push(function.clone()),
+                        None => return Err(ImportError::UnknownFunction { name: import.name.clone(), path: use_node.path.clone(), start_pos: import.start_pos.clone(), end_pos: import.end_pos.clone() })
+                    }
+                }
+                for function in library.functions {
+                    if !names.iter().any(|import| import.name == function.dfrs_name) {
+                        result.left_out.insert(function.df_name, use_node.path.clone());
+                    }
+                }
+            }
+            None => result.functions.extend(library.functions)
+        }
+    }
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{definitions, parse, project};
+    use crate::validate::ValidateError;
+
+    const LIB: &str = "fn helperA() {\n}\nfn helperB(amount: number) {\n}\nfn helperC() {\n}";
+
+    fn resolve(dir: &Path, file: &str) -> Result<(FileNode, Imports), ImportError> {
+        let path = dir.join(file);
+        let node = parse(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        let imports = resolve_imports(&node, &path, &Config::default(), &definitions())?;
+        Ok((node, imports))
+    }
+
+    fn names(imports: &Imports) -> Vec<&str> {
+        imports.functions.iter().map(|function| function.dfrs_name.as_str()).collect()
+    }
+
+    fn validate(node: FileNode, imports: &Imports) -> Result<FileNode, ValidateError> {
+        let config = Config::default();
+        let mut validator = Validator::new(&config, definitions());
+        validator.set_imports(imports);
+        validator.validate(node)
+    }
+
+    #[test]
+    fn import_everything() {
+        let dir = project("import-everything", &[("lib.dfrs", LIB), ("main.dfrs", "use \"lib.dfrs\";\n@join {\n    call(\"helperC\");\n}")]);
+        let (node, imports) = resolve(&dir, "main.dfrs").unwrap();
+        assert_eq!(names(&imports), ["helperA", "helperB", "helperC"]);
+        assert!(imports.left_out.is_empty());
+        assert!(validate(node, &imports).is_ok());
+    }
+
+    #[test]
+    fn import_named_functions() {
+        let dir = project("import-named", &[("lib.dfrs", LIB), ("main.dfrs", "use \"lib.dfrs\" { helperA, helperB };\n@join {\n    call(\"helperB\", 5);\n}")]);
+        let (node, imports) = resolve(&dir, "main.dfrs").unwrap();
+        assert_eq!(names(&imports), ["helperA", "helperB"]);
+        assert_eq!(imports.left_out.get("helperC").map(String::as_str), Some("lib.dfrs"));
+        assert!(validate(node, &imports).is_ok());
+    }
+
+    #[test]
+    fn call_to_left_out_function() {
+        let dir = project("import-left-out", &[("lib.dfrs", LIB), ("main.dfrs", "use \"lib.dfrs\" { helperA };\n@join {\n    call(\"helperC\");\n}")]);
+        let (node, imports) = resolve(&dir, "main.dfrs").unwrap();
+        match validate(node, &imports) {
+            Err(ValidateError::UnknownFunction { name, left_out_of, .. }) => {
+                assert_eq!(name, "helperC");
+                assert_eq!(left_out_of.as_deref(), Some("lib.dfrs"));
+            }
+            other => panic!("Expected an unknown function, got {other:?}")
+        }
+    }
+
+    #[test]
+    fn imported_parameters_are_checked() {
+        let dir = project("import-params", &[("lib.dfrs", LIB), ("main.dfrs", "use \"lib.dfrs\" { helperB };\n@join {\n    call(\"helperB\", \"five\");\n}")]);
+        let (node, imports) = resolve(&dir, "main.dfrs").unwrap();
+        assert!(validate(node, &imports).is_err());
+    }
+
+    #[test]
+    fn unknown_import_name() {
+        let dir = project("import-unknown", &[("lib.dfrs", LIB), ("main.dfrs", "use \"lib.dfrs\" { helperA, helperD };")]);
+        match resolve(&dir, "main.dfrs") {
+            Err(err @ ImportError::UnknownFunction { .. }) => {
+                assert_eq!(err.code(), "E203");
+                let ImportError::UnknownFunction { name, path, start_pos, end_pos } = err else { unreachable!() };
+                assert_eq!((name.as_str(), path.as_str()), ("helperD", "lib.dfrs"));
+                assert_eq!((start_pos, end_pos), (Position::new(1, 27), Position::new(1, 34)));
+            }
+            Err(other) => panic!("Expected an unknown function, got {other:?}"),
+            Ok(_) => panic!("Expected an unknown function")
+        }
+    }
+
+    #[test]
+    fn missing_and_invalid_files() {
+        let dir = project("import-missing", &[("bad.dfrs", "fn broken( {"), ("a.dfrs", "use \"missing.dfrs\";"), ("b.dfrs", "use \"bad.dfrs\";")]);
+        assert!(matches!(resolve(&dir, "a.dfrs"), Err(ImportError::FileNotFound { path, .. }) if path == "missing.dfrs"));
+        assert!(matches!(resolve(&dir, "b.dfrs"), Err(ImportError::InvalidFile { path, .. }) if path == "bad.dfrs"));
+    }
+
+    #[test]
+    fn nested_and_cyclic_imports() {
+        let dir = project("import-nested", &[
+            ("a.dfrs", "use \"b.dfrs\";\nfn fromA() {\n}"),
+            ("b.dfrs", "use \"a.dfrs\";\nuse \"c.dfrs\";\nfn fromB() {\n}"),
+            ("c.dfrs", "fn fromC() {\n}")
+        ]);
+        let (_, imports) = resolve(&dir, "a.dfrs").unwrap();
+        assert_eq!(names(&imports), ["fromB", "fromC"]);
+        assert!(imports.partial);
+    }
+
+    #[test]
+    fn globals_from_every_file() {
+        let dir = project("globals", &[
+            ("a.dfrs", "game players;\nsave levels = `%default levels`;\n@join {\n    line count;\n}"),
+            ("b.dfrs", "save coins;\n@join {\n    p.sendMessage("),
+            ("c.dfrs", "game \"unterminated"),
+            ("notes.txt", "game ignored;")
+        ]);
+        let mut globals: Vec<(String, String, String)> = project_globals(&dir).into_iter().map(|variable| (variable.dfrs_name, variable.df_name, variable.var_type.scope().to_string())).collect();
+        globals.sort();
+        assert_eq!(globals, [
+            ("coins".to_string(), "coins".to_string(), "saved".to_string()),
+            ("levels".to_string(), "%default levels".to_string(), "saved".to_string()),
+            ("players".to_string(), "players".to_string(), "unsaved".to_string())
+        ]);
+    }
+
+    #[test]
+    fn globals_of_missing_directory() {
+        assert!(project_globals(&std::env::temp_dir().join("dfrs-missing-project")).is_empty());
+    }
+}
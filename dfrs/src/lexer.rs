@@ -1,4 +1,5 @@
 use crate::token::{Position, Token, TokenWithPos, KEYWORDS, SELECTORS};
+use crate::utility::next_tab_stop;
 
 #[derive(Debug)]
 pub enum LexerError {
@@ -11,50 +12,62 @@ pub enum LexerError {
 
 pub struct Lexer {
     char_pos: i32,
-    input: String,
+    input: Vec<char>,
     position: Position,
     current_char: Option<char>,
-    next_char_in_new_line: bool
+    next_char_in_new_line: bool,
+    tab_width: u32
 }
 
 impl Lexer {
     pub fn new(input: String) -> Lexer {
-        Lexer { input, current_char: None, char_pos: -1, position: Position::new(1, 0), next_char_in_new_line: false }
+        // Files saved on windows can start with a byte order mark and use crlf line endings
+        let input = input.strip_prefix('\u{feff}').unwrap_or(&input).replace("\r\n", "\n").chars().collect();
+        // The first advance moves onto the first character
+        Lexer { input, current_char: None, char_pos: -1, position: Position::new(1, 0), next_char_in_new_line: false, tab_width: 1 }
+    }
+
+    // Tabs move the column to the next tab stop so it matches the column shown in editors
+    pub fn with_tab_width(mut self, tab_width: u32) -> Lexer {
+        self.tab_width = tab_width;
+        self
     }
 
     fn advance(&mut self) {
         self.char_pos += 1;
-        self.position.advance();
-
-        if self.char_pos >= self.input.chars().count() as i32 { 
-            self.current_char = None 
+        if self.current_char == Some('\t') {
+            self.position.col = next_tab_stop(self.position.col, self.tab_width);
         } else {
-            self.current_char = Some(self.input.chars().nth(self.char_pos as usize).unwrap())
+            self.position.advance();
         }
 
+        self.current_char = self.input.get(self.char_pos as usize).copied();
+
         if self.next_char_in_new_line {
             self.next_char_in_new_line = false;
             self.position.next_line();
         }
-        if self.current_char.is_some() && self.current_char.unwrap() == '\n' {
+        if self.current_char == Some('\n') {
             self.next_char_in_new_line = true;
         }
     }
 
+    fn peek(&self) -> Option<char> {
+        self.input.get((self.char_pos + 1) as usize).copied()
+    }
+
     fn make_number(&mut self) -> Result<TokenWithPos, LexerError> {
         let mut num_string: String = String::from("");
         let mut dot_count = 0;
         let start_pos = self.position.clone();
 
-        while self.current_char.is_some() && (self.current_char.unwrap().is_ascii_digit() || self.current_char.unwrap() == '.' || self.current_char.unwrap() == '-') {
-            if self.current_char.unwrap() == '.' { dot_count += 1 }
-            if self.current_char.unwrap() == '-' {
-                if !num_string.is_empty() {
-                    return Err(LexerError::InvalidNumber {pos: start_pos});
-                }
+        while let Some(char) = self.current_char.filter(|char| char.is_ascii_digit() || *char == '.' || *char == '-') {
+            if char == '.' { dot_count += 1 }
+            if char == '-' && !num_string.is_empty() {
+                break;
             }
             if dot_count > 1 { return Err(LexerError::InvalidNumber{ pos: self.position.clone() }) }
-            num_string.push_str(&self.current_char.unwrap().to_string());
+            num_string.push(char);
             self.advance();
         }
 
@@ -62,7 +75,27 @@ impl Lexer {
             return Err(LexerError::InvalidNumber { pos: start_pos })
         }
 
-        Ok(TokenWithPos { token: Token::Number { value: num_string.parse::<f32>().unwrap() }, start_pos, end_pos: self.position.clone()})
+        let value = match num_string.parse::<f32>() {
+            Ok(value) => value,
+            Err(_) => return Err(LexerError::InvalidNumber { pos: start_pos })
+        };
+
+        Ok(TokenWithPos { token: Token::Number { value }, start_pos, end_pos: self.position.clone()})
+    }
+
+    fn make_hex_color(&mut self) -> Option<TokenWithPos> {
+        let digits: String = self.input.iter().skip((self.char_pos + 1) as usize).take(6).collect();
+        let after = self.input.get((self.char_pos + 7) as usize);
+        if digits.len() != 6 || !digits.chars().all(|char| char.is_ascii_hexdigit()) || after.is_some_and(|char| char.is_alphanumeric() || *char == '_') {
+            return None
+        }
+        let value = i32::from_str_radix(&digits, 16).ok()? as f32;
+
+        let start_pos = self.position.clone();
+        for _ in 0..7 {
+            self.advance();
+        }
+        Some(TokenWithPos { token: Token::Number { value }, start_pos, end_pos: self.position.clone() })
     }
 
     fn make_string(&mut self) -> Result<TokenWithPos, LexerError> {
@@ -73,15 +106,13 @@ impl Lexer {
 
         loop {
             self.advance();
-            if self.current_char.is_none() {
+            let Some(char) = self.current_char else {
                 return Err(LexerError::UnterminatedString { pos: start_pos })
-            }
+            };
 
             is_escaped = escape;
             escape = false;
 
-            let char = self.current_char.unwrap();
-
             if !is_escaped && char == '\'' {
                 self.advance();
                 break;
@@ -105,15 +136,13 @@ impl Lexer {
 
         loop {
             self.advance();
-            if self.current_char.is_none() {
+            let Some(char) = self.current_char else {
                 return Err(LexerError::UnterminatedText { pos: start_pos })
-            }
+            };
 
             is_escaped = escape;
             escape = false;
 
-            let char = self.current_char.unwrap();
-
             if !is_escaped && char == '\"' {
                 self.advance();
                 break;
@@ -137,15 +166,13 @@ impl Lexer {
 
         loop {
             self.advance();
-            if self.current_char.is_none() {
+            let Some(char) = self.current_char else {
                 return Err(LexerError::UnterminatedVariable { pos: start_pos })
-            }
+            };
 
             is_escaped = escape;
             escape = false;
 
-            let char = self.current_char.unwrap();
-
             if !is_escaped && char == '`' {
                 self.advance();
                 break;
@@ -165,18 +192,8 @@ impl Lexer {
         let mut value: String = String::from("");
         let start_pos = self.position.clone();
 
-        loop {
-            if self.current_char.is_none() {
-                break;
-            }
-
-            let char = self.current_char.unwrap();
-
-            if !char.is_ascii_alphanumeric() && char != '_' {
-                break;
-            }
-
-            value.push_str(&char.to_string());
+        while let Some(char) = self.current_char.filter(|char| char.is_ascii_alphanumeric() || *char == '_') {
+            value.push(char);
             self.advance();
         }
 
@@ -200,9 +217,8 @@ impl Lexer {
         let mut comment = 0;
         let mut is_comment = false;
 
-        while self.current_char.is_some() {
-            let current = self.current_char.unwrap();
-        
+        while let Some(current) = self.current_char {
+
             if current != '/' {
                 comment = 0;
             }
@@ -243,14 +259,12 @@ impl Lexer {
                     self.advance();
                 }
                 '-' => {
-                    let token = match self.make_number() {
-                        Ok(res) => res,
-                        Err(_) => {
-                            self.advance();
-                            self.token(Token::Minus)
-                        }
-                    };
-                    result.push(token);
+                    if self.peek().is_some_and(|next| next.is_ascii_digit() || next == '.') {
+                        result.push(self.make_number()?);
+                    } else {
+                        result.push(self.token(Token::Minus));
+                        self.advance();
+                    }
                 }
                 '*' => {
                     result.push(self.token(Token::Multiply));
@@ -270,6 +284,15 @@ impl Lexer {
                     result.push(self.token(Token::At));
                     self.advance();
                 }
+                '#' => {
+                    match self.make_hex_color() {
+                        Some(color) => result.push(color),
+                        None => {
+                            result.push(self.token(Token::Hash));
+                            self.advance();
+                        }
+                    }
+                }
                 ':' => {
                     result.push(self.token(Token::Colon));
                     self.advance();
@@ -319,6 +342,206 @@ impl Lexer {
     }
 
     fn token(&self, token: Token) -> TokenWithPos {
-        TokenWithPos::new(token, self.position.clone(), self.position.clone())
+        let mut end_pos = self.position.clone();
+        end_pos.advance();
+        TokenWithPos::new(token, self.position.clone(), end_pos)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{blocks, lex};
+
+    fn numbers(source: &str) -> Vec<f32> {
+        lex(source).into_iter().filter_map(|token| match token.token {
+            Token::Number { value } => Some(value),
+            _ => None
+        }).collect()
+    }
+
+    #[test]
+    fn hex_color_is_number() {
+        let tokens = lex("rgb=#ff8800");
+        assert!(matches!(tokens[2].token, Token::Number { value } if value == 16746496.0));
+        assert_eq!((tokens[2].start_pos.col, tokens[2].end_pos.col), (5, 12));
+        assert_eq!(numbers("#000000 #FFFFFF #0000ff"), vec![0.0, 16777215.0, 255.0]);
+    }
+
+    #[test]
+    fn other_hashes_are_kept() {
+        for source in ["#icon", "#ff88", "#ff8800aa", "#ff880g", "#ff8800_"] {
+            let tokens = lex(source);
+            assert!(matches!(tokens[0].token, Token::Hash), "{source} should start with a hash");
+            assert!(numbers(source).is_empty(), "{source} should not contain a color");
+        }
+    }
+
+    #[test]
+    fn hex_color_in_particle() {
+        let blocks = blocks("@join {\n    p.particle(Particle(\"Fade Dust\", 1, 0, 0, rgb=#ff8800, rgbFade=#0000ff), Location(0, 0, 0));\n}");
+        let data = &blocks[0][1]["args"]["items"][0]["item"]["data"]["data"];
+        assert_eq!(data["rgb"], 16746496);
+        assert_eq!(data["rgb_fade"], 255);
+    }
+
+    fn positions(source: &str) -> Vec<(Token, u32, u32, u32, u32)> {
+        lex(source).into_iter().map(|token| (token.token, token.start_pos.line, token.start_pos.col, token.end_pos.line, token.end_pos.col)).collect()
+    }
+
+    const SOURCE: &str = "@join {\n    p.sendMessage(\"A\nB\");\n}\n";
+
+    #[test]
+    fn crlf_positions() {
+        let crlf = SOURCE.replace('\n', "\r\n");
+        assert_eq!(positions(&crlf), positions(SOURCE));
+        let tokens = lex(&crlf);
+        let message = tokens.iter().find(|token| matches!(token.token, Token::Text { .. })).unwrap();
+        assert!(matches!(&message.token, Token::Text { value } if value == "A\nB"));
+        assert_eq!((message.start_pos.line, message.start_pos.col), (2, 19));
+    }
+
+    #[test]
+    fn bom_is_skipped() {
+        let bom = format!("\u{feff}{SOURCE}");
+        assert_eq!(positions(&bom), positions(SOURCE));
+        assert!(matches!(lex(&bom)[0].token, Token::At));
+        assert_eq!(positions(&format!("\u{feff}{}", SOURCE.replace('\n', "\r\n"))), positions(SOURCE));
+    }
+
+    fn run(source: &str) -> Result<Vec<TokenWithPos>, LexerError> {
+        Lexer::new(source.into()).run()
+    }
+
+    #[test]
+    fn empty_input() {
+        assert!(run("").unwrap().is_empty());
+        assert!(run("\u{feff}").unwrap().is_empty());
+        assert!(run("\r\n\t \r").unwrap().is_empty());
+        assert!(run("//").unwrap().is_empty());
+        assert!(run("// unterminated comment '\"`").unwrap().is_empty());
+    }
+
+    #[test]
+    fn unterminated_literals() {
+        for (source, col) in [("'abc", 1), ("x '\\'", 3), ("\"abc", 1), ("\"abc\\", 1), ("`abc", 1), ("`abc\\`", 1), ("'", 1), ("\"", 1), ("`", 1)] {
+            let pos = match run(source) {
+                Err(LexerError::UnterminatedString { pos } | LexerError::UnterminatedText { pos } | LexerError::UnterminatedVariable { pos }) => pos,
+                other => panic!("Expected {source:?} to be unterminated, got {other:?}")
+            };
+            assert_eq!((pos.line, pos.col), (1, col), "{source:?}");
+        }
+    }
+
+    #[test]
+    fn invalid_numbers() {
+        for (source, col) in [("1.2.3", 4), ("-.", 1), ("-..", 3), ("x = 1..5", 7), ("1..", 3)] {
+            match run(source) {
+                Err(LexerError::InvalidNumber { pos }) => assert_eq!((pos.line, pos.col), (1, col), "{source:?}"),
+                other => panic!("Expected {source:?} to be an invalid number, got {other:?}")
+            }
+        }
+    }
+
+    #[test]
+    fn huge_numbers() {
+        assert!(matches!(run(&"9".repeat(400)).unwrap()[0].token, Token::Number { value } if value.is_infinite()));
+        assert!(matches!(run(&format!("-{}", "9".repeat(400))).unwrap()[0].token, Token::Number { value } if value == f32::NEG_INFINITY));
+        assert!(matches!(run(&format!("0.{}1", "0".repeat(400))).unwrap()[0].token, Token::Number { value } if value == 0.0));
+    }
+
+    #[test]
+    fn unexpected_characters() {
+        for (source, token, col) in [("~", '~', 1), ("é", 'é', 1), ("a\u{0}", '\u{0}', 2), ("x \u{202e}", '\u{202e}', 3), ("\u{fffd}", '\u{fffd}', 1), ("😀", '😀', 1), ("a\u{feff}", '\u{feff}', 2)] {
+            match run(source) {
+                Err(LexerError::InvalidToken { token: found, pos }) => assert_eq!((found, pos.line, pos.col), (token, 1, col), "{source:?}"),
+                other => panic!("Expected {source:?} to be an invalid token, got {other:?}")
+            }
+        }
+        // Anything goes inside literals
+        assert!(matches!(&run("'é😀\u{0}\u{202e}'").unwrap()[0].token, Token::String { value } if value == "é😀\u{0}\u{202e}"));
+    }
+
+    #[test]
+    fn tokens_at_end_of_input() {
+        for (source, token) in [("#", Token::Hash), ("#ff880", Token::Identifier { value: "ff880".into() }), ("-", Token::Minus), ("/", Token::Divide), (".", Token::Dot)] {
+            assert_eq!(run(source).unwrap().last().unwrap().token, token, "{source:?}");
+        }
+    }
+
+    #[test]
+    fn long_input() {
+        let tokens = run(&"(".repeat(200_000)).unwrap();
+        assert_eq!(tokens.len(), 200_000);
+        assert_eq!(tokens.last().unwrap().start_pos.col, 200_000);
+        assert_eq!(run(&format!("'{}'", "\\'".repeat(100_000))).unwrap().len(), 1);
+    }
+
+    // Random inputs built from fragments that are likely to trip the lexer up
+    #[test]
+    fn adversarial_inputs_do_not_panic() {
+        const FRAGMENTS: &[&str] = &[
+            "'", "\"", "`", "\\", "#", "#ff8800", "#ff", "#ff8800ff", "-", ".", "-.", "0", "9999999999999999999999999999999999999999", "1.5",
+            "/", "//", "\n", "\r", "\r\n", "\t", " ", "\u{feff}", "\u{fffd}", "é", "😀", "\u{0}", "\u{202e}", "a", "_x", "if", "fn",
+            "selection", "@", "$", ":", "{", "}", "(", ")", ";", ",", "=", "!", "?", "%", "~", "\u{10ffff}"
+        ];
+        let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+        let mut next = move || {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state as usize
+        };
+
+        // The second pass leaves out characters the lexer rejects, so more inputs make it to the end
+        let valid: Vec<&str> = FRAGMENTS.iter().copied().filter(|fragment| run(fragment.trim_matches(['\'', '"', '`'])).is_ok()).collect();
+        let mut lexed = 0;
+        for fragments in [FRAGMENTS, &valid] {
+            for _ in 0..5000 {
+                let length = next() % 40;
+                let source: String = (0..length).map(|_| fragments[next() % fragments.len()]).collect();
+                match std::panic::catch_unwind(|| run(&source)) {
+                    Ok(Ok(tokens)) => {
+                        lexed += 1;
+                        let mut previous = (1, 0);
+                        for token in tokens {
+                            let (start, end) = ((token.start_pos.line, token.start_pos.col), (token.end_pos.line, token.end_pos.col));
+                            assert!(start.0 >= 1 && start.1 >= 1 && start >= previous && end > start, "Bad positions for {source:?}");
+                            previous = end;
+                        }
+                    }
+                    Ok(Err(_)) => {}
+                    Err(_) => panic!("Lexer panicked on {source:?}")
+                }
+            }
+        }
+        assert!(lexed > 1000, "Only {lexed} inputs were lexed");
+    }
+
+    fn tab_positions(source: &str, tab_width: u32) -> Vec<(u32, u32, u32)> {
+        Lexer::new(source.into()).with_tab_width(tab_width).run().expect("Failed to lex").into_iter()
+            .map(|token| (token.start_pos.line, token.start_pos.col, token.end_pos.col))
+            .collect()
+    }
+
+    #[test]
+    fn tabs_move_to_the_next_tab_stop() {
+        let source = "@join {\n\tp.sendMessage(\"A\");\n\t\tline x;\n  \tline y;\n}";
+        let positions = tab_positions(source, 4);
+        assert_eq!(positions[3], (2, 5, 6));
+        assert_eq!(positions[5], (2, 7, 18));
+        assert_eq!(positions[10], (3, 9, 13));
+        assert_eq!(positions[13], (4, 5, 9));
+        assert_eq!(tab_positions(source, 8)[3], (2, 9, 10));
+        assert_eq!(tab_positions(source, 8)[10], (3, 17, 21));
+        assert_eq!(tab_positions("a\tb\tc", 4), [(1, 1, 2), (1, 5, 6), (1, 9, 10)]);
+    }
+
+    #[test]
+    fn tabs_count_once_by_default() {
+        assert_eq!(positions("\tp\t.a"), positions(" p .a"));
+        assert_eq!(tab_positions("\t\tp", 1), [(1, 3, 4)]);
+        assert_eq!(tab_positions("\t\tp", 0), [(1, 3, 4)]);
+        assert_eq!(tab_positions("\t\tp", u32::MAX), [(1, 33, 34)]);
+    }
+}
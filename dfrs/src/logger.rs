@@ -0,0 +1,70 @@
+use std::sync::atomic::{AtomicU8, Ordering};
+
+use colored::Colorize;
+
+use crate::config::LogLevel;
+
+static LEVEL: AtomicU8 = AtomicU8::new(LogLevel::Info as u8);
+
+pub fn set_level(level: LogLevel) {
+    LEVEL.store(level as u8, Ordering::Relaxed);
+}
+
+pub fn enabled(level: LogLevel) -> bool {
+    level as u8 <= LEVEL.load(Ordering::Relaxed)
+}
+
+pub fn error(message: String) {
+    if enabled(LogLevel::Error) {
+        eprintln!("{} {}", "Error:".bright_red(), message);
+    }
+}
+
+pub fn warn(message: String) {
+    if enabled(LogLevel::Warn) {
+        eprintln!("{} {}", "Warning:".bright_yellow(), message);
+    }
+}
+
+pub fn info(message: String) {
+    if enabled(LogLevel::Info) {
+        println!("{}", message);
+    }
+}
+
+pub fn debug(message: String) {
+    if enabled(LogLevel::Debug) {
+        println!("{} {}", "Debug:".bright_black(), message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    // The level is global, so every level is checked in one test
+    #[test]
+    fn levels() {
+        set_level(LogLevel::Warn);
+        assert!(enabled(LogLevel::Error));
+        assert!(enabled(LogLevel::Warn));
+        assert!(!enabled(LogLevel::Info));
+        assert!(!enabled(LogLevel::Debug));
+
+        set_level(LogLevel::Debug);
+        assert!(enabled(LogLevel::Info));
+        assert!(enabled(LogLevel::Debug));
+
+        set_level(LogLevel::default());
+        assert!(enabled(LogLevel::Info));
+        assert!(!enabled(LogLevel::Debug));
+    }
+
+    #[test]
+    fn level_from_config() {
+        let config: Config = toml::from_str("[logging]\nlevel = \"warn\"").unwrap();
+        assert_eq!(config.logging.level, LogLevel::Warn);
+        assert_eq!(Config::default().logging.level, LogLevel::Info);
+    }
+}
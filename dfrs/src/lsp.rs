@@ -1,30 +1,27 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use dashmap::DashMap;
 use crate::compile::compile;
-use crate::definitions::action_dump::{ActionDump, RawActionDump};
-use crate::definitions::game_values::GameValues;
+use std::sync::Arc;
+use crate::definitions::action_dump::{Action, ValueList};
+use crate::definitions::Definitions;
+use crate::import::{project_globals, resolve_imports, ImportError};
 use crate::lexer::{Lexer, LexerError};
-use crate::load_config;
+use crate::node::{Arg, ArgValue, Expression, ExpressionNode, FileNode, VariableNode};
+use crate::{event_kind, load_config, plot_variables, recursion_message, tag_option_message, unknown_action_message, unknown_function_message, unknown_variable_message};
 use crate::parser::{ParseError, Parser};
-use crate::token::{Keyword, Token};
-use crate::validate::{ValidateError, Validator};
+use crate::token::{Keyword, Selector, Token, SELECTORS};
+use crate::validate::{ValidateError, ValidateWarning, Validator};
 use ropey::Rope;
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer, LspService, Server};
-use crate::definitions::events::{EntityEvents, PlayerEvents};
 
 #[derive(Debug)]
 struct Backend {
     client: Client,
     document_map: DashMap<String, Rope>,
 
-    player_events: PlayerEvents,
-    entity_events: EntityEvents,
-
-    action_dump: ActionDump,
-
-    game_values: GameValues
+    definitions: Arc<Definitions>
 }
 
 #[tower_lsp::async_trait]
@@ -33,16 +30,21 @@ impl LanguageServer for Backend {
         Ok(InitializeResult {
             server_info: None,
             capabilities: ServerCapabilities {
-                text_document_sync: Some(TextDocumentSyncCapability::Kind(
-                    TextDocumentSyncKind::FULL,
-                )),
+                text_document_sync: Some(TextDocumentSyncCapability::Options(TextDocumentSyncOptions {
+                    open_close: Some(true),
+                    change: Some(TextDocumentSyncKind::FULL),
+                    save: Some(TextDocumentSyncSaveOptions::Supported(true)),
+                    ..Default::default()
+                })),
                 completion_provider: Some(CompletionOptions {
-                    resolve_provider: Some(false),
-                    trigger_characters: Some(vec![".".to_string()]),
+                    resolve_provider: Some(true),
+                    trigger_characters: Some(vec![".".to_string(), ":".to_string(), "$".to_string()]),
                     work_done_progress_options: Default::default(),
                     all_commit_characters: None,
                     ..Default::default()
                 }),
+                folding_range_provider: Some(FoldingRangeProviderCapability::Simple(true)),
+                document_highlight_provider: Some(OneOf::Left(true)),
                 diagnostic_provider: Some(DiagnosticServerCapabilities::Options(DiagnosticOptions { 
                     identifier: Some("dfrs-lsp".to_owned()),
                     inter_file_dependencies: false, 
@@ -87,42 +89,51 @@ impl LanguageServer for Backend {
 
     async fn completion(&self, params: CompletionParams) -> tower_lsp::jsonrpc::Result<Option<CompletionResponse>> {
         let uri = params.text_document_position.text_document.uri.to_string();
-        let line = params.text_document_position.position.line + 1;
-        let col = params.text_document_position.position.character;
-        self.get_completions(uri, line, col).await
+        let position = from_lsp_position(params.text_document_position.position);
+        // Completions depend on the character before the cursor
+        self.get_completions(uri, position.line, position.col - 1).await
     }
 
-    async fn diagnostic(&self, params: DocumentDiagnosticParams) -> tower_lsp::jsonrpc::Result<DocumentDiagnosticReportResult> {
-        let mut result: Vec<Diagnostic> = vec![];
+    async fn completion_resolve(&self, item: CompletionItem) -> tower_lsp::jsonrpc::Result<CompletionItem> {
+        Ok(resolve_completion(&self.definitions, item))
+    }
+
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
+        let text = match self.document_map.get(&params.text_document.uri.to_string()) {
+            Some(rope) => rope.to_string(),
+            None => return
+        };
+        self.publish_diagnostics(params.text_document.uri, text, None).await
+    }
+
+    async fn folding_range(&self, params: FoldingRangeParams) -> tower_lsp::jsonrpc::Result<Option<Vec<FoldingRange>>> {
+        let text = match self.document_map.get(&params.text_document.uri.to_string()) {
+            Some(rope) => rope.to_string(),
+            None => return Ok(None)
+        };
+        Ok(Some(folding_ranges(text)))
+    }
+
+    async fn document_highlight(&self, params: DocumentHighlightParams) -> tower_lsp::jsonrpc::Result<Option<Vec<DocumentHighlight>>> {
+        let position = params.text_document_position_params.position;
+        let text = match self.document_map.get(&params.text_document_position_params.text_document.uri.to_string()) {
+            Some(rope) => rope.to_string(),
+            None => return Ok(None)
+        };
+        let path = params.text_document_position_params.text_document.uri.to_file_path().unwrap_or_default();
+        Ok(Some(document_highlights(text, &path, from_lsp_position(position))))
+    }
 
+    async fn diagnostic(&self, params: DocumentDiagnosticParams) -> tower_lsp::jsonrpc::Result<DocumentDiagnosticReportResult> {
         let uri = params.text_document.uri.clone();
         let rope = self.document_map.get(&uri.to_string()).unwrap();
         let path = params.text_document.uri.to_file_path().unwrap();
 
-        match compile_file(rope.to_string(), path) {
-            Ok(_) => {},
-            Err(err) => {
-                let mut end_pos = err.pos.clone();
-                if err.end_pos.is_some() {
-                    end_pos = err.end_pos.unwrap();
-                }
-                result.push(Diagnostic {
-                    severity: Some(DiagnosticSeverity::ERROR),
-                    message: err.msg,
-                    range: Range {
-                        start: Position { line: err.pos.line - 1, character: err.pos.col - 1 },
-                        end: Position { line: end_pos.line - 1, character: end_pos.col - 1 }
-                    },
-                    ..Default::default()
-                });
-            }
-        }
-
         Ok(DocumentDiagnosticReportResult::Report(DocumentDiagnosticReport::Full(RelatedFullDocumentDiagnosticReport {
             related_documents: None,
             full_document_diagnostic_report: FullDocumentDiagnosticReport {
                 result_id: None,
-                items: result
+                items: diagnose(rope.to_string(), path, &self.definitions)
             }
         })))
     }
@@ -133,6 +144,15 @@ impl Backend {
         let rope = Rope::from_str(&params.text);
         self.document_map
             .insert(params.uri.to_string(), rope.clone());
+        self.publish_diagnostics(params.uri, params.text, Some(params.version)).await
+    }
+
+    async fn publish_diagnostics(&self, uri: Url, text: String, version: Option<i32>) {
+        let path = match uri.to_file_path() {
+            Ok(res) => res,
+            Err(_) => return
+        };
+        self.client.publish_diagnostics(uri, diagnose(text, path, &self.definitions), version).await
     }
 
     async fn get_completions(&self, uri: String, line: u32, col: u32) -> tower_lsp::jsonrpc::Result<Option<CompletionResponse>> {
@@ -146,9 +166,12 @@ impl Backend {
             Err(_) => return Ok(None)
         };
 
+        let cursor = crate::token::Position::new(line, col);
         let mut last_token: Option<crate::token::TokenWithPos> = None;
-        for token in tokens {
-            if token.start_pos.line == line && token.start_pos.col <= col && token.end_pos.col >= col {
+        let mut previous_tokens: Vec<Token> = vec![];
+        let mut tokens = tokens.into_iter().peekable();
+        while let Some(token) = tokens.next() {
+            if token.range().contains(&cursor) && tokens.peek().is_none_or(|next| next.start_pos != cursor) {
                 let mut is_event = false;
                 let mut is_player_action = false;
                 let mut is_entity_action = false;
@@ -161,11 +184,26 @@ impl Backend {
                 let mut is_game_conditional = false;
                 let mut is_variable_conditional = false;
                 let mut is_game_value = false;
+                let mut is_selector = false;
+                let mut has_selector = false;
 
                 let mut previous = String::from("");
                 match &token.token {
                     Token::At => is_event = true,
-                    Token::Dollar => is_game_value = true,
+                    Token::Dollar => {
+                        is_game_value = true;
+                        is_selector = true;
+                    }
+                    Token::Colon => {
+                        match (previous_tokens.iter().rev().nth(1), previous_tokens.last()) {
+                            (_, Some(Token::Keyword { value: Keyword::P | Keyword::E })) => is_selector = true,
+                            (Some(Token::Dollar), Some(Token::Selector { .. })) => {
+                                is_game_value = true;
+                                has_selector = true;
+                            }
+                            _ => {}
+                        }
+                    }
                     Token::Dot => {
                         match last_token.clone() {
                             Some(last) => {
@@ -200,9 +238,22 @@ impl Backend {
                         }
                         Token::Dollar => {
                             is_game_value = true;
-                            match token.token.clone() {
-                                Token::Identifier { value } => previous += &value,
-                                _ => {}
+                            is_selector = true;
+                            if let Some(name) = completion_prefix(&token.token) {
+                                previous += &name;
+                            }
+                        }
+                        Token::Colon => {
+                            if let Some(name) = completion_prefix(&token.token) {
+                                match (previous_tokens.iter().rev().nth(2), previous_tokens.iter().rev().nth(1)) {
+                                    (_, Some(Token::Keyword { value: Keyword::P | Keyword::E })) => is_selector = true,
+                                    (Some(Token::Dollar), Some(Token::Selector { .. })) => {
+                                        is_game_value = true;
+                                        has_selector = true;
+                                    }
+                                    _ => {}
+                                }
+                                previous += &name;
                             }
                         }
                         Token::Keyword { value } => {
@@ -228,12 +279,12 @@ impl Backend {
                 if is_event {
                     let mut events = vec![];
 
-                    for event in self.player_events.all() {
+                    for event in self.definitions.player_events.all() {
                         if event.dfrs_name.starts_with(&previous) || event.df_name.starts_with(&previous) {
                             events.push(CompletionItem::new_simple(event.dfrs_name.clone(), event.df_name.clone()));
                         }
                     }
-                    for event in self.entity_events.all() {
+                    for event in self.definitions.entity_events.all() {
                         if event.dfrs_name.starts_with(&previous) || event.df_name.starts_with(&previous) {
                             events.push(CompletionItem::new_simple(event.dfrs_name.clone(), event.df_name.clone()));
                         }
@@ -242,63 +293,89 @@ impl Backend {
                     return Ok(Some(CompletionResponse::Array(events)))
                 }
 
-                let mut all = None;
+                let mut category = None;
                 if is_player_action {
-                    all = Some(self.action_dump.player_actions.all());
+                    category = Some("p");
                 }
                 if is_entity_action {
-                    all = Some(self.action_dump.entity_actions.all());
+                    category = Some("e");
                 }
                 if is_game_action {
-                    all = Some(self.action_dump.game_actions.all());
+                    category = Some("g");
                 }
                 if is_variable_action {
-                    all = Some(self.action_dump.variable_actions.all());
+                    category = Some("v");
                 }
                 if is_control_action {
-                    all = Some(self.action_dump.control_actions.all());
+                    category = Some("c");
                 }
                 if is_select_action {
-                    all = Some(self.action_dump.select_actions.all());
+                    category = Some("s");
                 }
                 if is_player_conditional {
-                    all = Some(self.action_dump.player_conditionals.all());
+                    category = Some("ifp");
                 }
                 if is_entity_conditional {
-                    all = Some(self.action_dump.entity_conditionals.all());
+                    category = Some("ife");
                 }
                 if is_game_conditional {
-                    all = Some(self.action_dump.game_conditionals.all());
+                    category = Some("ifg");
                 }
                 if is_variable_conditional {
-                    all = Some(self.action_dump.variable_conditionals.all());
+                    category = Some("ifv");
                 }
 
                 self.client.log_message(MessageType::INFO, format!("ev {} pa {} ea {} ga {} va {} pc {} ec {} gc {} vc {} vl {}", is_event, is_player_action, is_entity_action, is_game_action, is_variable_action, is_player_conditional, is_entity_conditional, is_game_conditional, is_variable_conditional, is_game_value)).await;
 
-                if all.is_some() {
+                if let Some((category, all)) = category.and_then(|category| Some((category, action_category(&self.definitions, category)?))) {
                     let mut actions = vec![];
 
-                    for action in all.unwrap() {
+                    // The details are only filled in by completion_resolve for the highlighted item
+                    for action in all.all() {
                         if action.dfrs_name.starts_with(&previous) || action.df_name.starts_with(&previous) {
-                            actions.push(CompletionItem::new_simple(action.dfrs_name.clone(), action.df_name.clone()));
+                            actions.push(CompletionItem {
+                                label: action.dfrs_name.clone(),
+                                kind: Some(CompletionItemKind::FUNCTION),
+                                data: Some(serde_json::json!({ "category": category, "name": action.dfrs_name })),
+                                ..Default::default()
+                            });
                         }
                     }
                     return Ok(Some(CompletionResponse::Array(actions)))
                 }
 
+                let mut result = vec![];
+                if is_selector {
+                    for (name, selector) in SELECTORS.entries() {
+                        if name.starts_with(&previous) {
+                            result.push(CompletionItem {
+                                label: name.to_string(),
+                                kind: Some(CompletionItemKind::ENUM_MEMBER),
+                                detail: Some(selector_description(selector).into()),
+                                ..Default::default()
+                            });
+                        }
+                    }
+                }
+
                 if is_game_value {
-                    let game_values = self.game_values.all();
-                    let mut result = vec![];
+                    let game_values = self.definitions.game_values.all();
 
                     for game_value in game_values {
+                        if has_selector && !game_value.selector_allowed {
+                            continue;
+                        }
                         if game_value.dfrs_name.starts_with(&previous) || game_value.df_name.starts_with(&previous) {
                             result.push(CompletionItem::new_simple(game_value.dfrs_name.clone(), game_value.df_name.clone()));
                         }
                     }
+                }
+
+                if !result.is_empty() {
                     return Ok(Some(CompletionResponse::Array(result)))
                 }
             }
+            previous_tokens.push(token.token.clone());
             last_token = Some(token);
         }
 
@@ -311,39 +388,341 @@ pub async fn run_lsp() {
     let stdin = tokio::io::stdin();
     let stdout = tokio::io::stdout();
 
-    let ad = RawActionDump::load();
     let (service, socket) = LspService::new(|client| Backend {
         client,
         document_map: DashMap::new(),
 
-        player_events: PlayerEvents::new(&ad),
-        entity_events: EntityEvents::new(&ad),
+        definitions: Arc::new(Definitions::load())
+    });
+    Server::new(stdin, stdout, socket).serve(service).await;
+}
 
-        action_dump: ActionDump::new(&ad),
+fn action_category<'a>(definitions: &'a Definitions, category: &str) -> Option<&'a ValueList<Action>> {
+    let action_dump = &definitions.action_dump;
+    Some(match category {
+        "p" => &action_dump.player_actions,
+        "e" => &action_dump.entity_actions,
+        "g" => &action_dump.game_actions,
+        "v" => &action_dump.variable_actions,
+        "c" => &action_dump.control_actions,
+        "s" => &action_dump.select_actions,
+        "ifp" => &action_dump.player_conditionals,
+        "ife" => &action_dump.entity_conditionals,
+        "ifg" => &action_dump.game_conditionals,
+        "ifv" => &action_dump.variable_conditionals,
+        _ => return None
+    })
+}
 
-        game_values: GameValues::new(&ad)
+fn resolve_completion(definitions: &Definitions, mut item: CompletionItem) -> CompletionItem {
+    let action = item.data.as_ref().and_then(|data| {
+        let actions = action_category(definitions, data.get("category")?.as_str()?)?;
+        actions.get(data.get("name")?.as_str()?.to_owned())
     });
-    Server::new(stdin, stdout, socket).serve(service).await;
+    if let Some(action) = action {
+        let mut documentation = action.description.clone();
+        if !action.args.is_empty() {
+            documentation.push_str("\n\nArguments:");
+            for arg in &action.args {
+                let optional = if arg.optional { " (optional)" } else { "" };
+                let multiple = if arg.allow_multiple { "*" } else { "" };
+                let types: Vec<String> = arg.arg_types.iter().map(|arg_type| format!("{arg_type:?}")).collect();
+                documentation.push_str(&format!("\n- `{}`{multiple}: {}{optional}", arg.name, types.join(" or ")));
+            }
+        }
+        item.detail = Some(action.df_name.clone());
+        item.documentation = Some(Documentation::MarkupContent(MarkupContent { kind: MarkupKind::Markdown, value: documentation }));
+    }
+    item
+}
+
+fn completion_prefix(token: &Token) -> Option<String> {
+    match token {
+        Token::Identifier { value } => Some(value.clone()),
+        Token::Selector { value } => SELECTORS.entries().find(|(_, selector)| *selector == value).map(|(name, _)| name.to_string()),
+        _ => None
+    }
+}
+
+fn selector_description(selector: &Selector) -> &'static str {
+    match selector {
+        Selector::Default => "The player or entity that triggered the event",
+        Selector::Selection => "The currently selected targets",
+        Selector::Killer => "The killer in a kill or death event",
+        Selector::Damager => "The damager in a damage event",
+        Selector::Shooter => "The shooter in a projectile event",
+        Selector::Victim => "The victim in a kill or damage event",
+        Selector::AllPlayers => "All players on the plot",
+        Selector::Projectile => "The projectile in a projectile event",
+        Selector::AllEntities => "All entities on the plot",
+        Selector::AllMobs => "All mobs on the plot",
+        Selector::LastSpawned => "The most recently spawned entity"
+    }
+}
+
+fn folding_ranges(data: String) -> Vec<FoldingRange> {
+    let mut ranges = vec![];
+
+    let mut regions = vec![];
+    for (line, content) in data.lines().enumerate() {
+        let comment = match content.trim_start().strip_prefix("//") {
+            Some(comment) => comment.trim(),
+            None => continue
+        };
+        if comment.starts_with("#region") {
+            regions.push(line as u32);
+        } else if comment.starts_with("#endregion") {
+            if let Some(start_line) = regions.pop() {
+                ranges.push(FoldingRange { start_line, end_line: line as u32, kind: Some(FoldingRangeKind::Region), ..Default::default() });
+            }
+        }
+    }
+
+    let tokens = match Lexer::new(data).run() {
+        Ok(res) => res,
+        Err(_) => return ranges
+    };
+    let mut blocks = vec![];
+    for token in tokens {
+        match token.token {
+            Token::OpenParenCurly => blocks.push(token.start_pos.line - 1),
+            Token::CloseParenCurly => {
+                if let Some(start_line) = blocks.pop() {
+                    let end_line = token.start_pos.line - 1;
+                    if end_line > start_line + 1 {
+                        ranges.push(FoldingRange { start_line, end_line: end_line - 1, ..Default::default() });
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    ranges
+}
+
+fn document_highlights(data: String, path: &Path, cursor: crate::token::Position) -> Vec<DocumentHighlight> {
+    let tokens = match Lexer::new(data).run() {
+        Ok(res) => res,
+        Err(_) => return vec![]
+    };
+    let declared_globals = Parser::new(tokens.clone()).global_variables();
+    let mut parser = Parser::new(tokens);
+    parser.add_variables(project_globals(path.parent().unwrap_or(Path::new(""))));
+    let node = match parser.run() {
+        Ok(res) => res,
+        Err(_) => return vec![]
+    };
+
+    let occurrences = symbol_occurrences(&node, &declared_globals);
+    // Line variables that are never declared are generated by the parser, like the ones holding nested actions
+    let declared = |symbol: &Symbol| match symbol {
+        Symbol::Variable { owner: Some(_), .. } => occurrences.iter().any(|occurrence| occurrence.declaration && &occurrence.symbol == symbol),
+        _ => true
+    };
+    // Nested ranges are possible in conditions, the innermost one starts last
+    let symbol = match occurrences.iter()
+        .filter(|occurrence| occurrence.range.contains(&cursor) && declared(&occurrence.symbol))
+        .max_by_key(|occurrence| occurrence.range.start.clone()) {
+        Some(occurrence) => &occurrence.symbol,
+        None => return vec![]
+    };
+
+    let mut matching: Vec<&SymbolOccurrence> = occurrences.iter().filter(|occurrence| &occurrence.symbol == symbol).collect();
+    matching.sort_by_key(|occurrence| occurrence.range.start.clone());
+    let mut highlights: Vec<DocumentHighlight> = vec![];
+    for occurrence in matching {
+        let range = Range { start: to_lsp_position(&occurrence.range.start), end: to_lsp_position(&occurrence.range.end) };
+        if highlights.iter().any(|highlight| highlight.range == range) {
+            continue;
+        }
+        highlights.push(DocumentHighlight {
+            range,
+            kind: Some(if occurrence.declaration { DocumentHighlightKind::WRITE } else { DocumentHighlightKind::READ })
+        });
+    }
+    highlights
+}
+
+// Line and local variables belong to the event, function or process they are declared in, `owner` is its index
+#[derive(Debug, PartialEq)]
+enum Symbol {
+    Variable { name: String, scope: String, owner: Option<usize> },
+    Function { name: String },
+    Process { name: String }
+}
+
+#[derive(Debug)]
+struct SymbolOccurrence {
+    symbol: Symbol,
+    range: crate::token::Range,
+    declaration: bool
+}
+
+// Every place a variable, function or process is declared or used in the parsed file
+fn symbol_occurrences(node: &FileNode, globals: &[VariableNode]) -> Vec<SymbolOccurrence> {
+    let mut occurrences = vec![];
+    for variable in globals {
+        occurrences.push(variable_declaration(variable, None));
+    }
+
+    let mut owner = 0;
+    for event in &node.events {
+        expression_occurrences(&event.expressions, owner, &mut occurrences);
+        owner += 1;
+    }
+    for function in &node.functions {
+        occurrences.push(SymbolOccurrence {
+            symbol: Symbol::Function { name: function.df_name.clone() },
+            range: crate::token::Range::new(function.name_start_pos.clone(), function.name_end_pos.clone()),
+            declaration: true
+        });
+        for param in &function.params {
+            occurrences.push(SymbolOccurrence {
+                symbol: Symbol::Variable { name: param.name.clone(), scope: "line".into(), owner: Some(owner) },
+                range: crate::token::Range::new(param.start_pos.clone(), param.end_pos.clone()),
+                declaration: true
+            });
+        }
+        expression_occurrences(&function.expressions, owner, &mut occurrences);
+        owner += 1;
+    }
+    for process in &node.processes {
+        occurrences.push(SymbolOccurrence {
+            symbol: Symbol::Process { name: process.name.clone() },
+            range: crate::token::Range::new(process.name_start_pos.clone(), process.name_end_pos.clone()),
+            declaration: true
+        });
+        expression_occurrences(&process.expressions, owner, &mut occurrences);
+        owner += 1;
+    }
+    occurrences
+}
+
+fn variable_declaration(variable: &VariableNode, owner: Option<usize>) -> SymbolOccurrence {
+    SymbolOccurrence {
+        symbol: Symbol::Variable { name: variable.df_name.clone(), scope: variable.var_type.scope().into(), owner },
+        range: crate::token::Range::new(variable.name_start_pos.clone(), variable.name_end_pos.clone()),
+        declaration: true
+    }
+}
+
+fn expression_occurrences(expressions: &[ExpressionNode], owner: usize, occurrences: &mut Vec<SymbolOccurrence>) {
+    for expression in expressions {
+        match &expression.node {
+            Expression::Action { node } => arg_occurrences(&node.args, owner, occurrences),
+            Expression::Conditional { node } => {
+                arg_occurrences(&node.args, owner, occurrences);
+                expression_occurrences(&node.expressions, owner, occurrences);
+                expression_occurrences(&node.else_expressions, owner, occurrences);
+            }
+            Expression::Repeat { node } => {
+                arg_occurrences(&node.args, owner, occurrences);
+                expression_occurrences(&node.expressions, owner, occurrences);
+            }
+            Expression::Call { node } => {
+                occurrences.push(SymbolOccurrence {
+                    symbol: Symbol::Function { name: node.name.clone() },
+                    range: crate::token::Range::new(node.name_start_pos.clone(), node.name_end_pos.clone()),
+                    declaration: false
+                });
+                arg_occurrences(&node.args, owner, occurrences);
+            }
+            Expression::Start { node } => {
+                occurrences.push(SymbolOccurrence {
+                    symbol: Symbol::Process { name: node.name.clone() },
+                    range: crate::token::Range::new(node.name_start_pos.clone(), node.name_end_pos.clone()),
+                    declaration: false
+                });
+                arg_occurrences(&node.args, owner, occurrences);
+            }
+            Expression::Variable { node } => occurrences.push(variable_declaration(node, Some(owner)))
+        }
+    }
+}
+
+fn arg_occurrences(args: &[Arg], owner: usize, occurrences: &mut Vec<SymbolOccurrence>) {
+    for arg in args {
+        match &arg.value {
+            ArgValue::Variable { name, scope } => {
+                // Game and saved variables are shared by the whole file
+                let owner = if scope == "unsaved" || scope == "saved" { None } else { Some(owner) };
+                occurrences.push(SymbolOccurrence {
+                    symbol: Symbol::Variable { name: name.clone(), scope: scope.clone(), owner },
+                    range: crate::token::Range::new(arg.start_pos.clone(), arg.end_pos.clone()),
+                    declaration: false
+                });
+            }
+            ArgValue::Condition { args, .. } => arg_occurrences(args, owner, occurrences),
+            _ => {}
+        }
+    }
+}
+
+// LSP positions start at 0 and dfrs positions at 1, both use the character after a range as its end
+fn to_lsp_position(pos: &crate::token::Position) -> Position {
+    Position { line: pos.line.saturating_sub(1), character: pos.col.saturating_sub(1) }
+}
+
+fn from_lsp_position(pos: Position) -> crate::token::Position {
+    crate::token::Position::new(pos.line + 1, pos.character + 1)
+}
+
+fn diagnose(data: String, path: PathBuf, definitions: &Arc<Definitions>) -> Vec<Diagnostic> {
+    let mut result: Vec<Diagnostic> = vec![];
+
+    let (errors, severity) = match compile_file(data, path.clone(), definitions) {
+        Ok(warnings) => (warnings, DiagnosticSeverity::WARNING),
+        Err(err) => (vec![err], DiagnosticSeverity::ERROR)
+    };
+
+    for err in errors {
+        let mut end_pos = err.pos.clone();
+        if err.end_pos.is_some() {
+            end_pos = err.end_pos.unwrap();
+        }
+        result.push(Diagnostic {
+            severity: Some(severity),
+            message: err.msg,
+            code: err.code.map(|code| NumberOrString::String(code.into())),
+            related_information: match (err.related, Url::from_file_path(&path)) {
+                (Some((start, end, message)), Ok(uri)) => Some(vec![DiagnosticRelatedInformation {
+                    location: Location {
+                        uri,
+                        range: Range { start: to_lsp_position(&start), end: to_lsp_position(&end) }
+                    },
+                    message
+                }]),
+                _ => None
+            },
+            range: Range { start: to_lsp_position(&err.pos), end: to_lsp_position(&end_pos) },
+            ..Default::default()
+        });
+    }
+
+    result
 }
 
 struct CompileErr {
     pub pos: crate::token::Position,
     pub end_pos: Option<crate::token::Position>,
-    pub msg: String
+    pub msg: String,
+    pub code: Option<&'static str>,
+    pub related: Option<(crate::token::Position, crate::token::Position, String)>
 }
 
 impl CompileErr {
     pub fn new(pos: crate::token::Position, end_pos: Option<crate::token::Position>, msg: String) -> CompileErr {
-        CompileErr { pos, end_pos, msg }
+        CompileErr { pos, end_pos, msg, code: None, related: None }
     }
 }
 
-fn compile_file(data: String, path: PathBuf) -> Result<(), CompileErr> {
+fn compile_file(data: String, path: PathBuf, definitions: &Arc<Definitions>) -> Result<Vec<CompileErr>, CompileErr> {
     let mut config_path = path.clone();
     config_path.set_file_name("dfrs.toml");
     let config = match load_config(&config_path) {
         Ok(res) => res,
-        Err(_) => return Err(CompileErr::new(crate::token::Position::new(0, 0), None, "No config file found".into()))
+        Err(_) => return Err(CompileErr::new(crate::token::Position::start(), None, "No config file found".into()))
     };
 
     let mut lexer = Lexer::new(data.clone());
@@ -373,12 +752,15 @@ fn compile_file(data: String, path: PathBuf) -> Result<(), CompileErr> {
     };
 
     let mut parser = Parser::new(res);
+    parser.add_variables(project_globals(path.parent().unwrap_or(Path::new(""))));
+    parser.set_max_depth(config.validation.max_depth);
     let res = parser.run();
     let node;
     match res {
         Ok(res) =>node = res,
         Err(err) => {
-            match err {
+            let code = err.code();
+            let mut err = match err {
                 ParseError::InvalidToken { found,expected} => {
                     if found.is_some() {
                         let found = found.unwrap();
@@ -393,84 +775,437 @@ fn compile_file(data: String, path: PathBuf) -> Result<(), CompileErr> {
                             i += 1;
                         }
 
-                        return Err(CompileErr::new(found.start_pos, Some(found.end_pos), format!("Invalid token '{}', expected: {expected_string}", found.token)))
+                        CompileErr::new(found.start_pos, Some(found.end_pos), format!("Invalid token '{}', expected: {expected_string}", found.token))
                     } else {
                         // println!("Invalid EOF, expected: {expected:?}");
+                        return Ok(vec![])
                     }
                 }
                 ParseError::InvalidComplexNumber { pos, msg } => {
-                    return Err(CompileErr::new(pos, None, format!("Invalid number '{msg}'")))
+                    CompileErr::new(pos, None, format!("Invalid number '{msg}'"))
                 },
                 ParseError::InvalidLocation { pos, msg } => {
-                    return Err(CompileErr::new(pos, None, format!("Invalid location '{msg}'")))
+                    CompileErr::new(pos, None, format!("Invalid location '{msg}'"))
                 },
                 ParseError::InvalidVector { pos, msg } => {
-                    return Err(CompileErr::new(pos, None, format!("Invalid vector '{msg}'")))
+                    CompileErr::new(pos, None, format!("Invalid vector '{msg}'"))
                 },
                 ParseError::InvalidSound { pos, msg } => {
-                    return Err(CompileErr::new(pos, None, format!("Invalid sound '{msg}'")))
+                    CompileErr::new(pos, None, format!("Invalid sound '{msg}'"))
                 },
                 ParseError::InvalidPotion { pos, msg } => {
-                    return Err(CompileErr::new(pos, None, format!("Invalid potion '{msg}'")))
+                    CompileErr::new(pos, None, format!("Invalid potion '{msg}'"))
                 },
                 ParseError::InvalidParticle { pos, msg } => {
-                    return Err(CompileErr::new(pos, None, format!("Invalid particle '{msg}'")))
+                    CompileErr::new(pos, None, format!("Invalid particle '{msg}'"))
                 },
                 ParseError::InvalidItem { pos, msg } => {
-                    return Err(CompileErr::new(pos, None, format!("Invalid item '{msg}'")))
+                    CompileErr::new(pos, None, format!("Invalid item '{msg}'"))
                 },
-                ParseError::UnknownVariable { found, start_pos, end_pos } => {
-                    return Err(CompileErr::new(start_pos, Some(end_pos), format!("Unknown variable '{}'", found)))
+                ParseError::UnknownVariable { found, suggestions, start_pos, end_pos } => {
+                    CompileErr::new(start_pos, Some(end_pos), unknown_variable_message(found, suggestions))
                 },
                 ParseError::InvalidType { found, start_pos } => {
-                    return match found {
-                        Some(found) => Err(CompileErr::new(found.start_pos, Some(found.end_pos), format!("Unknown type: {}", found.token))),
-                        None => Err(CompileErr::new(start_pos, None, "Missing type".into()))
+                    match found {
+                        Some(found) => CompileErr::new(found.start_pos, Some(found.end_pos), format!("Unknown type: {}", found.token)),
+                        None => CompileErr::new(start_pos, None, "Missing type".into())
                     }
                 },
                 ParseError::InvalidCall { pos, msg } => {
-                    return Err(CompileErr::new(pos, None, format!("Invalid function call '{msg}'")))
+                    CompileErr::new(pos, None, format!("Invalid function call '{msg}'"))
                 },
-            }
-            return Ok(())
+                ParseError::MissingBody { name, start_pos, end_pos } => {
+                    CompileErr::new(start_pos, Some(end_pos), format!("Missing body for {name}, expected '{{'"))
+                },
+                ParseError::MultipleSelectors { start_pos, end_pos } => {
+                    CompileErr::new(start_pos, Some(end_pos), "Actions can only target one selector, use game values like $selection:location to reference other targets".into())
+                },
+                ParseError::DivisionByZero { start_pos, end_pos } => {
+                    CompileErr::new(start_pos, Some(end_pos), "Division by zero".into())
+                },
+                ParseError::UnknownSetting { found, start_pos, end_pos } => {
+                    CompileErr::new(start_pos, Some(end_pos), format!("Unknown setting '#{found}', available: #hidden"))
+                },
+                ParseError::UnknownEventPrefix { found, start_pos, end_pos } => {
+                    CompileErr::new(start_pos, Some(end_pos), format!("Unknown event prefix '{found}', expected 'player' or 'entity'"))
+                },
+                ParseError::MissingSemicolon { pos } => {
+                    CompileErr::new(pos, None, "Missing semicolon".into())
+                },
+                ParseError::TooDeep { max_depth, pos } => {
+                    CompileErr::new(pos, None, format!("Expansion too deep, code can only be nested {max_depth} levels deep"))
+                },
+                ParseError::UnknownTimeUnit { found, start_pos, end_pos } => {
+                    CompileErr::new(start_pos, Some(end_pos), format!("Unknown time unit '{found}', expected ticks, seconds or minutes"))
+                },
+                ParseError::ActionInRepeatCondition { start_pos, end_pos } => {
+                    CompileErr::new(start_pos, Some(end_pos), "Actions can't be used in a repeat condition, they would only run once before the loop".into())
+                },
+            };
+            err.code = Some(code);
+            return Err(err)
         }
     }
 
-    let validated;
-    match Validator::new().validate(node) {
+    let imports = match resolve_imports(&node, &path, &config, definitions) {
+        Ok(imports) => imports,
+        Err(err) => {
+            let code = err.code();
+            let mut err = match err {
+                ImportError::FileNotFound { path, start_pos, end_pos } => {
+                    CompileErr::new(start_pos, Some(end_pos), format!("Imported file '{path}' not found"))
+                }
+                ImportError::InvalidFile { path, start_pos, end_pos } => {
+                    CompileErr::new(start_pos, Some(end_pos), format!("Failed to compile imported file '{path}', open it to see the errors"))
+                }
+                ImportError::UnknownFunction { name, path, start_pos, end_pos } => {
+                    CompileErr::new(start_pos, Some(end_pos), format!("Function '{name}' not found in '{path}'"))
+                }
+            };
+            err.code = Some(code);
+            return Err(err)
+        }
+    };
+
+    let mut validator = Validator::new(&config, definitions.clone());
+    validator.set_imports(&imports);
+    if let Some(variables) = plot_variables(&path, &config) {
+        validator.set_plot_variables(variables);
+    }
+    let mut validated;
+    match validator.validate(node) {
         Ok(res) => validated = res,
         Err(err)  => {
-            return match err {
+            let code = err.code();
+            let mut err = match err {
                 ValidateError::UnknownEvent { node } => {
-                    Err(CompileErr::new(node.start_pos, Some(node.end_pos), format!("Unknown event '{}'", node.event)))
+                    let msg = format!("Unknown {} '{}'", event_kind(&node), node.event);
+                    CompileErr::new(node.start_pos, Some(node.end_pos), msg)
                 }
-                ValidateError::UnknownAction { name, start_pos, end_pos } => {
-                    Err(CompileErr::new(start_pos, Some(end_pos), format!("Unknown action '{}'", name)))
+                ValidateError::UnknownAction { name, suggestions, start_pos, end_pos } => {
+                    CompileErr::new(start_pos, Some(end_pos), unknown_action_message(name, suggestions))
                 },
                 ValidateError::MissingArgument { start_pos, end_pos, name } => {
-                    Err(CompileErr::new(start_pos, Some(end_pos), format!("Missing argument '{}'", name)))
+                    CompileErr::new(start_pos, Some(end_pos), format!("Missing argument '{}'", name))
                 }
                 ValidateError::WrongArgumentType { args, index, name, expected_types, found_type } => {
-                    Err(CompileErr::new(args.get(index as usize).unwrap().start_pos.clone(), Some(args.get(index as usize).unwrap().end_pos.clone()), format!("Wrong argument type for '{}', expected '{:?}' but found '{:?}'", name, expected_types, found_type)))
+                    CompileErr::new(args.get(index as usize).unwrap().start_pos.clone(), Some(args.get(index as usize).unwrap().end_pos.clone()), format!("Wrong argument type for '{}', expected '{:?}' but found '{:?}'", name, expected_types, found_type))
                 }
-                ValidateError::TooManyArguments { start_pos, mut end_pos, name } => {
-                    end_pos.col += name.chars().count() as u32;
-                    Err(CompileErr::new(start_pos.clone(), Some(start_pos), format!("Too many arguments for action '{}'", name)))
+                ValidateError::TooManyArguments { start_pos, end_pos, name } => {
+                    CompileErr::new(start_pos, Some(end_pos), format!("Too many arguments for action '{}'", name))
                 }
-                ValidateError::InvalidTagOption { tag_name, provided, options, start_pos, end_pos } => {
-                    Err(CompileErr::new(start_pos, Some(end_pos), format!("Invalid option '{}' for tag '{}', expected one of {:?}", provided, tag_name, options)))
+                ValidateError::InvalidTagOption { tag_name, provided, options, suggestion, start_pos, end_pos } => {
+                    CompileErr::new(start_pos, Some(end_pos), tag_option_message(tag_name, provided, options, suggestion))
                 }
                 ValidateError::UnknownTag { tag_name, available, start_pos, end_pos } => {
-                    Err(CompileErr::new(start_pos, Some(end_pos), format!("Unknown tag '{}', found tags: {:?}", tag_name, available)))
+                    CompileErr::new(start_pos, Some(end_pos), format!("Unknown tag '{}', found tags: {:?}", tag_name, available))
                 }
                 ValidateError::UnknownGameValue { game_value, start_pos, end_pos} => {
-                    Err(CompileErr::new(start_pos, Some(end_pos), format!("Unknown game value '{}'", game_value)))
+                    CompileErr::new(start_pos, Some(end_pos), format!("Unknown game value '{}'", game_value))
+                }
+                ValidateError::RecursiveFunction { name, through, start_pos, end_pos } => {
+                    CompileErr::new(start_pos, Some(end_pos), recursion_message(name, through))
+                }
+                ValidateError::InvalidSoundVariant { sound, variant, start_pos, end_pos } => {
+                    CompileErr::new(start_pos, Some(end_pos), format!("Invalid variant '{variant}', sound '{sound}' has no variants"))
+                }
+                ValidateError::MissingTargetVariable { name, start_pos, end_pos } => {
+                    CompileErr::new(start_pos, Some(end_pos), format!("Expected a variable as first argument for '{name}'"))
+                }
+                ValidateError::UnexpectedGameValueSelector { game_value, start_pos, end_pos } => {
+                    CompileErr::new(start_pos, Some(end_pos), format!("Game value '{game_value}' does not take a selector"))
+                }
+                ValidateError::DuplicateDefinition { kind, name, start_pos, end_pos, previous_start_pos, previous_end_pos } => {
+                    let mut err = CompileErr::new(start_pos, Some(end_pos), format!("Duplicate {kind} '{name}'"));
+                    err.related = Some((previous_start_pos, previous_end_pos, format!("'{name}' is first defined here")));
+                    err
+                }
+                ValidateError::UnknownValue { kind, name, start_pos, end_pos } => {
+                    CompileErr::new(start_pos, Some(end_pos), format!("Unknown {kind} '{name}'"))
+                }
+                ValidateError::OutOfRange { name, value, min, max, start_pos, end_pos } => {
+                    CompileErr::new(start_pos, Some(end_pos), format!("{value} is out of range for '{name}', expected a number from {min} to {max}"))
+                }
+                ValidateError::ReadOnlyGameValue { game_value, name, start_pos, end_pos } => {
+                    CompileErr::new(start_pos, Some(end_pos), format!("Game value '{game_value}' is read-only, '{name}' needs a variable"))
+                }
+                ValidateError::UnsupportedConditionType { action, found, allowed, start_pos, end_pos } => {
+                    CompileErr::new(start_pos, Some(end_pos), format!("'{action}' does not take '{found}' conditions, expected one of: {}", allowed.join(", ")))
+                }
+                ValidateError::UnexpectedCondition { action, start_pos, end_pos } => {
+                    CompileErr::new(start_pos, Some(end_pos), format!("'{action}' does not take a condition"))
+                }
+                ValidateError::UnexpectedSelector { kind, start_pos, end_pos } => {
+                    CompileErr::new(start_pos, Some(end_pos), format!("Selectors can not be used on {kind}"))
+                }
+                ValidateError::InvalidDefault { name, expected, found, start_pos, end_pos } => {
+                    CompileErr::new(start_pos, Some(end_pos), format!("Default value of '{name}' should be '{expected:?}' but is '{found:?}'"))
                 }
+                ValidateError::NullArgument { name, start_pos, end_pos } => {
+                    CompileErr::new(start_pos, Some(end_pos), format!("Argument '{name}' is required, got null"))
+                }
+                ValidateError::UnknownFunction { name, left_out_of, start_pos, end_pos } => {
+                    CompileErr::new(start_pos, Some(end_pos), unknown_function_message(name, left_out_of))
+                }
+            };
+            err.code = Some(code);
+            return Err(err)
+        }
+    }
+
+    let mut warnings = vec![];
+    for warning in validator.take_warnings() {
+        match warning {
+            ValidateWarning::RecursiveFunction { name, through, start_pos, end_pos } => {
+                warnings.push(CompileErr::new(start_pos, Some(end_pos), recursion_message(name, through)));
+            }
+            ValidateWarning::RedundantInversion { start_pos, end_pos } => {
+                warnings.push(CompileErr::new(start_pos, Some(end_pos), "Redundant double negation, use a single '!' or none".into()));
+            }
+            ValidateWarning::ItemCountExceedsStack { count, max, start_pos, end_pos } => {
+                warnings.push(CompileErr::new(start_pos, Some(end_pos), format!("Item count {count} is larger than the maximum stack size of {max}")));
+            }
+            ValidateWarning::UnexpectedProcessArguments { name, start_pos, end_pos } => {
+                warnings.push(CompileErr::new(start_pos, Some(end_pos), format!("Process '{name}' takes no arguments, they will be ignored")));
+            }
+            ValidateWarning::IdenticalBranches { start_pos, end_pos } => {
+                warnings.push(CompileErr::new(start_pos, Some(end_pos), "Both branches of this conditional are identical, so the condition has no effect".into()));
+            }
+            ValidateWarning::TooManySlots { name, count, max, start_pos, end_pos } => {
+                warnings.push(CompileErr::new(start_pos, Some(end_pos), format!("'{name}' needs {count} slots for its arguments and tags, but a block only has {max}")));
+            }
+            ValidateWarning::UnsupportedParticleField { particle, field, start_pos, end_pos } => {
+                warnings.push(CompileErr::new(start_pos, Some(end_pos), format!("'{particle}' particles do not use '{field}', it will be ignored")));
+            }
+            ValidateWarning::MotionVariationWithoutMotion { start_pos, end_pos } => {
+                warnings.push(CompileErr::new(start_pos, Some(end_pos), "'motionVariation' has no effect without 'motion'".into()));
+            }
+            ValidateWarning::UnassignedVariable { name, scope, start_pos, end_pos } => {
+                warnings.push(CompileErr::new(start_pos, Some(end_pos), format!("{scope} variable '{name}' is read before it is assigned")));
+            }
+            ValidateWarning::UnknownPlotVariable { name, start_pos, end_pos } => {
+                warnings.push(CompileErr::new(start_pos, Some(end_pos), format!("Variable '{name}' does not exist on the plot")));
+            }
+            ValidateWarning::Deprecated { kind, name, note, start_pos, end_pos } => {
+                warnings.push(CompileErr::new(start_pos, Some(end_pos), format!("{kind} '{name}' is deprecated: {note}")));
             }
         }
     }
 
-    let compiled = compile(validated, config.debug.compile);
+    validated.functions.extend(imports.functions);
 
-    Ok(())
-}
\ No newline at end of file
+    if let Err(err) = compile(validated, config.debug.compile, false, &config.variable_prefix) {
+        return Err(CompileErr::new(crate::token::Position::start(), None, err.to_string()))
+    }
+
+    Ok(warnings)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{definitions, project};
+
+    // Highlights as (line, start column, end column, is declaration), with LSP positions starting at 0
+    fn highlights(data: &str, line: u32, col: u32) -> Vec<(u32, u32, u32, bool)> {
+        let cursor = crate::token::Position::new(line, col);
+        document_highlights(data.into(), Path::new("test.dfrs"), cursor).into_iter()
+            .map(|highlight| (highlight.range.start.line, highlight.range.start.character, highlight.range.end.character, highlight.kind == Some(DocumentHighlightKind::WRITE)))
+            .collect()
+    }
+
+    #[test]
+    fn highlights_variable_in_its_event() {
+        let data = "@join {\n    line count;\n    v.set(count, 1);\n    p.sendMessage(count);\n}\n@quit {\n    line count;\n    p.sendMessage(count);\n}";
+        let expected = vec![(1, 9, 14, true), (2, 10, 15, false), (3, 18, 23, false)];
+        assert_eq!(highlights(data, 4, 20), expected);
+        assert_eq!(highlights(data, 8, 20), vec![(6, 9, 14, true), (7, 18, 23, false)]);
+    }
+
+    #[test]
+    fn highlights_backtick_variable() {
+        let data = "@join {\n    line `my var`;\n    p.sendMessage(`my var`);\n}";
+        assert_eq!(highlights(data, 3, 20), vec![(1, 9, 17, true), (2, 18, 26, false)]);
+    }
+
+    #[test]
+    fn highlights_globals_across_events() {
+        let data = "game points;\n@join {\n    p.sendMessage(points);\n}\n@quit {\n    v.set(points, 0);\n}";
+        assert_eq!(highlights(data, 1, 7), vec![(0, 5, 11, true), (2, 18, 24, false), (5, 10, 16, false)]);
+    }
+
+    #[test]
+    fn highlights_params_and_calls() {
+        let data = "fn greet(name: string) {\n    p.sendMessage(name);\n}\n@join {\n    call(\"greet\", \"Hi\");\n}";
+        assert_eq!(highlights(data, 2, 20), vec![(0, 9, 13, true), (1, 18, 22, false)]);
+        assert_eq!(highlights(data, 1, 5), vec![(0, 3, 8, true), (4, 9, 16, false)]);
+    }
+
+    #[test]
+    fn highlights_processes_and_starts() {
+        let data = "proc loop {\n}\n@join {\n    start(\"loop\");\n    start(\"loop\");\n}";
+        let expected = vec![(0, 5, 9, true), (3, 10, 16, false), (4, 10, 16, false)];
+        assert_eq!(highlights(data, 4, 12), expected);
+        assert_eq!(highlights(data, 1, 6), expected);
+    }
+
+    #[test]
+    fn ignores_action_and_tag_names() {
+        let data = "@join {\n    line alignmentMode;\n    p.sendMessage(\"Hi\", alignmentMode=\"Centered\");\n}";
+        assert_eq!(highlights(data, 3, 25), vec![]);
+        assert_eq!(highlights(data, 3, 8), vec![]);
+    }
+
+    #[test]
+    fn skips_generated_variables() {
+        let data = "@join, @respawn {\n    line count;\n    p.sendMessage(v.add(count, 1));\n}";
+        assert_eq!(highlights(data, 3, 19), vec![]);
+        assert_eq!(highlights(data, 3, 26), vec![(1, 9, 14, true), (2, 24, 29, false)]);
+    }
+
+    #[test]
+    fn diagnoses_errors_and_warnings() {
+        let dir = project("diagnose", &[]);
+        let diagnostics = diagnose("@join {\n    p.sendMessage(5);\n}".into(), dir.join("test.dfrs"), &definitions());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::ERROR));
+        assert_eq!(diagnostics[0].code, Some(NumberOrString::String("E005".into())));
+        assert_eq!(diagnostics[0].range, Range { start: Position::new(1, 18), end: Position::new(1, 19) });
+
+        let diagnostics = diagnose("@join {\n    line count;\n    p.sendMessage(count);\n}".into(), dir.join("test.dfrs"), &definitions());
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].severity, Some(DiagnosticSeverity::WARNING));
+        assert_eq!(diagnostics[0].range, Range { start: Position::new(2, 18), end: Position::new(2, 23) });
+    }
+
+    #[test]
+    fn duplicate_points_at_first_definition() {
+        let dir = project("diagnose-duplicate", &[]);
+        let diagnostics = diagnose("fn foo() {\n}\nfn foo() {\n}".into(), dir.join("test.dfrs"), &definitions());
+        assert_eq!(diagnostics[0].range, Range { start: Position::new(2, 3), end: Position::new(2, 6) });
+        let related = diagnostics[0].related_information.as_ref().unwrap();
+        assert_eq!(related[0].location.range, Range { start: Position::new(0, 3), end: Position::new(0, 6) });
+        assert_eq!(related[0].message, "'foo' is first defined here");
+    }
+
+    #[test]
+    fn suggests_tag_option_case() {
+        let dir = project("diagnose-tag", &[]);
+        let diagnostics = diagnose("@join {\n    p.sendMessage(\"Hi\", alignmentMode=\"centered\");\n}".into(), dir.join("test.dfrs"), &definitions());
+        assert_eq!(diagnostics[0].message, "Invalid option 'centered' for tag 'alignmentMode', did you mean 'Centered'?");
+    }
+
+    #[test]
+    fn diagnoses_missing_config() {
+        let diagnostics = diagnose("@join {\n}".into(), std::env::temp_dir().join("dfrs-no-config").join("test.dfrs"), &definitions());
+        assert_eq!(diagnostics[0].message, "No config file found");
+    }
+
+    #[test]
+    fn folds_blocks_and_regions() {
+        let data = "// #region Events\n@join {\n    ifp isSneaking() {\n        p.sendMessage(\"A\");\n        p.sendMessage(\"B\");\n    }\n    p.sendMessage(\"C\");\n}\n// #endregion";
+        let ranges: Vec<(u32, u32, Option<FoldingRangeKind>)> = folding_ranges(data.into()).into_iter().map(|range| (range.start_line, range.end_line, range.kind)).collect();
+        assert_eq!(ranges, vec![(0, 8, Some(FoldingRangeKind::Region)), (2, 4, None), (1, 6, None)]);
+    }
+
+    // Items completed with the cursor at the given LSP position, after the document was opened with the given text
+    async fn completion_items(text: &str, line: u32, character: u32) -> Vec<CompletionItem> {
+        let (service, _) = LspService::new(|client| Backend { client, document_map: DashMap::new(), definitions: definitions() });
+        let backend = service.inner();
+        backend.document_map.insert("file:///test.dfrs".into(), Rope::from_str(text));
+        let position = from_lsp_position(Position { line, character });
+        match backend.get_completions("file:///test.dfrs".into(), position.line, position.col - 1).await.unwrap() {
+            Some(CompletionResponse::Array(items)) => items,
+            Some(_) => panic!("Expected a list of completions"),
+            None => vec![]
+        }
+    }
+
+    async fn completions(text: &str, line: u32, character: u32) -> Vec<String> {
+        completion_items(text, line, character).await.into_iter().map(|item| item.label).collect()
+    }
+
+    #[tokio::test]
+    async fn selectors_after_action_keyword() {
+        let labels = completions("@join {\n    p:\n}", 1, 6).await;
+        assert_eq!(labels.len(), SELECTORS.len());
+        assert!(labels.contains(&"selection".to_string()));
+        assert_eq!(completions("@join {\n    p:sel\n}", 1, 9).await, vec!["selection"]);
+    }
+
+    #[tokio::test]
+    async fn selectors_after_game_value() {
+        let labels = completions("@join {\n    p.sendMessage($\n}", 1, 19).await;
+        assert!(labels.contains(&"default".to_string()));
+        assert!(labels.contains(&"playerCount".to_string()));
+    }
+
+    #[tokio::test]
+    async fn game_values_after_selector() {
+        let labels = completions("@join {\n    p.sendMessage($default:\n}", 1, 27).await;
+        assert!(labels.contains(&"currentHealth".to_string()));
+        assert!(!labels.contains(&"playerCount".to_string()));
+        assert!(!labels.contains(&"default".to_string()));
+    }
+
+    #[tokio::test]
+    async fn no_selectors_after_game_keyword() {
+        assert!(completions("@join {\n    g:\n}", 1, 6).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn action_details_are_resolved_later() {
+        let items = completion_items("@join {\n    p.\n}", 1, 6).await;
+        let item = items.into_iter().find(|item| item.label == "sendMessage").unwrap();
+        assert!(item.detail.is_none() && item.documentation.is_none());
+        assert_eq!(item.data, Some(serde_json::json!({ "category": "p", "name": "sendMessage" })));
+
+        let resolved = resolve_completion(&definitions(), item);
+        assert_eq!(resolved.detail.as_deref(), Some("SendMessage"));
+        let Some(Documentation::MarkupContent(documentation)) = resolved.documentation else { panic!("Expected markdown documentation") };
+        assert!(documentation.value.starts_with("Sends a chat message to a player."));
+        assert!(documentation.value.contains("\n\nArguments:\n- `"));
+        assert!(documentation.value.contains("*: TEXT (optional)"));
+    }
+
+    #[test]
+    fn unknown_completions_are_not_resolved() {
+        for data in [None, Some(serde_json::json!({ "category": "p", "name": "dance" })), Some(serde_json::json!({ "category": "x", "name": "sendMessage" })), Some(serde_json::json!("sendMessage"))] {
+            let item = CompletionItem { label: "item".into(), data, ..Default::default() };
+            assert_eq!(resolve_completion(&definitions(), item.clone()), item);
+        }
+    }
+
+    #[tokio::test]
+    async fn resolve_provider_is_enabled() {
+        let (service, _) = LspService::new(|client| Backend { client, document_map: DashMap::new(), definitions: definitions() });
+        let result = service.inner().initialize(InitializeParams::default()).await.unwrap();
+        assert_eq!(result.capabilities.completion_provider.unwrap().resolve_provider, Some(true));
+        let item = CompletionItem { label: "heal".into(), data: Some(serde_json::json!({ "category": "p", "name": "heal" })), ..Default::default() };
+        assert_eq!(service.inner().completion_resolve(item).await.unwrap().detail.as_deref(), Some("Heal"));
+    }
+
+    #[test]
+    fn lsp_positions_round_trip() {
+        assert_eq!(to_lsp_position(&crate::token::Position::start()), Position::new(0, 0));
+        assert_eq!(from_lsp_position(Position::new(0, 0)), crate::token::Position::start());
+        for (line, col) in [(1, 1), (1, 2), (2, 1), (7, 40), (120, 3)] {
+            let pos = crate::token::Position::new(line, col);
+            let lsp = to_lsp_position(&pos);
+            assert_eq!((lsp.line, lsp.character), (line - 1, col - 1));
+            assert_eq!(from_lsp_position(lsp), pos);
+        }
+        for (line, character) in [(0, 0), (0, 5), (3, 0), (9, 17)] {
+            assert_eq!(to_lsp_position(&from_lsp_position(Position::new(line, character))), Position::new(line, character));
+        }
+    }
+
+    #[test]
+    fn diagnostics_at_first_column() {
+        let dir = project("diagnose-first-column", &[]);
+        let diagnostics = diagnose("foo @join {\n}".into(), dir.join("test.dfrs"), &definitions());
+        assert_eq!(diagnostics[0].range, Range { start: Position::new(0, 0), end: Position::new(0, 3) });
+        let diagnostics = diagnose("@join {\n    ifv !!equal(1, 1) {\n    }\n}".into(), dir.join("test.dfrs"), &definitions());
+        assert_eq!(diagnostics[0].range, Range { start: Position::new(1, 8), end: Position::new(1, 10) });
+    }
+}
@@ -1,20 +1,36 @@
+use std::collections::HashSet;
 use std::{cmp, fs};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
 
 use clap::{Parser as _, Subcommand};
 use crate::config::Config;
-use crate::send::send;
+use crate::definitions::action_dump::RawActionDump;
+use crate::definitions::Definitions;
+use crate::send::{filter_changed, give_command, load_plot_variables, save_plot_variables, save_sent, scan_plot, send, Connection, ScanError};
 use crate::token::Position;
-use crate::compile::compile;
-use crate::lexer::{Lexer, LexerError};
-use crate::parser::{ParseError, Parser};
-use crate::validate::{Validator, ValidateError};
+use crate::node::{ActionType, EventNode, FileNode, VariableNode};
+use crate::compile::CompiledLine;
+use crate::compiler::{Compiler, CompilerError};
+use crate::lexer::LexerError;
+use crate::parser::ParseError;
+use crate::validate::{ActionSuggestion, ValidateError, ValidateWarning};
 use lsp::run_lsp;
+use repl::run_repl;
 
-use colored::Colorize;
-use tungstenite::{connect, Message};
-use url::Url;
-use crate::decompile::Decompiler;
+use colored::{ColoredString, Colorize};
+use crate::decompile::{scan_variables, Decompiler};
+use crate::expect::{check, expectations, ExpectationError};
+use crate::explain::explain;
+use crate::features::features;
+use crate::import::{project_globals, ImportError};
+use crate::pack::Pack;
+use crate::utility::expand_tabs;
+use crate::preview::preview;
+use crate::stats::Stats;
 
 mod lsp;
 pub mod config;
@@ -24,13 +40,27 @@ pub mod node;
 pub mod parser;
 pub mod validate;
 pub mod compile;
+pub mod compiler;
 pub mod send;
 pub mod definitions;
 pub mod utility;
 pub mod decompile;
+pub mod expect;
+pub mod explain;
+pub mod features;
+pub mod import;
+pub mod logger;
+pub mod pack;
+pub mod preview;
+pub mod stats;
+mod repl;
+#[cfg(test)]
+mod testing;
 
 pub struct ConfigFileNotFoundError {}
 
+pub struct CompileFailedError {}
+
 pub fn load_config(file: &PathBuf) -> Result<Config, ConfigFileNotFoundError> {
     let data = if !file.exists() {
         return Err(ConfigFileNotFoundError {})
@@ -45,31 +75,233 @@ pub fn load_config(file: &PathBuf) -> Result<Config, ConfigFileNotFoundError> {
 }
 
 fn print_err(message: String, data: String, start_pos: Position, end_pos: Option<Position>) {
-    let lines = data.split("\n").collect::<Vec<&str>>();
-    let line = lines.get((start_pos.line - 1) as usize).unwrap();
-    let ln = start_pos.line;
-    let ln_length = ln.to_string().chars().count();
+    print_message("Error:".bright_red(), message, data, start_pos, end_pos);
+}
+
+fn print_warn(message: String, data: String, start_pos: Position, end_pos: Option<Position>) {
+    print_message("Warning:".bright_yellow(), message, data, start_pos, end_pos);
+}
+
+// Long ranges only show this many lines at their start and end
+const MESSAGE_CONTEXT_LINES: u32 = 2;
 
-    println!("{} {}", "Error:".bright_red(), message);
+// Tabs are shown as spaces in messages, using the width the lexer counted them with
+static TAB_WIDTH: AtomicU32 = AtomicU32::new(1);
+
+fn print_message(prefix: ColoredString, message: String, data: String, start_pos: Position, end_pos: Option<Position>) {
+    let lines = data.trim_start_matches('\u{feff}').split("\n").collect::<Vec<&str>>();
+    let end_pos = end_pos.filter(|end_pos| end_pos.line >= start_pos.line).unwrap_or_else(|| start_pos.clone());
+    let ln_length = end_pos.line.to_string().chars().count();
+
+    println!("{} {}", prefix, message);
     println!("{} {}", " ".repeat(ln_length), "|".bright_black());
-    println!("{} {} {}", ln.to_string().bright_black(), "|".bright_black(), line);
-    let arrows;
-    match end_pos {
-        Some(end_pos) => {
-            if end_pos.line != start_pos.line {
-                // TODO
-                return;
+    let skipped = (start_pos.line + MESSAGE_CONTEXT_LINES)..=(end_pos.line.saturating_sub(MESSAGE_CONTEXT_LINES));
+    for ln in start_pos.line..=end_pos.line {
+        if skipped.clone().count() > 1 && skipped.contains(&ln) {
+            if ln == *skipped.start() {
+                println!("{} {}", format!("{:>ln_length$}", "...").bright_black(), format!("{} more lines", skipped.clone().count()).bright_black());
+            }
+            continue;
+        }
+        let line = expand_tabs(lines.get((ln - 1) as usize).unwrap_or(&"").trim_end_matches('\r'), TAB_WIDTH.load(Ordering::Relaxed));
+        // Lines after the first are marked from their indentation, lines before the last up to their end
+        let from = if ln == start_pos.line { start_pos.col } else { (line.chars().take_while(|char| char.is_whitespace()).count() + 1) as u32 };
+        let to = if ln == end_pos.line { end_pos.col } else { (line.chars().count() + 1) as u32 };
+        let arrows = "^".repeat(cmp::max(to.saturating_sub(from), 1) as usize).bright_blue();
+        println!("{} {} {}", format!("{:>ln_length$}", ln).bright_black(), "|".bright_black(), line);
+        println!("{} {} {}{}", " ".repeat(ln_length), "|".bright_black(), " ".repeat(from.saturating_sub(1) as usize), arrows);
+    }
+}
+
+fn print_scan_error(err: ScanError) {
+    match err {
+        ScanError::InvalidUrl { url } => {
+            println!("{} Invalid url '{}'", "Error:".bright_red(), url);
+        }
+        ScanError::ConnectionFailed { url, msg } => {
+            println!("{} Failed to connect to '{}': {}", "Error:".bright_red(), url, msg);
+        }
+        ScanError::ConnectionLost { msg } => {
+            println!("{} Lost connection while scanning: {}", "Error:".bright_red(), msg);
+        }
+        ScanError::InvalidResponse => {
+            println!("{} Received an invalid scan response", "Error:".bright_red());
+        }
+    }
+}
+
+fn print_explain_hint(code: &str) {
+    println!("{} dfrs explain {code} {}", "Use".bright_black(), "for more information".bright_black());
+}
+
+fn recursion_message(name: String, through: Option<String>) -> String {
+    match through {
+        Some(through) => format!("Function '{name}' calls itself through '{through}'"),
+        None => format!("Function '{name}' calls itself")
+    }
+}
+
+fn tag_option_message(tag_name: String, provided: String, options: Vec<String>, suggestion: Option<String>) -> String {
+    match suggestion {
+        Some(suggestion) => format!("Invalid option '{provided}' for tag '{tag_name}', did you mean '{suggestion}'?"),
+        None => format!("Invalid option '{}' for tag '{}', expected one of {:?}", provided, tag_name, options)
+    }
+}
+
+fn unknown_action_message(name: String, suggestions: Vec<ActionSuggestion>) -> String {
+    if suggestions.is_empty() {
+        return format!("Unknown action '{}'", name)
+    }
+    let suggestions: Vec<String> = suggestions.into_iter()
+        .map(|suggestion| format!("'{}' ({})", suggestion.name, suggestion.description))
+        .collect();
+    format!("Unknown action '{}', did you mean {}?", name, suggestions.join(" or "))
+}
+
+fn unknown_variable_message(found: String, suggestions: Vec<String>) -> String {
+    if suggestions.is_empty() {
+        return format!("Unknown variable '{}'", found)
+    }
+    let suggestions: Vec<String> = suggestions.into_iter().map(|suggestion| format!("'{suggestion}'")).collect();
+    format!("Unknown variable '{}', did you mean {}?", found, suggestions.join(" or "))
+}
+
+fn unknown_function_message(name: String, left_out_of: Option<String>) -> String {
+    match left_out_of {
+        Some(path) => format!("Function '{name}' is not imported, add it to the import list of '{path}'"),
+        None => format!("Unknown function '{name}', it is not defined in this file or imported")
+    }
+}
+
+fn event_kind(node: &EventNode) -> &'static str {
+    match node.event_type {
+        Some(ActionType::Player) => "player event",
+        Some(ActionType::Entity) => "entity event",
+        _ => "event"
+    }
+}
+
+// Chooses which files of a directory get compiled, by their file name
+struct FileFilter {
+    include: Vec<String>,
+    exclude: Vec<String>
+}
+
+impl FileFilter {
+    fn matches(&self, file: &Path) -> bool {
+        let name = file.file_name().unwrap_or_default().to_string_lossy();
+        (self.include.is_empty() || self.include.iter().any(|pattern| glob_match(pattern, &name)))
+            && !self.exclude.iter().any(|pattern| glob_match(pattern, &name))
+    }
+}
+
+// `*` matches any number of characters and `?` a single one
+fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|char| *char == '*')
+}
+
+enum CompileOutput {
+    Send { connection: Option<Connection> },
+    Pack { pack: Pack },
+    Preview,
+    Give
+}
+
+fn compile_path(path: &PathBuf, verbose: bool, filter: &FileFilter, definitions: &Arc<Definitions>, output: &mut CompileOutput, mut stats: Option<&mut Stats>) -> bool {
+    let root = if path.is_dir() { path.as_path() } else { path.parent().unwrap_or(Path::new("")) };
+    let mut compile_file = |file: &PathBuf, globals: &[VariableNode]| -> bool {
+        match compile_cmd(file, verbose, definitions, globals, stats.as_deref_mut()) {
+            Ok((compiled, config)) => {
+                match output {
+                    CompileOutput::Send { connection } => send_cmd(file, compiled, config, connection),
+                    CompileOutput::Pack { pack } => pack.add(file.strip_prefix(root).unwrap_or(file), &fs::read_to_string(file).unwrap_or_default(), compiled),
+                    CompileOutput::Preview => print!("{}", preview(&compiled, &definitions.action_dump)),
+                    CompileOutput::Give => {
+                        for line in &compiled {
+                            println!("{}", give_command(line, &config.sending));
+                        }
+                    }
+                }
+                true
+            }
+            Err(_) => false
+        }
+    };
+
+    if path.is_dir() {
+        let paths = fs::read_dir(path).unwrap();
+        let globals = project_globals(path);
+
+        println!("{} {}", "Compiling project".bright_black(), path.file_name().unwrap_or(path.as_os_str()).to_string_lossy());
+        let mut succeeded = 0;
+        let mut failed = 0;
+        let mut skipped = 0;
+        for path in paths {
+            let file = path.unwrap().path();
+            if file.is_file() && file.extension().is_some_and(|extension| extension == "dfrs") {
+                if !filter.matches(&file) {
+                    if verbose {
+                        println!("{} {}", "Skipping".bright_black(), file.file_name().unwrap().to_string_lossy());
+                    }
+                    skipped += 1;
+                } else if compile_file(&file, &globals) {
+                    succeeded += 1;
+                } else {
+                    failed += 1;
+                }
             }
-            arrows = "^".repeat(cmp::max(end_pos.col - start_pos.col, 1) as usize).bright_blue();
         }
-        None => {
-            arrows = "^".bright_blue();
+        if failed > 0 {
+            println!("{} {} {}", format!("Compiled {succeeded} files,").bright_black(), failed, "failed".bright_red());
+        } else {
+            println!("{}", format!("Compiled {succeeded} files").green());
+        }
+        if skipped > 0 {
+            println!("{}", format!("Skipped {skipped} files").bright_black());
         }
+        failed == 0
+    } else {
+        compile_file(path, &project_globals(path.parent().unwrap_or(Path::new(""))))
     }
-    println!("{} {} {}{}", " ".repeat(ln_length), "|".bright_black(), " ".repeat((start_pos.col - 1) as usize), arrows);
 }
 
-fn compile_cmd(file: &PathBuf) {
+fn modified_times(path: &PathBuf) -> Vec<(PathBuf, Option<SystemTime>)> {
+    let files = if path.is_dir() {
+        fs::read_dir(path).map(|entries| entries.flatten().map(|entry| entry.path()).collect()).unwrap_or_default()
+    } else {
+        vec![path.clone()]
+    };
+    let mut times: Vec<(PathBuf, Option<SystemTime>)> = files.into_iter()
+        .filter(|file| file.extension().is_some_and(|extension| extension == "dfrs" || extension == "toml"))
+        .map(|file| {
+            let modified = fs::metadata(&file).and_then(|metadata| metadata.modified()).ok();
+            (file, modified)
+        })
+        .collect();
+    times.sort();
+    times
+}
+
+fn compile_cmd(file: &PathBuf, verbose: bool, definitions: &Arc<Definitions>, globals: &[VariableNode], stats: Option<&mut Stats>) -> Result<(Vec<CompiledLine>, Config), CompileFailedError> {
     println!("{} {}", "Compiling".bright_black(), file.file_name().unwrap().to_string_lossy());
     let mut config_file = file.clone();
     config_file.set_file_name("dfrs.toml");
@@ -78,26 +310,62 @@ fn compile_cmd(file: &PathBuf) {
         Err(_) => {
             println!("{} No config file found", "Error:".bright_red());
             println!("{} dfrs init <path> {}", "Use".bright_black(), "to create a new config file".bright_black());
-            return;
+            return Err(CompileFailedError {});
         }
     };
+    logger::set_level(config.logging.level);
 
+    if verbose {
+        let dump = RawActionDump::load();
+        println!("{} {}", "Config".bright_black(), config_file.to_string_lossy());
+        print!("{}", toml::to_string(&config).expect("Failed to serialize config"));
+        println!("{} {} ({} actions, {} game values)", "Action dump".bright_black(), RawActionDump::hash(), dump.actions.len(), dump.game_values.len());
+    }
+
+    let compiler = file_compiler(file, config, definitions, globals);
     let data = std::fs::read_to_string(file).expect("could not open file");
+    let compiled = compile_source(data, file, &compiler, stats)?;
+    println!("{}  {}", "Compiled".green(), file.file_name().unwrap().to_string_lossy());
+    if compiler.config().debug.source_map {
+        let mut map_file = file.clone().into_os_string();
+        map_file.push(".map.json");
+        let maps: Vec<serde_json::Value> = compiled.iter().map(|line| serde_json::json!({ "name": line.name, "blocks": line.source_map })).collect();
+        if let Err(err) = fs::write(&map_file, serde_json::to_string_pretty(&maps).unwrap()) {
+            println!("{} Failed to write source map: {}", "Error:".bright_red(), err);
+        }
+    }
+    Ok((compiled, compiler.into_config()))
+}
 
-    let mut lexer = Lexer::new(data.clone());
-    let result = lexer.run();
+// The compiler for a file of a project, which knows the global variables of the other files and the plot
+fn file_compiler(file: &Path, config: Config, definitions: &Arc<Definitions>, globals: &[VariableNode]) -> Compiler {
+    let variables = plot_variables(file, &config);
+    if variables.is_none() && config.validation.plot_variables {
+        logger::warn(format!("No plot variables found, run dfrs scan-variables to save them in {PLOT_VARIABLES_FILE}"));
+    }
+    Compiler::new(config, definitions.clone()).with_globals(globals.to_vec()).with_plot_variables(variables)
+}
 
-    let res = match result {
-        Ok(res) => {
-            if config.debug.tokens {
-                for token in &res {
-                    println!("{:?}", token);
-                }
-                println!("\n");
-            }
-            res
-        }
+fn compile_source(data: String, file: &Path, compiler: &Compiler, stats: Option<&mut Stats>) -> Result<Vec<CompiledLine>, CompileFailedError> {
+    let (validated, _) = check_source(data, file, compiler)?;
+    if let Some(stats) = stats {
+        stats.add(&validated);
+    }
+    match compiler.compile_node(validated) {
+        Ok(compiled) => Ok(compiled),
         Err(err) => {
+            println!("{} {}", "Error:".bright_red(), err);
+            Err(CompileFailedError {})
+        }
+    }
+}
+
+// Runs everything before compiling and prints the errors and warnings, returning how many warnings there were
+fn check_source(data: String, file: &Path, compiler: &Compiler) -> Result<(FileNode, usize), CompileFailedError> {
+    TAB_WIDTH.store(compiler.config().tab_width, Ordering::Relaxed);
+    let (validated, warnings) = match compiler.check(&data, file) {
+        Ok(res) => res,
+        Err(CompilerError::Lexer(err)) => {
             match err {
                 LexerError::InvalidNumber { pos } => {
                     print_err(format!("Invalid number in line {pos}"), data, pos, None);
@@ -115,77 +383,10 @@ fn compile_cmd(file: &PathBuf) {
                     print_err(format!("Unterminated variable in line {pos}"), data, pos, None);
                 }
             }
-            std::process::exit(0);
-        }
-    };
-
-    let mut parser = Parser::new(res);
-    let res = parser.run();
-    let node;
-    match res {
-        Ok(res) => {
-            if config.debug.nodes {
-                for event in &res.events {
-                    println!("{}", event.event);
-                    for expression in &event.expressions {
-                        match &expression.node {
-                            node::Expression::Action { node } => {
-                                println!("{:?} {:?} {:?} {:?}", node.action_type, node.selector, node.name, node.args)
-                            } 
-                            node::Expression::Conditional { node } => {
-                                println!("{:?} {:?} {:?} {:?}", node.conditional_type, node.selector, node.name, node.args)
-                            },
-                            node::Expression::Call { node } => {
-                                println!("{:?} {:?}", node.name, node.args)
-                            }
-                            node::Expression::Start { node } => {
-                                println!("{:?} {:?}", node.name, node.args)
-                            }
-                            node::Expression::Repeat { node } => {
-                                println!("{:?} {:?}", node.name, node.args)
-                            },
-                            node::Expression::Variable { node } => {
-                                println!("{:?} {:?} {:?}", node.var_type, node.dfrs_name, node.df_name)
-                            },
-                            
-                        }
-                    }
-                }
-                println!("\n");
-                for function in &res.functions {
-                    println!("{} / {}", function.dfrs_name, function.df_name);
-                    for param in &function.params {
-                        println!("{:?}", param);
-                    }
-                    for expression in &function.expressions {
-                        match &expression.node {
-                            node::Expression::Action { node } => {
-                                println!("{:?} {:?} {:?} {:?}", node.action_type, node.selector, node.name, node.args)
-                            }
-                            node::Expression::Conditional { node } => {
-                                println!("{:?} {:?} {:?} {:?}", node.conditional_type, node.selector, node.name, node.args)
-                            }
-                            node::Expression::Call { node } => {
-                                println!("{:?} {:?}", node.name, node.args)
-                            }
-                            node::Expression::Start { node } => {
-                                println!("{:?} {:?}", node.name, node.args)
-                            }
-                            node::Expression::Repeat { node } => {
-                                println!("{:?} {:?}", node.name, node.args)
-                            },
-                            node::Expression::Variable { node } => {
-                                println!("{:?} {:?} {:?}", node.var_type, node.dfrs_name, node.df_name)
-                            },
-                            
-                        }
-                    }
-                }
-                println!("\n");
-            }
-            node = res;
+            return Err(CompileFailedError {});
         }
-        Err(err) => {
+        Err(CompilerError::Parse(err)) => {
+            let code = err.code();
             match err {
                 ParseError::InvalidToken { found,expected} => {
                     if found.is_some() {
@@ -230,8 +431,8 @@ fn compile_cmd(file: &PathBuf) {
                 ParseError::InvalidItem { pos, msg } => {
                     print_err(format!("Invalid Item: {}", msg), data, pos, None)
                 }
-                ParseError::UnknownVariable { found, start_pos, end_pos } => {
-                    print_err(format!("Unknown variable: {}", found), data, start_pos, Some(end_pos))
+                ParseError::UnknownVariable { found, suggestions, start_pos, end_pos } => {
+                    print_err(unknown_variable_message(found, suggestions), data, start_pos, Some(end_pos))
                 }
                 ParseError::InvalidType { found, start_pos } => {
                     match found {
@@ -239,21 +440,61 @@ fn compile_cmd(file: &PathBuf) {
                         None => print_err("Missing type".into(), data, start_pos, None)
                     }
                 },
+                ParseError::MissingBody { name, start_pos, end_pos } => {
+                    print_err(format!("Missing body for {name}, expected '{{'"), data, start_pos, Some(end_pos))
+                }
+                ParseError::MultipleSelectors { start_pos, end_pos } => {
+                    print_err("Actions can only target one selector, use game values like $selection:location to reference other targets".into(), data, start_pos, Some(end_pos))
+                }
+                ParseError::DivisionByZero { start_pos, end_pos } => {
+                    print_err("Division by zero".into(), data, start_pos, Some(end_pos))
+                }
+                ParseError::UnknownSetting { found, start_pos, end_pos } => {
+                    print_err(format!("Unknown setting '#{found}', available: #hidden"), data, start_pos, Some(end_pos))
+                }
+                ParseError::UnknownEventPrefix { found, start_pos, end_pos } => {
+                    print_err(format!("Unknown event prefix '{found}', expected 'player' or 'entity'"), data, start_pos, Some(end_pos))
+                }
+                ParseError::MissingSemicolon { pos } => {
+                    print_err("Missing semicolon".into(), data, pos, None)
+                }
+                ParseError::TooDeep { max_depth, pos } => {
+                    print_err(format!("Expansion too deep, code can only be nested {max_depth} levels deep"), data, pos, None)
+                }
+                ParseError::UnknownTimeUnit { found, start_pos, end_pos } => {
+                    print_err(format!("Unknown time unit '{found}', expected ticks, seconds or minutes"), data, start_pos, Some(end_pos))
+                }
+                ParseError::ActionInRepeatCondition { start_pos, end_pos } => {
+                    print_err("Actions can't be used in a repeat condition, they would only run once before the loop".into(), data, start_pos, Some(end_pos))
+                }
             }
-            std::process::exit(0);
+            print_explain_hint(code);
+            return Err(CompileFailedError {});
         }
-    }
-
-    let validated;
-    match Validator::new().validate(node) {
-        Ok(res) => validated = res,
-        Err(err)  => {
+        Err(CompilerError::Import(err)) => {
+            let code = err.code();
+            match err {
+                ImportError::FileNotFound { path, start_pos, end_pos } => {
+                    print_err(format!("Imported file '{path}' not found"), data, start_pos, Some(end_pos));
+                }
+                ImportError::InvalidFile { path, start_pos, end_pos } => {
+                    print_err(format!("Failed to compile imported file '{path}', compile it directly to see the errors"), data, start_pos, Some(end_pos));
+                }
+                ImportError::UnknownFunction { name, path, start_pos, end_pos } => {
+                    print_err(format!("Function '{name}' not found in '{path}'"), data, start_pos, Some(end_pos));
+                }
+            }
+            print_explain_hint(code);
+            return Err(CompileFailedError {});
+        }
+        Err(CompilerError::Validate(err)) => {
+            let code = err.code();
             match err {
                 ValidateError::UnknownEvent { node } => {
-                    print_err(format!("Unknown event '{}'", node.event), data, node.start_pos, Some(node.name_end_pos));
+                    print_err(format!("Unknown {} '{}'", event_kind(&node), node.event), data, node.start_pos, Some(node.name_end_pos));
                 }
-                ValidateError::UnknownAction { name, start_pos, end_pos } => {
-                    print_err(format!("Unknown action '{}'", name), data, start_pos, Some(end_pos));
+                ValidateError::UnknownAction { name, suggestions, start_pos, end_pos } => {
+                    print_err(unknown_action_message(name, suggestions), data, start_pos, Some(end_pos));
                 }
                 ValidateError::MissingArgument { name, start_pos, end_pos } => {
                     print_err(format!("Missing argument '{}'", name), data, start_pos, Some(end_pos));
@@ -264,8 +505,8 @@ fn compile_cmd(file: &PathBuf) {
                 ValidateError::TooManyArguments { start_pos, end_pos, name } => {
                     print_err(format!("Too many arguments for action '{}'", name), data, start_pos, Some(end_pos));
                 }
-                ValidateError::InvalidTagOption { tag_name, provided, options, start_pos, end_pos } => {
-                    print_err(format!("Invalid option '{}' for tag '{}', expected one of {:?}", provided, tag_name, options), data, start_pos, Some(end_pos));
+                ValidateError::InvalidTagOption { tag_name, provided, options, suggestion, start_pos, end_pos } => {
+                    print_err(tag_option_message(tag_name, provided, options, suggestion), data, start_pos, Some(end_pos));
                 }
                 ValidateError::UnknownTag { tag_name, available, start_pos, end_pos } => {
                     print_err(format!("Unknown tag '{}', found tags: {:?}", tag_name, available), data, start_pos, Some(end_pos));
@@ -273,14 +514,209 @@ fn compile_cmd(file: &PathBuf) {
                 ValidateError::UnknownGameValue { game_value, start_pos, end_pos} => {
                     print_err(format!("Unknown game_value '{game_value}'"), data, start_pos, Some(end_pos));
                 }
+                ValidateError::RecursiveFunction { name, through, start_pos, end_pos } => {
+                    print_err(recursion_message(name, through), data, start_pos, Some(end_pos));
+                }
+                ValidateError::InvalidSoundVariant { sound, variant, start_pos, end_pos } => {
+                    print_err(format!("Invalid variant '{variant}', sound '{sound}' has no variants"), data, start_pos, Some(end_pos));
+                }
+                ValidateError::MissingTargetVariable { name, start_pos, end_pos } => {
+                    print_err(format!("Expected a variable as first argument for '{name}'"), data, start_pos, Some(end_pos));
+                }
+                ValidateError::UnexpectedGameValueSelector { game_value, start_pos, end_pos } => {
+                    print_err(format!("Game value '{game_value}' does not take a selector"), data, start_pos, Some(end_pos));
+                }
+                ValidateError::DuplicateDefinition { kind, name, start_pos, end_pos, previous_start_pos, previous_end_pos } => {
+                    print_err(format!("Duplicate {kind} '{name}'"), data.clone(), start_pos, Some(end_pos));
+                    print_message("Note:".bright_blue(), format!("'{name}' is first defined here"), data, previous_start_pos, Some(previous_end_pos));
+                }
+                ValidateError::UnknownValue { kind, name, start_pos, end_pos } => {
+                    print_err(format!("Unknown {kind} '{name}'"), data, start_pos, Some(end_pos));
+                }
+                ValidateError::OutOfRange { name, value, min, max, start_pos, end_pos } => {
+                    print_err(format!("{value} is out of range for '{name}', expected a number from {min} to {max}"), data, start_pos, Some(end_pos));
+                }
+                ValidateError::ReadOnlyGameValue { game_value, name, start_pos, end_pos } => {
+                    print_err(format!("Game value '{game_value}' is read-only, '{name}' needs a variable"), data, start_pos, Some(end_pos));
+                }
+                ValidateError::UnsupportedConditionType { action, found, allowed, start_pos, end_pos } => {
+                    print_err(format!("'{action}' does not take '{found}' conditions, expected one of: {}", allowed.join(", ")), data, start_pos, Some(end_pos));
+                }
+                ValidateError::UnexpectedCondition { action, start_pos, end_pos } => {
+                    print_err(format!("'{action}' does not take a condition"), data, start_pos, Some(end_pos));
+                }
+                ValidateError::UnexpectedSelector { kind, start_pos, end_pos } => {
+                    print_err(format!("Selectors can not be used on {kind}"), data, start_pos, Some(end_pos));
+                }
+                ValidateError::InvalidDefault { name, expected, found, start_pos, end_pos } => {
+                    print_err(format!("Default value of '{name}' should be '{expected:?}' but is '{found:?}'"), data, start_pos, Some(end_pos));
+                }
+                ValidateError::NullArgument { name, start_pos, end_pos } => {
+                    print_err(format!("Argument '{name}' is required, got null"), data, start_pos, Some(end_pos));
+                }
+                ValidateError::UnknownFunction { name, left_out_of, start_pos, end_pos } => {
+                    print_err(unknown_function_message(name, left_out_of), data, start_pos, Some(end_pos));
+                }
+            }
+            print_explain_hint(code);
+            return Err(CompileFailedError {});
+        }
+        Err(CompilerError::Compile(err)) => {
+            println!("{} {}", "Error:".bright_red(), err);
+            return Err(CompileFailedError {});
+        }
+    };
+
+    let warning_count = warnings.len();
+    for warning in warnings {
+        match warning {
+            ValidateWarning::RecursiveFunction { name, through, start_pos, end_pos } => {
+                print_warn(recursion_message(name, through), data.clone(), start_pos, Some(end_pos));
+            }
+            ValidateWarning::RedundantInversion { start_pos, end_pos } => {
+                print_warn("Redundant double negation, use a single '!' or none".into(), data.clone(), start_pos, Some(end_pos));
+            }
+            ValidateWarning::ItemCountExceedsStack { count, max, start_pos, end_pos } => {
+                print_warn(format!("Item count {count} is larger than the maximum stack size of {max}"), data.clone(), start_pos, Some(end_pos));
+            }
+            ValidateWarning::UnexpectedProcessArguments { name, start_pos, end_pos } => {
+                print_warn(format!("Process '{name}' takes no arguments, they will be ignored"), data.clone(), start_pos, Some(end_pos));
+            }
+            ValidateWarning::IdenticalBranches { start_pos, end_pos } => {
+                print_warn("Both branches of this conditional are identical, so the condition has no effect".into(), data.clone(), start_pos, Some(end_pos));
+            }
+            ValidateWarning::TooManySlots { name, count, max, start_pos, end_pos } => {
+                print_warn(format!("'{name}' needs {count} slots for its arguments and tags, but a block only has {max}"), data.clone(), start_pos, Some(end_pos));
+            }
+            ValidateWarning::UnsupportedParticleField { particle, field, start_pos, end_pos } => {
+                print_warn(format!("'{particle}' particles do not use '{field}', it will be ignored"), data.clone(), start_pos, Some(end_pos));
+            }
+            ValidateWarning::MotionVariationWithoutMotion { start_pos, end_pos } => {
+                print_warn("'motionVariation' has no effect without 'motion'".into(), data.clone(), start_pos, Some(end_pos));
+            }
+            ValidateWarning::UnassignedVariable { name, scope, start_pos, end_pos } => {
+                print_warn(format!("{scope} variable '{name}' is read before it is assigned"), data.clone(), start_pos, Some(end_pos));
+            }
+            ValidateWarning::UnknownPlotVariable { name, start_pos, end_pos } => {
+                print_warn(format!("Variable '{name}' does not exist on the plot"), data.clone(), start_pos, Some(end_pos));
+            }
+            ValidateWarning::Deprecated { kind, name, note, start_pos, end_pos } => {
+                print_warn(format!("{kind} '{name}' is deprecated: {note}"), data.clone(), start_pos, Some(end_pos));
             }
-            std::process::exit(0);
         }
     }
 
-    let compiled = compile(validated, config.debug.compile);
-    println!("{}  {}", "Compiled".green(), file.file_name().unwrap().to_string_lossy());
-    send(compiled, config);
+    Ok((validated, warning_count))
+}
+
+fn lint_path(path: &Path, definitions: &Arc<Definitions>) -> usize {
+    let (files, globals) = if path.is_dir() {
+        let files = fs::read_dir(path).map(|entries| entries.flatten().map(|entry| entry.path()).collect()).unwrap_or_default();
+        (files, project_globals(path))
+    } else {
+        (vec![path.to_path_buf()], project_globals(path.parent().unwrap_or(Path::new(""))))
+    };
+
+    let mut findings = 0;
+    for file in files.into_iter().filter(|file| file.is_file() && file.extension().is_some_and(|extension| extension == "dfrs")) {
+        println!("{} {}", "Linting".bright_black(), file.file_name().unwrap().to_string_lossy());
+        let mut config_file = file.clone();
+        config_file.set_file_name("dfrs.toml");
+        let config = load_config(&config_file).unwrap_or_default();
+        logger::set_level(config.logging.level);
+
+        let compiler = file_compiler(&file, config, definitions, &globals);
+        let data = fs::read_to_string(&file).expect("could not open file");
+        findings += match check_source(data, &file, &compiler) {
+            Ok((_, warnings)) => warnings,
+            Err(_) => 1
+        };
+    }
+    findings
+}
+
+// Compiles every file and compares it with its `#expect` comments, returning how many passed and failed
+fn test_path(path: &Path, definitions: &Arc<Definitions>) -> (usize, usize) {
+    let (files, globals) = if path.is_dir() {
+        let files = fs::read_dir(path).map(|entries| entries.flatten().map(|entry| entry.path()).collect()).unwrap_or_default();
+        (files, project_globals(path))
+    } else {
+        (vec![path.to_path_buf()], project_globals(path.parent().unwrap_or(Path::new(""))))
+    };
+
+    let mut passed = 0;
+    let mut failed = 0;
+    for file in files.into_iter().filter(|file| file.is_file() && file.extension().is_some_and(|extension| extension == "dfrs")) {
+        let data = fs::read_to_string(&file).expect("could not open file");
+        let expected = expectations(&data);
+        if expected.is_empty() {
+            continue;
+        }
+        println!("{} {}", "Testing".bright_black(), file.file_name().unwrap().to_string_lossy());
+        let mut config_file = file.clone();
+        config_file.set_file_name("dfrs.toml");
+        let config = load_config(&config_file).unwrap_or_default();
+        logger::set_level(config.logging.level);
+
+        let compiler = file_compiler(&file, config, definitions, &globals);
+        let compiled = match compile_source(data, &file, &compiler, None) {
+            Ok(compiled) => compiled,
+            Err(_) => {
+                failed += expected.len();
+                continue;
+            }
+        };
+        for expectation in expected {
+            match check(&expectation, &compiled) {
+                Ok(()) => passed += 1,
+                Err(err) => {
+                    failed += 1;
+                    println!("{} {} {}", "Failed:".bright_red(), expectation.codeline(), format!("(line {})", expectation.line).bright_black());
+                    match err {
+                        ExpectationError::MissingCodeline => println!("  No codeline starts with '{}'", expectation.codeline()),
+                        ExpectationError::WrongBlocks { found } => {
+                            println!("  {} {}", "expected".bright_black(), expectation.blocks.join(" "));
+                            println!("  {}    {}", "found".bright_black(), found.join(" "));
+                        }
+                    }
+                }
+            }
+        }
+    }
+    (passed, failed)
+}
+
+const PLOT_VARIABLES_FILE: &str = ".dfrs-plot-variables.json";
+
+// The variables saved by `dfrs scan-variables` next to the file, if checking them is enabled
+fn plot_variables(file: &Path, config: &Config) -> Option<HashSet<String>> {
+    if !config.validation.plot_variables {
+        return None
+    }
+    let mut variables_file = file.to_path_buf();
+    variables_file.set_file_name(PLOT_VARIABLES_FILE);
+    load_plot_variables(&variables_file)
+}
+
+fn send_cmd(file: &Path, mut compiled: Vec<CompiledLine>, config: Config, connection: &mut Option<Connection>) {
+    let mut cache_file = file.to_path_buf();
+    cache_file.set_file_name(".dfrs-sent.json");
+    if config.sending.only_changed {
+        let total = compiled.len();
+        compiled = filter_changed(compiled, &cache_file);
+        if compiled.len() < total {
+            println!("{} {} {}", "Skipping".bright_black(), total - compiled.len(), "unchanged codelines".bright_black());
+        }
+        if compiled.is_empty() {
+            return;
+        }
+    }
+
+    // Codelines that didn't reach the client are sent again next time
+    let only_changed = config.sending.only_changed;
+    if send(compiled.clone(), config, connection) && only_changed {
+        save_sent(&compiled, &cache_file);
+    }
 }
 
 #[derive(clap::Parser)]
@@ -294,42 +730,144 @@ struct Cli {
 enum Commands {
     Compile {
         path: PathBuf,
+        #[arg(long)]
+        verbose: bool,
+        #[arg(long)]
+        watch: bool,
+        #[arg(long)]
+        pack: Option<String>,
+        #[arg(long)]
+        preview: bool,
+        #[arg(long)]
+        give: bool,
+        #[arg(long)]
+        include: Vec<String>,
+        #[arg(long)]
+        exclude: Vec<String>,
+        #[arg(long)]
+        stats: bool
+    },
+    Install {
+        pack: PathBuf
     },
     Init {
         path: PathBuf,
     },
     Decompile {
         code: String,
-        file: Option<PathBuf>
+        file: Option<PathBuf>,
+        #[arg(long)]
+        pretty: bool
     },
     DecompilePlot {
-        file: Option<PathBuf>
+        file: Option<PathBuf>,
+        #[arg(long)]
+        url: Option<String>,
+        #[arg(long)]
+        pretty: bool
+    },
+    Explain {
+        code: String
+    },
+    Lint {
+        path: PathBuf
     },
-    LSP {}
+    Test {
+        path: PathBuf
+    },
+    ScanVariables {
+        #[arg(long)]
+        url: Option<String>
+    },
+    Features {},
+    LSP {},
+    Repl {}
 }
 
 fn main() {
     let cli = Cli::parse();
 
     match &cli.command {
-        Some(Commands::Compile { path }) => {
+        Some(Commands::Compile { path, verbose, watch, pack: pack_name, preview, give, include, exclude, stats: show_stats }) => {
             if !path.exists() {
                 println!("{} File not found", "Error:".bright_red());
                 return;
             }
-            if path.is_dir() {
-                let paths = fs::read_dir(path).unwrap();
+            // A pack is saved once after compiling, recompiling into it while watching would only add duplicates
+            if pack_name.is_some() && (*watch || *preview || *give) {
+                println!("{} --pack can't be combined with --watch, --preview or --give", "Error:".bright_red());
+                std::process::exit(1);
+            }
+            if *preview && *give {
+                println!("{} --preview can't be combined with --give", "Error:".bright_red());
+                std::process::exit(1);
+            }
+            let definitions = Arc::new(Definitions::load());
+            let mut output = match pack_name {
+                Some(name) => CompileOutput::Pack { pack: Pack::new(name) },
+                None if *preview => CompileOutput::Preview,
+                None if *give => CompileOutput::Give,
+                None => CompileOutput::Send { connection: None }
+            };
+            let filter = FileFilter { include: include.clone(), exclude: exclude.clone() };
+            let mut stats = show_stats.then(Stats::default);
+            let succeeded = compile_path(path, *verbose, &filter, &definitions, &mut output, stats.as_mut());
+            if let Some(stats) = &stats {
+                print!("{stats}");
+            }
 
-                println!("{} {}", "Compiling project".bright_black(), path.file_name().unwrap().to_string_lossy());
-                for path in paths {
-                    let file = path.unwrap().path();
-                    if file.is_file() && file.extension().unwrap() == "dfrs" {
-                        compile_cmd(&file);
+            if let CompileOutput::Pack { pack } = &output {
+                if !succeeded {
+                    println!("{} Not creating pack because compiling failed", "Error:".bright_red());
+                    std::process::exit(1);
+                }
+                let file = PathBuf::from(format!("{}.dfpack", pack.name));
+                match pack.save(&file) {
+                    Ok(_) => println!("{} {} codelines into {}", "Packed".green(), pack.lines.len(), file.to_string_lossy()),
+                    Err(err) => {
+                        println!("{} Failed to save pack: {}", "Error:".bright_red(), err);
+                        std::process::exit(1);
                     }
                 }
-            } else {
-                compile_cmd(path);
             }
+
+            // When watching, failed files can still be fixed
+            if !succeeded && !*watch {
+                std::process::exit(1);
+            }
+
+            if *watch {
+                println!("{}", "Watching for changes".bright_black());
+                let mut modified = modified_times(path);
+                loop {
+                    thread::sleep(Duration::from_millis(500));
+                    let current = modified_times(path);
+                    if current != modified {
+                        modified = current;
+                        let mut stats = show_stats.then(Stats::default);
+                        compile_path(path, *verbose, &filter, &definitions, &mut output, stats.as_mut());
+                        if let Some(stats) = &stats {
+                            print!("{stats}");
+                        }
+                    }
+                }
+            }
+        }
+        Some(Commands::Install { pack }) => {
+            let pack = match Pack::load(pack) {
+                Ok(pack) => pack,
+                Err(err) => {
+                    println!("{} Failed to read pack: {}", "Error:".bright_red(), err);
+                    return;
+                }
+            };
+            if pack.dfrs_version != env!("CARGO_PKG_VERSION") {
+                println!("{} Pack was created with dfrs {}, this is {}", "Warning:".bright_yellow(), pack.dfrs_version, env!("CARGO_PKG_VERSION"));
+            }
+            let config = load_config(&PathBuf::from("dfrs.toml")).unwrap_or_default();
+            logger::set_level(config.logging.level);
+            println!("{} {} ({} codelines from {} files)", "Installing".bright_black(), pack.name, pack.lines.len(), pack.sources.len());
+            send(pack.lines, config, &mut None);
         }
         Some(Commands::Init { path }) => {
             if !path.exists() {
@@ -347,39 +885,261 @@ fn main() {
             new_config.save(&config_path);
             println!("{} {}", "Created new config".green(), config_path.to_string_lossy());
         }
-        Some(Commands::Decompile { code, file }) => {
-            let mut decompiler = Decompiler::new();
-            let result = decompiler.decompile(code);
+        Some(Commands::Decompile { code, file, pretty }) => {
+            let config = load_config(&PathBuf::from("dfrs.toml")).unwrap_or_default();
+            logger::set_level(config.logging.level);
+            let mut decompiler = Decompiler::new(*pretty, &config, Arc::new(Definitions::load()));
+            let result = match decompiler.decompile(code) {
+                Ok(result) => result,
+                Err(err) => {
+                    logger::error(err.to_string());
+                    std::process::exit(1);
+                }
+            };
+            for warning in decompiler.take_warnings() {
+                logger::warn(warning.to_string());
+            }
             if let Some(file) = file {
                 fs::write(file, result).expect("Failed to write file");
             } else {
                 println!("{}", result)
             }
         }
-        Some(Commands::DecompilePlot { file }) => {
-            let (mut socket, response) = connect(Url::parse("ws://localhost:31375").unwrap()).expect("Can't connect");
-            socket.send(Message::Text("scopes read_plot".into())).unwrap();
+        Some(Commands::DecompilePlot { file, url, pretty }) => {
+            let config = load_config(&PathBuf::from("dfrs.toml")).unwrap_or_default();
+            logger::set_level(config.logging.level);
+            let url = url.clone().unwrap_or(config.scanning.url.clone());
 
-            let msg = socket.read().expect("Error reading message");
-            socket.send(Message::Text("scan".into())).unwrap();
-            let msg = socket.read().expect("Error reading message");
+            let msg = match scan_plot(&url, &config.scanning, config.debug.connection) {
+                Ok(res) => res,
+                Err(err) => {
+                    print_scan_error(err);
+                    return;
+                }
+            };
 
+            // A codeline that can't be decompiled is reported and left out, so the rest of the plot is still decompiled
+            let lines: Vec<&str> = msg.split('\n').map(str::trim).filter(|line| !line.is_empty()).collect();
             let mut result = String::new();
-            for line in msg.to_text().unwrap().split('\n') {
-                let mut decompiler = Decompiler::new();
-                result.push_str(&decompiler.decompile(line));
+            let mut failed = 0;
+            let definitions = Arc::new(Definitions::load());
+            for (index, line) in lines.iter().enumerate() {
+                let number = index + 1;
+                if file.is_some() {
+                    println!("{} {}/{}", "Decompiling codeline".bright_black(), number, lines.len());
+                }
+                let mut decompiler = Decompiler::new(*pretty, &config, definitions.clone());
+                match decompiler.decompile(line) {
+                    Ok(line) => result.push_str(&line),
+                    Err(err) => {
+                        logger::error(format!("Codeline {number}: {err}"));
+                        failed += 1;
+                        continue;
+                    }
+                }
+                for warning in decompiler.take_warnings() {
+                    logger::warn(format!("Codeline {number}: {warning}"));
+                }
                 result.push_str("\n");
             }
 
             if let Some(file) = file {
                 fs::write(file, result).expect("Failed to write file");
+                let decompiled = lines.len() - failed;
+                if failed > 0 {
+                    println!("{} {} {}", format!("Decompiled {decompiled} codelines,").bright_black(), failed, "failed".bright_red());
+                } else {
+                    println!("{}", format!("Decompiled {decompiled} codelines").green());
+                }
             } else {
                 println!("{}", result)
             }
+            if failed > 0 {
+                std::process::exit(1);
+            }
+        }
+        Some(Commands::ScanVariables { url }) => {
+            let config = load_config(&PathBuf::from("dfrs.toml")).unwrap_or_default();
+            logger::set_level(config.logging.level);
+            let url = url.clone().unwrap_or(config.scanning.url.clone());
+
+            let msg = match scan_plot(&url, &config.scanning, config.debug.connection) {
+                Ok(res) => res,
+                Err(err) => {
+                    print_scan_error(err);
+                    return;
+                }
+            };
+            let variables = scan_variables(&msg);
+            match save_plot_variables(&variables, Path::new(PLOT_VARIABLES_FILE)) {
+                Ok(_) => println!("{} {} variables into {}", "Saved".green(), variables.len(), PLOT_VARIABLES_FILE),
+                Err(err) => println!("{} Failed to save plot variables: {}", "Error:".bright_red(), err)
+            }
+        }
+        Some(Commands::Lint { path }) => {
+            if !path.exists() {
+                println!("{} File not found", "Error:".bright_red());
+                return;
+            }
+            let definitions = Arc::new(Definitions::load());
+            match lint_path(path, &definitions) {
+                0 => println!("{}", "No problems found".green()),
+                findings => {
+                    let problems = if findings == 1 { "problem found" } else { "problems found" };
+                    println!("{} {}", findings, problems.bright_red());
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Commands::Test { path }) => {
+            if !path.exists() {
+                println!("{} File not found", "Error:".bright_red());
+                return;
+            }
+            let definitions = Arc::new(Definitions::load());
+            match test_path(path, &definitions) {
+                (0, 0) => println!("{}", "No expectations found".bright_black()),
+                (passed, 0) => println!("{}", format!("{passed} passed").green()),
+                (passed, failed) => {
+                    println!("{} {} {}", format!("{passed} passed,").bright_black(), failed, "failed".bright_red());
+                    std::process::exit(1);
+                }
+            }
+        }
+        Some(Commands::Explain { code }) => {
+            match explain(code) {
+                Some(explanation) => println!("{} {}", code.to_uppercase().bright_red(), explanation),
+                None => println!("{} Unknown error code '{code}'", "Error:".bright_red())
+            }
+        }
+        Some(Commands::Features {}) => {
+            let definitions = Definitions::load();
+            println!("{}", serde_json::to_string_pretty(&features(&definitions)).unwrap());
         }
         Some(Commands::LSP {}) => {
             run_lsp();
         }
+        Some(Commands::Repl {}) => {
+            run_repl(Arc::new(Definitions::load()));
+        }
         None => {}
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{compress, definitions, project};
+
+    fn no_filter() -> FileFilter {
+        FileFilter { include: vec![], exclude: vec![] }
+    }
+
+    // Compiles into a pack, which records the files that compiled
+    fn compiled_files(dir: &PathBuf, filter: &FileFilter) -> (bool, Vec<String>) {
+        let mut output = CompileOutput::Pack { pack: Pack::new("test") };
+        let succeeded = compile_path(dir, false, filter, &definitions(), &mut output, None);
+        let CompileOutput::Pack { pack } = output else { unreachable!() };
+        let mut files: Vec<String> = pack.sources.into_iter().map(|source| source.file).collect();
+        files.sort();
+        (succeeded, files)
+    }
+
+    #[test]
+    fn directory_continues_after_failing_file() {
+        let dir = project("continue", &[
+            ("a.dfrs", "@join {\n    p.sendMessage(\"A\");\n}"),
+            ("b.dfrs", "@join {\n    p.sendMessage(5);\n}"),
+            ("c.dfrs", "@leave {\n    p.sendMessage(\"C\");\n}")
+        ]);
+        assert_eq!(compiled_files(&dir, &no_filter()), (false, vec!["a.dfrs".into(), "c.dfrs".into()]));
+    }
+
+    fn snippet(i: usize) -> String {
+        format!("fn helper{i}(amount: number) {{\n    p.sendMessage(\"{i}\");\n}}\n@join {{\n    call(\"helper{i}\", {i});\n}}")
+    }
+
+    #[test]
+    fn many_compiles_share_definitions() {
+        let definitions = definitions();
+        let compiler = Compiler::new(Config::default(), definitions.clone());
+        let references = Arc::strong_count(&definitions);
+        for i in 0..100 {
+            let lines = compiler.compile(&snippet(i)).unwrap_or_else(|_| panic!("Snippet {i} failed"));
+            let names: Vec<&str> = lines.iter().map(|line| line.name.as_str()).collect();
+            assert_eq!(names, [format!("Function helper{i} helper{i}"), "Event Join".to_string()]);
+            assert!(lines[0].code.contains(&format!("\"name\":\"{i}\"")));
+        }
+        assert_eq!(Arc::strong_count(&definitions), references);
+    }
+
+    #[test]
+    fn compiles_share_definitions_across_threads() {
+        let compiler = Arc::new(Compiler::new(Config::default(), definitions()));
+        let handles: Vec<_> = (0..4).map(|thread| {
+            let compiler = compiler.clone();
+            std::thread::spawn(move || {
+                (0..25).all(|i| compiler.compile(&snippet(thread * 25 + i)).is_ok())
+            })
+        }).collect();
+        for handle in handles {
+            assert!(handle.join().unwrap());
+        }
+    }
+
+    #[test]
+    fn many_decompiles_share_definitions() {
+        let definitions = definitions();
+        let compiler = Compiler::new(Config::default(), definitions.clone());
+        for i in 0..50 {
+            let lines = compiler.compile(&snippet(i)).unwrap_or_else(|_| panic!("Snippet {i} failed"));
+            let mut decompiler = Decompiler::new(false, compiler.config(), definitions.clone());
+            let result = decompiler.decompile(&compress(&lines[0].code)).unwrap();
+            assert!(result.starts_with(&format!("fn helper{i}(amount: number) {{")), "{result}");
+        }
+    }
+
+    #[test]
+    fn directory_of_many_files() {
+        let files: Vec<(String, String)> = (0..30).map(|i| (format!("file{i:02}.dfrs"), snippet(i))).collect();
+        let files: Vec<(&str, &str)> = files.iter().map(|(file, source)| (file.as_str(), source.as_str())).collect();
+        let dir = project("many-files", &files);
+        let (succeeded, compiled) = compiled_files(&dir, &no_filter());
+        assert!(succeeded);
+        assert_eq!(compiled.len(), 30);
+    }
+
+    #[test]
+    fn glob_patterns() {
+        for (pattern, text) in [("*.dfrs", "a.dfrs"), ("wip_*", "wip_shop.dfrs"), ("?.dfrs", "a.dfrs"), ("*", ""), ("a*b*c", "aXbYbZc"), ("**.dfrs", "x.dfrs"), ("shop.dfrs", "shop.dfrs")] {
+            assert!(glob_match(pattern, text), "{pattern} should match {text}");
+        }
+        for (pattern, text) in [("*.dfrs", "a.dfrs.bak"), ("?.dfrs", "ab.dfrs"), ("wip_*", "shop.dfrs"), ("a*b*c", "aXbY"), ("", "a"), ("shop", "shop.dfrs")] {
+            assert!(!glob_match(pattern, text), "{pattern} should not match {text}");
+        }
+    }
+
+    #[test]
+    fn directory_with_exclude() {
+        let dir = project("exclude", &[
+            ("a.dfrs", "@join {\n    p.sendMessage(\"A\");\n}"),
+            ("wip_b.dfrs", "@join {\n    p.sendMessage(5);\n}"),
+            ("c.dfrs", "@leave {\n    p.sendMessage(\"C\");\n}")
+        ]);
+        let filter = FileFilter { include: vec![], exclude: vec!["wip_*".into()] };
+        assert_eq!(compiled_files(&dir, &filter), (true, vec!["a.dfrs".into(), "c.dfrs".into()]));
+    }
+
+    #[test]
+    fn directory_with_include_and_exclude() {
+        let dir = project("include", &[
+            ("shop.dfrs", "@join {\n    p.sendMessage(\"A\");\n}"),
+            ("shop_test.dfrs", "@join {\n    p.sendMessage(\"B\");\n}"),
+            ("lobby.dfrs", "@leave {\n    p.sendMessage(\"C\");\n}")
+        ]);
+        let filter = FileFilter { include: vec!["shop*".into()], exclude: vec![] };
+        assert_eq!(compiled_files(&dir, &filter).1, ["shop.dfrs", "shop_test.dfrs"]);
+        let filter = FileFilter { include: vec!["shop*".into(), "lobby.dfrs".into()], exclude: vec!["*_test.dfrs".into()] };
+        assert_eq!(compiled_files(&dir, &filter).1, ["lobby.dfrs", "shop.dfrs"]);
+    }
+}
@@ -1,6 +1,8 @@
 use serde::{Deserialize, Serialize};
 use crate::{definitions::{ArgType, DefinedTag}, token::{Position, Selector, Type}};
 
+pub const INFINITE_POTION_DURATION: f32 = 1000000.0;
+
 pub trait Node {
     fn json(&self) -> String;
 }
@@ -10,6 +12,22 @@ pub struct FileNode {
     pub events: Vec<EventNode>,
     pub functions: Vec<FunctionNode>,
     pub processes: Vec<ProcessNode>,
+    pub uses: Vec<UseNode>,
+    pub start_pos: Position,
+    pub end_pos: Position
+}
+
+#[derive(Clone, Debug)]
+pub struct UseNode {
+    pub path: String,
+    pub names: Option<Vec<ImportNode>>,
+    pub start_pos: Position,
+    pub end_pos: Position
+}
+
+#[derive(Clone, Debug)]
+pub struct ImportNode {
+    pub name: String,
     pub start_pos: Position,
     pub end_pos: Position
 }
@@ -30,8 +48,10 @@ pub struct FunctionNode {
     pub df_name: String,
     pub dfrs_name: String,
     pub params: Vec<FunctionParamNode>,
+    pub hidden: bool,
     pub expressions: Vec<ExpressionNode>,
     pub start_pos: Position,
+    pub name_start_pos: Position,
     pub name_end_pos: Position,
     pub end_pos: Position
 }
@@ -39,8 +59,10 @@ pub struct FunctionNode {
 #[derive(Clone, Debug)]
 pub struct ProcessNode {
     pub name: String,
+    pub hidden: bool,
     pub expressions: Vec<ExpressionNode>,
     pub start_pos: Position,
+    pub name_start_pos: Position,
     pub name_end_pos: Position,
     pub end_pos: Position
 }
@@ -51,7 +73,9 @@ pub struct FunctionParamNode {
     pub param_type: Type,
     pub optional: bool,
     pub multiple: bool,
-    pub default: Option<ArgValueWithPos>
+    pub default: Option<ArgValueWithPos>,
+    pub start_pos: Position,
+    pub end_pos: Position
 }
 
 #[derive(Clone, Debug)]
@@ -71,6 +95,38 @@ pub enum Expression {
     Repeat { node: RepeatNode }
 }
 
+// Structural equality, ignoring where in the source the nodes are
+impl ExpressionNode {
+    pub fn same_as(&self, other: &ExpressionNode) -> bool {
+        match (&self.node, &other.node) {
+            (Expression::Action { node: a }, Expression::Action { node: b }) => {
+                a.action_type == b.action_type && a.selector == b.selector && a.name == b.name && same_args(&a.args, &b.args)
+            }
+            (Expression::Conditional { node: a }, Expression::Conditional { node: b }) => {
+                a.conditional_type == b.conditional_type && a.selector == b.selector && a.name == b.name && a.inverted == b.inverted
+                    && same_args(&a.args, &b.args) && same_expressions(&a.expressions, &b.expressions) && same_expressions(&a.else_expressions, &b.else_expressions)
+            }
+            (Expression::Variable { node: a }, Expression::Variable { node: b }) => {
+                a.dfrs_name == b.dfrs_name && a.df_name == b.df_name && a.var_type == b.var_type
+            }
+            (Expression::Call { node: a }, Expression::Call { node: b }) => a.name == b.name && same_args(&a.args, &b.args),
+            (Expression::Start { node: a }, Expression::Start { node: b }) => a.name == b.name && same_args(&a.args, &b.args),
+            (Expression::Repeat { node: a }, Expression::Repeat { node: b }) => {
+                a.name == b.name && same_args(&a.args, &b.args) && same_expressions(&a.expressions, &b.expressions)
+            }
+            _ => false
+        }
+    }
+}
+
+pub fn same_expressions(a: &[ExpressionNode], b: &[ExpressionNode]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.same_as(b))
+}
+
+fn same_args(a: &[Arg], b: &[Arg]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).all(|(a, b)| a.index == b.index && a.arg_type == b.arg_type && a.value.same_as(&b.value))
+}
+
 #[derive(Clone, Debug)]
 pub struct ActionNode {
     pub action_type: ActionType,
@@ -95,7 +151,9 @@ pub struct ConditionalNode {
     pub end_pos: Position,
     pub expressions: Vec<ExpressionNode>,
     pub else_expressions: Vec<ExpressionNode>,
-    pub inverted: bool
+    pub inverted: bool,
+    pub inversion_start_pos: Option<Position>,
+    pub inversion_end_pos: Option<Position>
 }
 
 #[derive(Clone, Debug)]
@@ -103,6 +161,8 @@ pub struct CallNode {
     pub name: String,
     pub args: Vec<Arg>,
     pub start_pos: Position,
+    pub name_start_pos: Position,
+    pub name_end_pos: Position,
     pub end_pos: Position
 }
 
@@ -111,6 +171,8 @@ pub struct StartNode {
     pub name: String,
     pub args: Vec<Arg>,
     pub start_pos: Position,
+    pub name_start_pos: Position,
+    pub name_end_pos: Position,
     pub end_pos: Position
 }
 
@@ -138,6 +200,8 @@ pub struct VariableNode {
     pub df_name: String,
     pub var_type: VariableType,
     pub start_pos: Position,
+    pub name_start_pos: Position,
+    pub name_end_pos: Position,
     pub end_pos: Position
 }
 
@@ -150,7 +214,7 @@ pub enum ArgValue {
     Text { text: String },
     Location { x: f32, y: f32, z: f32, pitch: Option<f32>, yaw: Option<f32> },
     Vector { x: f32, y: f32, z: f32},
-    Sound { sound: String, volume: f32, pitch: f32 },
+    Sound { sound: String, variant: Option<String>, volume: f32, pitch: f32 },
     Potion { potion: String, amplifier: f32, duration: f32 },
     Particle { particle: String, cluster: ParticleCluster, data: ParticleData },
     Item { item: String },
@@ -160,14 +224,90 @@ pub enum ArgValue {
     Condition { name: String, args: Vec<Arg>, selector: Selector, conditional_type: ConditionalType, inverted: bool }
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+impl ArgValue {
+    pub fn kind(&self) -> ArgType {
+        match self {
+            ArgValue::Empty => ArgType::EMPTY,
+            ArgValue::Number { .. } => ArgType::NUMBER,
+            ArgValue::ComplexNumber { .. } => ArgType::NUMBER,
+            ArgValue::String { .. } => ArgType::STRING,
+            ArgValue::Text { .. } => ArgType::TEXT,
+            ArgValue::Location { .. } => ArgType::LOCATION,
+            ArgValue::Potion { .. } => ArgType::POTION,
+            ArgValue::Sound { .. } => ArgType::SOUND,
+            ArgValue::Particle { .. } => ArgType::PARTICLE,
+            ArgValue::Item { .. } => ArgType::ITEM,
+            ArgValue::Vector { .. } => ArgType::VECTOR,
+            ArgValue::Tag { .. } => ArgType::TAG,
+            ArgValue::Variable { .. } => ArgType::VARIABLE,
+            ArgValue::GameValue { .. } => ArgType::GameValue,
+            ArgValue::Condition { .. } => ArgType::CONDITION
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f32> {
+        match self {
+            ArgValue::Number { number } => Some(*number),
+            _ => None
+        }
+    }
+
+    // Strings and texts are both accepted wherever a plain name is expected
+    pub fn as_text(&self) -> Option<&str> {
+        match self {
+            ArgValue::String { string } => Some(string),
+            ArgValue::Text { text } => Some(text),
+            _ => None
+        }
+    }
+
+    // Values that are fully known when compiling, unlike variables, game values or math expressions
+    pub fn is_literal(&self) -> bool {
+        !matches!(self, ArgValue::Empty | ArgValue::ComplexNumber { .. } | ArgValue::Tag { .. } | ArgValue::Variable { .. } | ArgValue::GameValue { .. } | ArgValue::Condition { .. })
+    }
+
+    pub fn same_as(&self, other: &ArgValue) -> bool {
+        match (self, other) {
+            (ArgValue::Empty, ArgValue::Empty) => true,
+            (ArgValue::Number { number: a }, ArgValue::Number { number: b }) => a == b,
+            (ArgValue::ComplexNumber { number: a }, ArgValue::ComplexNumber { number: b }) => a == b,
+            (ArgValue::String { string: a }, ArgValue::String { string: b }) => a == b,
+            (ArgValue::Text { text: a }, ArgValue::Text { text: b }) => a == b,
+            (ArgValue::Location { x, y, z, pitch, yaw }, ArgValue::Location { x: x2, y: y2, z: z2, pitch: pitch2, yaw: yaw2 }) => {
+                x == x2 && y == y2 && z == z2 && pitch == pitch2 && yaw == yaw2
+            }
+            (ArgValue::Vector { x, y, z }, ArgValue::Vector { x: x2, y: y2, z: z2 }) => x == x2 && y == y2 && z == z2,
+            (ArgValue::Sound { sound, variant, volume, pitch }, ArgValue::Sound { sound: sound2, variant: variant2, volume: volume2, pitch: pitch2 }) => {
+                sound == sound2 && variant == variant2 && volume == volume2 && pitch == pitch2
+            }
+            (ArgValue::Potion { potion, amplifier, duration }, ArgValue::Potion { potion: potion2, amplifier: amplifier2, duration: duration2 }) => {
+                potion == potion2 && amplifier == amplifier2 && duration == duration2
+            }
+            (ArgValue::Particle { particle, cluster, data }, ArgValue::Particle { particle: particle2, cluster: cluster2, data: data2 }) => {
+                particle == particle2 && cluster == cluster2 && data == data2
+            }
+            (ArgValue::Item { item: a }, ArgValue::Item { item: b }) => a == b,
+            (ArgValue::Tag { tag, value, .. }, ArgValue::Tag { tag: tag2, value: value2, .. }) => tag == tag2 && value.same_as(value2),
+            (ArgValue::Variable { name, scope }, ArgValue::Variable { name: name2, scope: scope2 }) => name == name2 && scope == scope2,
+            (ArgValue::GameValue { df_name, dfrs_name, selector, .. }, ArgValue::GameValue { df_name: df_name2, dfrs_name: dfrs_name2, selector: selector2, .. }) => {
+                df_name == df_name2 && dfrs_name == dfrs_name2 && selector == selector2
+            }
+            (ArgValue::Condition { name, args, selector, conditional_type, inverted }, ArgValue::Condition { name: name2, args: args2, selector: selector2, conditional_type: conditional_type2, inverted: inverted2 }) => {
+                name == name2 && selector == selector2 && conditional_type == conditional_type2 && inverted == inverted2 && same_args(args, args2)
+            }
+            _ => false
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 pub struct ParticleCluster {
     pub amount: i32,
     pub horizontal: f32,
     pub vertical: f32
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
 #[serde(rename_all="camelCase")]
 pub struct ParticleData {
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -225,4 +365,50 @@ pub enum VariableType {
     Local,
     Game,
     Save
-}
\ No newline at end of file
+}
+
+impl VariableType {
+    // The scope DiamondFire uses for variables of this type
+    pub fn scope(&self) -> &'static str {
+        match self {
+            VariableType::Line => "line",
+            VariableType::Local => "local",
+            VariableType::Game => "unsaved",
+            VariableType::Save => "saved"
+        }
+    }
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::parse;
+
+    fn args(source: &str) -> Vec<ArgValue> {
+        let node = parse(&format!("@join {{\n    line x;\n    p.sendMessage({source});\n}}")).unwrap();
+        match &node.events[0].expressions[1].node {
+            Expression::Action { node } => node.args.iter().map(|arg| arg.value.clone()).collect(),
+            other => panic!("Expected an action, got {other:?}")
+        }
+    }
+
+    #[test]
+    fn parsed_kinds() {
+        let kinds: Vec<ArgType> = args("1, 'a', \"b\", x, Location(1, 2, 3), Vector(1, 2, 3), Item(\"stone\"), $default:currentHealth").iter().map(ArgValue::kind).collect();
+        assert_eq!(kinds, [ArgType::NUMBER, ArgType::STRING, ArgType::TEXT, ArgType::VARIABLE, ArgType::LOCATION, ArgType::VECTOR, ArgType::ITEM, ArgType::GameValue]);
+    }
+
+    #[test]
+    fn accessors() {
+        let values = args("2.5, 'a', \"b\", x");
+        assert_eq!(values.iter().map(ArgValue::as_number).collect::<Vec<_>>(), [Some(2.5), None, None, None]);
+        assert_eq!(values.iter().map(ArgValue::as_text).collect::<Vec<_>>(), [None, Some("a"), Some("b"), None]);
+        assert_eq!(values.iter().map(ArgValue::is_literal).collect::<Vec<_>>(), [true, true, true, false]);
+    }
+
+    #[test]
+    fn complex_numbers_are_not_literal() {
+        let number = ArgValue::ComplexNumber { number: "%math(1+1)".into() };
+        assert_eq!((number.kind(), number.as_number(), number.is_literal()), (ArgType::NUMBER, None, false));
+        assert!(!ArgValue::Empty.is_literal());
+    }
+}
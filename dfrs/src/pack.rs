@@ -0,0 +1,108 @@
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+
+use crate::compile::CompiledLine;
+use crate::utility::sha256;
+
+// A pack is a gzipped json file holding the compiled codelines and where they came from
+#[derive(Deserialize, Serialize)]
+pub struct Pack {
+    pub name: String,
+    pub dfrs_version: String,
+    pub sources: Vec<PackSource>,
+    pub lines: Vec<CompiledLine>
+}
+
+#[derive(Deserialize, Serialize)]
+pub struct PackSource {
+    pub file: String,
+    pub hash: String
+}
+
+#[derive(Debug)]
+pub enum PackError {
+    Io { err: std::io::Error },
+    InvalidPack { err: serde_json::Error }
+}
+
+impl std::fmt::Display for PackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PackError::Io { err } => write!(f, "{err}"),
+            PackError::InvalidPack { err } => write!(f, "Invalid pack: {err}")
+        }
+    }
+}
+
+impl Pack {
+    pub fn new(name: &str) -> Pack {
+        Pack { name: name.into(), dfrs_version: env!("CARGO_PKG_VERSION").into(), sources: vec![], lines: vec![] }
+    }
+
+    // The file is stored relative to the project, so files with the same name in different folders stay apart
+    pub fn add(&mut self, file: &Path, source: &str, mut lines: Vec<CompiledLine>) {
+        let file = file.components().map(|component| component.as_os_str().to_string_lossy()).collect::<Vec<_>>().join("/");
+        self.sources.push(PackSource { file, hash: sha256(source) });
+        self.lines.append(&mut lines);
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), PackError> {
+        let file = File::create(path).map_err(|err| PackError::Io { err })?;
+        let mut encoder = GzEncoder::new(file, Compression::default());
+        let data = serde_json::to_vec(self).map_err(|err| PackError::InvalidPack { err })?;
+        encoder.write_all(&data).map_err(|err| PackError::Io { err })?;
+        encoder.finish().map_err(|err| PackError::Io { err })?;
+        Ok(())
+    }
+
+    pub fn load(path: &Path) -> Result<Pack, PackError> {
+        let file = File::open(path).map_err(|err| PackError::Io { err })?;
+        let mut data = String::new();
+        GzDecoder::new(file).read_to_string(&mut data).map_err(|err| PackError::Io { err })?;
+        serde_json::from_str(&data).map_err(|err| PackError::InvalidPack { err })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::testing::{compile_lines, project};
+
+    #[test]
+    fn saved_pack_is_read_back() {
+        let dir = project("pack", &[]);
+        let source = "@join {\n    p.sendMessage(\"Hi\");\n}";
+        let lines = compile_lines(source);
+        let mut pack = Pack::new("greeting");
+        pack.add(&PathBuf::from("events").join("join.dfrs"), source, lines.clone());
+        pack.save(&dir.join("greeting.dfpack")).unwrap();
+
+        let loaded = Pack::load(&dir.join("greeting.dfpack")).unwrap();
+        assert_eq!(loaded.name, "greeting");
+        assert_eq!(loaded.dfrs_version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(loaded.sources.len(), 1);
+        assert_eq!(loaded.sources[0].file, "events/join.dfrs");
+        assert_eq!(loaded.sources[0].hash, sha256(source));
+        assert_eq!(loaded.lines.iter().map(|line| (&line.name, &line.code)).collect::<Vec<_>>(), lines.iter().map(|line| (&line.name, &line.code)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn invalid_pack_is_rejected() {
+        let dir = project("invalid-pack", &[("plain.dfpack", "not gzipped")]);
+        assert!(matches!(Pack::load(&dir.join("plain.dfpack")), Err(PackError::Io { .. })));
+        assert!(matches!(Pack::load(&dir.join("missing.dfpack")), Err(PackError::Io { .. })));
+
+        let mut encoder = GzEncoder::new(File::create(dir.join("json.dfpack")).unwrap(), Compression::default());
+        encoder.write_all(b"{\"name\": \"broken\"}").unwrap();
+        encoder.finish().unwrap();
+        assert!(matches!(Pack::load(&dir.join("json.dfpack")), Err(PackError::InvalidPack { .. })));
+    }
+}
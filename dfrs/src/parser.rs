@@ -1,10 +1,22 @@
-use crate::{definitions::ArgType, node::{ActionNode, ActionType, Arg, ArgValue, ArgValueWithPos, CallNode, ConditionalNode, ConditionalType, EventNode, Expression, ExpressionNode, FileNode, FunctionNode, FunctionParamNode, ProcessNode, RepeatNode, VariableNode, VariableType}, token::{Keyword, Position, Selector, Token, TokenWithPos, SELECTORS, TYPES}};
-use crate::node::{ParticleCluster, ParticleData, StartNode};
+use crate::config::default_max_depth;
+use crate::{definitions::ArgType, node::{ActionNode, ActionType, Arg, ArgValue, ArgValueWithPos, CallNode, ConditionalNode, ConditionalType, EventNode, Expression, ExpressionNode, FileNode, FunctionNode, FunctionParamNode, ProcessNode, RepeatNode, VariableNode, VariableType}, token::{Keyword, Position, Selector, Token, TokenWithPos, KEYWORDS, SELECTORS, TYPES}};
+use crate::utility::{edit_distance, to_camel_case, with_item_count};
+use crate::node::{ImportNode, ParticleCluster, ParticleData, StartNode, UseNode, INFINITE_POTION_DURATION};
+
+// Units of the wait shorthand and the Time Unit option of the wait action they choose
+const TIME_UNITS: [(&str, &str); 6] = [
+    ("ticks", "Ticks"),
+    ("tick", "Ticks"),
+    ("seconds", "Seconds"),
+    ("second", "Seconds"),
+    ("minutes", "Minutes"),
+    ("minute", "Minutes")
+];
 
 #[derive(Debug)]
 pub enum ParseError {
     InvalidToken { found: Option<TokenWithPos>, expected: Vec<Token> },
-    UnknownVariable { found: String, start_pos: Position, end_pos: Position },
+    UnknownVariable { found: String, suggestions: Vec<String>, start_pos: Position, end_pos: Position },
     InvalidCall { pos: Position, msg: String },
     InvalidComplexNumber { pos: Position, msg: String },
     InvalidLocation { pos: Position, msg: String },
@@ -13,7 +25,43 @@ pub enum ParseError {
     InvalidPotion { pos: Position, msg: String },
     InvalidParticle { pos: Position, msg: String },
     InvalidItem { pos: Position, msg: String },
-    InvalidType { found: Option<TokenWithPos>, start_pos: Position }
+    InvalidType { found: Option<TokenWithPos>, start_pos: Position },
+    MissingBody { name: String, start_pos: Position, end_pos: Position },
+    MultipleSelectors { start_pos: Position, end_pos: Position },
+    DivisionByZero { start_pos: Position, end_pos: Position },
+    UnknownSetting { found: String, start_pos: Position, end_pos: Position },
+    UnknownEventPrefix { found: String, start_pos: Position, end_pos: Position },
+    MissingSemicolon { pos: Position },
+    TooDeep { max_depth: u32, pos: Position },
+    UnknownTimeUnit { found: String, start_pos: Position, end_pos: Position },
+    ActionInRepeatCondition { start_pos: Position, end_pos: Position }
+}
+
+impl ParseError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ParseError::InvalidToken { .. } => "E101",
+            ParseError::UnknownVariable { .. } => "E102",
+            ParseError::InvalidCall { .. } => "E103",
+            ParseError::InvalidComplexNumber { .. } => "E104",
+            ParseError::InvalidLocation { .. } => "E105",
+            ParseError::InvalidVector { .. } => "E106",
+            ParseError::InvalidSound { .. } => "E107",
+            ParseError::InvalidPotion { .. } => "E108",
+            ParseError::InvalidParticle { .. } => "E109",
+            ParseError::InvalidItem { .. } => "E110",
+            ParseError::InvalidType { .. } => "E111",
+            ParseError::MissingBody { .. } => "E112",
+            ParseError::MultipleSelectors { .. } => "E113",
+            ParseError::DivisionByZero { .. } => "E114",
+            ParseError::UnknownSetting { .. } => "E115",
+            ParseError::UnknownEventPrefix { .. } => "E116",
+            ParseError::MissingSemicolon { .. } => "E117",
+            ParseError::TooDeep { .. } => "E118",
+            ParseError::UnknownTimeUnit { .. } => "E119",
+            ParseError::ActionInRepeatCondition { .. } => "E120"
+        }
+    }
 }
 
 pub struct Parser {
@@ -21,11 +69,33 @@ pub struct Parser {
     token_index: i32,
     current_token: Option<TokenWithPos>,
     variables: Vec<VariableNode>,
+    pending_expressions: Vec<ExpressionNode>,
+    following_expressions: Vec<ExpressionNode>,
+    temp_variables: u32,
+    allow_infinite: bool,
+    allow_arithmetic: bool,
+    depth: u32,
+    max_depth: u32,
+    in_function: bool
 }
 
 impl Parser {
     pub fn new(tokens: Vec<TokenWithPos>) -> Parser {
-        Parser { tokens, token_index: -1, current_token: None, variables: vec![] }
+        Parser { tokens, token_index: -1, current_token: None, variables: vec![], pending_expressions: vec![], following_expressions: vec![], temp_variables: 0, allow_infinite: false, allow_arithmetic: false, depth: 0, max_depth: default_max_depth(), in_function: false }
+    }
+
+    pub fn set_max_depth(&mut self, max_depth: u32) {
+        self.max_depth = max_depth;
+    }
+
+    // Bodies and nested args recurse, so limit how deep they can go instead of overflowing the stack
+    fn enter(&mut self) -> Result<(), ParseError> {
+        self.depth += 1;
+        if self.depth > self.max_depth {
+            let pos = self.current_token.clone().map(|token| token.start_pos).unwrap_or(Position::start());
+            return Err(ParseError::TooDeep { max_depth: self.max_depth, pos })
+        }
+        Ok(())
     }
 
     fn peak(&self) -> Option<TokenWithPos> {
@@ -57,11 +127,26 @@ impl Parser {
         Ok(token.unwrap())
     }
 
+    // End positions are exclusive, so the previous token ends where the semicolon belongs
+    fn missing_semicolon(&self) -> ParseError {
+        let pos = self.tokens[self.token_index as usize - 1].end_pos.clone();
+        ParseError::MissingSemicolon { pos }
+    }
+
     fn require_token(&mut self, required_token: Token) -> Result<TokenWithPos, ParseError> {
-        let token = self.advance_err()?;
-        if token.token == required_token {
-            return Ok(token);
+        let token = self.advance();
+        if let Some(token) = &token {
+            if token.token == required_token {
+                return Ok(token.clone());
+            }
+        }
+        if required_token == Token::Semicolon {
+            return Err(self.missing_semicolon())
         }
+        let token = match token {
+            Some(token) => token,
+            None => return Err(ParseError::InvalidToken { found: None, expected: vec![] })
+        };
         Err(ParseError::InvalidToken { found: Some(token), expected: vec![required_token] })
     }
 
@@ -69,16 +154,37 @@ impl Parser {
         self.file()
     }
 
+    pub fn add_variables(&mut self, variables: Vec<VariableNode>) {
+        self.variables.extend(variables);
+    }
+
+    /// Collects the game and save variables declared at the top level, skipping everything else
+    pub fn global_variables(&mut self) -> Vec<VariableNode> {
+        let mut variables = vec![];
+        let mut depth = 0;
+        while let Some(token) = self.advance() {
+            match token.token {
+                Token::OpenParenCurly => depth += 1,
+                Token::CloseParenCurly => depth -= 1,
+                Token::Keyword { value: Keyword::VarGame } if depth == 0 => variables.extend(self.variable(VariableType::Game)),
+                Token::Keyword { value: Keyword::VarSave } if depth == 0 => variables.extend(self.variable(VariableType::Save)),
+                _ => {}
+            }
+        }
+        variables
+    }
+
     fn file(&mut self) -> Result<FileNode, ParseError> {
         let mut token = self.advance();
         let mut events: Vec<EventNode> = vec![];
         let mut functions: Vec<FunctionNode> = vec![];
         let mut processes: Vec<ProcessNode> = vec![];
-        let start_pos = Position::new(1, 0);
+        let mut uses: Vec<UseNode> = vec![];
+        let start_pos = Position::start();
 
         while token.is_some() {
             match token.clone().unwrap().token {
-                Token::At => events.push(self.event()?),
+                Token::At => events.extend(self.events()?),
                 Token::Keyword { value } => {
                     match value {
                         Keyword::Function => {
@@ -87,6 +193,9 @@ impl Parser {
                         Keyword::Process => {
                             processes.push(self.process()?);
                         }
+                        Keyword::Use => {
+                            uses.push(self.use_statement()?);
+                        }
                         Keyword::VarGame => {
                             let node = self.variable(VariableType::Game)?;
                             self.variables.push(node);
@@ -109,44 +218,97 @@ impl Parser {
         } else {
             start_pos.clone()
         };
-        Ok(FileNode { events, functions, processes, start_pos, end_pos })
+        Ok(FileNode { events, functions, processes, uses, start_pos, end_pos })
     }
 
-    fn event(&mut self) -> Result<EventNode, ParseError> {
-        let mut expressions: Vec<ExpressionNode> = vec![];
-        let start_pos = self.current_token.clone().unwrap().end_pos;
-        let mut cancelled = false;
-
-        let name_token = self.advance_err()?;
+    fn use_statement(&mut self) -> Result<UseNode, ParseError> {
+        let start_pos = self.current_token.clone().unwrap().start_pos;
 
-        let event = match name_token.token {
-            Token::Identifier { value } => value,
-            _ => return Err(ParseError::InvalidToken { found: self.current_token.clone(), expected: vec![Token::Identifier { value: String::from("<any>")}] })
+        let token = self.advance_err()?;
+        let path = match token.token {
+            Token::Text { value } => value,
+            Token::String { value } => value,
+            _ => return Err(ParseError::InvalidToken { found: Some(token), expected: vec![Token::Text { value: "<any>".into() }] })
         };
 
-        let mut token = self.advance_err()?;
-        match token.token {
-            Token::ExclamationMark => {
-                cancelled = true;
-                self.require_token(Token::OpenParenCurly)?;
+        let mut names = None;
+        if self.peak().is_some_and(|token| token.token == Token::OpenParenCurly) {
+            self.advance_err()?;
+            let mut imports = vec![];
+            loop {
+                let token = self.advance_err()?;
+                match token.token {
+                    Token::Identifier { value } => imports.push(ImportNode { name: value, start_pos: token.start_pos, end_pos: token.end_pos }),
+                    Token::CloseParenCurly if imports.is_empty() => break,
+                    _ => return Err(ParseError::InvalidToken { found: Some(token), expected: vec![Token::Identifier { value: "<any>".into() }] })
+                }
+                let token = self.advance_err()?;
+                match token.token {
+                    Token::Comma => {}
+                    Token::CloseParenCurly => break,
+                    _ => return Err(ParseError::InvalidToken { found: Some(token), expected: vec![Token::Comma, Token::CloseParenCurly] })
+                }
             }
-            Token::OpenParenCurly => {}
-            _ => return Err(ParseError::InvalidToken { found: self.current_token.clone(), expected: vec![Token::OpenParenCurly, Token::ExclamationMark] })
+            names = Some(imports);
         }
 
+        let end_pos = self.require_token(Token::Semicolon)?.end_pos;
+        Ok(UseNode { path, names, start_pos, end_pos })
+    }
+
+    // Several events can share one body, each of them becomes its own codeline
+    fn events(&mut self) -> Result<Vec<EventNode>, ParseError> {
+        let mut heads = vec![];
         loop {
-            token = self.advance_err()?;
+            let start_pos = self.current_token.clone().unwrap().end_pos;
+            let mut cancelled = false;
+
+            let mut event_type = None;
+            let mut name_token = self.advance_err()?;
+
+            let mut event = match name_token.token.clone() {
+                Token::Identifier { value } => value,
+                _ => return Err(ParseError::InvalidToken { found: self.current_token.clone(), expected: vec![Token::Identifier { value: String::from("<any>")}] })
+            };
+
+            let mut token = self.advance_err()?;
+            if token.token == Token::Colon {
+                event_type = match event.as_str() {
+                    "player" => Some(ActionType::Player),
+                    "entity" => Some(ActionType::Entity),
+                    _ => return Err(ParseError::UnknownEventPrefix { found: event, start_pos: name_token.start_pos, end_pos: name_token.end_pos })
+                };
+                name_token = self.advance_err()?;
+                event = match name_token.token.clone() {
+                    Token::Identifier { value } => value,
+                    _ => return Err(ParseError::InvalidToken { found: self.current_token.clone(), expected: vec![Token::Identifier { value: String::from("<any>")}] })
+                };
+                token = self.advance_err()?;
+            }
+
+            if token.token == Token::ExclamationMark {
+                cancelled = true;
+                token = self.advance_err()?;
+            }
+            heads.push((event_type, event, start_pos, name_token.end_pos, cancelled));
+
             match token.token {
-                Token::CloseParenCurly => break,
-                _ => expressions.push(self.expression()?)
+                Token::OpenParenCurly => break,
+                Token::Comma => {
+                    self.require_token(Token::At)?;
+                }
+                _ => return Err(ParseError::InvalidToken { found: self.current_token.clone(), expected: vec![Token::OpenParenCurly, Token::ExclamationMark, Token::Comma] })
             }
         }
 
-        Ok(EventNode { event_type: None, event, expressions, start_pos, name_end_pos: name_token.end_pos, end_pos: token.end_pos, cancelled })
+        let (expressions, token) = self.expressions()?;
+
+        Ok(heads.into_iter().map(|(event_type, event, start_pos, name_end_pos, cancelled)| {
+            EventNode { event_type, event, expressions: expressions.clone(), start_pos, name_end_pos, end_pos: token.end_pos.clone(), cancelled }
+        }).collect())
     }
 
     fn function(&mut self) -> Result<FunctionNode, ParseError> {
-        let mut expressions: Vec<ExpressionNode> = vec![];
         let start_pos = self.current_token.clone().unwrap().end_pos;
 
         let name_token = self.advance_err()?;
@@ -176,6 +338,7 @@ impl Parser {
 
         loop {
             let token = self.advance_err()?;
+            let (param_start_pos, param_end_pos) = (token.start_pos.clone(), token.end_pos.clone());
             let param_name = match token.token {
                 Token::Identifier { value } => value,
                 Token::CloseParen => break,
@@ -278,6 +441,8 @@ impl Parser {
                 df_name: param_name.clone(),
                 var_type: VariableType::Line,
                 start_pos: Position::new(0, 0),
+                name_start_pos: param_start_pos.clone(),
+                name_end_pos: param_end_pos.clone(),
                 end_pos: Position::new(0, 0),
             });
 
@@ -286,7 +451,9 @@ impl Parser {
                 param_type,
                 optional,
                 multiple,
-                default
+                default,
+                start_pos: param_start_pos,
+                end_pos: param_end_pos
             });
 
             let token = self.advance_err()?;
@@ -297,22 +464,16 @@ impl Parser {
             }
         }
 
-        self.require_token(Token::OpenParenCurly)?;
-
-        let mut token;
-        loop {
-            token = self.advance_err()?;
-            match token.token {
-                Token::CloseParenCurly => break,
-                _ => expressions.push(self.expression()?)
-            }
-        }
+        let hidden = self.settings()?;
+        self.in_function = true;
+        let result = self.expressions();
+        self.in_function = false;
+        let (expressions, token) = result?;
 
-        Ok(FunctionNode { df_name, dfrs_name, expressions, start_pos, name_end_pos: name_token.end_pos, end_pos: token.end_pos, params })
+        Ok(FunctionNode { df_name, dfrs_name, expressions, start_pos, name_start_pos: name_token.start_pos, name_end_pos: name_token.end_pos, end_pos: token.end_pos, params, hidden })
     }
 
     fn process(&mut self) -> Result<ProcessNode, ParseError> {
-        let mut expressions: Vec<ExpressionNode> = vec![];
         let start_pos = self.current_token.clone().unwrap().end_pos;
 
         let name_token = self.advance_err()?;
@@ -321,18 +482,29 @@ impl Parser {
             _ => return Err(ParseError::InvalidToken { found: self.current_token.clone(), expected: vec![Token::Identifier { value: String::from("<any>")}] })
         };
 
-        self.require_token(Token::OpenParenCurly)?;
+        let hidden = self.settings()?;
+        let (expressions, token) = self.expressions()?;
 
-        let mut token;
+        Ok(ProcessNode { name, expressions, start_pos, name_start_pos: name_token.start_pos, name_end_pos: name_token.end_pos, end_pos: token.end_pos, hidden })
+    }
+
+    fn settings(&mut self) -> Result<bool, ParseError> {
+        let mut hidden = false;
         loop {
-            token = self.advance_err()?;
+            let token = self.advance_err()?;
             match token.token {
-                Token::CloseParenCurly => break,
-                _ => expressions.push(self.expression()?)
+                Token::OpenParenCurly => return Ok(hidden),
+                Token::Hash => {
+                    let token = self.advance_err()?;
+                    match token.token {
+                        Token::Identifier { value } if value == "hidden" => hidden = true,
+                        Token::Identifier { value } => return Err(ParseError::UnknownSetting { found: value, start_pos: token.start_pos, end_pos: token.end_pos }),
+                        _ => return Err(ParseError::InvalidToken { found: self.current_token.clone(), expected: vec![Token::Identifier { value: "hidden".into() }] })
+                    }
+                }
+                _ => return Err(ParseError::InvalidToken { found: self.current_token.clone(), expected: vec![Token::Hash, Token::OpenParenCurly] })
             }
         }
-
-        Ok(ProcessNode { name, expressions, start_pos, name_end_pos: name_token.end_pos, end_pos: token.end_pos })
     }
 
     fn expression(&mut self) -> Result<ExpressionNode, ParseError> {
@@ -371,6 +543,7 @@ impl Parser {
                     }
                     Keyword::S => {
                         let res = self.action(ActionType::Select)?;
+                        let res = self.split_conditions(res);
                         end_pos = res.end_pos.clone();
                         node = Expression::Action { node: res };
                     }
@@ -394,6 +567,11 @@ impl Parser {
                         end_pos = res.end_pos.clone();
                         node = Expression::Conditional { node: res };
                     }
+                    Keyword::Unless => {
+                        let res = self.unless()?;
+                        end_pos = res.end_pos.clone();
+                        node = Expression::Conditional { node: res };
+                    }
                     Keyword::VarLine => {
                         let res = self.variable(VariableType::Line)?;
                         end_pos = res.end_pos.clone();
@@ -419,9 +597,24 @@ impl Parser {
                         end_pos = res.end_pos.clone();
                         node = Expression::Repeat { node: res }
                     }
+                    Keyword::Return => {
+                        let res = self.return_action()?;
+                        end_pos = res.end_pos.clone();
+                        node = Expression::Action { node: res }
+                    }
                     _ => return Err(ParseError::InvalidToken { found: self.current_token.clone(), expected: vec![Token::Keyword { value: Keyword::E }, Token::Keyword { value: Keyword::P }] })
                 }
             }
+            Token::Identifier { value } if value == "wait" => {
+                let res = self.wait()?;
+                end_pos = res.end_pos.clone();
+                node = Expression::Action { node: res }
+            }
+            Token::Identifier { value } if value == "guard" => {
+                let res = self.guard()?;
+                end_pos = res.end_pos.clone();
+                node = Expression::Conditional { node: res }
+            }
             _ => return Err(ParseError::InvalidToken { found: self.current_token.clone(), expected: vec![Token::Keyword { value: Keyword::E }, Token::Keyword { value: Keyword::P }] })
         }
 
@@ -429,11 +622,82 @@ impl Parser {
     }
 
     fn action(&mut self, action_type: ActionType) -> Result<ActionNode, ParseError> {
+        let node = self.action_call(action_type)?;
+        self.require_token(Token::Semicolon)?;
+        Ok(node)
+    }
+
+    fn return_action(&mut self) -> Result<ActionNode, ParseError> {
+        let start_pos = self.current_token.clone().unwrap().start_pos;
+        let end_pos = self.require_token(Token::Semicolon)?.end_pos;
+        Ok(self.stop_action(start_pos, end_pos))
+    }
+
+    fn stop_action(&self, start_pos: Position, end_pos: Position) -> ActionNode {
+        // Functions return to the caller, events and processes have nothing to return to so their thread ends
+        let name = if self.in_function { "return" } else { "end" };
+        ActionNode {
+            action_type: ActionType::Control,
+            selector: Selector::Default,
+            name: name.into(),
+            args: vec![],
+            start_pos: start_pos.clone(),
+            selector_start_pos: start_pos.clone(),
+            selector_end_pos: start_pos,
+            end_pos
+        }
+    }
+
+    // `wait(5 seconds);` is `c.wait(5, timeUnit="Seconds");`, without a unit it waits ticks
+    fn wait(&mut self) -> Result<ActionNode, ParseError> {
+        let start_pos = self.current_token.clone().unwrap().start_pos;
+        self.require_token(Token::OpenParen)?;
+
+        let token = self.advance_err()?;
+        let duration = match token.token.clone() {
+            Token::Number { value } => ArgValue::Number { number: value },
+            Token::Identifier { value } => match self.get_variable(value.clone()) {
+                Some((name, scope)) => ArgValue::Variable { name, scope },
+                None => return Err(self.unknown_variable(value, token.start_pos, token.end_pos))
+            },
+            _ => return Err(ParseError::InvalidToken { found: Some(token), expected: vec![Token::Number { value: 0.0 }, Token::Identifier { value: "<any>".into() }] })
+        };
+        let mut args = vec![Arg { arg_type: duration.kind(), value: duration, index: 0, start_pos: token.start_pos, end_pos: token.end_pos }];
+
+        let mut token = self.advance_err()?;
+        if let Token::Identifier { value } = token.token.clone() {
+            let unit = match TIME_UNITS.iter().find(|(name, _)| *name == value) {
+                Some((_, unit)) => unit,
+                None => return Err(ParseError::UnknownTimeUnit { found: value, start_pos: token.start_pos, end_pos: token.end_pos })
+            };
+            let tag = ArgValue::Tag { tag: "timeUnit".into(), value: Box::new(ArgValue::Text { text: unit.to_string() }), definition: None, name_end_pos: token.start_pos.clone(), value_start_pos: token.start_pos.clone() };
+            args.push(Arg { arg_type: tag.kind(), value: tag, index: 1, start_pos: token.start_pos.clone(), end_pos: token.end_pos.clone() });
+            token = self.advance_err()?;
+        }
+        if token.token != Token::CloseParen {
+            return Err(ParseError::InvalidToken { found: Some(token), expected: vec![Token::Identifier { value: "seconds".into() }, Token::CloseParen] })
+        }
+        let end_pos = self.require_token(Token::Semicolon)?.end_pos;
+
+        Ok(ActionNode {
+            action_type: ActionType::Control,
+            selector: Selector::Default,
+            name: "wait".into(),
+            args,
+            start_pos: start_pos.clone(),
+            selector_start_pos: start_pos.clone(),
+            selector_end_pos: start_pos,
+            end_pos
+        })
+    }
+
+    fn action_call(&mut self, action_type: ActionType) -> Result<ActionNode, ParseError> {
         let mut selector = Selector::Default;
-        let mut implicit_selector = true;
         let mut token = self.advance_err()?;
         let mut start_pos = token.start_pos.clone();
         start_pos.col += 1;
+        let mut selector_start_pos = start_pos.clone();
+        let mut selector_end_pos = start_pos.clone();
 
         match token.token {
             Token::Colon => {
@@ -441,10 +705,23 @@ impl Parser {
                     return Err(ParseError::InvalidToken { found: self.current_token.clone(), expected: vec![Token::Dot]})
                 }
                 token = self.advance_err()?;
-                match token.token {
+                let quoted_selector = match &token.token {
+                    Token::Variable { value } => SELECTORS.get(value).cloned(),
+                    _ => None
+                };
+                match quoted_selector.map(|value| Token::Selector { value }).unwrap_or(token.token) {
                     Token::Selector { value } => {
                         selector = value;
-                        implicit_selector = false;
+                        selector_start_pos = token.start_pos;
+                        selector_end_pos = token.end_pos;
+                        if self.peak().is_some_and(|next| next.token == Token::Colon) {
+                            self.advance_err()?;
+                            let next = self.advance_err()?;
+                            if let Token::Selector { .. } = next.token {
+                                return Err(ParseError::MultipleSelectors { start_pos: next.start_pos, end_pos: next.end_pos })
+                            }
+                            return Err(ParseError::InvalidToken { found: Some(next), expected: vec![Token::Dot] })
+                        }
                         self.require_token(Token::Dot)?;
                     }
                     _ => return Err(ParseError::InvalidToken { found: self.current_token.clone(), expected: vec![Token::Selector { value: Selector::AllPlayers }]})
@@ -455,43 +732,139 @@ impl Parser {
         }
 
         token = self.advance_err()?;
-        let name = match token.token {
-            Token::Identifier { value } => value,
+        // Only an action name can follow the dot, so keywords like `return` in `c.return();` don't need quoting
+        let name = match (&token.token, action_name(&token.token)) {
+            (_, Some(name)) => name,
+            (Token::Keyword { value }, None) => keyword_name(value),
             _ => return Err(ParseError::InvalidToken { found: self.current_token.clone(), expected: vec![Token::Identifier { value: String::from("<any>") }]} )
         };
 
         let args = self.make_args()?;
 
-        let mut selector_start_pos = start_pos.clone();
-        selector_start_pos.col += 2;
-        let mut selector_end_pos = selector_start_pos.clone();
-        if !implicit_selector {
-            for (name, sel) in SELECTORS.entries() {
-                if sel == &selector {
-                    selector_end_pos.col += 1 + name.len() as u32;
+        Ok(ActionNode { action_type, selector, name, args, start_pos, selector_start_pos, selector_end_pos, end_pos: token.end_pos })
+    }
+
+    fn conditional(&mut self, conditional_type: ConditionalType) -> Result<ConditionalNode, ParseError> {
+        let mut conditions = vec![self.condition(conditional_type)?];
+        while self.peak().is_some_and(|token| token.token == Token::Keyword { value: Keyword::And }) {
+            self.advance_err()?;
+            let conditional_type = match self.peak().map(|token| token.token) {
+                Some(Token::Keyword { value: Keyword::IfP }) => ConditionalType::Player,
+                Some(Token::Keyword { value: Keyword::IfE }) => ConditionalType::Entity,
+                Some(Token::Keyword { value: Keyword::IfG }) => ConditionalType::Game,
+                Some(Token::Keyword { value: Keyword::IfV }) => ConditionalType::Variable,
+                _ => {
+                    let previous = conditions.last().unwrap().conditional_type.clone();
+                    conditions.push(self.condition(previous)?);
+                    continue;
+                }
+            };
+            self.advance_err()?;
+            conditions.push(self.condition(conditional_type)?);
+        }
+
+        let mut node = conditions.pop().unwrap();
+        node.expressions = self.body(format!("conditional '{}'", node.name), node.start_pos.clone(), node.end_pos.clone())?;
+
+        let else_expressions = self.else_body()?;
+        node.else_expressions = else_expressions.clone();
+
+        while let Some(mut outer) = conditions.pop() {
+            outer.expressions = vec![ExpressionNode { start_pos: node.start_pos.clone(), end_pos: node.end_pos.clone(), node: Expression::Conditional { node } }];
+            outer.else_expressions = else_expressions.clone();
+            node = outer;
+        }
+
+        Ok(node)
+    }
+
+    fn else_body(&mut self) -> Result<Vec<ExpressionNode>, ParseError> {
+        let mut else_expressions = vec![];
+        match self.peak() {
+            Some(token) => {
+                match token.token {
+                    Token::Keyword { value } => {
+                        match value {
+                            Keyword::Else => {
+                                self.advance_err()?;
+                                let conditional_type = match self.peak().map(|token| token.token) {
+                                    Some(Token::Keyword { value: Keyword::IfP }) => Some(ConditionalType::Player),
+                                    Some(Token::Keyword { value: Keyword::IfE }) => Some(ConditionalType::Entity),
+                                    Some(Token::Keyword { value: Keyword::IfG }) => Some(ConditionalType::Game),
+                                    Some(Token::Keyword { value: Keyword::IfV }) => Some(ConditionalType::Variable),
+                                    _ => None
+                                };
+                                else_expressions = match conditional_type {
+                                    // `else ifp cond { }` is an else body that only contains that conditional
+                                    Some(conditional_type) => {
+                                        let start_pos = self.advance_err()?.start_pos;
+                                        let node = self.conditional(conditional_type)?;
+                                        vec![ExpressionNode { start_pos, end_pos: node.end_pos.clone(), node: Expression::Conditional { node } }]
+                                    }
+                                    None => self.body("else".into(), token.start_pos, token.end_pos)?
+                                };
+                            }
+                            _ => {}
+                        }
+                    }
+                    _ => {}
                 }
             }
+            None => {}
         }
+        Ok(else_expressions)
+    }
 
-        self.require_token(Token::Semicolon)?;
+    // `unless ifp cond { }` is `ifp !cond { }`, combining conditions with and is not allowed as it would invert each of them
+    fn unless(&mut self) -> Result<ConditionalNode, ParseError> {
+        let conditional_type = self.conditional_keyword()?;
+        let mut node = self.condition(conditional_type)?;
+        node.inverted = !node.inverted;
+        node.expressions = self.body(format!("conditional '{}'", node.name), node.start_pos.clone(), node.end_pos.clone())?;
+        node.else_expressions = self.else_body()?;
+        Ok(node)
+    }
 
-        Ok(ActionNode { action_type, selector, name, args, start_pos, selector_start_pos, selector_end_pos, end_pos: token.end_pos })
+    // `guard ifp cond else { }` is `ifp !cond { <else body> return; }`, so the code after it only runs if the condition is true
+    fn guard(&mut self) -> Result<ConditionalNode, ParseError> {
+        let conditional_type = self.conditional_keyword()?;
+        let mut node = self.condition(conditional_type)?;
+        node.inverted = !node.inverted;
+        let token = self.require_token(Token::Keyword { value: Keyword::Else })?;
+        node.expressions = self.body(format!("guard '{}'", node.name), token.start_pos.clone(), token.end_pos.clone())?;
+        let stop = self.stop_action(token.start_pos.clone(), token.end_pos.clone());
+        node.expressions.push(ExpressionNode { start_pos: token.start_pos, end_pos: token.end_pos, node: Expression::Action { node: stop } });
+        Ok(node)
     }
 
-    fn conditional(&mut self, conditional_type: ConditionalType) -> Result<ConditionalNode, ParseError> {
+    fn conditional_keyword(&mut self) -> Result<ConditionalType, ParseError> {
+        let token = self.advance_err()?;
+        match token.token {
+            Token::Keyword { value: Keyword::IfP } => Ok(ConditionalType::Player),
+            Token::Keyword { value: Keyword::IfE } => Ok(ConditionalType::Entity),
+            Token::Keyword { value: Keyword::IfG } => Ok(ConditionalType::Game),
+            Token::Keyword { value: Keyword::IfV } => Ok(ConditionalType::Variable),
+            _ => Err(ParseError::InvalidToken { found: self.current_token.clone(), expected: vec![Token::Keyword { value: Keyword::IfP }, Token::Keyword { value: Keyword::IfE }, Token::Keyword { value: Keyword::IfG }, Token::Keyword { value: Keyword::IfV }] })
+        }
+    }
+
+    fn condition(&mut self, conditional_type: ConditionalType) -> Result<ConditionalNode, ParseError> {
         let mut token = self.advance_err()?;
         let mut selector = Selector::Default;
         let start_pos = token.start_pos.clone();
         let mut selector_start_pos = None;
         let mut selector_end_pos = None;
         let mut inverted = false;
+        let mut inversion_start_pos = None;
+        let mut inversion_end_pos = None;
 
-        match token.token {
-            Token::ExclamationMark => {
-                inverted = true;
-                token = self.advance_err()?;
+        while token.token == Token::ExclamationMark {
+            inverted = !inverted;
+            if inversion_start_pos.is_none() {
+                inversion_start_pos = Some(token.start_pos.clone());
             }
-            _ => {}
+            inversion_end_pos = Some(token.end_pos);
+            token = self.advance_err()?;
         }
 
         match token.token {
@@ -501,59 +874,20 @@ impl Parser {
                 selector_end_pos = Some(token.end_pos);
                 self.require_token(Token::Colon)?;
                 token = self.advance_err()?;
+                if let Token::Selector { .. } = token.token {
+                    return Err(ParseError::MultipleSelectors { start_pos: token.start_pos, end_pos: token.end_pos })
+                }
             }
             _ => {}
         }
-        let name = match token.token {
-            Token::Identifier { value } => value,
+        let name = match action_name(&token.token) {
+            Some(name) => name,
             _ => return Err(ParseError::InvalidToken { found: Some(token), expected: vec![Token::Identifier { value: "any".into() }] })
         };
 
         let args = self.make_args()?;
         let end_pos = token.end_pos;
 
-        self.require_token(Token::OpenParenCurly)?;
-        let mut expressions = vec![];
-        loop {
-            token = self.advance_err()?;
-            match token.token {
-                Token::CloseParenCurly => break,
-                _ => {
-                    let expression = self.expression()?;
-                    expressions.push(expression);
-                }
-            }
-        }
-
-        let mut else_expressions = vec![];
-        match self.peak() {
-            Some(token) => {
-                match token.token {
-                    Token::Keyword { value } => {
-                        match value {
-                            Keyword::Else => {
-                                self.advance_err()?;
-                                self.require_token(Token::OpenParenCurly)?;
-                                loop {
-                                    let token = self.advance_err()?;
-                                    match token.token {
-                                        Token::CloseParenCurly => break,
-                                        _ => {
-                                            let expression = self.expression()?;
-                                            else_expressions.push(expression);
-                                        }
-                                    }
-                                }
-                            }
-                            _ => {}
-                        }
-                    }
-                    _ => {}
-                }
-            }
-            None => {}
-        }
-        
         Ok(ConditionalNode {
             conditional_type,
             selector,
@@ -563,9 +897,11 @@ impl Parser {
             selector_end_pos,
             start_pos,
             end_pos,
-            expressions,
-            else_expressions,
-            inverted
+            expressions: vec![],
+            else_expressions: vec![],
+            inverted,
+            inversion_start_pos,
+            inversion_end_pos
         })
     }
 
@@ -592,6 +928,8 @@ impl Parser {
             name,
             args,
             start_pos,
+            name_start_pos: name_arg.start_pos,
+            name_end_pos: name_arg.end_pos,
             end_pos,
         })
     }
@@ -619,12 +957,14 @@ impl Parser {
             name,
             args,
             start_pos,
+            name_start_pos: name_arg.start_pos,
+            name_end_pos: name_arg.end_pos,
             end_pos,
         })
     }
 
     fn repeat(&mut self) -> Result<RepeatNode, ParseError> {
-        let mut token = self.advance_err()?;
+        let token = self.advance_err()?;
         let start_pos = token.start_pos.clone();
 
         let name = match token.token {
@@ -632,22 +972,61 @@ impl Parser {
             _ => return Err(ParseError::InvalidToken { found: Some(token), expected: vec![Token::Identifier { value: "any".into() }] })
         };
 
+        let pending = self.pending_expressions.len();
         let args = self.make_args()?;
         let end_pos = token.end_pos;
-
-        self.require_token(Token::OpenParenCurly)?;
-        let mut expressions = vec![];
-        loop {
-            token = self.advance_err()?;
-            match token.token {
-                Token::CloseParenCurly => break,
-                _ => {
-                    let expression = self.expression()?;
-                    expressions.push(expression);
-                }
+        // Nested actions run once before the repeat, so a condition checked every iteration would only see their first result
+        if args.iter().any(|arg| arg.arg_type == ArgType::CONDITION) {
+            if let Some(nested) = self.pending_expressions.get(pending) {
+                return Err(ParseError::ActionInRepeatCondition { start_pos: nested.start_pos.clone(), end_pos: nested.end_pos.clone() })
             }
         }
-        
+
+        let mut expressions = self.body(format!("repeat '{name}'"), start_pos.clone(), end_pos.clone())?;
+
+        if let Some(TokenWithPos { token: Token::Keyword { value: Keyword::Else }, start_pos: else_start_pos, end_pos: else_end_pos }) = self.peak() {
+            self.advance_err()?;
+            let else_expressions = self.body("else".into(), else_start_pos, else_end_pos)?;
+
+            // DiamondFire has no repeat-else, so a flag set inside the loop decides whether the else body runs
+            let flag = self.temp_variable();
+            let set_flag = |value: f32| ExpressionNode {
+                start_pos: start_pos.clone(),
+                end_pos: end_pos.clone(),
+                node: Expression::Action { node: ActionNode {
+                    action_type: ActionType::Variable,
+                    selector: Selector::Default,
+                    name: "equal".into(),
+                    args: flag_args(&flag, value, &start_pos, &end_pos),
+                    start_pos: start_pos.clone(),
+                    selector_start_pos: start_pos.clone(),
+                    selector_end_pos: start_pos.clone(),
+                    end_pos: end_pos.clone()
+                }}
+            };
+            self.pending_expressions.push(set_flag(0.0));
+            expressions.insert(0, set_flag(1.0));
+            self.following_expressions.push(ExpressionNode {
+                start_pos: start_pos.clone(),
+                end_pos: end_pos.clone(),
+                node: Expression::Conditional { node: ConditionalNode {
+                    conditional_type: ConditionalType::Variable,
+                    selector: Selector::Default,
+                    name: "equal".into(),
+                    args: flag_args(&flag, 0.0, &start_pos, &end_pos),
+                    start_pos: start_pos.clone(),
+                    selector_start_pos: None,
+                    selector_end_pos: None,
+                    end_pos: end_pos.clone(),
+                    expressions: else_expressions,
+                    else_expressions: vec![],
+                    inverted: false,
+                    inversion_start_pos: None,
+                    inversion_end_pos: None
+                }}
+            });
+        }
+
         Ok(RepeatNode {
             name,
             args,
@@ -657,13 +1036,64 @@ impl Parser {
         })
     }
 
+    fn body(&mut self, name: String, start_pos: Position, end_pos: Position) -> Result<Vec<ExpressionNode>, ParseError> {
+        match self.peak() {
+            Some(TokenWithPos { token: Token::OpenParenCurly, .. }) => {
+                self.advance_err()?;
+            }
+            _ => return Err(ParseError::MissingBody { name, start_pos, end_pos })
+        }
+
+        let (expressions, _) = self.expressions()?;
+        Ok(expressions)
+    }
+
+    // s.filterCondition(ifp a(), ifv b()) narrows the selection by each condition in turn
+    fn split_conditions(&mut self, mut node: ActionNode) -> ActionNode {
+        while node.args.len() > 1 && node.args.iter().all(|arg| arg.arg_type == ArgType::CONDITION) {
+            let mut arg = node.args.pop().unwrap();
+            arg.index = 0;
+            self.following_expressions.insert(0, ExpressionNode {
+                start_pos: arg.start_pos.clone(),
+                end_pos: arg.end_pos.clone(),
+                node: Expression::Action { node: ActionNode { args: vec![arg], ..node.clone() } }
+            });
+        }
+        node
+    }
+
+    fn expressions(&mut self) -> Result<(Vec<ExpressionNode>, TokenWithPos), ParseError> {
+        self.enter()?;
+        let outer_pending = std::mem::take(&mut self.pending_expressions);
+        let outer_following = std::mem::take(&mut self.following_expressions);
+        let mut expressions = vec![];
+        let token = loop {
+            let token = self.advance_err()?;
+            match token.token {
+                Token::CloseParenCurly => break token,
+                _ => {
+                    let expression = self.expression()?;
+                    expressions.append(&mut self.pending_expressions);
+                    expressions.push(expression);
+                    expressions.append(&mut self.following_expressions);
+                }
+            }
+        };
+        self.pending_expressions = outer_pending;
+        self.following_expressions = outer_following;
+        self.depth -= 1;
+        Ok((expressions, token))
+    }
+
     fn variable(&mut self, var_type: VariableType) -> Result<VariableNode, ParseError> {
         let start_pos = self.current_token.clone().unwrap().start_pos;
         let end_pos = start_pos.clone();
         
         let token = self.advance_err()?;
+        let (name_start_pos, name_end_pos) = (token.start_pos.clone(), token.end_pos.clone());
+        // Names that aren't valid identifiers can be quoted with backticks, like `line `my var`;`
         let dfrs_name = match token.token {
-            Token::Identifier { value } => value,
+            Token::Identifier { value } | Token::Variable { value } => value,
             _ => return Err(ParseError::InvalidToken { found: self.current_token.clone(), expected: vec![Token::Identifier { value: "any".into() }] })
         };
 
@@ -672,12 +1102,12 @@ impl Parser {
             Token::Equal => {}
             Token::Semicolon => {
                 return {
-                    let node = VariableNode { dfrs_name: dfrs_name.clone(), df_name: dfrs_name, var_type, start_pos, end_pos };
+                    let node = VariableNode { dfrs_name: dfrs_name.clone(), df_name: dfrs_name, var_type, start_pos, name_start_pos, name_end_pos, end_pos };
                     self.variables.push(node.clone());
                     Ok(node)
                 }
             }
-            _ => return Err(ParseError::InvalidToken { found: self.current_token.clone(), expected: vec![Token::Equal, Token::Semicolon] })
+            _ => return Err(self.missing_semicolon())
         };
 
         let token = self.advance_err()?;
@@ -688,7 +1118,7 @@ impl Parser {
 
         self.require_token(Token::Semicolon)?;
 
-        let node = VariableNode { dfrs_name, df_name, var_type, start_pos, end_pos };
+        let node = VariableNode { dfrs_name, df_name, var_type, start_pos, name_start_pos, name_end_pos, end_pos };
         self.variables.push(node.clone());
         Ok(node)
     }
@@ -733,13 +1163,13 @@ impl Parser {
                         if let Some((var, scope)) = self.get_variable(tag_name.clone()) {
                             params.push(ArgValueWithPos {
                                 value: ArgValue::Variable { name: var, scope },
-                                start_pos: self.current_token.clone().unwrap().start_pos,
-                                end_pos: self.current_token.clone().unwrap().end_pos,
+                                start_pos: tag_start_pos.clone(),
+                                end_pos: tag_end_pos.clone(),
                             });
                             is_value = true;
                             self.token_index -= 1;
                         } else {
-                            return Err(ParseError::UnknownVariable { found: tag_name.clone(), start_pos: tag_start_pos, end_pos: tag_end_pos });
+                            return Err(self.unknown_variable(tag_name.clone(), tag_start_pos, tag_end_pos));
                         }
                     }
                 }
@@ -828,6 +1258,10 @@ impl Parser {
                 }
             } else {
                 match token.token.clone() {
+                    Token::Number { value } if self.allow_arithmetic => {
+                        params.push(self.constant(value, token.start_pos)?);
+                        is_value = true;
+                    }
                     Token::Number { value } => {
                         params.push(ArgValueWithPos {
                             value: ArgValue::Number { number: value },
@@ -882,6 +1316,14 @@ impl Parser {
                                 params.push(self.make_item()?);
                                 is_value = true;
                             }
+                            "infinite" if self.allow_infinite => {
+                                params.push(ArgValueWithPos {
+                                    value: ArgValue::Number { number: f32::INFINITY },
+                                    start_pos: self.current_token.clone().unwrap().start_pos,
+                                    end_pos: self.current_token.clone().unwrap().end_pos
+                                });
+                                is_value = true;
+                            }
                             "null" => {
                                 params.push(ArgValueWithPos {
                                     value: ArgValue::Empty,
@@ -898,6 +1340,17 @@ impl Parser {
                             }
                         }
                     }
+                    Token::Variable { value } => {
+                        match self.get_variable(value.clone()) {
+                            Some((name, scope)) => params.push(ArgValueWithPos {
+                                value: ArgValue::Variable { name, scope },
+                                start_pos: token.start_pos,
+                                end_pos: token.end_pos
+                            }),
+                            None => return Err(self.unknown_variable(value, token.start_pos, token.end_pos))
+                        }
+                        is_value = true;
+                    }
                     Token::Dollar => is_game_value = true,
                     Token::Keyword { value } => {
                         let arg = match value {
@@ -905,6 +1358,7 @@ impl Parser {
                             Keyword::IfE => self.conditional_arg(ConditionalType::Entity)?,
                             Keyword::IfG => self.conditional_arg(ConditionalType::Game)?,
                             Keyword::IfV => self.conditional_arg(ConditionalType::Variable)?,
+                            Keyword::V => self.nested_action()?,
                             _ => return Err(ParseError::InvalidToken { found: self.current_token.clone(), expected })
                         };
                         params.push(arg);
@@ -922,26 +1376,12 @@ impl Parser {
     }
 
     fn make_args(&mut self) -> Result<Vec<Arg>, ParseError> {
+        self.enter()?;
         let params = self.make_params()?;
+        self.depth -= 1;
         let mut args = vec![];
         for (i, param) in params.into_iter().enumerate() {
-            let arg_type = match param.value {
-                ArgValue::Empty => ArgType::EMPTY,
-                ArgValue::Number { .. } => ArgType::NUMBER,
-                ArgValue::ComplexNumber { .. } => ArgType::NUMBER,
-                ArgValue::String { .. } => ArgType::STRING,
-                ArgValue::Text { .. } => ArgType::TEXT,
-                ArgValue::Location { .. } => ArgType::LOCATION,
-                ArgValue::Potion { .. } => ArgType::POTION,
-                ArgValue::Sound { .. } => ArgType::SOUND,
-                ArgValue::Particle { .. } => ArgType::PARTICLE,
-                ArgValue::Item { .. } => ArgType::ITEM,
-                ArgValue::Vector { .. } => ArgType::VECTOR,
-                ArgValue::Tag { ..} => ArgType::TAG,
-                ArgValue::Variable { .. } => ArgType::VARIABLE,
-                ArgValue::GameValue { .. } => ArgType::GameValue,
-                ArgValue::Condition { .. } => ArgType::CONDITION
-            };
+            let arg_type = param.value.kind();
             args.push(Arg { value: param.value, index: i as i32, arg_type, start_pos: param.start_pos, end_pos: param.end_pos});
         }
         Ok(args)
@@ -968,37 +1408,114 @@ impl Parser {
         })
     }
 
+    fn constant(&mut self, value: f32, start_pos: Position) -> Result<ArgValueWithPos, ParseError> {
+        let mut sum = 0.0;
+        let mut term = value;
+        let mut end_pos = self.current_token.clone().unwrap().end_pos;
+
+        loop {
+            let operator = match self.peak() {
+                Some(token) => token.token,
+                None => break
+            };
+            match operator {
+                Token::Plus | Token::Minus | Token::Multiply | Token::Divide => {
+                    self.advance();
+                    let operand = match self.advance_err()? {
+                        TokenWithPos { token: Token::Number { value }, end_pos: operand_end_pos, .. } => {
+                            end_pos = operand_end_pos;
+                            value
+                        }
+                        token => return Err(ParseError::InvalidToken { found: Some(token), expected: vec![Token::Number { value: 0.0 }] })
+                    };
+                    match operator {
+                        Token::Plus => {
+                            sum += term;
+                            term = operand;
+                        }
+                        Token::Minus => {
+                            sum += term;
+                            term = -operand;
+                        }
+                        Token::Multiply => term *= operand,
+                        _ => {
+                            if operand == 0.0 {
+                                return Err(ParseError::DivisionByZero { start_pos, end_pos })
+                            }
+                            term /= operand;
+                        }
+                    }
+                }
+                Token::Number { value } if value < 0.0 => {
+                    end_pos = self.advance().unwrap().end_pos;
+                    sum += term;
+                    term = value;
+                }
+                _ => break
+            }
+        }
+
+        Ok(ArgValueWithPos {
+            value: ArgValue::Number { number: sum + term },
+            start_pos,
+            end_pos
+        })
+    }
+
     fn make_location(&mut self) -> Result<ArgValueWithPos, ParseError> {
         let mut pitch = None;
         let mut yaw = None;
         let start_pos = self.current_token.clone().unwrap().start_pos;
-        let loc_params = self.make_params()?;
+        self.allow_arithmetic = true;
+        let loc_params = self.make_params();
+        self.allow_arithmetic = false;
+        let loc_params = split_coordinates(loc_params?).map_err(|pos| ParseError::InvalidLocation { pos, msg: "Invalid coordinates".into() })?;
+        let (named, loc_params): (Vec<ArgValueWithPos>, Vec<ArgValueWithPos>) = loc_params.into_iter().partition(|param| matches!(param.value, ArgValue::Tag { .. }));
 
         if loc_params.len() < 3 {
             return Err(ParseError::InvalidLocation { pos: self.current_token.clone().unwrap().start_pos, msg: "Not enough arguments".into() })
         }
-        let x = match loc_params[0].value {
-            ArgValue::Number { number } => number,
-            _ => return Err(ParseError::InvalidLocation { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid x coordinate".into() })
+        if !named.is_empty() && loc_params.len() > 3 {
+            return Err(ParseError::InvalidLocation { pos: self.current_token.clone().unwrap().start_pos, msg: "Rotation can be given either by position or by name, not both".into() })
+        }
+        for param in named {
+            let (tag, value) = match param.value {
+                ArgValue::Tag { tag, value, .. } => (tag, value),
+                _ => unreachable!()
+            };
+            let number = match value.as_number() {
+                Some(number) => number,
+                None => return Err(ParseError::InvalidLocation { pos: param.start_pos, msg: format!("Expected {tag} to be a number") })
+            };
+            match tag.as_str() {
+                "pitch" if pitch.is_none() => pitch = Some(number),
+                "yaw" if yaw.is_none() => yaw = Some(number),
+                "pitch" | "yaw" => return Err(ParseError::InvalidLocation { pos: param.start_pos, msg: format!("Duplicate {tag}") }),
+                _ => return Err(ParseError::InvalidLocation { pos: param.start_pos, msg: format!("Unknown field '{tag}', expected pitch or yaw") })
+            }
+        }
+        let x = match loc_params[0].value.as_number() {
+            Some(number) => number,
+            None => return Err(ParseError::InvalidLocation { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid x coordinate".into() })
         };
-        let y = match loc_params[1].value {
-            ArgValue::Number { number } => number,
-            _ => return Err(ParseError::InvalidLocation { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid y coordinate".into() })
+        let y = match loc_params[1].value.as_number() {
+            Some(number) => number,
+            None => return Err(ParseError::InvalidLocation { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid y coordinate".into() })
         };
-        let z = match loc_params[2].value {
-            ArgValue::Number { number } => number,
-            _ => return Err(ParseError::InvalidLocation { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid z coordinate".into() })
+        let z = match loc_params[2].value.as_number() {
+            Some(number) => number,
+            None => return Err(ParseError::InvalidLocation { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid z coordinate".into() })
         };
         if loc_params.len() >= 4 {
-            match loc_params[3].value {
-                ArgValue::Number { number } => pitch = Some(number),
-                _ => return Err(ParseError::InvalidLocation { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid pitch".into() })
+            match loc_params[3].value.as_number() {
+                Some(number) => pitch = Some(number),
+                None => return Err(ParseError::InvalidLocation { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid pitch".into() })
             }
         }
         if loc_params.len() == 5 {
-            match loc_params[4].value {
-                ArgValue::Number { number } => yaw = Some(number),
-                _ => return Err(ParseError::InvalidLocation { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid yaw".into() })
+            match loc_params[4].value.as_number() {
+                Some(number) => yaw = Some(number),
+                None => return Err(ParseError::InvalidLocation { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid yaw".into() })
             }
         }
         if loc_params.len() > 5 {
@@ -1013,22 +1530,25 @@ impl Parser {
 
     fn make_vector(&mut self) -> Result<ArgValueWithPos, ParseError> {
         let start_pos = self.current_token.clone().unwrap().start_pos;
-        let vec_params = self.make_params()?;
+        self.allow_arithmetic = true;
+        let vec_params = self.make_params();
+        self.allow_arithmetic = false;
+        let vec_params = split_coordinates(vec_params?).map_err(|pos| ParseError::InvalidVector { pos, msg: "Invalid coordinates".into() })?;
 
         if vec_params.len() < 3 {
             return Err(ParseError::InvalidVector { pos: self.current_token.clone().unwrap().start_pos, msg: "Not enough arguments".into() })
         }
-        let x = match vec_params[0].value {
-            ArgValue::Number { number } => number,
-            _ => return Err(ParseError::InvalidVector { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid x coordinate".into() })
+        let x = match vec_params[0].value.as_number() {
+            Some(number) => number,
+            None => return Err(ParseError::InvalidVector { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid x coordinate".into() })
         };
-        let y = match vec_params[1].value {
-            ArgValue::Number { number } => number,
-            _ => return Err(ParseError::InvalidVector { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid y coordinate".into() })
+        let y = match vec_params[1].value.as_number() {
+            Some(number) => number,
+            None => return Err(ParseError::InvalidVector { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid y coordinate".into() })
         };
-        let z = match vec_params[2].value {
-            ArgValue::Number { number } => number,
-            _ => return Err(ParseError::InvalidVector { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid z coordinate".into() })
+        let z = match vec_params[2].value.as_number() {
+            Some(number) => number,
+            None => return Err(ParseError::InvalidVector { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid z coordinate".into() })
         };
         if vec_params.len() > 3 {
             return Err(ParseError::InvalidVector { pos: self.current_token.clone().unwrap().start_pos, msg: "Too many arguments".into() })
@@ -1047,24 +1567,30 @@ impl Parser {
         if sound_params.len() < 3 {
             return Err(ParseError::InvalidSound { pos: self.current_token.clone().unwrap().start_pos, msg: "Not enough arguments".into() })
         }
-        let sound = match &sound_params[0].value {
-            ArgValue::String { string } => string.clone(),
-            ArgValue::Text { text } => text.clone(),
-            _ => return Err(ParseError::InvalidSound { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid sound type".into() })
+        let sound = match sound_params[0].value.as_text() {
+            Some(sound) => sound.to_owned(),
+            None => return Err(ParseError::InvalidSound { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid sound type".into() })
         };
-        let volume = match sound_params[1].value {
-            ArgValue::Number { number } => number,
-            _ => return Err(ParseError::InvalidSound { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid volume".into() })
+        let volume = match sound_params[1].value.as_number() {
+            Some(number) => number,
+            None => return Err(ParseError::InvalidSound { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid volume".into() })
         };
-        let pitch = match sound_params[2].value {
-            ArgValue::Number { number } => number,
-            _ => return Err(ParseError::InvalidSound { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid pitch".into() })
+        let pitch = match sound_params[2].value.as_number() {
+            Some(number) => number,
+            None => return Err(ParseError::InvalidSound { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid pitch".into() })
+        };
+        let variant = match sound_params.get(3) {
+            Some(param) => match param.value.as_text() {
+                Some(variant) => Some(variant.to_owned()),
+                None => return Err(ParseError::InvalidSound { pos: param.start_pos.clone(), msg: "Invalid variant".into() })
+            },
+            None => None
         };
-        if sound_params.len() > 3 {
+        if sound_params.len() > 4 {
             return Err(ParseError::InvalidSound { pos: self.current_token.clone().unwrap().start_pos, msg: "Too many arguments".into() })
         }
         Ok(ArgValueWithPos {
-            value: ArgValue::Sound { sound, volume, pitch },
+            value: ArgValue::Sound { sound, variant, volume, pitch },
             start_pos,
             end_pos: self.current_token.clone().unwrap().end_pos
         }) 
@@ -1072,21 +1598,25 @@ impl Parser {
 
     fn make_potion(&mut self) -> Result<ArgValueWithPos, ParseError> {
         let start_pos = self.current_token.clone().unwrap().start_pos;
-        let potion_params = self.make_params()?;
+        self.allow_infinite = true;
+        let potion_params = self.make_params();
+        self.allow_infinite = false;
+        let potion_params = potion_params?;
 
         if potion_params.len() < 3 {
             return Err(ParseError::InvalidPotion { pos: self.current_token.clone().unwrap().start_pos, msg: "Not enough arguments".into() })
         }
-        let potion = match &potion_params[0].value {
-            ArgValue::String { string } => string.clone(),
-            ArgValue::Text { text } => text.clone(),
-            _ => return Err(ParseError::InvalidPotion { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid potion type".into() })
+        let potion = match potion_params[0].value.as_text() {
+            Some(potion) => potion.to_owned(),
+            None => return Err(ParseError::InvalidPotion { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid potion type".into() })
         };
         let amplifier = match potion_params[1].value {
+            ArgValue::Number { number } if number.is_infinite() => return Err(ParseError::InvalidPotion { pos: potion_params[1].start_pos.clone(), msg: "Amplifier can not be infinite".into() }),
             ArgValue::Number { number } => number,
             _ => return Err(ParseError::InvalidPotion { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid amplifier".into() })
         };
         let duration = match potion_params[2].value {
+            ArgValue::Number { number } if number.is_infinite() => INFINITE_POTION_DURATION,
             ArgValue::Number { number } => number,
             _ => return Err(ParseError::InvalidPotion { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid duration".into() })
         };
@@ -1239,12 +1769,18 @@ impl Parser {
         if item_params.len() < 1 {
             return Err(ParseError::InvalidItem { pos: self.current_token.clone().unwrap().start_pos, msg: "Not enough arguments".into() })
         }
-        let item = match &item_params[0].value {
+        let mut item = match &item_params[0].value {
             ArgValue::String { string } => string.clone(),
             ArgValue::Text { text } => text.clone(),
             _ => return Err(ParseError::InvalidItem { pos: self.current_token.clone().unwrap().start_pos, msg: "Invalid item arg type".into() })
         };
-        if item_params.len() > 1 {
+        if let Some(count_param) = item_params.get(1) {
+            match count_param.value {
+                ArgValue::Number { number } if number >= 1.0 && number.fract() == 0.0 => item = with_item_count(&item, number as u32),
+                _ => return Err(ParseError::InvalidItem { pos: count_param.start_pos.clone(), msg: "Count must be a positive whole number".into() })
+            }
+        }
+        if item_params.len() > 2 {
             return Err(ParseError::InvalidItem { pos: self.current_token.clone().unwrap().start_pos, msg: "Too many arguments".into() })
         }
         Ok(ArgValueWithPos {
@@ -1298,19 +1834,579 @@ impl Parser {
     }
 
 
+    fn nested_action(&mut self) -> Result<ArgValueWithPos, ParseError> {
+        let start_pos = self.current_token.clone().unwrap().start_pos;
+        let mut node = self.action_call(ActionType::Variable)?;
+        let end_pos = self.current_token.clone().unwrap().end_pos;
+
+        let value = self.temp_variable();
+
+        for arg in node.args.iter_mut() {
+            arg.index += 1;
+        }
+        node.args.insert(0, Arg { value: value.clone(), index: 0, arg_type: ArgType::VARIABLE, start_pos: start_pos.clone(), end_pos: end_pos.clone() });
+
+        self.pending_expressions.push(ExpressionNode { start_pos: start_pos.clone(), end_pos: node.end_pos.clone(), node: Expression::Action { node } });
+        Ok(ArgValueWithPos { value, start_pos, end_pos })
+    }
+
+    // Every generated variable comes from here, skipping names that also appear in the source
+    fn temp_variable(&mut self) -> ArgValue {
+        loop {
+            self.temp_variables += 1;
+            let name = format!("dfrs_tmp_{}", self.temp_variables);
+            let taken = self.tokens.iter().any(|token| match &token.token {
+                Token::Identifier { value } | Token::Variable { value } => *value == name,
+                _ => false
+            });
+            if !taken {
+                return ArgValue::Variable { name, scope: "line".into() }
+            }
+        }
+    }
+
     fn get_variable(&self, value: String) -> Option<(String, String)> {
         for node in &self.variables {
             if node.dfrs_name == value {
-                let scope = match node.var_type {
-                    VariableType::Line => "line",
-                    VariableType::Local => "local",
-                    VariableType::Game => "unsaved",
-                    VariableType::Save => "saved",
-                };
-                return Some((node.df_name.clone(), scope.to_owned()))
+                return Some((node.df_name.clone(), node.var_type.scope().to_owned()))
             }
         }
 
         None
     }
-}
\ No newline at end of file
+
+    fn unknown_variable(&self, found: String, start_pos: Position, end_pos: Position) -> ParseError {
+        let max_distance = (found.chars().count() / 3).max(2);
+        let mut candidates: Vec<(usize, &String)> = self.variables.iter()
+            .map(|node| (edit_distance(&found, &node.dfrs_name), &node.dfrs_name))
+            .filter(|(distance, _)| *distance <= max_distance)
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        let suggestions = candidates.into_iter().take(3).map(|(_, name)| name.clone()).collect();
+        ParseError::UnknownVariable { found, suggestions, start_pos, end_pos }
+    }
+}
+
+// Names that collide with a keyword or contain spaces can be quoted with backticks, `send message` is sendMessage
+fn action_name(token: &Token) -> Option<String> {
+    match token {
+        Token::Identifier { value } => Some(value.clone()),
+        Token::Variable { value } if value.contains(char::is_whitespace) => Some(to_camel_case(value)),
+        Token::Variable { value } => Some(value.clone()),
+        _ => None
+    }
+}
+
+fn keyword_name(keyword: &Keyword) -> String {
+    KEYWORDS.entries().find(|(_, value)| *value == keyword).map(|(name, _)| name.to_string()).unwrap_or_else(|| keyword.to_string())
+}
+
+// Coordinates copied from the debug screen are a single space separated string
+fn split_coordinates(params: Vec<ArgValueWithPos>) -> Result<Vec<ArgValueWithPos>, Position> {
+    let (named, positional): (Vec<ArgValueWithPos>, Vec<ArgValueWithPos>) = params.into_iter().partition(|param| matches!(param.value, ArgValue::Tag { .. }));
+    let text = match positional.as_slice() {
+        [param] => param.value.as_text().map(|text| text.to_owned()),
+        _ => None
+    };
+    let Some(text) = text else {
+        return Ok(positional.into_iter().chain(named).collect())
+    };
+    let param = &positional[0];
+    let mut coordinates = vec![];
+    for component in text.split_whitespace() {
+        match component.parse::<f32>() {
+            Ok(number) => coordinates.push(ArgValueWithPos { value: ArgValue::Number { number }, start_pos: param.start_pos.clone(), end_pos: param.end_pos.clone() }),
+            Err(_) => return Err(param.start_pos.clone())
+        }
+    }
+    Ok(coordinates.into_iter().chain(named).collect())
+}
+
+fn flag_args(flag: &ArgValue, value: f32, start_pos: &Position, end_pos: &Position) -> Vec<Arg> {
+    vec![
+        Arg { value: flag.clone(), index: 0, arg_type: ArgType::VARIABLE, start_pos: start_pos.clone(), end_pos: end_pos.clone() },
+        Arg { value: ArgValue::Number { number: value }, index: 1, arg_type: ArgType::NUMBER, start_pos: start_pos.clone(), end_pos: end_pos.clone() }
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{lex, parse};
+
+    #[test]
+    fn repeat_without_body() {
+        match parse("@join {\n    repeat forever();\n}") {
+            Err(ParseError::MissingBody { name, start_pos, end_pos }) => {
+                assert_eq!(name, "repeat 'forever'");
+                assert_eq!((start_pos, end_pos), (Position::new(2, 12), Position::new(2, 19)));
+            }
+            other => panic!("Expected a missing body, got {other:?}")
+        }
+    }
+
+    #[test]
+    fn conditional_without_body() {
+        assert!(matches!(parse("@join {\n    ifp isSneaking();\n}"), Err(ParseError::MissingBody { name, .. }) if name == "conditional 'isSneaking'"));
+    }
+
+    #[test]
+    fn nested_action_in_repeat_condition() {
+        assert!(matches!(parse("@join {\n    repeat while(ifv equal(v.add(1, 2), 3)) {\n    }\n}"), Err(ParseError::ActionInRepeatCondition { .. })));
+    }
+
+    #[test]
+    fn infinite_potion_duration() {
+        let node = parse("@join {\n    p.givePotion(Potion(\"speed\", 1, infinite));\n}").unwrap();
+        let Expression::Action { node } = &node.events[0].expressions[0].node else { panic!("Expected an action") };
+        assert!(matches!(node.args[0].value, ArgValue::Potion { amplifier: 1.0, duration: INFINITE_POTION_DURATION, .. }));
+    }
+
+    #[test]
+    fn infinite_potion_amplifier() {
+        assert!(matches!(parse("@join {\n    p.givePotion(Potion(\"speed\", infinite, 5));\n}"), Err(ParseError::InvalidPotion { msg, .. }) if msg == "Amplifier can not be infinite"));
+    }
+
+    #[test]
+    fn action_with_two_selectors() {
+        match parse("@join {\n    p:default:selection.sendMessage(\"Hi\");\n}") {
+            Err(ParseError::MultipleSelectors { start_pos, end_pos }) => assert_eq!((start_pos, end_pos), (Position::new(2, 15), Position::new(2, 24))),
+            other => panic!("Expected multiple selectors, got {other:?}")
+        }
+    }
+
+    #[test]
+    fn action_with_one_selector() {
+        let node = parse("@join {\n    p:selection.sendMessage(\"Hi\");\n}").unwrap();
+        let Expression::Action { node } = &node.events[0].expressions[0].node else { panic!("Expected an action") };
+        assert_eq!(node.selector, Selector::Selection);
+    }
+
+    // The value of the first argument of the first action in the join event
+    fn first_arg(args: &str) -> Result<ArgValue, ParseError> {
+        let node = parse(&format!("@join {{\n    p.teleport({args});\n}}"))?;
+        let Expression::Action { node } = &node.events[0].expressions[0].node else { panic!("Expected an action") };
+        Ok(node.args[0].value.clone())
+    }
+
+    #[test]
+    fn location_arithmetic() {
+        assert!(matches!(first_arg("Location(10 + 5, 2 * 3 - 1, 64 / 2)").unwrap(), ArgValue::Location { x: 15.0, y: 5.0, z: 32.0, pitch: None, yaw: None }));
+        assert!(matches!(first_arg("Location(1 + 2 * 3, 8 - 2 - 1, 1 -1, 45 + 45)").unwrap(), ArgValue::Location { x: 7.0, y: 5.0, z: 0.0, pitch: Some(90.0), yaw: None }));
+    }
+
+    #[test]
+    fn vector_arithmetic() {
+        assert!(matches!(first_arg("Vector(0.5 * 2, -1 + 3, 9 / 3)").unwrap(), ArgValue::Vector { x: 1.0, y: 2.0, z: 3.0 }));
+    }
+
+    #[test]
+    fn coordinate_division_by_zero() {
+        match first_arg("Location(1 / 0, 0, 0)") {
+            Err(ParseError::DivisionByZero { start_pos, end_pos }) => assert_eq!((start_pos, end_pos), (Position::new(2, 25), Position::new(2, 30))),
+            other => panic!("Expected a division by zero, got {other:?}")
+        }
+    }
+
+    #[test]
+    fn arithmetic_needs_numbers() {
+        assert!(matches!(first_arg("Location(1 + \"a\", 0, 0)"), Err(ParseError::InvalidToken { .. })));
+    }
+
+    #[test]
+    fn arithmetic_only_in_coordinates() {
+        assert!(first_arg("1 + 2").is_err());
+    }
+
+    // Type, name and inversion of each conditional in an and chain, outermost first
+    fn chain(source: &str) -> Vec<(ConditionalType, String, bool)> {
+        let node = parse(source).unwrap();
+        let mut chain = vec![];
+        let mut expressions = &node.events[0].expressions;
+        while let Some(ExpressionNode { node: Expression::Conditional { node }, .. }) = expressions.first() {
+            chain.push((node.conditional_type.clone(), node.name.clone(), node.inverted));
+            expressions = &node.expressions;
+        }
+        chain
+    }
+
+    #[test]
+    fn and_chain() {
+        let chain = chain("@join {\n    ifp isSneaking() and !isFlying() and ife isNear(Location(0,0,0), 10) and isGrounded() {\n        p.sendMessage(\"A\");\n    }\n}");
+        assert_eq!(chain, [
+            (ConditionalType::Player, "isSneaking".to_string(), false),
+            (ConditionalType::Player, "isFlying".to_string(), true),
+            (ConditionalType::Entity, "isNear".to_string(), false),
+            (ConditionalType::Entity, "isGrounded".to_string(), false)
+        ]);
+    }
+
+    #[test]
+    fn and_chain_else_on_every_conditional() {
+        let node = parse("@join {\n    ifp isSneaking() and isFlying() {\n        p.sendMessage(\"A\");\n    } else {\n        p.sendMessage(\"B\");\n    }\n}").unwrap();
+        let Expression::Conditional { node: outer } = &node.events[0].expressions[0].node else { panic!("Expected a conditional") };
+        let Expression::Conditional { node: inner } = &outer.expressions[0].node else { panic!("Expected a nested conditional") };
+        assert_eq!(outer.expressions.len(), 1);
+        assert_eq!(outer.else_expressions.len(), 1);
+        assert_eq!(inner.else_expressions.len(), 1);
+    }
+
+    #[test]
+    fn and_without_condition() {
+        assert!(matches!(parse("@join {\n    ifp isSneaking() and {\n    }\n}"), Err(ParseError::InvalidToken { .. })));
+    }
+
+    // DiamondFire functions pass results back through variables, so a return type has nothing to describe
+    #[test]
+    fn function_return_type_is_rejected() {
+        match parse("fn foo(): number {\n}") {
+            Err(ParseError::InvalidToken { found: Some(found), .. }) => assert_eq!(found.token, Token::Colon),
+            other => panic!("Expected an invalid token, got {other:?}")
+        }
+        assert!(parse("fn foo(result: variable) {\n}").is_ok());
+    }
+
+    fn first_conditional(source: &str) -> ConditionalNode {
+        match parse(source).unwrap().events[0].expressions[0].node.clone() {
+            Expression::Conditional { node } => node,
+            other => panic!("Expected a conditional, got {other:?}")
+        }
+    }
+
+    #[test]
+    fn inversion_range() {
+        let node = first_conditional("@join {\n    ifp !isSneaking() {\n    }\n}");
+        assert!(node.inverted);
+        assert_eq!((node.inversion_start_pos, node.inversion_end_pos), (Some(Position::new(2, 9)), Some(Position::new(2, 10))));
+
+        let node = first_conditional("@join {\n    ifp isSneaking() {\n    }\n}");
+        assert!(!node.inverted);
+        assert_eq!((node.inversion_start_pos, node.inversion_end_pos), (None, None));
+    }
+
+    #[test]
+    fn double_inversion_cancels_out() {
+        let node = first_conditional("@join {\n    ifp !!isSneaking() {\n    }\n}");
+        assert!(!node.inverted);
+        assert_eq!((node.inversion_start_pos, node.inversion_end_pos), (Some(Position::new(2, 9)), Some(Position::new(2, 11))));
+        assert!(first_conditional("@join {\n    ifp !!!isSneaking() {\n    }\n}").inverted);
+    }
+
+    #[test]
+    fn unknown_function_setting() {
+        match parse("fn foo() #icon {\n}") {
+            Err(ParseError::UnknownSetting { found, start_pos, end_pos }) => {
+                assert_eq!(found, "icon");
+                assert_eq!((start_pos, end_pos), (Position::new(1, 11), Position::new(1, 15)));
+            }
+            other => panic!("Expected an unknown setting, got {other:?}")
+        }
+        assert!(parse("fn foo() #hidden {\n}").unwrap().functions[0].hidden);
+        assert!(parse("proc loop #hidden {\n}").unwrap().processes[0].hidden);
+    }
+
+    #[test]
+    fn event_prefixes() {
+        let types: Vec<Option<ActionType>> = parse("@entity:teleport {\n}\n@player:teleport {\n}\n@teleport {\n}").unwrap().events.into_iter().map(|event| event.event_type).collect();
+        assert_eq!(types, [Some(ActionType::Entity), Some(ActionType::Player), None]);
+        match parse("@mob:teleport {\n}") {
+            Err(ParseError::UnknownEventPrefix { found, start_pos, end_pos }) => {
+                assert_eq!(found, "mob");
+                assert_eq!((start_pos, end_pos), (Position::new(1, 2), Position::new(1, 5)));
+            }
+            other => panic!("Expected an unknown event prefix, got {other:?}")
+        }
+    }
+
+    fn missing_semicolon(source: &str) -> Position {
+        match parse(source) {
+            Err(ParseError::MissingSemicolon { pos }) => pos,
+            other => panic!("Expected a missing semicolon, got {other:?}")
+        }
+    }
+
+    #[test]
+    fn missing_semicolon_after_statement() {
+        assert_eq!(missing_semicolon("@join {\n    p.sendMessage(\"A\")\n    p.sendMessage(\"B\");\n}"), Position::new(2, 23));
+        assert_eq!(missing_semicolon("@join {\n    line count\n    p.sendMessage(count);\n}"), Position::new(2, 15));
+        assert_eq!(missing_semicolon("@join {\n    call(\"foo\")   \n}"), Position::new(2, 16));
+        assert_eq!(missing_semicolon("game players\n@join {\n}"), Position::new(1, 13));
+    }
+
+    #[test]
+    fn missing_semicolon_at_end_of_file() {
+        assert_eq!(missing_semicolon("@join {\n    p.sendMessage(\"A\")"), Position::new(2, 23));
+    }
+
+    fn invalid_item(args: &str) -> (Position, String) {
+        match first_arg(args) {
+            Err(ParseError::InvalidItem { pos, msg }) => (pos, msg),
+            other => panic!("Expected an invalid item, got {other:?}")
+        }
+    }
+
+    #[test]
+    fn item_count() {
+        let ArgValue::Item { item } = first_arg("Item(\"{Count:1b,id:\\\"minecraft:stone\\\"}\", 16)").unwrap() else { panic!("Expected an item") };
+        assert_eq!(item, "{Count:16b,id:\"minecraft:stone\"}");
+    }
+
+    #[test]
+    fn invalid_item_counts() {
+        let message = "Count must be a positive whole number".to_string();
+        assert_eq!(invalid_item("Item(\"{Count:1b}\", 1.5)"), (Position::new(2, 35), message.clone()));
+        assert_eq!(invalid_item("Item(\"{Count:1b}\", 0)").1, message);
+        assert_eq!(invalid_item("Item(\"{Count:1b}\", -3)").1, message);
+        assert_eq!(invalid_item("Item(\"{Count:1b}\", \"2\")").1, message);
+    }
+
+    fn nested_conditionals(depth: usize) -> String {
+        format!("@join {{\n{}p.sendMessage(\"A\");\n{}}}", "ifp isSneaking() {\n".repeat(depth), "}\n".repeat(depth))
+    }
+
+    fn nested_args(depth: usize) -> String {
+        format!("@join {{\n    p.sendMessage({}1{});\n}}", "v.add(".repeat(depth), ", 1)".repeat(depth))
+    }
+
+    fn parse_with_max_depth(source: &str, max_depth: u32) -> Result<FileNode, ParseError> {
+        let mut parser = Parser::new(lex(source));
+        parser.set_max_depth(max_depth);
+        parser.run()
+    }
+
+    #[test]
+    fn nesting_within_limit() {
+        assert!(parse(&nested_conditionals(45)).is_ok());
+        assert!(parse(&nested_args(45)).is_ok());
+        assert!(parse_with_max_depth(&nested_conditionals(60), 80).is_ok());
+    }
+
+    #[test]
+    fn nesting_too_deep() {
+        let err = parse(&nested_conditionals(80)).unwrap_err();
+        assert_eq!(err.code(), "E118");
+        assert!(matches!(err, ParseError::TooDeep { max_depth: 50, pos } if pos.line == 51));
+        assert!(matches!(parse(&nested_args(80)), Err(ParseError::TooDeep { max_depth: 50, .. })));
+        assert!(matches!(parse_with_max_depth(&nested_conditionals(6), 5), Err(ParseError::TooDeep { max_depth: 5, .. })));
+    }
+
+    #[test]
+    fn pathological_nesting_does_not_overflow() {
+        assert!(matches!(parse(&nested_conditionals(20000)), Err(ParseError::TooDeep { .. })));
+        assert!(matches!(parse(&nested_args(20000)), Err(ParseError::TooDeep { .. })));
+    }
+
+    fn location_error(args: &str) -> (String, Position) {
+        match first_arg(args) {
+            Err(ParseError::InvalidLocation { pos, msg }) => (msg, pos),
+            Err(err) => panic!("Expected an invalid location, got {}", err.code()),
+            Ok(value) => panic!("Expected an invalid location, got {value:?}")
+        }
+    }
+
+    #[test]
+    fn named_rotation() {
+        assert!(matches!(first_arg("Location(1, 2, 3, yaw=90, pitch=10)").unwrap(), ArgValue::Location { x: 1.0, y: 2.0, z: 3.0, pitch: Some(10.0), yaw: Some(90.0) }));
+        assert!(matches!(first_arg("Location(1, 2, 3, pitch=10, yaw=90)").unwrap(), ArgValue::Location { pitch: Some(10.0), yaw: Some(90.0), .. }));
+        assert!(matches!(first_arg("Location(1, 2, 3, yaw=-45)").unwrap(), ArgValue::Location { pitch: None, yaw: Some(-45.0), .. }));
+        assert!(matches!(first_arg("Location(1, 2, 3, 10, 90)").unwrap(), ArgValue::Location { pitch: Some(10.0), yaw: Some(90.0), .. }));
+        assert!(matches!(first_arg("Location(1, 2, 3, 10)").unwrap(), ArgValue::Location { pitch: Some(10.0), yaw: None, .. }));
+    }
+
+    #[test]
+    fn invalid_named_rotation() {
+        assert_eq!(location_error("Location(1, 2, 3, 10, yaw=90)").0, "Rotation can be given either by position or by name, not both");
+        assert_eq!(location_error("Location(1, 2, 3, yaw=90, yaw=80)"), ("Duplicate yaw".into(), Position::new(2, 42)));
+        assert_eq!(location_error("Location(1, 2, 3, roll=90)").0, "Unknown field 'roll', expected pitch or yaw");
+        assert_eq!(location_error("Location(1, 2, 3, pitch=\"up\")").0, "Expected pitch to be a number");
+        assert_eq!(location_error("Location(1, 2, yaw=90)").0, "Not enough arguments");
+    }
+
+    fn unknown_variable(source: &str) -> (String, Vec<String>, Position, Position) {
+        match parse(source) {
+            Err(ParseError::UnknownVariable { found, suggestions, start_pos, end_pos }) => (found, suggestions, start_pos, end_pos),
+            Err(err) => panic!("Expected an unknown variable, got {}", err.code()),
+            Ok(_) => panic!("Expected an unknown variable")
+        }
+    }
+
+    #[test]
+    fn unknown_variable_suggestions() {
+        let declared = "game points;\ngame pointer;\nsave coins;\n";
+        let (found, suggestions, start_pos, end_pos) = unknown_variable(&format!("{declared}@join {{\n    p.sendMessage(pints);\n}}"));
+        assert_eq!((found.as_str(), suggestions), ("pints", vec!["points".to_owned()]));
+        assert_eq!((start_pos, end_pos), (Position::new(5, 19), Position::new(5, 24)));
+        assert_eq!(unknown_variable(&format!("{declared}@join {{\n    p.sendMessage(pointe);\n}}")).1, vec!["pointer", "points"]);
+        assert_eq!(unknown_variable(&format!("{declared}@join {{\n    v.equal(coin, 1);\n}}")).1, vec!["coins"]);
+        assert!(unknown_variable(&format!("{declared}@join {{\n    p.sendMessage(health);\n}}")).1.is_empty());
+    }
+
+    // Line variables only exist in the event that declares them, so they aren't suggested anywhere else
+    #[test]
+    fn unknown_variable_suggestions_are_scoped() {
+        let (_, suggestions, ..) = unknown_variable("@join {\n    line counter;\n}\n@leave {\n    p.heal(countr);\n}");
+        assert!(suggestions.is_empty());
+        let (_, suggestions, ..) = unknown_variable("@join {\n    line counter;\n    p.heal(countr);\n}");
+        assert_eq!(suggestions, vec!["counter"]);
+    }
+
+    #[test]
+    fn grouped_events() {
+        let events = parse("@join, @entity:blockFall!, @respawn {\n    p.sendMessage(\"A\");\n}").unwrap().events;
+        let heads: Vec<(Option<ActionType>, &str, bool, Position)> = events.iter().map(|event| (event.event_type.clone(), event.event.as_str(), event.cancelled, event.name_end_pos.clone())).collect();
+        assert_eq!(heads, [
+            (None, "join", false, Position::new(1, 6)),
+            (Some(ActionType::Entity), "blockFall", true, Position::new(1, 25)),
+            (None, "respawn", false, Position::new(1, 36))
+        ]);
+        assert!(events.iter().all(|event| event.expressions.len() == 1 && event.end_pos == events[0].end_pos));
+    }
+
+    #[test]
+    fn grouped_events_need_an_event() {
+        assert!(matches!(parse("@join, {\n}"), Err(ParseError::InvalidToken { .. })));
+        assert!(matches!(parse("@join @leave {\n}"), Err(ParseError::InvalidToken { .. })));
+    }
+
+    #[test]
+    fn event_priority_is_rejected() {
+        match parse("@join #priority(2) {\n}") {
+            Err(ParseError::InvalidToken { found: Some(found), expected }) => {
+                assert_eq!((found.token, found.start_pos), (Token::Hash, Position::new(1, 7)));
+                assert_eq!(expected, [Token::OpenParenCurly, Token::ExclamationMark, Token::Comma]);
+            }
+            other => panic!("Expected an invalid token, got {other:?}")
+        }
+    }
+
+    #[test]
+    fn coordinates_from_string() {
+        assert!(matches!(first_arg("Location(\"10 64 -5\")").unwrap(), ArgValue::Location { x: 10.0, y: 64.0, z: -5.0, pitch: None, yaw: None }));
+        assert!(matches!(first_arg("Location('10.5  64\t-5.25 0 90')").unwrap(), ArgValue::Location { x: 10.5, y: 64.0, z: -5.25, pitch: Some(0.0), yaw: Some(90.0) }));
+        assert!(matches!(first_arg("Location(\"10 64 -5\", yaw=90)").unwrap(), ArgValue::Location { x: 10.0, pitch: None, yaw: Some(90.0), .. }));
+        assert!(matches!(first_arg("Vector(\"1 0 -1\")").unwrap(), ArgValue::Vector { x: 1.0, y: 0.0, z: -1.0 }));
+    }
+
+    #[test]
+    fn invalid_coordinates_from_string() {
+        assert_eq!(location_error("Location(\"10 up -5\")"), ("Invalid coordinates".into(), Position::new(2, 25)));
+        assert_eq!(location_error("Location(\"10 64\")").0, "Not enough arguments");
+        assert_eq!(location_error("Location(\"\")").0, "Not enough arguments");
+        assert!(matches!(first_arg("Vector(\"1, 0, 1\")"), Err(ParseError::InvalidVector { msg, .. }) if msg == "Invalid coordinates"));
+    }
+
+    #[test]
+    fn return_takes_no_value() {
+        assert!(matches!(parse("@join {\n    return 5;\n}"), Err(ParseError::MissingSemicolon { pos }) if pos == Position::new(2, 11)));
+    }
+
+    #[test]
+    fn unless_inverts_condition() {
+        let node = first_conditional("@join {\n    unless ifp isSneaking() {\n        p.sendMessage(\"A\");\n    } else {\n        p.sendMessage(\"B\");\n        p.sendMessage(\"C\");\n    }\n}");
+        assert_eq!((node.conditional_type, node.name.as_str(), node.inverted), (ConditionalType::Player, "isSneaking", true));
+        assert_eq!((node.expressions.len(), node.else_expressions.len()), (1, 2));
+        assert!(!first_conditional("@join {\n    unless ifv !equal(1, 1) {\n    }\n}").inverted);
+    }
+
+    #[test]
+    fn unless_takes_one_condition() {
+        assert!(matches!(parse("@join {\n    unless ifp isSneaking() and isFlying() {\n    }\n}"), Err(ParseError::MissingBody { .. })));
+        assert!(matches!(parse("@join {\n    unless p.sendMessage(\"A\");\n}"), Err(ParseError::InvalidToken { .. })));
+    }
+
+    fn first_action(source: &str) -> ActionNode {
+        let node = parse(source).unwrap();
+        let Expression::Action { node } = &node.events[0].expressions[0].node else { panic!("Expected an action") };
+        node.clone()
+    }
+
+    #[test]
+    fn quoted_action_names() {
+        let node = first_action("@join {\n    p:`selection`.`send message`(\"Hi\");\n}");
+        assert_eq!((node.name.as_str(), node.selector, node.args.len()), ("sendMessage", Selector::Selection, 1));
+        assert_eq!((node.selector_start_pos, node.selector_end_pos), (Position::new(2, 7), Position::new(2, 18)));
+        assert_eq!(first_action("@join {\n    c.`return`();\n}").name, "return");
+        assert_eq!(first_action("@join {\n    p.`Send   Message`();\n}").name, "sendMessage");
+        assert_eq!(first_conditional("@join {\n    ifp `is sneaking`() {\n    }\n}").name, "isSneaking");
+    }
+
+    #[test]
+    fn quoted_unknown_selector() {
+        // A quoted name after the colon has to be a selector
+        assert!(matches!(parse("@join {\n    p:`nobody`.sendMessage(\"Hi\");\n}"), Err(ParseError::InvalidToken { .. })));
+    }
+
+    #[test]
+    fn else_if_chains() {
+        let node = first_conditional("@join {\n    ifp isSneaking() {\n        p.sendMessage(\"A\");\n    } else ifv equal(1, 2) {\n        p.sendMessage(\"B\");\n    } else {\n        p.sendMessage(\"C\");\n    }\n}");
+        assert_eq!(node.else_expressions.len(), 1);
+        let else_expression = &node.else_expressions[0];
+        assert_eq!((else_expression.start_pos.clone(), else_expression.end_pos.clone()), (Position::new(4, 12), Position::new(4, 21)));
+        let Expression::Conditional { node: else_if } = &else_expression.node else { panic!("Expected a conditional") };
+        assert_eq!((else_if.conditional_type.clone(), else_if.name.as_str()), (ConditionalType::Variable, "equal"));
+        assert_eq!((else_if.expressions.len(), else_if.else_expressions.len()), (1, 1));
+        assert!(matches!(parse("@join {\n    ifp isSneaking() {\n    } else ifp {\n    }\n}"), Err(ParseError::InvalidToken { .. })));
+    }
+
+    fn wait_unit(source: &str) -> Option<String> {
+        let node = first_action(source);
+        assert_eq!((node.action_type, node.name.as_str()), (ActionType::Control, "wait"));
+        node.args.iter().find_map(|arg| match &arg.value {
+            ArgValue::Tag { tag, value, .. } if tag == "timeUnit" => match value.as_ref() {
+                ArgValue::Text { text } => Some(text.clone()),
+                _ => None
+            },
+            _ => None
+        })
+    }
+
+    #[test]
+    fn wait_units() {
+        assert_eq!(wait_unit("@join {\n    wait(5);\n}"), None);
+        for (unit, option) in [("tick", "Ticks"), ("ticks", "Ticks"), ("second", "Seconds"), ("seconds", "Seconds"), ("minute", "Minutes"), ("minutes", "Minutes")] {
+            assert_eq!(wait_unit(&format!("@join {{\n    wait(2 {unit});\n}}")).as_deref(), Some(option), "{unit}");
+        }
+        let node = parse("@join {\n    line delay;\n    wait(delay minutes);\n}").unwrap();
+        let Expression::Action { node } = &node.events[0].expressions[1].node else { panic!("Expected an action") };
+        assert!(matches!(&node.args[0].value, ArgValue::Variable { name, .. } if name == "delay"));
+    }
+
+    #[test]
+    fn invalid_wait_units() {
+        match parse("@join {\n    wait(2 hours);\n}") {
+            Err(ParseError::UnknownTimeUnit { found, start_pos, end_pos }) => {
+                assert_eq!((found.as_str(), start_pos, end_pos), ("hours", Position::new(2, 12), Position::new(2, 17)));
+            }
+            other => panic!("Expected an unknown time unit, got {other:?}")
+        }
+        assert_eq!(parse("@join {\n    wait(2 Seconds);\n}").unwrap_err().code(), "E119");
+        assert!(matches!(parse("@join {\n    wait(\"2\");\n}"), Err(ParseError::InvalidToken { .. })));
+        assert!(matches!(parse("@join {\n    wait(2 seconds ticks);\n}"), Err(ParseError::InvalidToken { .. })));
+        assert!(matches!(parse("@join {\n    wait(delay);\n}"), Err(ParseError::UnknownVariable { .. })));
+    }
+
+    #[test]
+    fn guard_inverts_condition_and_stops() {
+        let node = first_conditional("@join {\n    guard ifp isFlying() else {\n        p.sendMessage(\"A\");\n    }\n}");
+        assert_eq!((node.conditional_type, node.name.as_str(), node.inverted), (ConditionalType::Player, "isFlying", true));
+        assert!(node.else_expressions.is_empty());
+        let names: Vec<&str> = node.expressions.iter().map(|expression| match &expression.node {
+            Expression::Action { node } => node.name.as_str(),
+            _ => panic!("Expected an action")
+        }).collect();
+        assert_eq!(names, ["sendMessage", "end"]);
+        assert_eq!(node.expressions[1].start_pos, Position::new(2, 26));
+        assert!(!first_conditional("@join {\n    guard ifv !equal(1, 1) else {\n    }\n}").inverted);
+
+        let node = parse("fn check() {\n    guard ifp isFlying() else {\n    }\n}").unwrap();
+        let Expression::Conditional { node } = &node.functions[0].expressions[0].node else { panic!("Expected a conditional") };
+        assert!(matches!(&node.expressions[0].node, Expression::Action { node } if node.name == "return"));
+    }
+
+    #[test]
+    fn guard_needs_else() {
+        assert!(matches!(parse("@join {\n    guard ifp isFlying() {\n    }\n}"), Err(ParseError::InvalidToken { .. })));
+        assert!(matches!(parse("@join {\n    guard p.sendMessage(\"A\");\n}"), Err(ParseError::InvalidToken { .. })));
+    }
+}
@@ -0,0 +1,109 @@
+use crate::compile::{Block, CompiledLine, Codeline};
+use crate::definitions::action_dump::{Action, ActionDump, ValueList};
+use crate::utility::to_dfrs_name;
+
+pub fn preview(lines: &[CompiledLine], action_dump: &ActionDump) -> String {
+    let mut result = String::new();
+    for line in lines {
+        let codeline: Codeline = match serde_json::from_str(&line.code) {
+            Ok(codeline) => codeline,
+            Err(_) => continue
+        };
+
+        let mut indent = 1;
+        for block in codeline.blocks {
+            if block.id == "bracket" {
+                match block.direct.as_deref() {
+                    Some("open") => indent += 1,
+                    _ => indent -= 1
+                }
+                continue;
+            }
+            let (depth, text) = match describe(&block, action_dump) {
+                Some(text) if is_header(&block) => (0, text),
+                Some(text) => (indent, text),
+                None => continue
+            };
+            result.push_str(&"  ".repeat(depth));
+            result.push_str(&text);
+            result.push('\n');
+        }
+    }
+    result
+}
+
+fn is_header(block: &Block) -> bool {
+    matches!(block.block.as_deref(), Some("event" | "entity_event" | "func" | "process"))
+}
+
+fn describe(block: &Block, action_dump: &ActionDump) -> Option<String> {
+    let action = block.action.clone().unwrap_or_default();
+    let data = block.data.clone().unwrap_or_default();
+    let text = match block.block.as_deref()? {
+        "event" => format!("When player event '{}' happens:", to_dfrs_name(&action)),
+        "entity_event" => format!("When entity event '{}' happens:", to_dfrs_name(&action)),
+        "func" => format!("Function '{data}':"),
+        "process" => format!("Process '{data}':"),
+        "call_func" => format!("Call function '{data}'"),
+        "start_process" => format!("Start process '{data}'"),
+        "else" => "Otherwise:".into(),
+        "player_action" => describe_action(&action_dump.player_actions, &action, ""),
+        "entity_action" => describe_action(&action_dump.entity_actions, &action, ""),
+        "game_action" => describe_action(&action_dump.game_actions, &action, ""),
+        "set_var" => describe_action(&action_dump.variable_actions, &action, ""),
+        "control" => describe_action(&action_dump.control_actions, &action, ""),
+        "select_obj" => describe_action(&action_dump.select_actions, &action, ""),
+        "repeat" => describe_action(&action_dump.repeats, &action, "Repeat "),
+        "if_player" | "if_entity" | "if_game" | "if_var" => {
+            let conditionals = match block.block.as_deref()? {
+                "if_player" => &action_dump.player_conditionals,
+                "if_entity" => &action_dump.entity_conditionals,
+                "if_game" => &action_dump.game_conditionals,
+                _ => &action_dump.variable_conditionals
+            };
+            let prefix = if block.attribute.as_deref() == Some("NOT") { "If not " } else { "If " };
+            describe_action(conditionals, &action, prefix)
+        }
+        _ => return None
+    };
+    Some(text)
+}
+
+fn describe_action(actions: &ValueList<Action>, name: &str, prefix: &str) -> String {
+    let name = to_dfrs_name(name);
+    match actions.get(name.clone()) {
+        Some(action) if !action.description.is_empty() => format!("{prefix}{name}: {}", action.description),
+        _ => format!("{prefix}{name}")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{compile_lines, definitions};
+
+    #[test]
+    fn describes_every_block() {
+        let source = "fn greet() {\n    p.sendMessage(\"Hi\");\n}\nproc loop {\n}\n@join {\n    ifp !isSneaking() {\n        call(\"greet\");\n    } else {\n        repeat forever() {\n            start(\"loop\");\n        }\n    }\n}\n@entity:blockFall {\n    e.heal(1);\n}";
+        assert_eq!(preview(&compile_lines(source), &definitions().action_dump), "\
+Function 'greet':
+  sendMessage: Sends a chat message to a player.
+Process 'loop':
+When player event 'join' happens:
+  If not isSneaking: Checks if a player is sneaking.
+    Call function 'greet'
+  Otherwise:
+    Repeat forever: Repeats code indefinitely.
+      Start process 'loop'
+When entity event 'blockFall' happens:
+  heal: Restores a mob's health.
+");
+    }
+
+    #[test]
+    fn invalid_codeline_is_skipped() {
+        let mut lines = compile_lines("@join {\n    p.sendMessage(\"Hi\");\n}");
+        lines.insert(0, CompiledLine { name: "broken".into(), code: "not json".into(), source_map: vec![] });
+        assert_eq!(preview(&lines, &definitions().action_dump), "When player event 'join' happens:\n  sendMessage: Sends a chat message to a player.\n");
+    }
+}
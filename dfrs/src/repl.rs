@@ -0,0 +1,85 @@
+use std::io::{stdin, stdout, Write};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use colored::Colorize;
+
+use crate::compile::Codeline;
+use crate::compiler::Compiler;
+use crate::definitions::Definitions;
+use crate::{compile_source, load_config, logger};
+
+pub fn run_repl(definitions: Arc<Definitions>) {
+    let config = load_config(&PathBuf::from("dfrs.toml")).unwrap_or_default();
+    logger::set_level(config.logging.level);
+    let compiler = Compiler::new(config, definitions);
+    println!("{}", "Enter an expression to see the blocks it compiles to, or 'exit' to quit".bright_black());
+
+    loop {
+        print!("> ");
+        stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin().read_line(&mut line).unwrap_or(0) == 0 {
+            break;
+        }
+        let line = line.trim();
+        match line {
+            "" => continue,
+            "exit" | "quit" => break,
+            _ => {
+                if let Some(output) = repl_eval(line, &compiler) {
+                    println!("{output}");
+                }
+            }
+        }
+    }
+}
+
+// Expressions are compiled inside a throwaway event, whose block is left out of the output
+fn repl_eval(line: &str, compiler: &Compiler) -> Option<String> {
+    let is_definition = line.starts_with('@') || line.starts_with("fn ") || line.starts_with("proc ");
+    let source = if is_definition { line.to_owned() } else { format!("@join {{ {line} }}") };
+
+    let compiled = compile_source(source, &PathBuf::from("repl.dfrs"), compiler, None).ok()?;
+    let mut output = vec![];
+    for line in compiled {
+        let codeline: Codeline = serde_json::from_str(&line.code).ok()?;
+        let blocks = if is_definition { &codeline.blocks[..] } else { &codeline.blocks[1..] };
+        output.push(serde_json::to_string_pretty(blocks).ok()?);
+    }
+    Some(output.join("\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::Value;
+
+    use super::*;
+    use crate::config::Config;
+    use crate::testing::{actions, definitions};
+
+    fn eval(line: &str) -> Option<Vec<Value>> {
+        repl_eval(line, &Compiler::new(Config::default(), definitions())).map(|output| serde_json::from_str(&output).expect("Invalid blocks"))
+    }
+
+    #[test]
+    fn expression_leaves_out_event() {
+        let blocks = eval("p.sendMessage(\"Hi\");").unwrap();
+        assert_eq!(actions(&blocks), vec!["player_action SendMessage"]);
+        assert_eq!(blocks[0]["args"]["items"][0]["item"]["data"]["name"], "Hi");
+        assert_eq!(actions(&eval("p.sendMessage(v.add(1, 2));").unwrap()), vec!["set_var +", "player_action SendMessage"]);
+    }
+
+    #[test]
+    fn definition_is_kept() {
+        assert_eq!(actions(&eval("@leave { p.heal(1); }").unwrap()), vec!["event Leave", "player_action Heal"]);
+        assert_eq!(actions(&eval("fn greet() { p.heal(1); }").unwrap()), vec!["func greet", "player_action Heal"]);
+    }
+
+    #[test]
+    fn errors_give_no_blocks() {
+        assert!(eval("p.sendMesage(\"Hi\");").is_none());
+        assert!(eval("p.sendMessage(\"Hi\")").is_none());
+        assert!(eval("p.sendMessage(\"Hi\");").is_some());
+    }
+}
@@ -1,110 +1,261 @@
+use std::collections::{HashMap, HashSet};
 use std::io::Read;
+use std::path::{Path, PathBuf};
 use std::{io::Write, net::TcpStream};
 use base64::prelude::*;
 use flate2::write::GzEncoder;
 use flate2::Compression;
 
-use crate::config::Config;
+use crate::config::{Config, Scanning, Sending};
 use crate::compile::CompiledLine;
-use tungstenite::{connect, Message};
+use crate::logger;
+use crate::utility::sha256;
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{connect, Message, WebSocket};
 use url::Url;
 
-pub fn send(code: Vec<CompiledLine>, config: Config) {
+pub type Connection = WebSocket<MaybeTlsStream<TcpStream>>;
+
+// Returns whether every codeline reached the client
+pub fn send(code: Vec<CompiledLine>, config: Config, connection: &mut Option<Connection>) -> bool {
     match config.sending.api {
-        crate::config::SendApi::CodeClient => {
-            send_codeclient(code, config);
-        }
+        crate::config::SendApi::CodeClient => send_codeclient(code, config, connection),
         crate::config::SendApi::Recode => {
+            let mut sent = true;
             for line in code {
-                send_recode(line.code, line.name, config.debug.connection);
+                sent &= send_recode(line.code, line.name, &config);
             }
+            sent
         }
     }
 }
 
-fn send_recode(code: String, name: String, debug: bool) {
-    let data = ("{\"type\": \"template\", \"source\": \"df.rs\", \"data\": \"{\\\"name\\\": \\\"".to_owned() + &name +" \\\",\\\"data\\\":\\\"" + &compress(code) + "\\\"}\"}\n").to_owned();
+pub fn filter_changed(code: Vec<CompiledLine>, cache_file: &PathBuf) -> Vec<CompiledLine> {
+    let sent = load_sent(cache_file);
+    code.into_iter().filter(|line| sent.get(&line.name) != Some(&hash_line(line))).collect()
+}
+
+pub fn save_sent(code: &[CompiledLine], cache_file: &PathBuf) {
+    let mut sent = load_sent(cache_file);
+    for line in code {
+        sent.insert(line.name.clone(), hash_line(line));
+    }
+    let data = serde_json::to_string_pretty(&sent).expect("Failed to serialize sent codelines");
+    if let Err(err) = std::fs::write(cache_file, data) {
+        logger::error(format!("Failed to save sent codelines: {}", err));
+    }
+}
+
+pub fn save_plot_variables(variables: &[String], file: &Path) -> std::io::Result<()> {
+    std::fs::write(file, serde_json::to_string_pretty(variables).expect("Failed to serialize plot variables"))
+}
+
+pub fn load_plot_variables(file: &Path) -> Option<HashSet<String>> {
+    let data = std::fs::read_to_string(file).ok()?;
+    serde_json::from_str(&data).ok()
+}
+
+fn load_sent(cache_file: &PathBuf) -> HashMap<String, String> {
+    match std::fs::read_to_string(cache_file) {
+        Ok(data) => serde_json::from_str(&data).unwrap_or_default(),
+        Err(_) => HashMap::new()
+    }
+}
+
+fn hash_line(line: &CompiledLine) -> String {
+    sha256(&line.code)
+}
+
+fn send_recode(code: String, name: String, config: &Config) -> bool {
+    let debug = config.debug.connection;
+    let data = ("{\"type\": \"template\", \"source\": \"df.rs\", \"data\": \"{\\\"name\\\": \\\"".to_owned() + &name +" \\\",\\\"data\\\":\\\"" + &compress(code, &config.sending) + "\\\"}\"}\n").to_owned();
 
     if debug {
-        println!("{}", data);
+        logger::info(data.clone());
     }
 
     let server_address = "127.0.0.1:31372";
     match TcpStream::connect(server_address) {
         Ok(mut stream) => {
             if debug {
-                println!("Connected to server!");
+                logger::info("Connected to server!".into());
             }
             match stream.write_all(data.as_bytes()) {
                 Ok(_) => {
                     if debug {
-                        println!("Data sent successfully!");
+                        logger::info("Data sent successfully!".into());
                         let mut buffer = [0; 2048];
                         match stream.read(&mut buffer) {
                             Ok(bytes_read) => {
                                 if bytes_read > 0 {
                                     let response = String::from_utf8_lossy(&buffer[..bytes_read]);
-                                    println!("Server response: {:?}", response);
+                                    logger::info(format!("Server response: {:?}", response));
                                 } else {
-                                    println!("No data received from the server.");
+                                    logger::info("No data received from the server.".into());
                                 }
                             }
-                            Err(err) => eprintln!("Error reading from server: {}", err),
+                            Err(err) => logger::error(format!("Error reading from server: {}", err)),
                         }
                     }
+                    true
+                }
+                Err(err) => {
+                    logger::error(format!("Error sending data to server: {}", err));
+                    false
                 }
-                Err(err) => eprintln!("Error sending data to server: {}", err),
             }
         }
-        Err(err) => eprintln!("Failed to connect to server: {}", err),
+        Err(err) => {
+            logger::error(format!("Failed to connect to server: {}", err));
+            false
+        }
     }
 }
 
-fn send_codeclient(code: Vec<CompiledLine>, config: Config) {
-    //TODO error handling
-    let (mut socket, response) = connect(Url::parse("ws://localhost:31375").unwrap()).expect("Can't connect");
-    
-    if config.debug.connection {
-        println!("Connected to server; {:?}", response)
+const CODECLIENT_URL: &str = "ws://localhost:31375";
+
+fn send_codeclient(code: Vec<CompiledLine>, config: Config, connection: &mut Option<Connection>) -> bool {
+    send_codeclient_to(CODECLIENT_URL, code, config, connection)
+}
+
+fn send_codeclient_to(url: &str, code: Vec<CompiledLine>, config: Config, connection: &mut Option<Connection>) -> bool {
+    if connection.is_none() {
+        *connection = connect_codeclient(url, &config);
     }
+    let Some(socket) = connection.as_mut() else { return false };
 
-    socket.send(Message::Text("scopes write_code".into())).unwrap();
+    let Err(err) = place(socket, code.clone(), &config) else { return true };
+    // An open connection may have been closed by the client in the meantime, so reconnect once
+    logger::warn(format!("Lost connection to the code client ({}), reconnecting", err));
+    *connection = connect_codeclient(url, &config);
+    let Some(socket) = connection.as_mut() else { return false };
+    match place(socket, code, &config) {
+        Ok(_) => true,
+        Err(err) => {
+            logger::error(format!("Failed to send code: {}", err));
+            *connection = None;
+            false
+        }
+    }
+}
 
-    loop {
-        let msg = socket.read().expect("Error reading message");
-        
-        if config.debug.connection {
-            println!("Received: {}", msg);
+fn connect_codeclient(url: &str, config: &Config) -> Option<Connection> {
+    let (mut socket, response) = match connect(url) {
+        Ok(res) => res,
+        Err(err) => {
+            logger::error(format!("Failed to connect to the code client: {}", err));
+            return None;
         }
+    };
+
+    if config.debug.connection {
+        logger::info(format!("Connected to server; {:?}", response))
+    }
+
+    let authenticated = socket.send(Message::Text("scopes write_code".into())).map_err(Box::new).and_then(|_| -> Result<(), Box<tungstenite::Error>> {
+        loop {
+            let msg = socket.read()?;
+
+            if config.debug.connection {
+                logger::info(format!("Received: {}", msg));
+            }
 
-        if msg.to_text().expect("response should be text") == "auth" {
-            break;
+            if msg.to_text()? == "auth" {
+                return Ok(());
+            }
+        }
+    });
+    match authenticated {
+        Ok(_) => Some(socket),
+        Err(err) => {
+            logger::error(format!("Failed to authenticate with the code client: {}", err));
+            None
         }
     }
-    
-    socket.send(Message::Text("place swap".into())).unwrap();
+}
+
+fn place(socket: &mut Connection, code: Vec<CompiledLine>, config: &Config) -> Result<(), Box<tungstenite::Error>> {
+    socket.send(Message::Text("place swap".into()))?;
     for line in code {
-        let data = compress(line.code);
-        socket.send(Message::Text(format!("place {}", data))).unwrap();
+        let data = compress(line.code, &config.sending);
+        socket.send(Message::Text(format!("place {}", data)))?;
     }
-    socket.send(Message::Text("place go".into())).unwrap();
+    socket.send(Message::Text("place go".into()))?;
 
     loop {
-        let msg = socket.read().expect("Error reading message");
-        
+        let msg = socket.read()?;
+
         if config.debug.connection {
-            println!("Received: {}", msg);
+            logger::info(format!("Received: {}", msg));
         }
 
-        if msg.to_text().expect("response should be text") == "place done" {
-            break;
+        if msg.to_text()? == "place done" {
+            return Ok(());
         }
     }
 }
 
-fn compress(code: String) -> String {
+#[derive(Debug)]
+pub enum ScanError {
+    InvalidUrl { url: String },
+    ConnectionFailed { url: String, msg: String },
+    ConnectionLost { msg: String },
+    InvalidResponse
+}
+
+pub fn scan_plot(url: &str, scanning: &Scanning, debug: bool) -> Result<String, ScanError> {
+    let url = match Url::parse(url) {
+        Ok(res) => res,
+        Err(_) => return Err(ScanError::InvalidUrl { url: url.to_owned() })
+    };
+    let (mut socket, response) = match connect(url.clone()) {
+        Ok(res) => res,
+        Err(err) => return Err(ScanError::ConnectionFailed { url: url.to_string(), msg: err.to_string() })
+    };
+
+    if debug {
+        logger::info(format!("Connected to server; {:?}", response))
+    }
+
+    let lost = |err: tungstenite::Error| ScanError::ConnectionLost { msg: err.to_string() };
+
+    socket.send(Message::Text(scanning.scope_message.clone())).map_err(lost)?;
+    let msg = socket.read().map_err(lost)?;
+    if debug {
+        logger::info(format!("Received: {}", msg));
+    }
+
+    socket.send(Message::Text(scanning.scan_message.clone())).map_err(lost)?;
+    let msg = socket.read().map_err(lost)?;
+    if debug {
+        logger::info(format!("Received: {}", msg));
+    }
+
+    match msg.into_text() {
+        Ok(res) => Ok(res),
+        Err(_) => Err(ScanError::InvalidResponse)
+    }
+}
+
+// Template items store their code in a json string, which is itself inside a single quoted snbt string
+pub fn give_command(line: &CompiledLine, sending: &Sending) -> String {
+    let template = serde_json::json!({ "author": "dfrs", "name": line.name, "version": 1, "code": compress(line.code.clone(), sending) }).to_string();
+    let name = serde_json::json!({ "text": line.name, "italic": false }).to_string();
+    let snbt = |value: &str| value.replace('\\', "\\\\").replace('\'', "\\'");
+    format!(
+        "/give @p minecraft:ender_chest[minecraft:custom_name='{}',minecraft:custom_data={{PublicBukkitValues:{{\"hypercube:codetemplatedata\":'{}'}}}}]",
+        snbt(&name),
+        snbt(&template)
+    )
+}
+
+fn compress(code: String, sending: &Sending) -> String {
+    let code = match serde_json::from_str::<serde_json::Value>(&code) {
+        Ok(json) if !sending.minify => serde_json::to_string_pretty(&json).unwrap_or(code),
+        _ => code
+    };
     let mut compressed_data = Vec::new();
-    let mut encoder = GzEncoder::new(&mut compressed_data, Compression::default());
+    let mut encoder = GzEncoder::new(&mut compressed_data, Compression::new(sending.compression_level.min(9)));
     
     match encoder.write_all(code.as_bytes()) {
         Ok(_) => {},
@@ -116,4 +267,206 @@ fn compress(code: String) -> String {
     }
 
     BASE64_STANDARD.encode(compressed_data)
-}   
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+
+    use super::*;
+    use crate::testing::{compile_lines, project};
+
+    // A local url that nothing is listening on
+    fn dead_url() -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        drop(listener);
+        format!("ws://127.0.0.1:{port}")
+    }
+
+    // A fake code client that answers authentication and placing, and records every connection's messages.
+    // With `drop_after_place` it closes each connection once code has been placed on it.
+    fn code_client(drop_after_place: bool) -> (String, Arc<Mutex<Vec<Vec<String>>>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("ws://127.0.0.1:{}", listener.local_addr().unwrap().port());
+        let connections = Arc::new(Mutex::new(Vec::new()));
+        let recorded = connections.clone();
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let Ok(mut socket) = tungstenite::accept(stream.unwrap()) else { continue };
+                let index = {
+                    let mut connections = recorded.lock().unwrap();
+                    connections.push(Vec::new());
+                    connections.len() - 1
+                };
+                while let Ok(msg) = socket.read() {
+                    let Ok(text) = msg.into_text() else { break };
+                    recorded.lock().unwrap()[index].push(text.clone());
+                    match text.as_str() {
+                        "scopes write_code" => socket.send(Message::Text("auth".into())).unwrap(),
+                        "place go" => {
+                            socket.send(Message::Text("place done".into())).unwrap();
+                            if drop_after_place {
+                                break;
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+        });
+        (url, connections)
+    }
+
+    #[test]
+    fn connection_is_kept_between_sends() {
+        let (url, connections) = code_client(false);
+        let mut connection = None;
+        assert!(send_codeclient_to(&url, compile_lines("@join {\n    p.sendMessage(\"Hi\");\n}"), Config::default(), &mut connection));
+        assert!(connection.is_some());
+        assert!(send_codeclient_to(&url, compile_lines("@leave {\n    p.sendMessage(\"Bye\");\n}"), Config::default(), &mut connection));
+
+        let connections = connections.lock().unwrap();
+        assert_eq!(connections.len(), 1);
+        let messages = &connections[0];
+        assert_eq!(messages.iter().filter(|msg| *msg == "scopes write_code").count(), 1);
+        assert_eq!(messages.iter().filter(|msg| *msg == "place swap").count(), 2);
+        assert_eq!(messages.iter().filter(|msg| *msg == "place go").count(), 2);
+    }
+
+    #[test]
+    fn lost_connection_is_reopened() {
+        let (url, connections) = code_client(true);
+        let mut connection = None;
+        assert!(send_codeclient_to(&url, compile_lines("@join {\n    p.sendMessage(\"Hi\");\n}"), Config::default(), &mut connection));
+        assert!(send_codeclient_to(&url, compile_lines("@leave {\n    p.sendMessage(\"Bye\");\n}"), Config::default(), &mut connection));
+
+        let connections = connections.lock().unwrap();
+        assert_eq!(connections.len(), 2);
+        assert_eq!(connections[1].first().map(String::as_str), Some("scopes write_code"));
+        assert_eq!(connections[1].last().map(String::as_str), Some("place go"));
+    }
+
+    #[test]
+    fn unreachable_client_is_not_sent_to() {
+        let mut connection = None;
+        assert!(!send_codeclient_to(&dead_url(), compile_lines("@join {\n    p.sendMessage(\"Hi\");\n}"), Config::default(), &mut connection));
+        assert!(connection.is_none());
+    }
+
+    #[test]
+    fn scan_reports_dead_url() {
+        let url = dead_url();
+        match scan_plot(&url, &Scanning::default(), false) {
+            Err(ScanError::ConnectionFailed { url: failed, .. }) => assert!(failed.starts_with(&url)),
+            other => panic!("Expected a failed connection, got {other:?}")
+        }
+    }
+
+    #[test]
+    fn scan_reports_invalid_url() {
+        assert!(matches!(scan_plot("not a url", &Scanning::default(), false), Err(ScanError::InvalidUrl { url }) if url == "not a url"));
+    }
+
+    #[test]
+    fn only_changed_codelines_are_sent_again() {
+        let cache_file = project("only-changed", &[]).join(".dfrs-sent.json");
+        let first = compile_lines("@join {\n    p.sendMessage(\"Hi\");\n}\n@leave {\n    p.sendMessage(\"Bye\");\n}");
+        let changed = filter_changed(first, &cache_file);
+        assert_eq!(changed.len(), 2);
+        save_sent(&changed, &cache_file);
+
+        let second = compile_lines("@join {\n    p.sendMessage(\"Hello\");\n}\n@leave {\n    p.sendMessage(\"Bye\");\n}");
+        let changed = filter_changed(second, &cache_file);
+        assert_eq!(changed.iter().map(|line| line.name.as_str()).collect::<Vec<_>>(), vec!["Event Join"]);
+    }
+
+    #[test]
+    fn unsaved_codelines_are_sent_again() {
+        let cache_file = project("unsaved", &[]).join(".dfrs-sent.json");
+        let code = compile_lines("@join {\n    p.sendMessage(\"Hi\");\n}");
+        assert_eq!(filter_changed(code.clone(), &cache_file).len(), 1);
+        // Nothing is saved when sending fails, so the codeline is still seen as changed
+        assert_eq!(filter_changed(code.clone(), &cache_file).len(), 1);
+        save_sent(&code, &cache_file);
+        assert!(filter_changed(code, &cache_file).is_empty());
+    }
+
+    fn decompress(data: &str) -> String {
+        let mut code = String::new();
+        flate2::read::GzDecoder::new(&BASE64_STANDARD.decode(data).unwrap()[..]).read_to_string(&mut code).unwrap();
+        code
+    }
+
+    // Reverses the snbt escaping of a single quoted string
+    fn unescape(snbt: &str) -> String {
+        let mut result = String::new();
+        let mut chars = snbt.chars();
+        while let Some(char) = chars.next() {
+            result.push(if char == '\\' { chars.next().unwrap() } else { char });
+        }
+        result
+    }
+
+    fn quoted_after<'a>(command: &'a str, prefix: &str) -> &'a str {
+        let start = command.find(prefix).unwrap() + prefix.len();
+        let mut escaped = false;
+        for (index, char) in command[start..].char_indices() {
+            match char {
+                '\\' if !escaped => escaped = true,
+                '\'' if !escaped => return &command[start..start + index],
+                _ => escaped = false
+            }
+        }
+        panic!("Unterminated string")
+    }
+
+    #[test]
+    fn give_command_holds_template() {
+        let line = compile_lines("@join {\n    p.sendMessage(\"It's \\\"quoted\\\"\");\n}").remove(0);
+        let command = give_command(&line, &Sending::default());
+        assert!(command.starts_with("/give @p minecraft:ender_chest["));
+
+        let name: serde_json::Value = serde_json::from_str(&unescape(quoted_after(&command, "minecraft:custom_name='"))).unwrap();
+        assert_eq!(name["text"], "Event Join");
+        let template: serde_json::Value = serde_json::from_str(&unescape(quoted_after(&command, "\"hypercube:codetemplatedata\":'"))).unwrap();
+        assert_eq!((template["author"].as_str(), template["name"].as_str()), (Some("dfrs"), Some("Event Join")));
+
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&decompress(template["code"].as_str().unwrap())).unwrap(), serde_json::from_str::<serde_json::Value>(&line.code).unwrap());
+    }
+
+    #[test]
+    fn give_command_escapes_name() {
+        let line = CompiledLine { name: "It's a \\ test".into(), code: "{}".into(), source_map: vec![] };
+        let command = give_command(&line, &Sending::default());
+        let name: serde_json::Value = serde_json::from_str(&unescape(quoted_after(&command, "minecraft:custom_name='"))).unwrap();
+        assert_eq!(name["text"], "It's a \\ test");
+    }
+
+    #[test]
+    fn compression_levels_decompress() {
+        let line = compile_lines("@join {\n    p.sendMessage(\"A\");\n    p.sendMessage(\"A\");\n    p.sendMessage(\"A\");\n}").remove(0);
+        let mut sizes = vec![];
+        for level in [0, 1, 6, 9, 20] {
+            let sending: Sending = toml::from_str(&format!("api = \"recode\"\ncompression_level = {level}")).unwrap();
+            let data = compress(line.code.clone(), &sending);
+            assert_eq!(decompress(&data), line.code);
+            sizes.push(data.len());
+        }
+        assert!(sizes[0] > sizes[3], "{sizes:?}");
+        assert_eq!(sizes[3], sizes[4]);
+    }
+
+    #[test]
+    fn minify_can_be_turned_off() {
+        let line = compile_lines("@join {\n    p.sendMessage(\"A\");\n}").remove(0);
+        assert_eq!(decompress(&compress(line.code.clone(), &Sending::default())), line.code);
+
+        let sending: Sending = toml::from_str("api = \"recode\"\nminify = false").unwrap();
+        let pretty = decompress(&compress(line.code.clone(), &sending));
+        assert!(pretty.contains("\n  \"blocks\": ["));
+        assert_eq!(serde_json::from_str::<serde_json::Value>(&pretty).unwrap(), serde_json::from_str::<serde_json::Value>(&line.code).unwrap());
+    }
+}
@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::node::{ActionType, Arg, ArgValue, ConditionalType, Expression, ExpressionNode, FileNode};
+use crate::utility::to_dfrs_name;
+
+// How often each action, event and game value is used, tallied from validated files
+#[derive(Default)]
+pub struct Stats {
+    actions: HashMap<String, usize>,
+    events: HashMap<String, usize>,
+    game_values: HashMap<String, usize>
+}
+
+impl Stats {
+    pub fn add(&mut self, node: &FileNode) {
+        for event in &node.events {
+            let name = to_dfrs_name(&event.event);
+            let name = match event.event_type {
+                Some(ActionType::Entity) => format!("@entity:{name}"),
+                _ => format!("@{name}")
+            };
+            *self.events.entry(name).or_default() += 1;
+            self.add_expressions(&event.expressions);
+        }
+        for function in &node.functions {
+            self.add_expressions(&function.expressions);
+        }
+        for process in &node.processes {
+            self.add_expressions(&process.expressions);
+        }
+    }
+
+    fn add_expressions(&mut self, expressions: &[ExpressionNode]) {
+        for expression in expressions {
+            match &expression.node {
+                Expression::Action { node } => {
+                    let prefix = match node.action_type {
+                        ActionType::Player => "p",
+                        ActionType::Entity => "e",
+                        ActionType::Game => "g",
+                        ActionType::Variable => "v",
+                        ActionType::Control => "c",
+                        ActionType::Select => "s"
+                    };
+                    self.add_action(format!("{prefix}.{}", to_dfrs_name(&node.name)));
+                    self.add_args(&node.args);
+                }
+                Expression::Conditional { node } => {
+                    self.add_action(format!("{} {}", conditional_prefix(&node.conditional_type), to_dfrs_name(&node.name)));
+                    self.add_args(&node.args);
+                    self.add_expressions(&node.expressions);
+                    self.add_expressions(&node.else_expressions);
+                }
+                Expression::Repeat { node } => {
+                    self.add_action(format!("repeat {}", to_dfrs_name(&node.name)));
+                    self.add_args(&node.args);
+                    self.add_expressions(&node.expressions);
+                }
+                Expression::Call { node } => self.add_args(&node.args),
+                Expression::Start { node } => self.add_args(&node.args),
+                Expression::Variable { .. } => {}
+            }
+        }
+    }
+
+    fn add_action(&mut self, name: String) {
+        *self.actions.entry(name).or_default() += 1;
+    }
+
+    fn add_args(&mut self, args: &[Arg]) {
+        for arg in args {
+            match &arg.value {
+                ArgValue::GameValue { dfrs_name, .. } => *self.game_values.entry(format!("${dfrs_name}")).or_default() += 1,
+                ArgValue::Condition { name, args, conditional_type, .. } => {
+                    self.add_action(format!("{} {}", conditional_prefix(conditional_type), to_dfrs_name(name)));
+                    self.add_args(args);
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+fn conditional_prefix(conditional_type: &ConditionalType) -> &'static str {
+    match conditional_type {
+        ConditionalType::Player => "ifp",
+        ConditionalType::Entity => "ife",
+        ConditionalType::Game => "ifg",
+        ConditionalType::Variable => "ifv"
+    }
+}
+
+// Most used first, ties sorted by name
+fn sorted(tally: &HashMap<String, usize>) -> Vec<(&String, &usize)> {
+    let mut entries: Vec<(&String, &usize)> = tally.iter().collect();
+    entries.sort_by(|(a_name, a_count), (b_name, b_count)| b_count.cmp(a_count).then(a_name.cmp(b_name)));
+    entries
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (title, tally) in [("Actions", &self.actions), ("Events", &self.events), ("Game values", &self.game_values)] {
+            writeln!(f, "{title} ({} used)", tally.values().sum::<usize>())?;
+            for (name, count) in sorted(tally) {
+                writeln!(f, "{count:>6}  {name}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::validate;
+
+    const SOURCE: &str = "fn greet() {\n    p.sendMessage($name);\n}\n@join {\n    line count;\n    p.sendMessage(\"A\");\n    ifp isSneaking() {\n        v.equal(count, $playerCount);\n        p.sendMessage($name);\n    } else {\n        repeat forever() {\n            c.wait(1);\n        }\n    }\n    s.filterCondition(ifp isFlying());\n}\n@entity:blockFall {\n    p.sendMessage(\"B\");\n}";
+
+    fn stats(sources: &[&str]) -> Stats {
+        let mut stats = Stats::default();
+        for source in sources {
+            stats.add(&validate(source).unwrap());
+        }
+        stats
+    }
+
+    #[test]
+    fn tallies_usage() {
+        let stats = stats(&[SOURCE, "@join {\n    p.sendMessage(\"C\");\n}"]);
+        assert_eq!(stats.actions["p.sendMessage"], 5);
+        assert_eq!((stats.actions["ifp isSneaking"], stats.actions["ifp isFlying"], stats.actions["repeat forever"]), (1, 1, 1));
+        assert_eq!((stats.actions["c.wait"], stats.actions["v.equal"], stats.actions["s.filterCondition"]), (1, 1, 1));
+        assert_eq!((stats.events["@join"], stats.events["@entity:blockFall"]), (2, 1));
+        assert_eq!((stats.game_values["$name"], stats.game_values["$playerCount"]), (2, 1));
+    }
+
+    #[test]
+    fn prints_most_used_first() {
+        assert_eq!(stats(&[SOURCE]).to_string(), "\
+Actions (10 used)
+     4  p.sendMessage
+     1  c.wait
+     1  ifp isFlying
+     1  ifp isSneaking
+     1  repeat forever
+     1  s.filterCondition
+     1  v.equal
+Events (2 used)
+     1  @entity:blockFall
+     1  @join
+Game values (3 used)
+     2  $name
+     1  $playerCount
+");
+        assert_eq!(Stats::default().to_string(), "Actions (0 used)\nEvents (0 used)\nGame values (0 used)\n");
+    }
+}
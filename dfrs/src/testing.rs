@@ -0,0 +1,115 @@
+// Helpers for tests that run source code through the compiler
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+
+use base64::prelude::*;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde_json::Value;
+
+use crate::compile::{compile, CompiledLine};
+use crate::config::Config;
+use crate::decompile::{DecompileError, DecompileWarning, Decompiler};
+use crate::definitions::Definitions;
+use crate::lexer::Lexer;
+use crate::node::FileNode;
+use crate::parser::{ParseError, Parser};
+use crate::token::TokenWithPos;
+use crate::validate::{ValidateError, ValidateWarning, Validator};
+
+// Loading the definitions is slow, so every test shares one copy
+pub fn definitions() -> Arc<Definitions> {
+    static DEFINITIONS: OnceLock<Arc<Definitions>> = OnceLock::new();
+    DEFINITIONS.get_or_init(|| Arc::new(Definitions::load())).clone()
+}
+
+pub fn lex(source: &str) -> Vec<TokenWithPos> {
+    Lexer::new(source.into()).run().expect("Failed to lex")
+}
+
+pub fn parse(source: &str) -> Result<FileNode, ParseError> {
+    Parser::new(lex(source)).run()
+}
+
+pub fn validate_with(source: &str, config: &Config) -> Result<(FileNode, Vec<ValidateWarning>), ValidateError> {
+    let node = parse(source).expect("Failed to parse");
+    let validator = Validator::new(config, definitions());
+    let node = validator.validate(node)?;
+    Ok((node, validator.take_warnings()))
+}
+
+pub fn validate(source: &str) -> Result<FileNode, ValidateError> {
+    validate_with(source, &Config::default()).map(|(node, _)| node)
+}
+
+pub fn warnings(source: &str) -> Vec<ValidateWarning> {
+    validate_with(source, &Config::default()).expect("Failed to validate").1
+}
+
+pub fn compile_lines(source: &str) -> Vec<CompiledLine> {
+    let node = validate(source).expect("Failed to validate");
+    compile(node, false, false, "").expect("Failed to compile")
+}
+
+// The blocks of every compiled codeline
+pub fn blocks(source: &str) -> Vec<Vec<Value>> {
+    compile_lines(source).iter()
+        .map(|line| serde_json::from_str::<Value>(&line.code).expect("Invalid codeline")["blocks"].as_array().cloned().unwrap_or_default())
+        .collect()
+}
+
+// Block and action of every block in a codeline, leaving out brackets
+pub fn actions(blocks: &[Value]) -> Vec<String> {
+    blocks.iter()
+        .filter(|block| block["id"] == "block")
+        .map(|block| format!("{} {}", block["block"].as_str().unwrap_or_default(), block["action"].as_str().or(block["data"].as_str()).unwrap_or_default()))
+        .collect()
+}
+
+// A fresh directory with a config and the given files, named after the test so tests don't share it
+pub fn project(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("dfrs-test-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).expect("Failed to create project");
+    fs::write(dir.join("dfrs.toml"), "[sending]\napi = \"recode\"\n").expect("Failed to write config");
+    for (file, source) in files {
+        fs::write(dir.join(file), source).expect("Failed to write file");
+    }
+    dir
+}
+
+// Every block of a codeline on its own, with brackets as { and } and inverted conditions marked with NOT
+pub fn layout(blocks: &[Value]) -> Vec<String> {
+    blocks.iter()
+        .map(|block| match block["direct"].as_str() {
+            Some("open") => "{".to_string(),
+            Some("close") => "}".to_string(),
+            _ => {
+                let action = block["action"].as_str().or(block["data"].as_str()).map(|action| format!(" {action}")).unwrap_or_default();
+                let attribute = block["attribute"].as_str().map(|attribute| format!(" {attribute}")).unwrap_or_default();
+                format!("{}{action}{attribute}", block["block"].as_str().unwrap_or_default())
+            }
+        })
+        .collect()
+}
+
+// A codeline in the format it is sent to the game in
+pub fn compress(code: &str) -> String {
+    let mut encoder = GzEncoder::new(vec![], Compression::default());
+    encoder.write_all(code.as_bytes()).expect("Failed to compress");
+    BASE64_STANDARD.encode(encoder.finish().expect("Failed to compress"))
+}
+
+pub fn decompile_with(code: &str, pretty: bool, config: &Config) -> Result<(String, Vec<DecompileWarning>), DecompileError> {
+    let mut decompiler = Decompiler::new(pretty, config, definitions());
+    let result = decompiler.decompile(&compress(code))?;
+    Ok((result, decompiler.take_warnings()))
+}
+
+// Compiles the source and decompiles its first codeline again
+pub fn round_trip(source: &str, pretty: bool) -> String {
+    let code = &compile_lines(source)[0].code;
+    decompile_with(code, pretty, &Config::default()).expect("Failed to decompile").0
+}
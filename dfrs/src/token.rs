@@ -3,7 +3,8 @@ use std::fmt::Display;
 use phf::phf_map;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+/// Lines and columns both start at 1. The end position of a token or range is the column after its last character
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Position {
     pub line: u32,
     pub col: u32
@@ -14,6 +15,11 @@ impl Position {
         Position { line, col }
     }
 
+    /// The first character of a file
+    pub fn start() -> Position {
+        Position::new(1, 1)
+    }
+
     pub fn advance(&mut self) {
         self.col += 1;
     }
@@ -30,6 +36,46 @@ impl Display for Position {
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Range {
+    pub start: Position,
+    pub end: Position
+}
+
+impl Range {
+    pub fn new(start: Position, end: Position) -> Range {
+        Range { start, end }
+    }
+
+    pub fn contains(&self, pos: &Position) -> bool {
+        &self.start <= pos && pos <= &self.end
+    }
+
+    pub fn merge(&self, other: &Range) -> Range {
+        Range {
+            start: self.start.clone().min(other.start.clone()),
+            end: self.end.clone().max(other.end.clone())
+        }
+    }
+
+    pub fn is_single_line(&self) -> bool {
+        self.start.line == self.end.line
+    }
+
+    /// Amount of columns covered by a single-line range, 0 for multi-line ranges
+    pub fn len(&self) -> u32 {
+        if self.is_single_line() {
+            self.end.col.saturating_sub(self.start.col)
+        } else {
+            0
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TokenWithPos {
     pub token: Token,
@@ -41,6 +87,29 @@ impl TokenWithPos {
     pub fn new(token: Token, start_pos: Position, end_pos: Position) -> TokenWithPos {
         TokenWithPos { token, start_pos, end_pos }
     }
+
+    pub fn range(&self) -> Range {
+        Range::new(self.start_pos.clone(), self.end_pos.clone())
+    }
+}
+
+impl Display for TokenWithPos {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (kind, value) = match &self.token {
+            Token::Number { value } => ("Number", value.to_string()),
+            Token::String { value } => ("String", format!("'{value}'")),
+            Token::Text { value } => ("Text", format!("\"{value}\"")),
+            Token::Variable { value } => ("Variable", format!("`{value}`")),
+            Token::Identifier { value } => ("Identifier", value.clone()),
+            Token::Keyword { value } => ("Keyword", value.to_string()),
+            Token::Selector { value } => {
+                let name = SELECTORS.entries().find(|(_, selector)| *selector == value).map(|(name, _)| name.to_string());
+                ("Selector", name.unwrap_or_else(|| format!("{value:?}")))
+            }
+            token => ("Symbol", token.to_string())
+        };
+        write!(f, "{:<10}{:<12}{}", self.start_pos.to_string(), kind, value)
+    }
 }
 
 
@@ -59,6 +128,7 @@ pub enum Token {
     Semicolon,
     QuestionMark,
     Dollar,
+    Hash,
     OpenParen,
     CloseParen,
     OpenParenCurly,
@@ -88,6 +158,7 @@ impl Display for Token {
             Token::Semicolon => write!(f, ";"),
             Token::QuestionMark => write!(f, "?"),
             Token::Dollar => write!(f, "$"),
+            Token::Hash => write!(f, "#"),
             Token::OpenParen => write!(f, "("),
             Token::CloseParen => write!(f, ")"),
             Token::OpenParenCurly => write!(f, "{{"),
@@ -116,6 +187,7 @@ pub enum Keyword {
     IfG,
     IfV,
     Else,
+    And,
     VarLine,
     VarLocal,
     VarGame,
@@ -125,6 +197,9 @@ pub enum Keyword {
     Call,
     Start,
     Repeat,
+    Use,
+    Return,
+    Unless,
 }
 
 impl Display for Keyword {
@@ -141,6 +216,7 @@ impl Display for Keyword {
             Keyword::IfG => write!(f, "ifg"),
             Keyword::IfV => write!(f, "ifv"),
             Keyword::Else => write!(f, "else"),
+            Keyword::And => write!(f, "and"),
             Keyword::VarLine => write!(f, "line"),
             Keyword::VarLocal => write!(f, "local"),
             Keyword::VarGame => write!(f, "game"),
@@ -150,6 +226,9 @@ impl Display for Keyword {
             Keyword::Call => write!(f, "call"),
             Keyword::Start => write!(f, "start"),
             Keyword::Repeat => write!(f, "repeat"),
+            Keyword::Use => write!(f, "use"),
+            Keyword::Return => write!(f, "return"),
+            Keyword::Unless => write!(f, "unless"),
         }
     }
 }
@@ -166,6 +245,7 @@ pub static KEYWORDS: phf::Map<&'static str, Keyword> = phf_map! {
     "ifg" => Keyword::IfG,
     "ifv" => Keyword::IfV,
     "else" => Keyword::Else,
+    "and" => Keyword::And,
     "line" => Keyword::VarLine,
     "local" => Keyword::VarLocal,
     "game" => Keyword::VarGame,
@@ -175,6 +255,9 @@ pub static KEYWORDS: phf::Map<&'static str, Keyword> = phf_map! {
     "call" => Keyword::Call,
     "start" => Keyword::Start,
     "repeat" => Keyword::Repeat,
+    "use" => Keyword::Use,
+    "return" => Keyword::Return,
+    "unless" => Keyword::Unless,
 };
 
 #[derive(Debug, Clone, PartialEq, PartialOrd, Serialize, Deserialize)]
@@ -255,4 +338,79 @@ pub fn get_type_str(input: Type) -> String {
         Type::List => "list",
         Type::Dict => "dict"
     }.into()
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(start: (u32, u32), end: (u32, u32)) -> Range {
+        Range::new(Position::new(start.0, start.1), Position::new(end.0, end.1))
+    }
+
+    #[test]
+    fn single_line_contains() {
+        let range = range((2, 5), (2, 9));
+        assert!(range.contains(&Position::new(2, 5)));
+        assert!(range.contains(&Position::new(2, 9)));
+        assert!(!range.contains(&Position::new(2, 4)));
+        assert!(!range.contains(&Position::new(2, 10)));
+        assert!(!range.contains(&Position::new(3, 6)));
+    }
+
+    #[test]
+    fn multi_line_contains() {
+        let range = range((2, 10), (4, 3));
+        assert!(range.contains(&Position::new(3, 1)));
+        assert!(range.contains(&Position::new(3, 80)));
+        assert!(range.contains(&Position::new(2, 40)));
+        assert!(range.contains(&Position::new(4, 2)));
+        assert!(!range.contains(&Position::new(2, 9)));
+        assert!(!range.contains(&Position::new(4, 4)));
+    }
+
+    #[test]
+    fn merge_and_len() {
+        assert_eq!(range((2, 5), (2, 9)).merge(&range((1, 3), (2, 7))), range((1, 3), (2, 9)));
+        assert_eq!(range((2, 5), (2, 9)).len(), 4);
+        assert_eq!(range((2, 5), (3, 9)).len(), 0);
+        assert!(range((2, 5), (2, 5)).is_empty());
+        assert!(!range((2, 5), (3, 5)).is_single_line());
+    }
+
+    #[test]
+    fn token_columns() {
+        let tokens = crate::testing::lex("p:selection.sendMessage(\"Hi\", 5);");
+        let lines: Vec<String> = tokens.iter().map(|token| token.to_string()).collect();
+        assert_eq!(lines, [
+            "1:1       Keyword     p",
+            "1:2       Symbol      :",
+            "1:3       Selector    selection",
+            "1:12      Symbol      .",
+            "1:13      Identifier  sendMessage",
+            "1:24      Symbol      (",
+            "1:25      Text        \"Hi\"",
+            "1:29      Symbol      ,",
+            "1:31      Number      5",
+            "1:32      Symbol      )",
+            "1:33      Symbol      ;"
+        ]);
+    }
+
+    #[test]
+    fn token_columns_for_values() {
+        let token = TokenWithPos::new(Token::String { value: "a b".into() }, Position::new(12, 3), Position::new(12, 8));
+        assert_eq!(token.to_string(), "12:3      String      'a b'");
+        let token = TokenWithPos::new(Token::Variable { value: "%default x".into() }, Position::new(1, 1), Position::new(1, 13));
+        assert_eq!(token.to_string(), "1:1       Variable    `%default x`");
+    }
+
+    #[test]
+    fn positions_start_at_one() {
+        assert_eq!(Position::start(), Position::new(1, 1));
+        let tokens = crate::testing::lex(";\n@join");
+        assert_eq!((tokens[0].start_pos.clone(), tokens[0].end_pos.clone()), (Position::start(), Position::new(1, 2)));
+        assert_eq!((tokens[1].start_pos.clone(), tokens[1].end_pos.clone()), (Position::new(2, 1), Position::new(2, 2)));
+        assert_eq!((tokens[2].start_pos.clone(), tokens[2].end_pos.clone()), (Position::new(2, 2), Position::new(2, 6)));
+    }
+}
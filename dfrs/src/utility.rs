@@ -1,3 +1,5 @@
+use sha2::{Digest, Sha256};
+
 pub fn to_camel_case(s: &str) -> String {
     let mut words = s.split_whitespace();
     let mut camel_case_string = String::new();
@@ -32,4 +34,100 @@ pub fn to_dfrs_name(s: &str) -> String {
     vv[0] = vv[0].to_lowercase().next().unwrap();
     let name: String = vv.into_iter().collect();
     name
-}
\ No newline at end of file
+}
+pub fn item_count(nbt: &str) -> Option<u32> {
+    let start = nbt.find("Count:")? + "Count:".len();
+    let digits: String = nbt[start..].chars().take_while(|char| char.is_ascii_digit()).collect();
+    digits.parse().ok()
+}
+
+pub fn with_item_count(nbt: &str, count: u32) -> String {
+    match nbt.find("Count:") {
+        Some(start) => {
+            let value_start = start + "Count:".len();
+            let value_end = value_start + nbt[value_start..].chars().take_while(|char| char.is_ascii_digit()).count();
+            format!("{}{count}{}", &nbt[..value_start], &nbt[value_end..])
+        }
+        None => nbt.replacen('{', &format!("{{Count:{count}b,"), 1)
+    }
+}
+
+pub fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for (i, a_char) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, b_char) in b.iter().enumerate() {
+            let cost = if a_char == *b_char { 0 } else { 1 };
+            current.push((previous[j] + cost).min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+    previous[b.len()]
+}
+
+// Wider tabs are counted as this many columns, so a huge configured width can't blow up the marked lines
+const MAX_TAB_WIDTH: u32 = 16;
+
+// Column a tab at `col` moves to, with columns starting at 1
+pub fn next_tab_stop(col: u32, tab_width: u32) -> u32 {
+    let tab_width = tab_width.clamp(1, MAX_TAB_WIDTH);
+    (col.saturating_sub(1) / tab_width * tab_width).saturating_add(tab_width + 1)
+}
+
+pub fn expand_tabs(line: &str, tab_width: u32) -> String {
+    let mut result = String::new();
+    let mut col = 1;
+    for char in line.chars() {
+        if char == '\t' {
+            let next = next_tab_stop(col, tab_width);
+            result.push_str(&" ".repeat((next - col) as usize));
+            col = next;
+        } else {
+            result.push(char);
+            col += 1;
+        }
+    }
+    result
+}
+
+// Hex encoded SHA-256 digest, which stays the same across builds unlike the std hashers
+pub fn sha256(data: &str) -> String {
+    Sha256::digest(data.as_bytes()).iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sha256_digest() {
+        assert_eq!(sha256("abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    }
+
+    #[test]
+    fn item_counts() {
+        assert_eq!(item_count("{Count:12b,id:\"minecraft:stone\"}"), Some(12));
+        assert_eq!(item_count("{id:\"minecraft:stone\"}"), None);
+        assert_eq!(with_item_count("{Count:1b,id:\"minecraft:stone\"}", 16), "{Count:16b,id:\"minecraft:stone\"}");
+        assert_eq!(with_item_count("{Count:64b}", 2), "{Count:2b}");
+    }
+
+    #[test]
+    fn tab_stops() {
+        assert_eq!([1, 2, 4, 5, 8].map(|col| next_tab_stop(col, 4)), [5, 5, 5, 9, 9]);
+        assert_eq!([1, 2, 3].map(|col| next_tab_stop(col, 1)), [2, 3, 4]);
+        assert_eq!(next_tab_stop(3, 0), 4);
+        assert_eq!(next_tab_stop(1, u32::MAX), 17);
+        assert_eq!(next_tab_stop(u32::MAX, 4), u32::MAX);
+    }
+
+    #[test]
+    fn expanded_tabs() {
+        assert_eq!(expand_tabs("\tp.a();", 4), "    p.a();");
+        assert_eq!(expand_tabs("ab\tc\t\td", 4), "ab  c       d");
+        assert_eq!(expand_tabs("  \tx", 8), "        x");
+        assert_eq!(expand_tabs("\tx", 1), " x");
+        assert_eq!(expand_tabs("\tx", u32::MAX).len(), 17);
+    }
+}
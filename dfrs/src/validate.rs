@@ -1,77 +1,214 @@
-use crate::{definitions::{action_dump::{Action, ActionDump}, ArgType, DefinedArg}, node::{ActionNode, ActionType, Arg, ArgValue, CallNode, ConditionalNode, ConditionalType, EventNode, Expression, FileNode, RepeatNode}, token::Position};
-use crate::definitions::action_dump::RawActionDump;
-use crate::definitions::events::{EntityEvents, PlayerEvents};
-use crate::definitions::game_values::GameValues;
-use crate::node::{ExpressionNode, StartNode};
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
 
+use crate::config::{Config, Validation};
+use crate::{definitions::{action_dump::Action, ArgType, DefinedArg, Definitions}, node::{ActionNode, ActionType, Arg, ArgValue, CallNode, ConditionalNode, ConditionalType, EventNode, Expression, FileNode, RepeatNode}, token::{Position, Range}};
+use crate::node::{same_expressions, ExpressionNode, FunctionParamNode, ParticleData, StartNode};
+use crate::token::Type;
+use crate::definitions::action_dump::ValueList;
+use crate::utility::{edit_distance, item_count};
+use crate::import::Imports;
+
+#[derive(Debug)]
 pub enum ValidateError {
     UnknownEvent { node: EventNode },
-    UnknownAction { name: String, start_pos: Position, end_pos: Position },
+    UnknownAction { name: String, suggestions: Vec<ActionSuggestion>, start_pos: Position, end_pos: Position },
     UnknownGameValue { start_pos: Position, end_pos: Position, game_value: String },
     MissingArgument { name: String, start_pos: Position, end_pos: Position },
     WrongArgumentType { args: Vec<Arg>, index: i32, name: String, expected_types: Vec<ArgType>, found_type: ArgType },
     TooManyArguments { name: String, start_pos: Position, end_pos: Position },
-    InvalidTagOption { tag_name: String, provided: String, options: Vec<String>, start_pos: Position, end_pos: Position },
-    UnknownTag { tag_name: String, available: Vec<String>, start_pos: Position, end_pos: Position }
+    InvalidTagOption { tag_name: String, provided: String, options: Vec<String>, suggestion: Option<String>, start_pos: Position, end_pos: Position },
+    UnknownTag { tag_name: String, available: Vec<String>, start_pos: Position, end_pos: Position },
+    RecursiveFunction { name: String, through: Option<String>, start_pos: Position, end_pos: Position },
+    InvalidSoundVariant { sound: String, variant: String, start_pos: Position, end_pos: Position },
+    MissingTargetVariable { name: String, start_pos: Position, end_pos: Position },
+    UnexpectedGameValueSelector { game_value: String, start_pos: Position, end_pos: Position },
+    DuplicateDefinition { kind: String, name: String, start_pos: Position, end_pos: Position, previous_start_pos: Position, previous_end_pos: Position },
+    UnknownValue { kind: String, name: String, start_pos: Position, end_pos: Position },
+    OutOfRange { name: String, value: f32, min: f32, max: f32, start_pos: Position, end_pos: Position },
+    UnsupportedConditionType { action: String, found: String, allowed: Vec<String>, start_pos: Position, end_pos: Position },
+    ReadOnlyGameValue { game_value: String, name: String, start_pos: Position, end_pos: Position },
+    UnexpectedCondition { action: String, start_pos: Position, end_pos: Position },
+    UnexpectedSelector { kind: String, start_pos: Position, end_pos: Position },
+    InvalidDefault { name: String, expected: Type, found: ArgType, start_pos: Position, end_pos: Position },
+    NullArgument { name: String, start_pos: Position, end_pos: Position },
+    UnknownFunction { name: String, left_out_of: Option<String>, start_pos: Position, end_pos: Position }
+}
+
+impl ValidateError {
+    pub fn code(&self) -> &'static str {
+        match self {
+            ValidateError::UnknownAction { .. } => "E001",
+            ValidateError::UnknownEvent { .. } => "E002",
+            ValidateError::UnknownGameValue { .. } => "E003",
+            ValidateError::MissingArgument { .. } => "E004",
+            ValidateError::WrongArgumentType { .. } => "E005",
+            ValidateError::TooManyArguments { .. } => "E006",
+            ValidateError::InvalidTagOption { .. } => "E007",
+            ValidateError::UnknownTag { .. } => "E008",
+            ValidateError::RecursiveFunction { .. } => "E009",
+            ValidateError::InvalidSoundVariant { .. } => "E010",
+            ValidateError::MissingTargetVariable { .. } => "E011",
+            ValidateError::UnexpectedGameValueSelector { .. } => "E012",
+            ValidateError::DuplicateDefinition { .. } => "E013",
+            ValidateError::UnknownValue { .. } => "E014",
+            ValidateError::OutOfRange { .. } => "E015",
+            ValidateError::UnsupportedConditionType { .. } => "E016",
+            ValidateError::ReadOnlyGameValue { .. } => "E017",
+            ValidateError::UnexpectedCondition { .. } => "E018",
+            ValidateError::UnexpectedSelector { .. } => "E019",
+            ValidateError::InvalidDefault { .. } => "E020",
+            ValidateError::NullArgument { .. } => "E021",
+            ValidateError::UnknownFunction { .. } => "E022"
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ActionSuggestion {
+    pub name: String,
+    pub description: String
+}
+
+#[derive(Debug)]
+pub enum ValidateWarning {
+    RecursiveFunction { name: String, through: Option<String>, start_pos: Position, end_pos: Position },
+    RedundantInversion { start_pos: Position, end_pos: Position },
+    ItemCountExceedsStack { count: u32, max: u32, start_pos: Position, end_pos: Position },
+    UnexpectedProcessArguments { name: String, start_pos: Position, end_pos: Position },
+    IdenticalBranches { start_pos: Position, end_pos: Position },
+    TooManySlots { name: String, count: usize, max: usize, start_pos: Position, end_pos: Position },
+    UnsupportedParticleField { particle: String, field: String, start_pos: Position, end_pos: Position },
+    MotionVariationWithoutMotion { start_pos: Position, end_pos: Position },
+    UnassignedVariable { name: String, scope: String, start_pos: Position, end_pos: Position },
+    UnknownPlotVariable { name: String, start_pos: Position, end_pos: Position },
+    Deprecated { kind: String, name: String, note: String, start_pos: Position, end_pos: Position }
 }
 
+// Arguments and tags of a block are stored in a single chest
+const CHEST_SLOTS: usize = 27;
+
 pub struct Validator {
-    player_events: PlayerEvents,
-    entity_events: EntityEvents,
+    definitions: Arc<Definitions>,
+
+    config: Validation,
+
+    functions: RefCell<HashMap<String, Vec<FunctionParamNode>>>,
+    imported: HashMap<String, Vec<FunctionParamNode>>,
+    left_out: HashMap<String, String>,
+    // Files that import functions have to import every function they call, unless an import cycle hid some of them
+    check_calls: Cell<bool>,
+    partial_imports: bool,
+    processes: RefCell<HashSet<String>>,
+    warnings: RefCell<Vec<ValidateWarning>>,
 
-    action_dump: ActionDump,
+    // Line and local variables written so far in the current codeline, as (scope, name)
+    assigned: RefCell<HashSet<(String, String)>>,
+    // Local variables can also be set by the caller or a called function, so they are only checked until that could happen
+    track_locals: Cell<bool>,
 
-    game_values: GameValues
+    variable_prefix: String,
+    // Game and saved variables that exist on the plot, names are added once they were reported
+    plot_variables: RefCell<Option<HashSet<String>>>
 }
 
 impl Validator {
-    pub fn new() -> Validator {
-        let action_dump = RawActionDump::load();
+    pub fn new(config: &Config, definitions: Arc<Definitions>) -> Validator {
         Validator {
-            player_events: PlayerEvents::new(&action_dump),
-            entity_events: EntityEvents::new(&action_dump),
+            definitions,
 
-            action_dump: ActionDump::new(&action_dump),
+            config: config.validation.clone(),
 
-            game_values: GameValues::new(&action_dump)
+            functions: RefCell::new(HashMap::new()),
+            imported: HashMap::new(),
+            left_out: HashMap::new(),
+            check_calls: Cell::new(false),
+            partial_imports: false,
+            processes: RefCell::new(HashSet::new()),
+            warnings: RefCell::new(vec![]),
+
+            assigned: RefCell::new(HashSet::new()),
+            track_locals: Cell::new(false),
+
+            variable_prefix: config.variable_prefix.clone(),
+            plot_variables: RefCell::new(None)
         }
     }
+
+    pub fn set_imports(&mut self, imports: &Imports) {
+        self.imported = imports.functions.iter().map(|function| (function.df_name.clone(), function.params.clone())).collect();
+        self.left_out.clone_from(&imports.left_out);
+        self.partial_imports = imports.partial;
+    }
+
+    pub fn set_plot_variables(&mut self, variables: HashSet<String>) {
+        *self.plot_variables.get_mut() = Some(variables);
+    }
+
+    pub fn take_warnings(&self) -> Vec<ValidateWarning> {
+        self.warnings.take()
+    }
+
     pub fn validate(&self, mut node: FileNode) -> Result<FileNode, ValidateError> {
+        self.check_duplicates(&node)?;
+        self.check_recursion(&node)?;
+        for function in &node.functions {
+            check_defaults(&function.params)?;
+        }
+        let mut functions = self.imported.clone();
+        functions.extend(node.functions.iter().map(|function| (function.df_name.clone(), function.params.clone())));
+        *self.functions.borrow_mut() = functions;
+        self.check_calls.set(!node.uses.is_empty() && !self.partial_imports);
+        *self.processes.borrow_mut() = node.processes.iter().map(|process| process.name.clone()).collect();
+
         for function in node.functions.iter_mut() {
+            self.begin_codeline(function.params.iter().map(|param| param.name.clone()).collect(), false);
             for expression in function.expressions.iter_mut() {
                 self.validate_expression_node(expression)?;
             }
         }
 
         for process in node.processes.iter_mut() {
+            self.begin_codeline(vec![], false);
             for expression in process.expressions.iter_mut() {
                 self.validate_expression_node(expression)?;
             }
         }
 
+        let mut events: HashMap<(bool, String), (Position, Position)> = HashMap::new();
         for event in node.events.iter_mut() {
-            let mut actual_event;
-            
-            actual_event = self.player_events.get(event.event.clone());
-            match actual_event {
-                Some(actual) => {
+            let player_event = match event.event_type {
+                None | Some(ActionType::Player) => self.definitions.player_events.get(event.event.clone()),
+                _ => None
+            };
+            let entity_event = match event.event_type {
+                None | Some(ActionType::Entity) => self.definitions.entity_events.get(event.event.clone()),
+                _ => None
+            };
+            match (player_event, entity_event) {
+                (Some(actual), _) => {
+                    self.check_deprecated("Event", &actual.dfrs_name, &actual.deprecated, &event.start_pos, &event.name_end_pos);
                     actual.df_name.clone_into(&mut event.event);
                     event.event_type = Some(ActionType::Player);
                 }
-                None => {
-                    actual_event = self.entity_events.get(event.event.clone());
-                    match actual_event {
-                        Some(actual) => {
-                            actual.df_name.clone_into(&mut event.event);
-                            event.event_type = Some(ActionType::Entity);
-                        }
-                        None => {
-                            return Err(ValidateError::UnknownEvent { node: event.clone() })
-                        }
-                    }
+                (None, Some(actual)) => {
+                    self.check_deprecated("Event", &actual.dfrs_name, &actual.deprecated, &event.start_pos, &event.name_end_pos);
+                    actual.df_name.clone_into(&mut event.event);
+                    event.event_type = Some(ActionType::Entity);
                 }
+                (None, None) => {
+                    return Err(ValidateError::UnknownEvent { node: event.clone() })
+                }
+            }
+
+            let key = (event.event_type == Some(ActionType::Entity), event.event.clone());
+            if let Some((previous_start_pos, previous_end_pos)) = events.get(&key) {
+                return Err(ValidateError::DuplicateDefinition { kind: "event".into(), name: event.event.clone(), start_pos: event.start_pos.clone(), end_pos: event.name_end_pos.clone(), previous_start_pos: previous_start_pos.clone(), previous_end_pos: previous_end_pos.clone() })
             }
+            events.insert(key, (event.start_pos.clone(), event.name_end_pos.clone()));
 
+            self.begin_codeline(vec![], true);
             for expression in event.expressions.iter_mut() {
                 self.validate_expression_node(expression)?
             }
@@ -80,6 +217,93 @@ impl Validator {
         Ok(node)
     }
 
+    fn begin_codeline(&self, params: Vec<String>, track_locals: bool) {
+        *self.assigned.borrow_mut() = params.into_iter().map(|name| ("line".into(), name)).collect();
+        self.track_locals.set(track_locals);
+    }
+
+    fn check_assigned(&self, name: &str, scope: &str, arg: &Arg) {
+        let tracked = match scope {
+            "line" => true,
+            "local" => self.track_locals.get(),
+            _ => false
+        };
+        // Only warn once per variable
+        if tracked && self.assigned.borrow_mut().insert((scope.into(), name.into())) {
+            self.warnings.borrow_mut().push(ValidateWarning::UnassignedVariable { name: name.into(), scope: scope.into(), start_pos: arg.start_pos.clone(), end_pos: arg.end_pos.clone() });
+        }
+    }
+
+    fn check_plot_variable(&self, name: &str, arg: &Arg) {
+        if let Some(variables) = self.plot_variables.borrow_mut().as_mut() {
+            if variables.insert(format!("{}{name}", self.variable_prefix)) {
+                self.warnings.borrow_mut().push(ValidateWarning::UnknownPlotVariable { name: name.into(), start_pos: arg.start_pos.clone(), end_pos: arg.end_pos.clone() });
+            }
+        }
+    }
+
+    fn unknown_value(&self, value: &ArgValue) -> Option<(&'static str, String)> {
+        match value {
+            ArgValue::Sound { sound, .. } if !sound.contains(':') => {
+                let names = self.definitions.action_dump.sounds.all().iter().map(|sound| sound.name());
+                (!is_known(sound, names, &self.config.extra_sounds)).then(|| ("sound", sound.clone()))
+            }
+            ArgValue::Potion { potion, .. } => {
+                let names = self.definitions.action_dump.potions.all().iter().map(|potion| potion.name());
+                (!is_known(potion, names, &self.config.extra_potions)).then(|| ("potion", potion.clone()))
+            }
+            ArgValue::Particle { particle, .. } => {
+                let names = self.definitions.action_dump.particles.all().iter().map(|particle| particle.name());
+                (!is_known(particle, names, &self.config.extra_particles)).then(|| ("particle", particle.clone()))
+            }
+            _ => None
+        }
+    }
+
+    fn check_duplicates(&self, node: &FileNode) -> Result<(), ValidateError> {
+        let mut functions: HashMap<String, (Position, Position)> = HashMap::new();
+        for function in &node.functions {
+            if let Some((previous_start_pos, previous_end_pos)) = functions.get(&function.df_name) {
+                return Err(ValidateError::DuplicateDefinition { kind: "function".into(), name: function.df_name.clone(), start_pos: function.name_start_pos.clone(), end_pos: function.name_end_pos.clone(), previous_start_pos: previous_start_pos.clone(), previous_end_pos: previous_end_pos.clone() })
+            }
+            functions.insert(function.df_name.clone(), (function.name_start_pos.clone(), function.name_end_pos.clone()));
+        }
+
+        let mut processes: HashMap<String, (Position, Position)> = HashMap::new();
+        for process in &node.processes {
+            if let Some((previous_start_pos, previous_end_pos)) = processes.get(&process.name) {
+                return Err(ValidateError::DuplicateDefinition { kind: "process".into(), name: process.name.clone(), start_pos: process.name_start_pos.clone(), end_pos: process.name_end_pos.clone(), previous_start_pos: previous_start_pos.clone(), previous_end_pos: previous_end_pos.clone() })
+            }
+            processes.insert(process.name.clone(), (process.name_start_pos.clone(), process.name_end_pos.clone()));
+        }
+
+        Ok(())
+    }
+
+    fn check_recursion(&self, node: &FileNode) -> Result<(), ValidateError> {
+        let mut calls: HashMap<String, Vec<CallNode>> = HashMap::new();
+        for function in &node.functions {
+            let mut found = vec![];
+            collect_calls(&function.expressions, &mut found);
+            calls.insert(function.df_name.clone(), found);
+        }
+
+        for function in &node.functions {
+            for call in calls.get(&function.df_name).unwrap() {
+                if !reaches(&calls, &call.name, &function.df_name, &mut HashSet::new()) {
+                    continue;
+                }
+                let through = if call.name == function.df_name { None } else { Some(call.name.clone()) };
+                if self.config.deny_recursion {
+                    return Err(ValidateError::RecursiveFunction { name: function.df_name.clone(), through, start_pos: call.start_pos.clone(), end_pos: call.end_pos.clone() });
+                }
+                self.warnings.borrow_mut().push(ValidateWarning::RecursiveFunction { name: function.df_name.clone(), through, start_pos: call.start_pos.clone(), end_pos: call.end_pos.clone() });
+                break;
+            }
+        }
+        Ok(())
+    }
+
     fn validate_expression_node(&self, mut expression_node: &mut ExpressionNode) -> Result<(), ValidateError> {
         match expression_node.node.clone() {
             Expression::Action { node } => {
@@ -103,26 +327,31 @@ impl Validator {
     }
 
     fn validate_action_node(&self, mut action_node: ActionNode) -> Result<ActionNode, ValidateError> {
-        let mut action = match action_node.action_type {
-            ActionType::Player => {
-                self.action_dump.player_actions.get(action_node.clone().name)
-            }
-            ActionType::Entity => {
-                self.action_dump.entity_actions.get(action_node.clone().name)
-            }
-            ActionType::Game => {
-                self.action_dump.game_actions.get(action_node.clone().name)
-            }
-            ActionType::Variable => {
-                self.action_dump.variable_actions.get(action_node.clone().name)
-            }
-            ActionType::Control => {
-                self.action_dump.control_actions.get(action_node.clone().name)
-            }
-            ActionType::Select => {
-                self.action_dump.select_actions.get(action_node.clone().name)
+        if action_node.selector_start_pos != action_node.selector_end_pos {
+            let kind = match action_node.action_type {
+                ActionType::Game => Some("game actions"),
+                ActionType::Control => Some("control actions"),
+                ActionType::Select => Some("select actions"),
+                _ => None
+            };
+            if let Some(kind) = kind {
+                return Err(ValidateError::UnexpectedSelector { kind: kind.into(), start_pos: action_node.selector_start_pos, end_pos: action_node.selector_end_pos })
             }
+        }
+
+        let actions = match action_node.action_type {
+            ActionType::Player => &self.definitions.action_dump.player_actions,
+            ActionType::Entity => &self.definitions.action_dump.entity_actions,
+            ActionType::Game => &self.definitions.action_dump.game_actions,
+            ActionType::Variable => &self.definitions.action_dump.variable_actions,
+            ActionType::Control => &self.definitions.action_dump.control_actions,
+            ActionType::Select => &self.definitions.action_dump.select_actions
         };
+        let mut action = actions.get(action_node.clone().name);
+
+        if let Some(definition) = action {
+            self.check_target_variable(&action_node, definition)?;
+        }
 
         let mut old_args = vec![];
         let mut old_name = "".into();
@@ -134,18 +363,20 @@ impl Validator {
                     old_args = action_node.args;
                     
                     match action {
-                        Some(res) => old_name = res.df_name.clone(),
-                        None => return Err(ValidateError::UnknownAction { name: action_node.name, start_pos: action_node.start_pos, end_pos: action_node.end_pos })
+                        Some(res) => {
+                            check_condition_type(res, &conditional_type, &old_args[0])?;
+                            old_name = res.df_name.clone()
+                        }
+                        None => return Err(unknown_action(action_node.name, actions, action_node.start_pos, action_node.end_pos))
                     };
 
+                    let conditionals = self.conditionals(&conditional_type);
+                    action = conditionals.get(name.clone());
+                    if action.is_none() {
+                        return Err(unknown_action(name, conditionals, old_args[0].start_pos.clone(), old_args[0].end_pos.clone()))
+                    }
                     action_node.args = args;
                     was_condition = true;
-                    action = match conditional_type {
-                        ConditionalType::Player => self.action_dump.player_conditionals.get(name),
-                        ConditionalType::Entity => self.action_dump.entity_conditionals.get(name),
-                        ConditionalType::Game => self.action_dump.game_conditionals.get(name),
-                        ConditionalType::Variable => self.action_dump.variable_conditionals.get(name),
-                    }
                 }
                 _ => unreachable!()
             }
@@ -153,7 +384,7 @@ impl Validator {
 
         match action {
             Some(res) => action_node = self.validate_action(action_node, res)?,
-            None => return Err(ValidateError::UnknownAction { name: action_node.name, start_pos: action_node.start_pos, end_pos: action_node.end_pos })
+            None => return Err(unknown_action(action_node.name, actions, action_node.start_pos, action_node.end_pos))
         };
 
         if was_condition {
@@ -170,31 +401,63 @@ impl Validator {
         Ok(action_node)
     }
 
+    fn check_target_variable(&self, action_node: &ActionNode, action: &Action) -> Result<(), ValidateError> {
+        if action_node.action_type != ActionType::Variable {
+            return Ok(())
+        }
+        let target = match action.args.first() {
+            Some(target) if target.arg_types == vec![ArgType::VARIABLE] && !target.optional => target,
+            _ => return Ok(())
+        };
+        match action_node.args.first() {
+            Some(arg) if arg.arg_type == ArgType::VARIABLE => Ok(()),
+            Some(Arg { value: ArgValue::GameValue { dfrs_name, .. }, start_pos, end_pos, .. }) => match self.definitions.game_values.get(dfrs_name.clone()) {
+                Some(_) => Err(ValidateError::ReadOnlyGameValue { game_value: dfrs_name.clone(), name: target.name.clone(), start_pos: start_pos.clone(), end_pos: end_pos.clone() }),
+                None => Err(ValidateError::UnknownGameValue { game_value: dfrs_name.clone(), start_pos: start_pos.clone(), end_pos: end_pos.clone() })
+            },
+            Some(arg) if arg.arg_type != ArgType::TAG => Err(ValidateError::MissingTargetVariable { name: target.name.clone(), start_pos: arg.start_pos.clone(), end_pos: arg.end_pos.clone() }),
+            _ => Err(ValidateError::MissingTargetVariable { name: target.name.clone(), start_pos: action_node.start_pos.clone(), end_pos: action_node.end_pos.clone() })
+        }
+    }
+
     fn validate_action(&self, mut action_node: ActionNode, action: &Action) -> Result<ActionNode, ValidateError> {
+        self.check_deprecated("Action", &action.dfrs_name, &action.deprecated, &action_node.start_pos, &action_node.end_pos);
         action_node.name.clone_from(&action.df_name);
         action_node.args = self.validate_args(action_node.args, action, action_node.start_pos.clone(), action_node.end_pos.clone())?;
         Ok(action_node)
     }
 
+    fn check_deprecated(&self, kind: &str, name: &str, note: &Option<String>, start_pos: &Position, end_pos: &Position) {
+        if let Some(note) = note {
+            self.warnings.borrow_mut().push(ValidateWarning::Deprecated { kind: kind.into(), name: name.into(), note: note.clone(), start_pos: start_pos.clone(), end_pos: end_pos.clone() });
+        }
+    }
+
     fn validate_conditional_node(&self, mut conditional_node: ConditionalNode) -> Result<ConditionalNode, ValidateError> {
-        let action = match conditional_node.conditional_type {
-            ConditionalType::Player => {
-                self.action_dump.player_conditionals.get(conditional_node.clone().name)
-            }
-            ConditionalType::Entity => {
-                self.action_dump.entity_conditionals.get(conditional_node.clone().name)
+        if let (Some(start_pos), Some(end_pos)) = (&conditional_node.inversion_start_pos, &conditional_node.inversion_end_pos) {
+            if Range::new(start_pos.clone(), end_pos.clone()).len() > 1 {
+                self.warnings.borrow_mut().push(ValidateWarning::RedundantInversion { start_pos: start_pos.clone(), end_pos: end_pos.clone() });
             }
-            ConditionalType::Game => {
-                self.action_dump.game_conditionals.get(conditional_node.clone().name)
-            }
-            ConditionalType::Variable => {
-                self.action_dump.variable_conditionals.get(conditional_node.clone().name)
+        }
+        if !conditional_node.expressions.is_empty() && same_expressions(&conditional_node.expressions, &conditional_node.else_expressions) {
+            self.warnings.borrow_mut().push(ValidateWarning::IdenticalBranches { start_pos: conditional_node.start_pos.clone(), end_pos: conditional_node.end_pos.clone() });
+        }
+
+        if let (Some(start_pos), Some(end_pos)) = (&conditional_node.selector_start_pos, &conditional_node.selector_end_pos) {
+            let kind = match conditional_node.conditional_type {
+                ConditionalType::Game => Some("game conditions"),
+                ConditionalType::Variable => Some("variable conditions"),
+                _ => None
+            };
+            if let Some(kind) = kind {
+                return Err(ValidateError::UnexpectedSelector { kind: kind.into(), start_pos: start_pos.clone(), end_pos: end_pos.clone() })
             }
-        };
+        }
 
-        match action {
+        let conditionals = self.conditionals(&conditional_node.conditional_type);
+        match conditionals.get(conditional_node.clone().name) {
             Some(res) => conditional_node = self.validate_conditional(conditional_node, res)?,
-            None => return Err(ValidateError::UnknownAction { name: conditional_node.name, start_pos: conditional_node.start_pos, end_pos: conditional_node.end_pos })
+            None => return Err(unknown_action(conditional_node.name, conditionals, conditional_node.start_pos, conditional_node.end_pos))
         };
 
         for expression in conditional_node.expressions.iter_mut() {
@@ -209,40 +472,77 @@ impl Validator {
     }
 
     fn validate_conditional(&self, mut conditional_node: ConditionalNode, action: &Action) -> Result<ConditionalNode, ValidateError> {
+        self.check_deprecated("Action", &action.dfrs_name, &action.deprecated, &conditional_node.start_pos, &conditional_node.end_pos);
         conditional_node.name.clone_from(&action.df_name);
         conditional_node.args = self.validate_args(conditional_node.args, action, conditional_node.start_pos.clone(), conditional_node.end_pos.clone())?;
         Ok(conditional_node)
     }
 
     fn validate_call(&self, mut call_node: CallNode) -> Result<CallNode, ValidateError> {
-        // TODO proper validation
         let mut args = vec![];
-        for arg in &call_node.args {
-            args.push(DefinedArg {
-                arg_types: vec![ArgType::ANY],
-                name: "".into(),
-                allow_multiple: false,
-                optional: false,
-            })
+        match self.functions.borrow().get(&call_node.name) {
+            Some(params) => {
+                for param in params {
+                    args.push(DefinedArg {
+                        arg_types: param_arg_types(&param.param_type),
+                        name: param.name.clone(),
+                        allow_multiple: param.multiple,
+                        optional: param.optional,
+                        range: None
+                    })
+                }
+            }
+            None if self.left_out.contains_key(&call_node.name) || self.check_calls.get() => {
+                return Err(ValidateError::UnknownFunction { name: call_node.name.clone(), left_out_of: self.left_out.get(&call_node.name).cloned(), start_pos: call_node.start_pos, end_pos: call_node.end_pos })
+            }
+            // Functions from other files can't be checked, so accept anything
+            None => {
+                for _ in &call_node.args {
+                    args.push(DefinedArg {
+                        arg_types: vec![ArgType::ANY],
+                        name: "".into(),
+                        allow_multiple: false,
+                        optional: false,
+                        range: None
+                    })
+                }
+            }
         }
         let action = Action {
-            df_name: "internal".into(),
-            dfrs_name: "internal".into(),
+            df_name: call_node.name.clone(),
+            dfrs_name: call_node.name.clone(),
             args,
             tags: vec![],
-            has_conditional_arg: false
+            sub_action_blocks: vec![],
+            description: "".into(),
+            deprecated: None
         };
         call_node.args = self.validate_args(call_node.args, &action, call_node.start_pos.clone(), call_node.end_pos.clone())?;
+        self.track_locals.set(false);
         Ok(call_node)
     }
 
     fn validate_start(&self, mut start_node: StartNode) -> Result<StartNode, ValidateError> {
-        start_node.args = self.validate_args(start_node.args, &self.action_dump.start_process_action, start_node.start_pos.clone(), start_node.end_pos.clone())?;
+        // Processes don't declare params, so anything other than tags passed to a known one is ignored
+        if self.processes.borrow().contains(&start_node.name) && start_node.args.iter().any(|arg| arg.arg_type != ArgType::TAG) {
+            let passed: Vec<&Arg> = start_node.args.iter().filter(|arg| arg.arg_type != ArgType::TAG).collect();
+            self.warnings.borrow_mut().push(ValidateWarning::UnexpectedProcessArguments {
+                name: start_node.name.clone(),
+                start_pos: passed.first().unwrap().start_pos.clone(),
+                end_pos: passed.last().unwrap().end_pos.clone()
+            });
+            start_node.args.retain(|arg| arg.arg_type == ArgType::TAG);
+            for (index, arg) in start_node.args.iter_mut().enumerate() {
+                arg.index = index as i32;
+            }
+        }
+        start_node.args = self.validate_args(start_node.args, &self.definitions.action_dump.start_process_action, start_node.start_pos.clone(), start_node.end_pos.clone())?;
         Ok(start_node)
     }
 
     fn validate_repeat_node(&self, mut repeat_node: RepeatNode) -> Result<RepeatNode, ValidateError> {
-        let mut action = self.action_dump.repeats.get(repeat_node.clone().name);
+        let repeats = &self.definitions.action_dump.repeats;
+        let mut action = repeats.get(repeat_node.clone().name);
         let mut old_args = vec![];
         let mut old_name = "".into();
         let mut was_condition = false;
@@ -253,18 +553,20 @@ impl Validator {
                     old_args = repeat_node.args;
                     
                     match action {
-                        Some(res) => old_name = res.df_name.clone(),
-                        None => return Err(ValidateError::UnknownAction { name: repeat_node.name, start_pos: repeat_node.start_pos, end_pos: repeat_node.end_pos })
+                        Some(res) => {
+                            check_condition_type(res, &conditional_type, &old_args[0])?;
+                            old_name = res.df_name.clone()
+                        }
+                        None => return Err(unknown_action(repeat_node.name, repeats, repeat_node.start_pos, repeat_node.end_pos))
                     };
 
+                    let conditionals = self.conditionals(&conditional_type);
+                    action = conditionals.get(name.clone());
+                    if action.is_none() {
+                        return Err(unknown_action(name, conditionals, old_args[0].start_pos.clone(), old_args[0].end_pos.clone()))
+                    }
                     repeat_node.args = args;
                     was_condition = true;
-                    action = match conditional_type {
-                        ConditionalType::Player => self.action_dump.player_conditionals.get(name),
-                        ConditionalType::Entity => self.action_dump.entity_conditionals.get(name),
-                        ConditionalType::Game => self.action_dump.game_conditionals.get(name),
-                        ConditionalType::Variable => self.action_dump.variable_conditionals.get(name),
-                    }
                 }
                 _ => unreachable!()
             }
@@ -272,7 +574,7 @@ impl Validator {
 
         match action {
             Some(res) => repeat_node = self.validate_repeat(repeat_node, res)?,
-            None => return Err(ValidateError::UnknownAction { name: repeat_node.name, start_pos: repeat_node.start_pos, end_pos: repeat_node.end_pos })
+            None => return Err(unknown_action(repeat_node.name, repeats, repeat_node.start_pos, repeat_node.end_pos))
         };
         if was_condition {
             match old_args.get(0).unwrap().clone().value {
@@ -292,7 +594,17 @@ impl Validator {
         Ok(repeat_node)
     }
 
+    fn conditionals(&self, conditional_type: &ConditionalType) -> &ValueList<Action> {
+        match conditional_type {
+            ConditionalType::Player => &self.definitions.action_dump.player_conditionals,
+            ConditionalType::Entity => &self.definitions.action_dump.entity_conditionals,
+            ConditionalType::Game => &self.definitions.action_dump.game_conditionals,
+            ConditionalType::Variable => &self.definitions.action_dump.variable_conditionals
+        }
+    }
+
     fn validate_repeat(&self, mut repeat_node: RepeatNode, action: &Action) -> Result<RepeatNode, ValidateError> {
+        self.check_deprecated("Action", &action.dfrs_name, &action.deprecated, &repeat_node.start_pos, &repeat_node.end_pos);
         repeat_node.name.clone_from(&action.df_name);
         repeat_node.args = self.validate_args(repeat_node.args, action, repeat_node.start_pos.clone(), repeat_node.end_pos.clone())?;
         Ok(repeat_node)
@@ -305,12 +617,17 @@ impl Validator {
         let mut index: i32 = -1;
 
         let mut tags: Vec<Arg> = vec![];
-        for arg in action.args.clone() {
+        // Variables written by this block, applied after its other arguments have been read
+        let mut written: Vec<(String, String)> = vec![];
+        for (arg_index, arg) in action.args.clone().into_iter().enumerate() {
+            let required_after: Vec<&DefinedArg> = action.args[arg_index + 1..].iter().filter(|arg| !arg.optional).collect();
             let mut match_more = true;
             let mut matched_one = false;
             while match_more {
                 if !arg.allow_multiple {
                     match_more = false;
+                } else if matched_one && !leaves_required(&node_args, &required_after) {
+                    break;
                 }
                 index += 1;
                 if node_args.is_empty() {
@@ -339,13 +656,35 @@ impl Validator {
                     continue;
                 }
 
-                if provided_arg.arg_type == ArgType::EMPTY && !arg.optional {
-                    return Err(ValidateError::MissingArgument { name: arg.name, start_pos, end_pos })
+                if provided_arg.arg_type == ArgType::CONDITION {
+                    return Err(ValidateError::UnexpectedCondition { action: action.dfrs_name.clone(), start_pos: provided_arg.start_pos, end_pos: provided_arg.end_pos })
+                }
+
+                // `null` leaves out an optional argument, required ones need a value
+                if provided_arg.arg_type == ArgType::EMPTY {
+                    if !arg.optional {
+                        return Err(ValidateError::NullArgument { name: arg.name, start_pos: provided_arg.start_pos, end_pos: provided_arg.end_pos })
+                    }
+                    provided_arg.index = index;
+                    args.push(provided_arg);
+                    matched_one = true;
+                    continue;
                 }
 
                 if let ArgValue::GameValue { df_name, dfrs_name, selector, selector_end_pos } = provided_arg.value {
-                    let actual_game_value = self.game_values.get(dfrs_name.clone());
+                    let actual_game_value = self.definitions.game_values.get(dfrs_name.clone());
                     match actual_game_value {
+                        Some(res) if !res.selector_allowed && selector_end_pos != provided_arg.start_pos => return Err(ValidateError::UnexpectedGameValueSelector {
+                            game_value: dfrs_name,
+                            start_pos: provided_arg.start_pos,
+                            end_pos: selector_end_pos
+                        }),
+                        Some(_) if arg.arg_types == vec![ArgType::VARIABLE] => return Err(ValidateError::ReadOnlyGameValue {
+                            game_value: dfrs_name,
+                            name: arg.name,
+                            start_pos: provided_arg.start_pos,
+                            end_pos: provided_arg.end_pos
+                        }),
                         Some(res) => {
                             provided_arg.value = ArgValue::GameValue {
                                 df_name: Some(res.df_name.clone()),
@@ -363,6 +702,42 @@ impl Validator {
                     }
                 }
 
+                if let ArgValue::Variable { name, scope } = &provided_arg.value {
+                    if scope == "unsaved" || scope == "saved" {
+                        self.check_plot_variable(name, &provided_arg);
+                    }
+                    if arg.arg_types == vec![ArgType::VARIABLE] {
+                        written.push((scope.clone(), name.clone()));
+                    } else {
+                        self.check_assigned(name, scope, &provided_arg);
+                    }
+                }
+
+                if let ArgValue::Sound { sound, variant: Some(variant), .. } = &provided_arg.value {
+                    let definition = self.definitions.action_dump.sounds.all().iter().find(|definition| definition.name() == sound);
+                    if definition.is_some_and(|definition| definition.variants() == 0) {
+                        return Err(ValidateError::InvalidSoundVariant { sound: sound.clone(), variant: variant.clone(), start_pos: provided_arg.start_pos, end_pos: provided_arg.end_pos })
+                    }
+                }
+
+                if let ArgValue::Item { item } = &provided_arg.value {
+                    if let Some(count) = item_count(item).filter(|count| *count > self.config.max_stack_size) {
+                        self.warnings.borrow_mut().push(ValidateWarning::ItemCountExceedsStack { count, max: self.config.max_stack_size, start_pos: provided_arg.start_pos.clone(), end_pos: provided_arg.end_pos.clone() });
+                    }
+                }
+
+                if let Some((kind, name)) = self.unknown_value(&provided_arg.value) {
+                    return Err(ValidateError::UnknownValue { kind: kind.into(), name, start_pos: provided_arg.start_pos, end_pos: provided_arg.end_pos })
+                }
+
+                if let ArgValue::Particle { particle, data, .. } = &provided_arg.value {
+                    self.check_particle_fields(particle, data, &provided_arg);
+                }
+
+                if !arg.arg_types.contains(&provided_arg.arg_type) && self.config.coerce_types {
+                    provided_arg = self.coerce_arg(provided_arg, &arg.arg_types);
+                }
+
                 if !arg.arg_types.contains(&provided_arg.arg_type) && !arg.arg_types.contains(&ArgType::ANY) && provided_arg.arg_type != ArgType::VARIABLE {
                     if arg.allow_multiple && matched_one {
                         node_args.insert(0, provided_arg);
@@ -372,12 +747,20 @@ impl Validator {
                     return Err(ValidateError::WrongArgumentType { args: all_provided_args, index, name: arg.name, expected_types: arg.arg_types, found_type: provided_arg.arg_type })
                 }
 
+                if let (ArgValue::Number { number }, Some((min, max))) = (&provided_arg.value, arg.range) {
+                    if *number < min || *number > max {
+                        return Err(ValidateError::OutOfRange { name: arg.name, value: *number, min, max, start_pos: provided_arg.start_pos, end_pos: provided_arg.end_pos })
+                    }
+                }
+
                 provided_arg.index = index;
                 args.push(provided_arg);
                 matched_one = true;
             }
         }
 
+        self.assigned.borrow_mut().extend(written);
+
         if !node_args.is_empty() {
             for val in node_args.clone() {
                 if val.arg_type != ArgType::TAG {
@@ -413,7 +796,7 @@ impl Validator {
                     ArgValue::Tag { tag: tag_name, value, name_end_pos, value_start_pos , ..} => {
                         let actual = match value.clone().as_ref() {
                             ArgValue::Text { text } => text.clone(),
-                            err => return Err(ValidateError::InvalidTagOption { tag_name, provided: format!("{err:?}"), options: tag.options, start_pos: value_start_pos, end_pos: given_tag.end_pos })
+                            err => return Err(ValidateError::InvalidTagOption { tag_name, provided: format!("{err:?}"), options: tag.options, suggestion: None, start_pos: value_start_pos, end_pos: given_tag.end_pos })
                         };
                         if tag.dfrs_name == tag_name {
                             if tag.options.contains(&actual) {
@@ -426,7 +809,8 @@ impl Validator {
                                     end_pos: given_tag.end_pos
                                 });
                             } else {
-                                return Err(ValidateError::InvalidTagOption { tag_name, provided: actual, options: tag.options, start_pos: value_start_pos, end_pos: given_tag.end_pos });
+                                let suggestion = tag.options.iter().find(|option| option.eq_ignore_ascii_case(&actual)).cloned();
+                                return Err(ValidateError::InvalidTagOption { tag_name, provided: actual, options: tag.options, suggestion, start_pos: value_start_pos, end_pos: given_tag.end_pos });
                             }
                         }
                     }
@@ -445,8 +829,811 @@ impl Validator {
             }
         }
 
+        if args.len() > CHEST_SLOTS {
+            self.warnings.borrow_mut().push(ValidateWarning::TooManySlots { name: action.dfrs_name.clone(), count: args.len(), max: CHEST_SLOTS, start_pos, end_pos });
+        }
+
         Ok(args)
     }
+
+    fn check_particle_fields(&self, particle: &str, data: &ParticleData, arg: &Arg) {
+        let used = particle_fields(data);
+        let definition = self.definitions.action_dump.particles.all().iter().find(|definition| definition.name() == particle);
+        if let Some(definition) = definition {
+            for (field, dump_name) in &used {
+                if !definition.fields().iter().any(|supported| supported == dump_name) {
+                    self.warnings.borrow_mut().push(ValidateWarning::UnsupportedParticleField { particle: particle.into(), field: field.to_string(), start_pos: arg.start_pos.clone(), end_pos: arg.end_pos.clone() });
+                }
+            }
+        }
+        // The variation is a percentage of the motion, so it does nothing on its own
+        if data.motion_variation.is_some() && data.x.is_none() {
+            self.warnings.borrow_mut().push(ValidateWarning::MotionVariationWithoutMotion { start_pos: arg.start_pos.clone(), end_pos: arg.end_pos.clone() });
+        }
+    }
+
+    fn coerce_arg(&self, mut arg: Arg, expected_types: &[ArgType]) -> Arg {
+        let text = match &arg.value {
+            ArgValue::Number { number } => number.to_string(),
+            ArgValue::String { string } => string.clone(),
+            _ => return arg
+        };
+
+        if arg.arg_type == ArgType::NUMBER && expected_types.contains(&ArgType::STRING) {
+            arg.value = ArgValue::String { string: text };
+            arg.arg_type = ArgType::STRING;
+        } else if expected_types.contains(&ArgType::TEXT) {
+            arg.value = ArgValue::Text { text };
+            arg.arg_type = ArgType::TEXT;
+        }
+        arg
+    }
+}
+
+fn unknown_action(name: String, actions: &ValueList<Action>, start_pos: Position, end_pos: Position) -> ValidateError {
+    let lowercase = name.to_lowercase();
+    let max_distance = (name.chars().count() / 3).max(2);
+    let mut candidates: Vec<(usize, &Action)> = actions.all().iter()
+        .map(|action| (edit_distance(&lowercase, &action.dfrs_name.to_lowercase()), action))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .collect();
+    candidates.sort_by_key(|(distance, _)| *distance);
+    let suggestions = candidates.into_iter().take(3)
+        .map(|(_, action)| ActionSuggestion { name: action.dfrs_name.clone(), description: action.description.clone() })
+        .collect();
+    ValidateError::UnknownAction { name, suggestions, start_pos, end_pos }
+}
+
+// Pairs the dfrs name of every field set on a particle with its name in the action dump
+fn particle_fields(data: &ParticleData) -> Vec<(&'static str, &'static str)> {
+    let mut fields = vec![];
+    if data.x.is_some() { fields.push(("motion", "Motion")) }
+    if data.motion_variation.is_some() { fields.push(("motionVariation", "Motion Variation")) }
+    if data.rgb.is_some() { fields.push(("rgb", "Color")) }
+    if data.rgb_fade.is_some() { fields.push(("rgbFade", "Fade Color")) }
+    if data.color_variation.is_some() { fields.push(("colorVariation", "Color Variation")) }
+    if data.material.is_some() { fields.push(("material", "Material")) }
+    if data.size.is_some() { fields.push(("size", "Size")) }
+    if data.size_variation.is_some() { fields.push(("sizeVariation", "Size Variation")) }
+    if data.roll.is_some() { fields.push(("roll", "Roll")) }
+    fields
+}
+
+fn check_condition_type(action: &Action, conditional_type: &ConditionalType, arg: &Arg) -> Result<(), ValidateError> {
+    let block = |block: &str| match block {
+        "if_player" => "ifp",
+        "if_entity" => "ife",
+        "if_game" => "ifg",
+        "if_var" => "ifv",
+        other => other
+    }.to_owned();
+    let found = match conditional_type {
+        ConditionalType::Player => "ifp",
+        ConditionalType::Entity => "ife",
+        ConditionalType::Game => "ifg",
+        ConditionalType::Variable => "ifv"
+    };
+    if action.sub_action_blocks.is_empty() {
+        return Err(ValidateError::UnexpectedCondition { action: action.dfrs_name.clone(), start_pos: arg.start_pos.clone(), end_pos: arg.end_pos.clone() })
+    }
+    let allowed: Vec<String> = action.sub_action_blocks.iter().map(|allowed| block(allowed)).collect();
+    if allowed.iter().any(|allowed| allowed == found) {
+        return Ok(())
+    }
+    Err(ValidateError::UnsupportedConditionType { action: action.dfrs_name.clone(), found: found.into(), allowed, start_pos: arg.start_pos.clone(), end_pos: arg.end_pos.clone() })
+}
+
+// Variable, list and dict params are passed by reference, so they need an actual variable
+fn param_arg_types(param_type: &Type) -> Vec<ArgType> {
+    match param_type {
+        Type::String => vec![ArgType::STRING],
+        Type::Text => vec![ArgType::TEXT],
+        Type::Number => vec![ArgType::NUMBER],
+        Type::Location => vec![ArgType::LOCATION],
+        Type::Vector => vec![ArgType::VECTOR],
+        Type::Sound => vec![ArgType::SOUND],
+        Type::Particle => vec![ArgType::PARTICLE],
+        Type::Potion => vec![ArgType::POTION],
+        Type::Item => vec![ArgType::ITEM],
+        Type::Any => vec![ArgType::ANY],
+        Type::Variable | Type::List | Type::Dict => vec![ArgType::VARIABLE]
+    }
+}
+
+fn check_defaults(params: &[FunctionParamNode]) -> Result<(), ValidateError> {
+    for param in params {
+        let Some(default) = &param.default else { continue };
+        let allowed = param_arg_types(&param.param_type);
+        let found = default.value.kind();
+        if !allowed.contains(&ArgType::ANY) && !allowed.contains(&found) {
+            return Err(ValidateError::InvalidDefault { name: param.name.clone(), expected: param.param_type.clone(), found, start_pos: default.start_pos.clone(), end_pos: default.end_pos.clone() })
+        }
+    }
+    Ok(())
 }
 
-// TODO validate potions, sounds, particles etc
\ No newline at end of file
+fn collect_calls(expressions: &[ExpressionNode], calls: &mut Vec<CallNode>) {
+    for expression in expressions {
+        match &expression.node {
+            Expression::Call { node } => calls.push(node.clone()),
+            Expression::Conditional { node } => {
+                collect_calls(&node.expressions, calls);
+                collect_calls(&node.else_expressions, calls);
+            }
+            Expression::Repeat { node } => collect_calls(&node.expressions, calls),
+            _ => {}
+        }
+    }
+}
+
+fn reaches(calls: &HashMap<String, Vec<CallNode>>, from: &str, target: &str, visited: &mut HashSet<String>) -> bool {
+    if from == target {
+        return true;
+    }
+    if !visited.insert(from.to_owned()) {
+        return false;
+    }
+    match calls.get(from) {
+        Some(called) => called.iter().any(|call| reaches(calls, &call.name, target, visited)),
+        None => false
+    }
+}
+
+// A plural argument only takes the next value if the values after it can still fill the required arguments that follow
+fn leaves_required(remaining: &[Arg], required: &[&DefinedArg]) -> bool {
+    let fitting = remaining.iter()
+        .filter(|value| value.arg_type != ArgType::TAG)
+        .skip(1)
+        .filter(|value| required.iter().any(|arg| {
+            // The type of variables and game values is only known on DiamondFire
+            matches!(value.arg_type, ArgType::VARIABLE | ArgType::GameValue) || arg.arg_types.contains(&value.arg_type) || arg.arg_types.contains(&ArgType::ANY)
+        }))
+        .count();
+    fitting >= required.len()
+}
+
+fn is_known<'a>(name: &str, mut names: impl Iterator<Item = &'a String>, extra: &[String]) -> bool {
+    name.contains('%') || names.any(|known| known.eq_ignore_ascii_case(name)) || extra.iter().any(|known| known.eq_ignore_ascii_case(name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::testing::{validate, validate_with, warnings};
+
+    fn first_action(node: &FileNode) -> &ActionNode {
+        match &node.events[0].expressions[0].node {
+            Expression::Action { node } => node,
+            other => panic!("Expected an action, got {other:?}")
+        }
+    }
+
+    #[test]
+    fn number_for_text_is_rejected_by_default() {
+        let err = validate("@join {\n    p.sendMessage(5);\n}").unwrap_err();
+        assert!(matches!(err, ValidateError::WrongArgumentType { expected_types, found_type: ArgType::NUMBER, .. } if expected_types == vec![ArgType::TEXT]));
+    }
+
+    #[test]
+    fn number_for_text_is_coerced() {
+        let mut config = Config::default();
+        config.validation.coerce_types = true;
+        let (node, _) = validate_with("@join {\n    p.sendMessage(5, 'hi');\n}", &config).unwrap();
+        let args = &first_action(&node).args;
+        assert!(matches!(&args[0].value, ArgValue::Text { text } if text == "5"));
+        assert!(matches!(&args[1].value, ArgValue::Text { text } if text == "hi"));
+    }
+
+    fn item(id: &str) -> String {
+        format!("Item(\"{{Count:1b,id:\\\"minecraft:{id}\\\"}}\")")
+    }
+
+    // The item, or the kind of every other value, in the order the validated arguments were matched
+    fn matched(node: &FileNode) -> Vec<(i32, String)> {
+        first_action(node).args.iter().filter(|arg| arg.arg_type != ArgType::TAG).map(|arg| (arg.index, match &arg.value {
+            ArgValue::Item { item } => item.split("minecraft:").nth(1).unwrap_or_default().trim_end_matches(['"', '\\', '}']).to_owned(),
+            value => format!("{:?}", value.kind())
+        })).collect()
+    }
+
+    #[test]
+    fn plural_stops_before_required_argument() {
+        let matched_from = |items: &str| matched(&validate(&format!("@join {{\n    p.replaceItems({items});\n}}")).unwrap());
+        let expected = vec![(0, "stone".into()), (1, "dirt".into()), (2, "sand".into()), (3, "EMPTY".into())];
+        assert_eq!(matched_from(&format!("{}, {}, {}", item("stone"), item("dirt"), item("sand"))), expected);
+        let expected = vec![(0, "dirt".into()), (1, "sand".into()), (2, "NUMBER".into())];
+        assert_eq!(matched_from(&format!("{}, {}, 5", item("dirt"), item("sand"))), expected);
+    }
+
+    #[test]
+    fn direct_recursion_warns() {
+        let warnings = warnings("fn foo() {\n    call(\"foo\");\n}");
+        assert!(matches!(&warnings[..], [ValidateWarning::RecursiveFunction { name, through: None, start_pos, .. }] if name == "foo" && *start_pos == Position::new(2, 5)));
+    }
+
+    #[test]
+    fn indirect_recursion_warns() {
+        let warnings = warnings("fn a() {\n    call(\"b\");\n}\nfn b() {\n    call(\"a\");\n}");
+        assert!(matches!(&warnings[0], ValidateWarning::RecursiveFunction { name, through: Some(through), .. } if name == "a" && through == "b"));
+    }
+
+    #[test]
+    fn recursion_can_be_denied() {
+        let mut config = Config::default();
+        config.validation.deny_recursion = true;
+        assert!(matches!(validate_with("fn foo() {\n    call(\"foo\");\n}", &config), Err(ValidateError::RecursiveFunction { name, .. }) if name == "foo"));
+    }
+
+    #[test]
+    fn namespaced_sound() {
+        let node = validate("@join {\n    p.playSound(Sound(\"minecraft:entity.pig.ambient\", 1, 1));\n}").unwrap();
+        assert!(matches!(&first_action(&node).args[0].value, ArgValue::Sound { sound, variant: None, .. } if sound == "minecraft:entity.pig.ambient"));
+    }
+
+    #[test]
+    fn sound_variants() {
+        assert!(validate("@join {\n    p.playSound(Sound(\"Axolotl Attack\", 1, 2, \"variant\"));\n}").is_ok());
+        let err = validate("@join {\n    p.playSound(Sound(\"Bat Death\", 1, 2, \"variant\"));\n}").unwrap_err();
+        assert!(matches!(err, ValidateError::InvalidSoundVariant { sound, variant, .. } if sound == "Bat Death" && variant == "variant"));
+    }
+
+    #[test]
+    fn variable_action_without_target() {
+        let err = validate("@join {\n    v.add();\n}").unwrap_err();
+        assert!(matches!(err, ValidateError::MissingTargetVariable { start_pos, end_pos, .. } if start_pos == Position::new(2, 7) && end_pos == Position::new(2, 10)));
+        assert!(validate("@join {\n    line sum;\n    v.add(sum, 1, 2);\n}").is_ok());
+    }
+
+    #[test]
+    fn selector_on_targetless_game_value() {
+        let err = validate("@join {\n    p.sendMessage($default:playerCount);\n}").unwrap_err();
+        assert!(matches!(err, ValidateError::UnexpectedGameValueSelector { game_value, start_pos, end_pos } if game_value == "playerCount" && start_pos == Position::new(2, 20) && end_pos == Position::new(2, 28)));
+        assert!(validate("@join {\n    p.sendMessage($selection:name);\n}").is_ok());
+    }
+
+    #[test]
+    fn errors_have_codes() {
+        assert_eq!(validate("@join {\n    p.sendMesage(\"Hi\");\n}").unwrap_err().code(), "E001");
+        assert_eq!(validate("@joinGame {\n}").unwrap_err().code(), "E002");
+    }
+
+    #[test]
+    fn redundant_inversion() {
+        let found = warnings("@join {\n    ifp !!isSneaking() {\n        p.sendMessage(\"A\");\n    }\n}");
+        assert!(matches!(found.as_slice(), [ValidateWarning::RedundantInversion { start_pos, end_pos }] if *start_pos == Position::new(2, 9) && *end_pos == Position::new(2, 11)));
+        assert!(warnings("@join {\n    ifp !isSneaking() {\n        p.sendMessage(\"A\");\n    }\n}").is_empty());
+    }
+
+    // Kind, name and both ranges of a duplicate definition
+    fn duplicate(source: &str) -> (String, String, (Position, Position), (Position, Position)) {
+        match validate(source) {
+            Err(ValidateError::DuplicateDefinition { kind, name, start_pos, end_pos, previous_start_pos, previous_end_pos }) => (kind, name, (start_pos, end_pos), (previous_start_pos, previous_end_pos)),
+            other => panic!("Expected a duplicate definition, got {other:?}")
+        }
+    }
+
+    #[test]
+    fn duplicate_function() {
+        let (kind, name, range, previous) = duplicate("fn foo() {\n}\nfn bar() {\n}\nfn foo(a: number) {\n}");
+        assert_eq!((kind.as_str(), name.as_str()), ("function", "foo"));
+        assert_eq!(range, (Position::new(5, 4), Position::new(5, 7)));
+        assert_eq!(previous, (Position::new(1, 4), Position::new(1, 7)));
+    }
+
+    #[test]
+    fn duplicate_process() {
+        let (kind, name, range, previous) = duplicate("proc loop {\n}\nproc loop {\n}");
+        assert_eq!((kind.as_str(), name.as_str()), ("process", "loop"));
+        assert_eq!(range, (Position::new(3, 6), Position::new(3, 10)));
+        assert_eq!(previous, (Position::new(1, 6), Position::new(1, 10)));
+    }
+
+    #[test]
+    fn duplicate_event() {
+        let (kind, name, _, _) = duplicate("@join {\n}\n@join {\n}");
+        assert_eq!((kind.as_str(), name.as_str()), ("event", "Join"));
+    }
+
+    #[test]
+    fn same_name_different_kinds() {
+        assert!(validate("fn foo() {\n}\nproc foo {\n}").is_ok());
+        assert!(validate("@teleport {\n}\n@entity:teleport {\n}").is_ok());
+    }
+
+    #[test]
+    fn extra_sounds() {
+        let source = "@join {\n    p.playSound(Sound(\"Custom Boom\", 1, 1));\n}";
+        assert!(matches!(validate(source), Err(ValidateError::UnknownValue { kind, name, .. }) if kind == "sound" && name == "Custom Boom"));
+        let mut config = Config::default();
+        config.validation.extra_sounds = vec!["custom boom".into()];
+        assert!(validate_with(source, &config).is_ok());
+        assert!(validate("@join {\n    p.playSound(Sound(\"minecraft:custom.boom\", 1, 1));\n}").is_ok());
+    }
+
+    #[test]
+    fn extra_potions_and_particles() {
+        let source = "@join {\n    p.givePotion(Potion(\"Glowing Skin\", 1, 10));\n    p.particle(Particle(\"Sparks\", 1, 0, 0), Location(0, 0, 0));\n}";
+        let mut config = Config::default();
+        config.validation.extra_potions = vec!["Glowing Skin".into()];
+        assert!(matches!(validate_with(source, &config), Err(ValidateError::UnknownValue { kind, name, .. }) if kind == "particle" && name == "Sparks"));
+        config.validation.extra_particles = vec!["Sparks".into()];
+        assert!(validate_with(source, &config).is_ok());
+    }
+
+    #[test]
+    fn out_of_range() {
+        match validate("@join {\n    p.walkSpeed(600);\n}") {
+            Err(ValidateError::OutOfRange { value, min, max, start_pos, end_pos, .. }) => {
+                assert_eq!((value, min, max), (600.0, 0.0, 500.0));
+                assert_eq!((start_pos, end_pos), (Position::new(2, 17), Position::new(2, 20)));
+            }
+            other => panic!("Expected an out of range number, got {other:?}")
+        }
+        assert!(matches!(validate("@join {\n    g.spawnTNT(Location(0, 0, 0), 5);\n}"), Err(ValidateError::OutOfRange { min: 0.0, max: 4.0, .. })));
+    }
+
+    #[test]
+    fn in_range() {
+        assert!(validate("@join {\n    p.walkSpeed(500);\n    p.walkSpeed(0);\n    g.spawnTNT(Location(0, 0, 0), 4);\n}").is_ok());
+        assert!(validate("@join {\n    line speed;\n    p.walkSpeed(speed);\n}").is_ok());
+    }
+
+    fn event_types(source: &str) -> Vec<(Option<ActionType>, String)> {
+        validate(source).unwrap().events.into_iter().map(|event| (event.event_type, event.event)).collect()
+    }
+
+    #[test]
+    fn prefixed_events() {
+        assert_eq!(event_types("@entity:teleport {\n}"), [(Some(ActionType::Entity), "Teleport".to_string())]);
+        assert_eq!(event_types("@player:teleport {\n}"), [(Some(ActionType::Player), "Teleport".to_string())]);
+        assert_eq!(event_types("@teleport {\n}"), [(Some(ActionType::Player), "Teleport".to_string())]);
+        assert_eq!(event_types("@blockFall {\n}"), [(Some(ActionType::Entity), "BlockFall".to_string())]);
+    }
+
+    #[test]
+    fn prefixed_event_of_wrong_kind() {
+        assert!(matches!(validate("@player:blockFall {\n}"), Err(ValidateError::UnknownEvent { node }) if node.event_type == Some(ActionType::Player)));
+    }
+
+    #[test]
+    fn item_count_exceeds_stack() {
+        let source = "@join {\n    p.giveItems(Item(\"{Count:1b,id:\\\"minecraft:stone\\\"}\", 100));\n}";
+        assert!(matches!(warnings(source).as_slice(), [ValidateWarning::ItemCountExceedsStack { count: 100, max: 64, .. }]));
+        let mut config = Config::default();
+        config.validation.max_stack_size = 127;
+        assert!(validate_with(source, &config).unwrap().1.is_empty());
+        assert!(warnings("@join {\n    p.giveItems(Item(\"{Count:64b,id:\\\"minecraft:stone\\\"}\"));\n}").is_empty());
+        assert!(!warnings("@join {\n    p.giveItems(Item(\"{Count:65b,id:\\\"minecraft:stone\\\"}\"));\n}").is_empty());
+    }
+
+    #[test]
+    fn repeat_else_flag_is_assigned() {
+        assert!(warnings("@join {\n    repeat forever() {\n        c.wait(1);\n    } else {\n        p.sendMessage(\"B\");\n    }\n}").is_empty());
+    }
+
+    #[test]
+    fn tag_option_case_suggestion() {
+        match validate("@join {\n    p.sendMessage(\"Hi\", alignmentMode=\"centered\");\n}") {
+            Err(ValidateError::InvalidTagOption { tag_name, provided, suggestion, start_pos, end_pos, .. }) => {
+                assert_eq!((tag_name.as_str(), provided.as_str()), ("alignmentMode", "centered"));
+                assert_eq!(suggestion.as_deref(), Some("Centered"));
+                assert_eq!((start_pos, end_pos), (Position::new(2, 39), Position::new(2, 49)));
+            }
+            other => panic!("Expected an invalid tag option, got {other:?}")
+        }
+    }
+
+    #[test]
+    fn tag_option_without_suggestion() {
+        assert!(matches!(validate("@join {\n    p.sendMessage(\"Hi\", alignmentMode=\"centred\");\n}"), Err(ValidateError::InvalidTagOption { suggestion: None, options, .. }) if options == ["Regular", "Centered"]));
+        assert!(validate("@join {\n    p.sendMessage(\"Hi\", alignmentMode=\"Centered\");\n}").is_ok());
+    }
+
+    #[test]
+    fn arguments_to_process_are_dropped() {
+        let source = "proc loop {\n}\n@join {\n    start(\"loop\", 5, \"hi\");\n}";
+        let (node, found) = validate_with(source, &Config::default()).unwrap();
+        match &found[..] {
+            [ValidateWarning::UnexpectedProcessArguments { name, start_pos, end_pos }] => {
+                assert_eq!(name, "loop");
+                assert_eq!((start_pos.line, start_pos.col, end_pos.line, end_pos.col), (4, 19, 4, 26));
+            }
+            _ => panic!("Expected one warning about process arguments")
+        }
+        match &node.events[0].expressions[0].node {
+            Expression::Start { node } => assert!(node.args.iter().all(|arg| arg.arg_type == ArgType::TAG)),
+            other => panic!("Expected a start, got {other:?}")
+        }
+    }
+
+    #[test]
+    fn process_without_arguments_is_not_warned() {
+        assert!(warnings("proc loop {\n}\n@join {\n    start(\"loop\");\n}").is_empty());
+        // Processes from other files aren't known here, so their arguments are left alone
+        assert!(warnings("@join {\n    start(\"elsewhere\");\n}").is_empty());
+    }
+
+    fn branches(then: &str, otherwise: &str) -> Vec<ValidateWarning> {
+        warnings(&format!("@join {{\n    ifp isSneaking() {{\n{then}\n    }} else {{\n{otherwise}\n    }}\n}}"))
+    }
+
+    #[test]
+    fn identical_branches() {
+        let found = branches("        p.sendMessage(\"A\");\n        p.heal(1);", "            p.sendMessage(\"A\");\n   p.heal( 1 );");
+        assert!(matches!(found.as_slice(), [ValidateWarning::IdenticalBranches { start_pos, end_pos }] if *start_pos == Position::new(2, 9) && *end_pos == Position::new(2, 19)));
+        let nested = "        ifp isFlying() {\n            p.sendMessage(\"A\");\n        }";
+        assert_eq!(branches(nested, nested).len(), 1);
+    }
+
+    #[test]
+    fn different_branches() {
+        assert!(branches("        p.sendMessage(\"A\");", "        p.sendMessage(\"B\");").is_empty());
+        assert!(branches("        p.heal(1);", "        p.damage(1);").is_empty());
+        assert!(branches("        p.sendMessage(\"A\");", "        p.sendMessage(\"a\");").is_empty());
+        assert!(branches("        p.sendMessage(\"A\");", "        p.sendMessage(\"A\");\n        p.heal(1);").is_empty());
+        assert!(branches("        ifp isFlying() {\n            p.heal(1);\n        }", "        ifp isFlying() {\n            p.heal(2);\n        }").is_empty());
+        assert!(branches("        ifp isFlying() {\n        }", "        ifp !isFlying() {\n        }").is_empty());
+        assert!(branches("", "").is_empty());
+    }
+
+    fn call(params: &str, args: &str) -> Result<FileNode, ValidateError> {
+        validate(&format!("game counter;\nfn target({params}) {{\n}}\n@join {{\n    call(\"target\"{args});\n}}"))
+    }
+
+    #[test]
+    fn reference_param_needs_variable() {
+        assert!(matches!(call("target: variable", ", 5"), Err(ValidateError::WrongArgumentType { name, expected_types, found_type: ArgType::NUMBER, .. }) if name == "target" && expected_types == vec![ArgType::VARIABLE]));
+        assert!(matches!(call("target: list", ", \"a\""), Err(ValidateError::WrongArgumentType { found_type: ArgType::TEXT, .. })));
+        assert!(call("target: variable", ", counter").is_ok());
+        assert!(call("target: dict", ", counter").is_ok());
+    }
+
+    #[test]
+    fn value_param_takes_copy() {
+        assert!(call("amount: number", ", 5").is_ok());
+        // A variable's value is copied into a value param
+        assert!(call("amount: number", ", counter").is_ok());
+        assert!(matches!(call("amount: number", ", \"5\""), Err(ValidateError::WrongArgumentType { name, expected_types, found_type: ArgType::TEXT, .. }) if name == "amount" && expected_types == vec![ArgType::NUMBER]));
+    }
+
+    #[test]
+    fn call_arguments_are_counted() {
+        assert!(matches!(call("target: variable", ""), Err(ValidateError::MissingArgument { name, .. }) if name == "target"));
+        assert!(call("target: variable, amount?: number", ", counter").is_ok());
+        // Functions from other files are unknown, so anything is accepted
+        assert!(validate("@join {\n    call(\"elsewhere\", 5, \"x\");\n}").is_ok());
+    }
+
+    #[test]
+    fn select_condition_type() {
+        let err = validate("@join {\n    s.playersCond(ife isNearLocation(Location(0, 64, 0)));\n}").unwrap_err();
+        assert_eq!(err.code(), "E016");
+        match err {
+            ValidateError::UnsupportedConditionType { action, found, allowed, start_pos, end_pos } => {
+                assert_eq!((action.as_str(), found.as_str()), ("playersCond", "ife"));
+                assert_eq!(allowed, vec!["ifp", "ifv", "ifg"]);
+                assert_eq!((start_pos, end_pos), (Position::new(2, 23), Position::new(2, 37)));
+            }
+            _ => panic!("Expected an unsupported condition type")
+        }
+        assert!(validate("@join {\n    s.playersCond(ifp isSneaking());\n}").is_ok());
+    }
+
+    fn suggestions(source: &str) -> (String, Vec<(String, String)>, Position, Position) {
+        match validate(source) {
+            Err(ValidateError::UnknownAction { name, suggestions, start_pos, end_pos }) => {
+                (name, suggestions.into_iter().map(|suggestion| (suggestion.name, suggestion.description)).collect(), start_pos, end_pos)
+            }
+            _ => panic!("Expected an unknown action")
+        }
+    }
+
+    #[test]
+    fn unknown_action_suggestions() {
+        let (name, found, ..) = suggestions("@join {\n    p.sendMesage(\"Hi\");\n}");
+        assert_eq!(name, "sendMesage");
+        assert_eq!(found[0], ("sendMessage".into(), "Sends a chat message to a player.".into()));
+        assert!(found.len() <= 3);
+        assert!(suggestions("@join {\n    p.qqqqqqqqqq(\"Hi\");\n}").1.is_empty());
+    }
+
+    #[test]
+    fn unknown_wrapped_condition_suggestions() {
+        let (name, found, start_pos, end_pos) = suggestions("@join {\n    s.filterCondition(ifp isSneakng());\n}");
+        assert_eq!(name, "isSneakng");
+        assert_eq!(found[0], ("isSneaking".into(), "Checks if a player is sneaking.".into()));
+        assert_eq!((start_pos, end_pos), (Position::new(2, 27), Position::new(2, 36)));
+    }
+
+    // Code and range of the error for the first argument of a set variable action
+    fn target_error(args: &str) -> (&'static str, Position, Position) {
+        let err = validate(&format!("game counter;\n@join {{\n    v.equal({args});\n}}")).unwrap_err();
+        match &err {
+            ValidateError::MissingTargetVariable { start_pos, end_pos, .. } | ValidateError::ReadOnlyGameValue { start_pos, end_pos, .. } => (err.code(), start_pos.clone(), end_pos.clone()),
+            _ => panic!("Expected a target error, got {}", err.code())
+        }
+    }
+
+    #[test]
+    fn literal_variable_target() {
+        assert_eq!(target_error("5, 1"), ("E011", Position::new(3, 13), Position::new(3, 14)));
+        assert_eq!(target_error("\"counter\", 1"), ("E011", Position::new(3, 13), Position::new(3, 22)));
+        assert_eq!(target_error(""), ("E011", Position::new(3, 7), Position::new(3, 12)));
+        assert_eq!(target_error("$selection:name, 1"), ("E017", Position::new(3, 14), Position::new(3, 28)));
+        assert!(validate("game counter;\n@join {\n    v.equal(counter, 1);\n}").is_ok());
+    }
+
+    fn read_only(source: &str) -> (String, String, Position, Position) {
+        match validate(source) {
+            Err(ValidateError::ReadOnlyGameValue { game_value, name, start_pos, end_pos }) => (game_value, name, start_pos, end_pos),
+            Err(err) => panic!("Expected a read-only game value, got {}", err.code()),
+            Ok(_) => panic!("Expected a read-only game value")
+        }
+    }
+
+    #[test]
+    fn game_value_as_variable() {
+        let (game_value, name, start_pos, end_pos) = read_only("@join {\n    v.equal($default:currentHealth, 20);\n}");
+        assert_eq!((game_value.as_str(), name.as_str()), ("currentHealth", "Variable to set"));
+        assert_eq!((start_pos, end_pos), (Position::new(2, 14), Position::new(2, 35)));
+        assert_eq!(read_only("@join {\n    repeat multiple($default:currentHealth, 3) {\n    }\n}").0, "currentHealth");
+        let (game_value, name, ..) = read_only("fn reset(target: variable) {\n}\n@join {\n    call(\"reset\", $default:currentHealth);\n}");
+        assert_eq!((game_value.as_str(), name.as_str()), ("currentHealth", "target"));
+    }
+
+    // An unknown game value is reported as unknown, whether or not it could be written to
+    #[test]
+    fn unknown_game_value_as_variable() {
+        assert!(matches!(validate("@join {\n    v.equal($default:health, 20);\n}"), Err(ValidateError::UnknownGameValue { game_value, .. }) if game_value == "health"));
+        assert!(matches!(validate("fn reset(target: variable) {\n}\n@join {\n    call(\"reset\", $default:health);\n}"), Err(ValidateError::UnknownGameValue { .. })));
+    }
+
+    #[test]
+    fn game_value_as_value() {
+        assert!(validate("@join {\n    p.sendMessage($default:name);\n}").is_ok());
+        assert!(validate("game health;\n@join {\n    v.equal(health, $default:currentHealth);\n}").is_ok());
+        assert!(validate("fn show(value: any) {\n}\n@join {\n    call(\"show\", $default:currentHealth);\n}").is_ok());
+    }
+
+    fn unexpected_condition(statement: &str) -> (String, Position, Position) {
+        match validate(&format!("@join {{\n    line i;\n    {statement}\n}}")) {
+            Err(ValidateError::UnexpectedCondition { action, start_pos, end_pos }) => (action, start_pos, end_pos),
+            Err(err) => panic!("Expected an unexpected condition, got {}", err.code()),
+            Ok(_) => panic!("Expected an unexpected condition")
+        }
+    }
+
+    #[test]
+    fn condition_in_plain_action() {
+        assert_eq!(unexpected_condition("p.sendMessage(ifp isSneaking());"), ("sendMessage".into(), Position::new(3, 23), Position::new(3, 33)));
+        assert_eq!(unexpected_condition("p.sendMessage(\"a\", ifp isSneaking());"), ("sendMessage".into(), Position::new(3, 28), Position::new(3, 38)));
+        assert_eq!(unexpected_condition("g.cancelEvent(ifv equal(1, 1));").0, "cancelEvent");
+        assert_eq!(unexpected_condition("repeat multiple(i, ifp isSneaking()) {\n    }").0, "multiple");
+        assert_eq!(unexpected_condition("call(\"elsewhere\", ifp isSneaking());").0, "elsewhere");
+    }
+
+    #[test]
+    fn condition_where_expected() {
+        assert!(validate("game points;\n@join {\n    s.filterCondition(ifv equal(points, 10));\n    repeat while(ifv equal(points, 10)) {\n    }\n}").is_ok());
+    }
+
+    fn slot_warnings(messages: usize) -> Vec<(String, usize, usize, Position, Position)> {
+        let args = vec!["\"A\""; messages].join(", ");
+        warnings(&format!("@join {{\n    p.sendMessage({args});\n}}")).into_iter().filter_map(|warning| match warning {
+            ValidateWarning::TooManySlots { name, count, max, start_pos, end_pos } => Some((name, count, max, start_pos, end_pos)),
+            _ => None
+        }).collect()
+    }
+
+    // sendMessage has three tags, leaving 24 slots for messages
+    #[test]
+    fn too_many_slots_warns() {
+        assert_eq!(slot_warnings(25), vec![("sendMessage".into(), CHEST_SLOTS + 1, CHEST_SLOTS, Position::new(2, 7), Position::new(2, 18))]);
+    }
+
+    #[test]
+    fn filled_slots_are_not_warned() {
+        assert!(slot_warnings(24).is_empty());
+    }
+
+    fn particle_warnings(particle: &str, fields: &str) -> Vec<String> {
+        warnings(&format!("@join {{\n    p.particle(Particle(\"{particle}\", 1, 0, 0, {fields}), Location(0, 0, 0));\n}}")).into_iter().filter_map(|warning| match warning {
+            ValidateWarning::UnsupportedParticleField { particle, field, .. } => Some(format!("{particle} {field}")),
+            ValidateWarning::MotionVariationWithoutMotion { .. } => Some("motionVariation without motion".into()),
+            _ => None
+        }).collect()
+    }
+
+    #[test]
+    fn motion_variation_without_motion() {
+        assert_eq!(particle_warnings("Cloud", "motionVariation=50"), ["motionVariation without motion"]);
+        assert!(particle_warnings("Cloud", "motion=Vector(0, 1, 0), motionVariation=50").is_empty());
+    }
+
+    #[test]
+    fn unsupported_particle_fields() {
+        assert_eq!(particle_warnings("Cloud", "motion=Vector(0, 1, 0), roll=1, size=2"), ["Cloud size", "Cloud roll"]);
+        assert!(particle_warnings("Sculk Charge", "motion=Vector(0, 1, 0), roll=1").is_empty());
+        assert_eq!(particle_warnings("Sweep Attack", "size=2, motionVariation=10"), ["Sweep Attack motionVariation", "motionVariation without motion"]);
+    }
+
+    fn unexpected_selector(condition: &str) -> (String, Position, Position) {
+        match validate(&format!("@join {{\n    line x;\n    {condition} {{\n    }}\n}}")) {
+            Err(ValidateError::UnexpectedSelector { kind, start_pos, end_pos }) => (kind, start_pos, end_pos),
+            other => panic!("Expected an unexpected selector, got {other:?}")
+        }
+    }
+
+    #[test]
+    fn selector_on_variable_condition() {
+        assert_eq!(unexpected_selector("ifv selection:equal(x, 1)"), ("variable conditions".into(), Position::new(3, 9), Position::new(3, 18)));
+        assert_eq!(unexpected_selector("ifv !default:equal(x, 1)"), ("variable conditions".into(), Position::new(3, 10), Position::new(3, 17)));
+    }
+
+    #[test]
+    fn selector_on_game_condition() {
+        assert_eq!(unexpected_selector("ifg selection:hasPlayer(\"Name\")"), ("game conditions".into(), Position::new(3, 9), Position::new(3, 18)));
+    }
+
+    #[test]
+    fn selector_on_player_condition() {
+        assert!(validate("@join {\n    ifp selection:isSneaking() {\n    }\n}").is_ok());
+    }
+
+    fn invalid_default(params: &str) -> (String, Type, ArgType, Position, Position) {
+        match validate(&format!("fn foo({params}) {{\n}}")) {
+            Err(ValidateError::InvalidDefault { name, expected, found, start_pos, end_pos }) => (name, expected, found, start_pos, end_pos),
+            other => panic!("Expected an invalid default, got {other:?}")
+        }
+    }
+
+    #[test]
+    fn text_default_for_number() {
+        assert_eq!(invalid_default("a: number = \"x\""), ("a".into(), Type::Number, ArgType::TEXT, Position::new(1, 20), Position::new(1, 23)));
+    }
+
+    #[test]
+    fn number_default_for_location() {
+        assert_eq!(invalid_default("a: number = 1, b: location = 5").0, "b");
+    }
+
+    #[test]
+    fn matching_defaults() {
+        assert!(validate("fn foo(a: number = 1, b: location = Location(1, 2, 3), d: any = \"x\", f: string = 'x', h: text = \"x\") {\n}").is_ok());
+    }
+
+    fn unassigned(source: &str) -> Vec<(String, String, Position, Position)> {
+        warnings(source).into_iter().filter_map(|warning| match warning {
+            ValidateWarning::UnassignedVariable { name, scope, start_pos, end_pos } => Some((name, scope, start_pos, end_pos)),
+            _ => None
+        }).collect()
+    }
+
+    #[test]
+    fn local_read_before_assignment() {
+        assert_eq!(unassigned("@join {\n    local x;\n    p.sendMessage(x);\n    p.sendMessage(x);\n    v.equal(x, 1);\n}"), [("x".into(), "local".into(), Position::new(3, 19), Position::new(3, 20))]);
+        assert!(unassigned("@join {\n    local x;\n    v.equal(x, 1);\n    p.sendMessage(x);\n}").is_empty());
+    }
+
+    #[test]
+    fn line_read_while_assigned() {
+        assert_eq!(unassigned("@join {\n    line x;\n    v.add(x, x, 1);\n}").len(), 1);
+        assert!(unassigned("@join {\n    line x;\n    line y;\n    v.equal(y, 2);\n    v.add(x, y, 1);\n    p.sendMessage(x);\n}").is_empty());
+    }
+
+    #[test]
+    fn global_variables_are_not_tracked() {
+        assert!(unassigned("game a;\nsave b;\n@join {\n    p.sendMessage(a, b);\n}").is_empty());
+    }
+
+    #[test]
+    fn function_params_are_assigned() {
+        assert!(unassigned("fn foo(a: number) {\n    local y;\n    p.sendMessage(a, y);\n}").is_empty());
+        assert_eq!(unassigned("fn foo(a: number) {\n    line y;\n    p.sendMessage(a, y);\n}")[0].0, "y");
+    }
+
+    #[test]
+    fn codelines_are_tracked_separately() {
+        assert_eq!(unassigned("@join {\n    line x;\n    v.equal(x, 1);\n}\n@leave {\n    line x;\n    p.sendMessage(x);\n}").len(), 1);
+    }
+
+    fn action_selector(action: &str) -> Result<(String, Position, Position), String> {
+        match validate(&format!("@join {{\n    {action};\n}}")) {
+            Err(ValidateError::UnexpectedSelector { kind, start_pos, end_pos }) => Ok((kind, start_pos, end_pos)),
+            other => Err(format!("{other:?}"))
+        }
+    }
+
+    #[test]
+    fn selector_on_control_action() {
+        assert_eq!(action_selector("c:selection.wait(1)"), Ok(("control actions".into(), Position::new(2, 7), Position::new(2, 16))));
+    }
+
+    #[test]
+    fn selector_on_game_and_select_actions() {
+        assert_eq!(action_selector("g:default.cancelEvent()").map(|error| error.0), Ok("game actions".into()));
+        assert_eq!(action_selector("s:killer.reset()").map(|error| error.0), Ok("select actions".into()));
+    }
+
+    #[test]
+    fn selector_on_player_action() {
+        assert!(action_selector("p:selection.sendMessage(\"A\")").is_err());
+        assert!(validate("@join {\n    p:selection.sendMessage(\"A\");\n    c.wait(1);\n}").is_ok());
+    }
+
+    #[test]
+    fn wrapped_argument_position() {
+        match validate("@join {\n    p.sendMessage(\n        \"A\",\n        \"B\");\n    p.teleport(\n        Vector(1,\n            2,\n            3)\n    );\n}") {
+            Err(ValidateError::WrongArgumentType { args, index, .. }) => {
+                let arg = &args[index as usize];
+                assert_eq!((arg.start_pos.clone(), arg.end_pos.clone()), (Position::new(6, 9), Position::new(8, 15)));
+            }
+            other => panic!("Expected a wrong argument type, got {other:?}")
+        }
+    }
+
+    fn plot_warnings(source: &str, config: &Config, variables: &[&str]) -> Vec<(String, Position)> {
+        let mut validator = Validator::new(config, crate::testing::definitions());
+        validator.set_plot_variables(variables.iter().map(|name| name.to_string()).collect());
+        validator.validate(crate::testing::parse(source).unwrap()).unwrap();
+        validator.take_warnings().into_iter().filter_map(|warning| match warning {
+            ValidateWarning::UnknownPlotVariable { name, start_pos, .. } => Some((name, start_pos)),
+            _ => None
+        }).collect()
+    }
+
+    const PLOT_SOURCE: &str = "game coins;\nsave gems;\n@join {\n    line x;\n    v.add(x, coins, gems);\n    p.sendMessage(gems, x);\n}";
+
+    #[test]
+    fn unknown_plot_variables() {
+        assert_eq!(plot_warnings(PLOT_SOURCE, &Config::default(), &["coins", "other"]), [("gems".into(), Position::new(5, 21))]);
+        assert!(plot_warnings(PLOT_SOURCE, &Config::default(), &["coins", "gems"]).is_empty());
+    }
+
+    #[test]
+    fn plot_variables_use_prefix() {
+        let config = Config { variable_prefix: "shop_".into(), ..Default::default() };
+        assert_eq!(plot_warnings(PLOT_SOURCE, &config, &["shop_coins", "gems"]), [("gems".into(), Position::new(5, 21))]);
+    }
+
+    #[test]
+    fn plot_variables_are_not_checked_without_scan() {
+        assert!(warnings(PLOT_SOURCE).iter().all(|warning| !matches!(warning, ValidateWarning::UnknownPlotVariable { .. })));
+    }
+
+    #[test]
+    fn null_for_required_argument() {
+        match validate("@join {\n    p.damage(null);\n}") {
+            Err(ValidateError::NullArgument { name, start_pos, end_pos }) => {
+                assert_eq!((name.as_str(), start_pos, end_pos), ("Damage to inflict", Position::new(2, 14), Position::new(2, 18)));
+            }
+            other => panic!("Expected a null argument, got {other:?}")
+        }
+        assert_eq!(validate("@join {\n    p.damage(null, null);\n}").unwrap_err().code(), "E021");
+    }
+
+    #[test]
+    fn null_for_optional_argument() {
+        assert!(validate("@join {\n    p.damage(5, null);\n}").is_ok());
+        assert!(validate("@join {\n    p.sendMessage(null);\n}").is_ok());
+        let items = |source: &str| crate::testing::blocks(source)[0][1]["args"]["items"].as_array().unwrap().iter()
+            .filter(|item| item["item"]["id"] != "bl_tag").count();
+        assert_eq!(items("@join {\n    p.damage(5, null);\n}"), items("@join {\n    p.damage(5);\n}"));
+    }
+
+    #[test]
+    fn deprecated_actions_warn() {
+        let found = warnings("@join {\n    p.rngTeleport(Location(0, 0, 0));\n    p.sendMessage(\"Hi\");\n}");
+        assert_eq!(found.len(), 1);
+        match &found[0] {
+            ValidateWarning::Deprecated { kind, name, note, start_pos, end_pos } => {
+                assert_eq!((kind.as_str(), name.as_str()), ("Action", "rngTeleport"));
+                assert!(note.starts_with("Use 'Set Variable: Set to Random Value' instead"), "{note}");
+                assert_eq!((start_pos.clone(), end_pos.clone()), (Position::new(2, 7), Position::new(2, 18)));
+            }
+            _ => panic!("Expected a deprecation warning")
+        }
+        assert!(warnings("@join {\n    p.teleport(Location(0, 0, 0));\n}").is_empty());
+    }
+}
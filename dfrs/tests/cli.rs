@@ -0,0 +1,386 @@
+use std::fs;
+use std::io::Write;
+use std::net::TcpListener;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::thread;
+
+use base64::prelude::*;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+// A fresh project directory with a config and the given files
+fn project(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("dfrs-cli-{}-{name}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("dfrs.toml"), "[sending]\napi = \"recode\"\n").unwrap();
+    for (file, source) in files {
+        fs::write(dir.join(file), source).unwrap();
+    }
+    dir
+}
+
+fn dfrs(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_dfrs")).args(args).output().unwrap()
+}
+
+// Runs dfrs inside a directory, so it picks up the config there
+fn dfrs_in(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_dfrs")).args(args).current_dir(dir).output().unwrap()
+}
+
+// A codeline in the format it is sent to the game in
+fn compress(code: &str) -> String {
+    let mut encoder = GzEncoder::new(vec![], Compression::default());
+    encoder.write_all(code.as_bytes()).unwrap();
+    BASE64_STANDARD.encode(encoder.finish().unwrap())
+}
+
+#[test]
+fn failed_compile_exits_with_error() {
+    let dir = project("failed", &[("a.dfrs", "@join {\n    p.sendMessage(\"A\");\n}"), ("b.dfrs", "@join {\n    p.sendMessage(5);\n}")]);
+    let output = dfrs(&["compile", dir.to_str().unwrap(), "--preview"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Compiled 1 files, 1 failed"));
+}
+
+#[test]
+fn successful_compile_exits_cleanly() {
+    let dir = project("succeeded", &[("a.dfrs", "@join {\n    p.sendMessage(\"A\");\n}")]);
+    assert!(dfrs(&["compile", dir.join("a.dfrs").to_str().unwrap(), "--preview"]).status.success());
+}
+
+#[test]
+fn explain_prints_description() {
+    let output = String::from_utf8_lossy(&dfrs(&["explain", "e001"]).stdout).to_string();
+    assert!(output.contains("E001"));
+    assert!(output.contains("Unknown action"));
+    let output = String::from_utf8_lossy(&dfrs(&["explain", "E999"]).stdout).to_string();
+    assert!(output.contains("Unknown error code 'E999'"));
+}
+
+#[test]
+fn log_level_silences_warnings() {
+    let code = compress(r#"{"blocks":[{"id":"block","block":"event","action":"Join"},{"id":"foo"}]}"#);
+    let dir = project("warnings", &[]);
+    let output = dfrs_in(&dir, &["decompile", &code]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unhandled block id 'foo'"));
+
+    fs::write(dir.join("dfrs.toml"), "[logging]\nlevel = \"error\"\n").unwrap();
+    let output = dfrs_in(&dir, &["decompile", &code]);
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("@join {"));
+}
+
+#[test]
+fn imported_functions_are_compiled() {
+    let dir = project("imports", &[
+        ("lib.dfrs", "fn helperA() {\n    p.sendMessage(\"A\");\n}\nfn helperC() {\n}"),
+        ("main.dfrs", "use \"lib.dfrs\" { helperA };\n@join {\n    call(\"helperA\");\n}")
+    ]);
+    let output = dfrs(&["compile", dir.join("main.dfrs").to_str().unwrap(), "--preview"]);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success());
+    assert!(stdout.contains("Function 'helperA'"));
+    assert!(!stdout.contains("helperC"));
+}
+
+#[test]
+fn missing_semicolon_caret() {
+    let dir = project("semicolon", &[("a.dfrs", "@join {\n    p.sendMessage(\"A\")\n    p.sendMessage(\"B\");\n}")]);
+    let output = dfrs(&["compile", dir.join("a.dfrs").to_str().unwrap(), "--preview"]);
+    let stdout = String::from_utf8_lossy(&output.stdout).to_string();
+    let lines: Vec<&str> = stdout.lines().collect();
+    let source = lines.iter().position(|line| line.ends_with("p.sendMessage(\"A\")")).unwrap();
+    assert_eq!(lines[source + 1].find('^'), Some(lines[source].len()));
+}
+
+#[test]
+fn globals_are_shared_between_files() {
+    let dir = project("globals", &[
+        ("globals.dfrs", "game players;\nsave levels;"),
+        ("main.dfrs", "@join {\n    v.add(players, players, 1);\n    p.sendMessage(levels);\n}")
+    ]);
+    let output = dfrs(&["compile", dir.to_str().unwrap(), "--preview"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stdout));
+    assert!(dfrs(&["compile", dir.join("main.dfrs").to_str().unwrap(), "--preview"]).status.success());
+
+    let other = project("globals-elsewhere", &[("main.dfrs", "@join {\n    p.sendMessage(levels);\n}")]);
+    assert!(!dfrs(&["compile", other.join("main.dfrs").to_str().unwrap(), "--preview"]).status.success());
+}
+
+#[test]
+fn unknown_action_suggests_with_description() {
+    let dir = project("suggestion", &[("a.dfrs", "@join {\n    p.sendMesage(\"Hi\");\n}")]);
+    let output = dfrs(&["compile", dir.join("a.dfrs").to_str().unwrap(), "--preview"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Unknown action 'sendMesage', did you mean 'sendMessage' (Sends a chat message to a player.)?"));
+}
+
+#[test]
+fn pack_is_created_and_installed() {
+    let dir = project("pack", &[("a.dfrs", "@join {\n    p.sendMessage(\"A\");\n}"), ("b.dfrs", "@leave {\n    p.sendMessage(\"B\");\n}")]);
+    let output = dfrs_in(&dir, &["compile", ".", "--pack", "shared"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("2 codelines into shared.dfpack"));
+    assert!(dir.join("shared.dfpack").exists());
+
+    // Nothing listens for the code, but the pack itself is read
+    let output = dfrs_in(&dir, &["install", "shared.dfpack"]);
+    assert!(String::from_utf8_lossy(&output.stdout).contains("shared (2 codelines from 2 files)"));
+}
+
+#[test]
+fn pack_cannot_be_watched() {
+    let dir = project("pack-watch", &[("a.dfrs", "@join {\n}")]);
+    let output = dfrs_in(&dir, &["compile", ".", "--pack", "shared", "--watch"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("--pack can't be combined with --watch"));
+    assert!(!dir.join("shared.dfpack").exists());
+}
+
+#[test]
+fn repl_continues_after_errors() {
+    let dir = project("repl", &[]);
+    let mut child = Command::new(env!("CARGO_BIN_EXE_dfrs")).arg("repl").current_dir(&dir)
+        .stdin(Stdio::piped()).stdout(Stdio::piped()).spawn().unwrap();
+    child.stdin.take().unwrap().write_all(b"p.sendMesage(\"Hi\");\n\np.sendMessage(\"Hi\");\nexit\np.heal(1);\n").unwrap();
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Unknown action 'sendMesage'"));
+    assert!(stdout.contains("\"action\": \"SendMessage\""));
+    assert!(!stdout.contains("\"action\": \"Heal\""));
+}
+
+#[test]
+fn give_prints_a_command_per_codeline() {
+    let dir = project("give", &[("a.dfrs", "@join {\n    p.sendMessage(\"A\");\n}\n@leave {\n    p.sendMessage(\"B\");\n}")]);
+    let output = dfrs(&["compile", dir.join("a.dfrs").to_str().unwrap(), "--give"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let commands: Vec<&str> = stdout.lines().filter(|line| line.starts_with("/give @p minecraft:ender_chest[")).collect();
+    assert_eq!(commands.len(), 2);
+    assert!(commands.iter().all(|command| command.contains("hypercube:codetemplatedata")));
+}
+
+#[test]
+fn give_conflicts_with_preview() {
+    let dir = project("give-preview", &[("a.dfrs", "@join {\n}")]);
+    assert!(!dfrs(&["compile", dir.join("a.dfrs").to_str().unwrap(), "--give", "--preview"]).status.success());
+}
+
+#[test]
+fn lint_reports_warnings() {
+    let dir = project("lint", &[("a.dfrs", "@join {\n    line x;\n    p.sendMessage(x);\n}")]);
+    let output = dfrs(&["lint", dir.join("a.dfrs").to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("line variable 'x' is read before it is assigned"));
+    assert!(stdout.contains("1 problem found"));
+}
+
+#[test]
+fn lint_counts_errors_and_warnings() {
+    let dir = project("lint-project", &[
+        ("a.dfrs", "@join {\n    line x;\n    p.sendMessage(x);\n}"),
+        ("b.dfrs", "@leave {\n    p.sendMesage(\"B\");\n}"),
+        ("c.dfrs", "@respawn {\n    p.sendMessage(\"C\");\n}")
+    ]);
+    let output = dfrs(&["lint", dir.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Unknown action 'sendMesage'"));
+    assert!(stdout.contains("2 problems found"));
+}
+
+#[test]
+fn lint_passes_clean_files() {
+    let dir = project("lint-clean", &[("a.dfrs", "@join {\n    line x;\n    v.equal(x, 1);\n    p.sendMessage(x);\n}")]);
+    let output = dfrs(&["lint", dir.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("No problems found"));
+}
+
+#[test]
+fn unknown_blocks_can_fail_decompiling() {
+    let code = compress(r#"{"blocks":[{"id":"block","block":"event","action":"Join"},{"id":"block","block":"fancy_block","action":"Something"}]}"#);
+    let dir = project("unknown-blocks", &[]);
+    assert!(dfrs_in(&dir, &["decompile", &code]).status.success());
+
+    fs::write(dir.join("dfrs.toml"), "[decompiling]\nunknown_blocks = \"error\"\n").unwrap();
+    let output = dfrs_in(&dir, &["decompile", &code]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Block 1: Unhandled block 'fancy_block'"));
+}
+
+#[test]
+fn features_prints_json() {
+    let output = dfrs(&["features"]);
+    assert!(output.status.success());
+    let manifest: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+    assert!(manifest["keywords"].as_array().unwrap().iter().any(|keyword| keyword == "unless"));
+}
+
+// The lines of an error message, from the source line onwards
+fn message_lines(stdout: &[u8], first: &str) -> Vec<String> {
+    let stdout = String::from_utf8_lossy(stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    let start = lines.iter().position(|line| line.starts_with(first)).unwrap();
+    lines[start..].iter().take_while(|line| !line.starts_with("Use dfrs explain")).map(|line| line.to_string()).collect()
+}
+
+#[test]
+fn wrapped_argument_error_marks_each_line() {
+    let dir = project("wrapped", &[("a.dfrs", "@join {\n    p.teleport(\n        Vector(1,\n            2,\n            3)\n    );\n}")]);
+    let output = dfrs(&["compile", dir.join("a.dfrs").to_str().unwrap(), "--preview"]);
+    assert_eq!(message_lines(&output.stdout, "3 |"), [
+        "3 |         Vector(1,",
+        "  |         ^^^^^^^^^",
+        "4 |             2,",
+        "  |             ^^",
+        "5 |             3)",
+        "  |             ^^"
+    ]);
+}
+
+#[test]
+fn long_error_skips_middle_lines() {
+    let dir = project("wrapped-long", &[("a.dfrs", "@join {\n    p.teleport(\n        Vector(1,\n            2,\n            3\n            + 1\n            + 1\n            + 1\n            + 1)\n    );\n}")]);
+    let output = dfrs(&["compile", dir.join("a.dfrs").to_str().unwrap(), "--preview"]);
+    assert_eq!(message_lines(&output.stdout, "3 |"), [
+        "3 |         Vector(1,",
+        "  |         ^^^^^^^^^",
+        "4 |             2,",
+        "  |             ^^",
+        "... 3 more lines",
+        "8 |             + 1",
+        "  |             ^^^",
+        "9 |             + 1)",
+        "  |             ^^^^"
+    ]);
+}
+
+#[test]
+fn excluded_files_are_skipped() {
+    let dir = project("exclude", &[("a.dfrs", "@join {\n    p.sendMessage(\"A\");\n}"), ("wip.dfrs", "@join {\n    p.sendMessage(5);\n}")]);
+    let output = dfrs(&["compile", dir.to_str().unwrap(), "--preview", "--exclude", "wip*"]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Skipped 1 files"));
+}
+
+#[test]
+fn error_at_first_column_is_marked_from_the_start() {
+    let dir = project("first-column", &[("a.dfrs", "foo @join {\n}"), ("b.dfrs", ";\n@join {\n}")]);
+    let output = dfrs(&["compile", dir.join("a.dfrs").to_str().unwrap(), "--preview"]);
+    assert_eq!(message_lines(&output.stdout, "1 |"), ["1 | foo @join {", "  | ^^^"]);
+    let output = dfrs(&["compile", dir.join("b.dfrs").to_str().unwrap(), "--preview"]);
+    assert_eq!(message_lines(&output.stdout, "1 |"), ["1 | ;", "  | ^"]);
+}
+
+#[test]
+fn test_passes_the_example() {
+    let example = Path::new(env!("CARGO_MANIFEST_DIR")).join("../examples/expectations.dfrs");
+    let output = dfrs(&["test", example.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("2 passed"));
+}
+
+#[test]
+fn test_reports_failed_expectations() {
+    let dir = project("test-failing", &[
+        ("a.dfrs", "// #expect event.Join player_action.SendMessage\n@join {\n    p.sendMessage(\"A\");\n}\n// #expect event.Leave player_action.SendMessage\n@leave {\n    p.creativeMode();\n}"),
+        ("b.dfrs", "// #expect event.Sneak\n@join {\n}"),
+        ("c.dfrs", "@join {\n}")
+    ]);
+    let output = dfrs(&["test", dir.to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Failed: event.Leave (line 5)"), "{stdout}");
+    assert!(stdout.contains("found    event.Leave player_action.CreativeMode"), "{stdout}");
+    assert!(stdout.contains("No codeline starts with 'event.Sneak'"), "{stdout}");
+    assert!(stdout.contains("1 passed, 2 failed"), "{stdout}");
+    assert!(!stdout.contains("c.dfrs"), "{stdout}");
+}
+
+#[test]
+fn test_without_expectations() {
+    let dir = project("test-empty", &[("a.dfrs", "@join {\n}")]);
+    let output = dfrs(&["test", dir.to_str().unwrap()]);
+    assert!(output.status.success());
+    assert!(String::from_utf8_lossy(&output.stdout).contains("No expectations found"));
+}
+
+// A websocket server that answers one plot scan with the given codelines, returning its url
+fn scan_server(scan: String) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut socket = tungstenite::accept(stream).unwrap();
+        socket.read().unwrap();
+        socket.send(tungstenite::Message::Text("auth".into())).unwrap();
+        socket.read().unwrap();
+        socket.send(tungstenite::Message::Text(scan)).unwrap();
+    });
+    url
+}
+
+#[test]
+fn decompile_plot_skips_malformed_codelines() {
+    let scan = [
+        compress(r#"{"blocks":[{"id":"block","block":"event","action":"Join"}]}"#),
+        "not a codeline".to_string(),
+        compress(r#"{"blocks":[{"id":"block","block":"event","action":"Leave"}]}"#),
+        compress(r#"{"blocks":[{"id":"block","block":"event","action":"Join"},{"id":"block","block":"repeat","action":"NotARealRepeat"},{"id":"bracket","direct":"open","type":"repeat"},{"id":"bracket","direct":"close","type":"repeat"}]}"#),
+        compress(r#"{"blocks":[{"id":"block","block":"event","action":"Join"},{"id":"block","block":"if_player","action":"NotARealCondition"},{"id":"bracket","direct":"open"},{"id":"bracket","direct":"close"}]}"#),
+        compress(r#"{"blocks":[{"id":"block","block":"event","action":"Respawn"}]}"#)
+    ].join("\n");
+    let dir = project("decompile-plot", &[]);
+    let output = dfrs_in(&dir, &["decompile-plot", "plot.dfrs", "--url", &scan_server(scan)]);
+    assert_eq!(output.status.code(), Some(1));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Decompiling codeline 1/6") && stdout.contains("Decompiling codeline 6/6"), "{stdout}");
+    assert!(stdout.contains("Decompiled 3 codelines, 3 failed"), "{stdout}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("Codeline 2: Invalid code"), "{stderr}");
+    assert!(stderr.contains("Codeline 4: Invalid code: Block 1: unknown repeat 'notARealRepeat'"), "{stderr}");
+    assert!(stderr.contains("Codeline 5: Invalid code: Block 1: unknown conditional 'notARealCondition'"), "{stderr}");
+    let decompiled = fs::read_to_string(dir.join("plot.dfrs")).unwrap();
+    assert!(decompiled.contains("@join {") && decompiled.contains("@leave {") && decompiled.contains("@respawn {"), "{decompiled}");
+    assert!(!decompiled.contains("notARealRepeat"), "{decompiled}");
+}
+
+#[test]
+fn errors_line_up_with_tabs() {
+    let dir = project("tabs", &[("a.dfrs", "@join {\n\tp.sendMessage(\"A\", 5);\n}")]);
+    let output = dfrs(&["compile", dir.join("a.dfrs").to_str().unwrap(), "--preview"]);
+    assert_eq!(message_lines(&output.stdout, "2 |"), ["2 |     p.sendMessage(\"A\", 5);", "  |       ^^^^^^^^^^^"]);
+
+    fs::write(dir.join("dfrs.toml"), "tab_width = 2\n[sending]\napi = \"recode\"\n").unwrap();
+    let output = dfrs(&["compile", dir.join("a.dfrs").to_str().unwrap(), "--preview"]);
+    assert_eq!(message_lines(&output.stdout, "2 |"), ["2 |   p.sendMessage(\"A\", 5);", "  |     ^^^^^^^^^^^"]);
+}
+
+#[test]
+fn lint_reports_deprecated_actions() {
+    let dir = project("lint-deprecated", &[("a.dfrs", "@join {\n    p.rngTeleport(Location(0, 0, 0));\n}")]);
+    let output = dfrs(&["lint", dir.join("a.dfrs").to_str().unwrap()]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Action 'rngTeleport' is deprecated: Use 'Set Variable: Set to Random Value' instead."));
+}
+
+#[test]
+fn compile_prints_stats() {
+    let dir = project("stats", &[
+        ("a.dfrs", "@join {\n    p.sendMessage(\"A\");\n    p.sendMessage(\"B\");\n}"),
+        ("b.dfrs", "@leave {\n    p.sendMessage($name);\n    c.wait(1);\n}")
+    ]);
+    let output = dfrs(&["compile", dir.to_str().unwrap(), "--preview", "--stats"]);
+    assert!(output.status.success());
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let start = stdout.find("Actions (4 used)").unwrap();
+    assert_eq!(&stdout[start..], "Actions (4 used)\n     3  p.sendMessage\n     1  c.wait\nEvents (2 used)\n     1  @join\n     1  @leave\nGame values (1 used)\n     1  $name\n");
+    assert!(!String::from_utf8_lossy(&dfrs(&["compile", dir.to_str().unwrap(), "--preview"]).stdout).contains("Actions ("));
+}